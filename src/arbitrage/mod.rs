@@ -0,0 +1,395 @@
+use crate::binance::api::{OrderInput, OrderSide, OrderType, SymbolInfo, TimeInForce};
+use crate::binance::websocket::StreamBookTicker;
+use crate::fees::FeeModel;
+use crate::shared::config::TriangleSetting;
+use anyhow::{anyhow, ensure, Result};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Best bid and ask of a symbol with the quantity resting at each
+#[derive(Clone, Debug)]
+pub struct BookTicker {
+  pub symbol: String,
+  pub time: i64, // local receive time, the stream doesn't carry one
+  pub bid_price: f64,
+  pub bid_qty: f64,
+  pub ask_price: f64,
+  pub ask_qty: f64,
+}
+
+impl BookTicker {
+  pub fn from_stream(ticker: &StreamBookTicker, time: i64) -> Result<Self> {
+    Ok(Self {
+      symbol: ticker.symbol.to_uppercase(),
+      time,
+      bid_price: ticker.bid_price.parse()?,
+      bid_qty: ticker.bid_qty.parse()?,
+      ask_price: ticker.ask_price.parse()?,
+      ask_qty: ticker.ask_qty.parse()?,
+    })
+  }
+}
+
+/// Trading rules of a symbol the cycles go through
+#[derive(Clone, Debug)]
+pub struct Market {
+  pub symbol: String,
+  pub base_asset: String,
+  pub quote_asset: String,
+  pub min_qty: f64,
+  pub step_size: f64,
+  pub min_notional: f64,
+  pub taker_fee: f64, // fraction of what is received, set from the fee model
+}
+
+impl Market {
+  pub fn from_info(info: &SymbolInfo) -> Result<Self> {
+    let (min_qty, _, step_size) = info
+      .lot_size()
+      .ok_or_else(|| anyhow!("No LOT_SIZE filter for {}", info.symbol))?;
+    ensure!(
+      step_size > 0.0,
+      "Step size of {} is {}",
+      info.symbol,
+      step_size
+    );
+    Ok(Self {
+      symbol: info.symbol.clone(),
+      base_asset: info.base_asset.clone(),
+      quote_asset: info.quote_asset.clone(),
+      min_qty,
+      step_size,
+      min_notional: info.min_notional(),
+      taker_fee: 0.0,
+    })
+  }
+
+  /// Round a base quantity down to the step size
+  pub fn round_qty(&self, quantity: f64) -> f64 {
+    // The epsilon keeps exact multiples from flooring one step short
+    (quantity / self.step_size + 1e-9).floor() * self.step_size
+  }
+}
+
+#[derive(Clone, Debug)]
+struct CycleLeg {
+  market: usize,
+  side: OrderSide, // buys the base asset with the quote asset or sells it for it
+}
+
+/// Three conversions from the start asset back to itself, leg `i`
+/// turns `assets[i]` into `assets[(i + 1) % 3]`
+#[derive(Clone, Debug)]
+pub struct Cycle {
+  pub assets: [String; 3],
+  legs: [CycleLeg; 3],
+}
+
+impl fmt::Display for Cycle {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} -> {} -> {} -> {}",
+      self.assets[0], self.assets[1], self.assets[2], self.assets[0]
+    )
+  }
+}
+
+/// Order of one leg, priced at the touch it was planned on
+#[derive(Clone, Debug)]
+pub struct PlannedLeg {
+  pub market: Market,
+  pub side: OrderSide,
+  pub price: f64,
+  pub quantity: f64, // base asset, rounded to the step size
+  pub fee: f64,      // taker fee as a fraction of what is received
+}
+
+impl PlannedLeg {
+  /// Base quantity `amount` of the asset going in trades, rounded down
+  pub fn quantity_for(&self, amount: f64) -> f64 {
+    match self.side {
+      OrderSide::Buy => self.market.round_qty(amount / self.price),
+      OrderSide::Sell => self.market.round_qty(amount),
+    }
+  }
+
+  /// Asset coming out of `quantity` base traded for `quote` quote, after fees
+  pub fn proceeds(&self, quantity: f64, quote: f64) -> f64 {
+    match self.side {
+      OrderSide::Buy => quantity * (1.0 - self.fee),
+      OrderSide::Sell => quote * (1.0 - self.fee),
+    }
+  }
+
+  /// Whether the exchange takes an order of `quantity` at the planned price
+  pub fn tradable(&self, quantity: f64) -> bool {
+    quantity > 0.0
+      && quantity >= self.market.min_qty
+      && quantity * self.price >= self.market.min_notional
+  }
+
+  /// Limit IOC order at the planned price, whatever the touch no longer
+  /// holds is canceled rather than chased
+  pub fn order(&self, quantity: f64, client_order_id: String, time: i64) -> OrderInput {
+    OrderInput {
+      symbol: self.market.symbol.clone(),
+      side: self.side.clone(),
+      order_type: OrderType::Limit,
+      time_in_force: Some(TimeInForce::IOC),
+      quantity: Some(quantity as f32),
+      quote_order_qty: None,
+      price: Some(self.price as f32),
+      new_client_order_id: client_order_id,
+      stop_price: None,
+      iceberg_qty: None,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: time,
+    }
+  }
+}
+
+/// Cycle that pays at the current books, sized to what the top of every
+/// book holds and `max_notional`
+#[derive(Clone, Debug)]
+pub struct Opportunity {
+  pub route: String,
+  pub time: i64,
+  pub start_asset: String,
+  pub start_amount: f64, // spent on the first leg
+  pub end_amount: f64,   // received from the last leg
+  pub legs: Vec<PlannedLeg>,
+}
+
+impl Opportunity {
+  pub fn profit(&self) -> f64 {
+    self.end_amount - self.start_amount
+  }
+
+  pub fn profit_bps(&self) -> f64 {
+    self.profit() / self.start_amount * 1e4
+  }
+}
+
+impl fmt::Display for Opportunity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}: {:.2} bps, {} {} on {} {} via",
+      self.route,
+      self.profit_bps(),
+      self.profit(),
+      self.start_asset,
+      self.start_amount,
+      self.start_asset
+    )?;
+    for leg in &self.legs {
+      write!(
+        f,
+        " {:?} {} {} @ {}",
+        leg.side, leg.quantity, leg.market.symbol, leg.price
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// Watches the book tickers of a set of symbols for cycles through three
+/// of them that end with more of the start asset than they began with,
+/// net of taker fees and lot size rounding. Every leg crosses the spread,
+/// buys pay the ask and sells get the bid. Fees paid in BNB are counted
+/// as if they came out of what each leg receives.
+pub struct TriangleArbitrage {
+  setting: TriangleSetting,
+  markets: Vec<Market>,
+  cycles: Vec<Cycle>,
+  by_symbol: HashMap<String, Vec<usize>>, // cycles each symbol is a leg of
+  books: HashMap<String, BookTicker>,
+  last_seen: HashMap<usize, i64>, // time of each cycle's last opportunity
+}
+
+impl TriangleArbitrage {
+  pub fn new(mut markets: Vec<Market>, setting: TriangleSetting, fees: &FeeModel) -> Result<Self> {
+    for market in &mut markets {
+      market.taker_fee = fees.rates(&market.symbol).taker;
+    }
+    let start_assets = setting
+      .start_assets
+      .iter()
+      .map(|asset| asset.to_uppercase())
+      .collect::<Vec<_>>();
+    let cycles = find_cycles(&markets, &start_assets);
+    ensure!(
+      !cycles.is_empty(),
+      "No triangle starting in {:?} among {:?}",
+      start_assets,
+      markets.iter().map(|m| &m.symbol).collect::<Vec<_>>()
+    );
+    let mut by_symbol: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, cycle) in cycles.iter().enumerate() {
+      for leg in &cycle.legs {
+        by_symbol
+          .entry(markets[leg.market].symbol.clone())
+          .or_default()
+          .push(index);
+      }
+    }
+    Ok(Self {
+      setting,
+      markets,
+      cycles,
+      by_symbol,
+      books: HashMap::new(),
+      last_seen: HashMap::new(),
+    })
+  }
+
+  pub fn cycles(&self) -> &[Cycle] {
+    &self.cycles
+  }
+
+  /// Update the book of the ticker's symbol and evaluate every cycle
+  /// going through it, cycles in their cooldown are skipped
+  pub fn on_book_ticker(&mut self, ticker: BookTicker) -> Vec<Opportunity> {
+    let time = ticker.time;
+    let cycles = match self.by_symbol.get(&ticker.symbol) {
+      Some(cycles) => cycles.clone(),
+      None => return vec![],
+    };
+    self.books.insert(ticker.symbol.clone(), ticker);
+    let mut opportunities = vec![];
+    for index in cycles {
+      if matches!(self.last_seen.get(&index), Some(last) if time - last < self.setting.cooldown_ms)
+      {
+        continue;
+      }
+      if let Some(opportunity) = self.evaluate(index, time) {
+        self.last_seen.insert(index, time);
+        opportunities.push(opportunity);
+      }
+    }
+    opportunities
+  }
+
+  fn evaluate(&self, index: usize, time: i64) -> Option<Opportunity> {
+    let cycle = &self.cycles[index];
+    let mut legs = vec![];
+    for leg in &cycle.legs {
+      let market = &self.markets[leg.market];
+      let book = self.books.get(&market.symbol)?;
+      if book.bid_price <= 0.0 || book.ask_price <= 0.0 {
+        return None;
+      }
+      let (price, top_qty) = match leg.side {
+        OrderSide::Buy => (book.ask_price, book.ask_qty),
+        OrderSide::Sell => (book.bid_price, book.bid_qty),
+      };
+      legs.push((market, leg.side.clone(), price, top_qty));
+    }
+
+    // Per unit of start asset, before rounding: the rate of the whole
+    // cycle and how much start asset each top of book can take
+    let mut amount = 1.0;
+    let mut start_amount = self.setting.max_notional;
+    for (market, side, price, top_qty) in &legs {
+      let quantity = match side {
+        OrderSide::Buy => amount / price,
+        OrderSide::Sell => amount,
+      };
+      start_amount = start_amount.min(top_qty / quantity);
+      amount = match side {
+        OrderSide::Buy => quantity * (1.0 - market.taker_fee),
+        OrderSide::Sell => quantity * price * (1.0 - market.taker_fee),
+      };
+    }
+    if (amount - 1.0) * 1e4 < self.setting.min_profit_bps {
+      return None;
+    }
+
+    // The same cycle in exchange quantities, each leg trades what the
+    // previous one received rounded down to its step size
+    let mut planned = vec![];
+    let mut amount = start_amount;
+    let mut spent = 0.0;
+    for (market, side, price, _) in legs {
+      let mut leg = PlannedLeg {
+        market: market.clone(),
+        side,
+        price,
+        quantity: 0.0,
+        fee: market.taker_fee,
+      };
+      leg.quantity = leg.quantity_for(amount);
+      if !leg.tradable(leg.quantity) {
+        return None;
+      }
+      if planned.is_empty() {
+        spent = match leg.side {
+          OrderSide::Buy => leg.quantity * price,
+          OrderSide::Sell => leg.quantity,
+        };
+      }
+      amount = leg.proceeds(leg.quantity, leg.quantity * price);
+      planned.push(leg);
+    }
+    let opportunity = Opportunity {
+      route: cycle.to_string(),
+      time,
+      start_asset: cycle.assets[0].clone(),
+      start_amount: spent,
+      end_amount: amount,
+      legs: planned,
+    };
+    if opportunity.profit_bps() < self.setting.min_profit_bps {
+      return None;
+    }
+    Some(opportunity)
+  }
+}
+
+// Every cycle of three distinct markets and three distinct assets that
+// starts in one of `start_assets`, or anywhere when it's empty. Both
+// directions around a triangle are separate cycles.
+fn find_cycles(markets: &[Market], start_assets: &[String]) -> Vec<Cycle> {
+  // Conversions from one asset to another through a market
+  let edges = markets
+    .iter()
+    .enumerate()
+    .flat_map(|(index, market)| {
+      let base = market.base_asset.to_uppercase();
+      let quote = market.quote_asset.to_uppercase();
+      vec![
+        (quote.clone(), base.clone(), index, OrderSide::Buy),
+        (base, quote, index, OrderSide::Sell),
+      ]
+    })
+    .collect::<Vec<_>>();
+
+  let mut cycles = vec![];
+  for first in &edges {
+    let start = &first.0;
+    if !start_assets.is_empty() && !start_assets.contains(start) {
+      continue;
+    }
+    for second in edges
+      .iter()
+      .filter(|e| e.0 == first.1 && e.2 != first.2 && e.1 != *start)
+    {
+      for third in edges
+        .iter()
+        .filter(|e| e.0 == second.1 && e.1 == *start && e.2 != first.2 && e.2 != second.2)
+      {
+        let leg = |edge: &(String, String, usize, OrderSide)| CycleLeg {
+          market: edge.2,
+          side: edge.3.clone(),
+        };
+        cycles.push(Cycle {
+          assets: [start.clone(), first.1.clone(), second.1.clone()],
+          legs: [leg(first), leg(second), leg(third)],
+        });
+      }
+    }
+  }
+  cycles
+}
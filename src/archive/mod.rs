@@ -0,0 +1,404 @@
+use crate::shared::csv_schema::{orderbook_header, CsvDataType, TRADE_HEADER};
+use crate::shared::reader::RecordReader;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use csv::StringRecord;
+use flate2::{write::GzEncoder, Compression};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression as ParquetCompression;
+use parquet::file::properties::WriterProperties;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Format recorded files are converted to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+  Raw, // the CSV layout `record` writes, under the same file name
+  CsvGz,
+  Parquet,
+}
+
+impl FromStr for ArchiveFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(format: &str) -> Result<Self> {
+    Ok(match format.to_lowercase().as_str() {
+      "raw" | "csv" => ArchiveFormat::Raw,
+      "csv.gz" | "gz" | "gzip" => ArchiveFormat::CsvGz,
+      "parquet" => ArchiveFormat::Parquet,
+      _ => bail!(
+        "Unknown archive format {}, expected raw, csv.gz or parquet",
+        format
+      ),
+    })
+  }
+}
+
+impl ArchiveFormat {
+  /// Appended to the name of the recorded file
+  pub fn extension(&self) -> &'static str {
+    match self {
+      ArchiveFormat::Raw => "",
+      ArchiveFormat::CsvGz => ".csv.gz",
+      ArchiveFormat::Parquet => ".parquet",
+    }
+  }
+}
+
+/// Row of a trade or orderbook file, `md_time` and the columns after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveRow {
+  pub time: i64,
+  pub values: Vec<f64>,
+}
+
+/// Rows of one file through conversion
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConvertStats {
+  pub read: usize,
+  pub invalid: usize,
+  pub duplicates: usize,
+  pub written: usize,
+}
+
+impl ConvertStats {
+  pub fn add(&mut self, other: &ConvertStats) {
+    self.read += other.read;
+    self.invalid += other.invalid;
+    self.duplicates += other.duplicates;
+    self.written += other.written;
+  }
+}
+
+impl fmt::Display for ConvertStats {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} rows read, {} invalid, {} duplicates, {} written",
+      self.read, self.invalid, self.duplicates, self.written
+    )
+  }
+}
+
+/// Columns of the files `record` writes for `data_type`, `md_time` first
+pub fn archive_header(data_type: &CsvDataType) -> Result<Vec<String>> {
+  Ok(match data_type {
+    CsvDataType::Trade => TRADE_HEADER
+      .iter()
+      .map(|column| column.to_string())
+      .collect(),
+    CsvDataType::OrderBook => orderbook_header(),
+    _ => bail!(
+      "Only trade and orderbook files can be archived, not {}",
+      String::from(data_type.clone())
+    ),
+  })
+}
+
+/// Check a row against `header`: as many columns, an integer `md_time`
+/// and finite numbers after it
+pub fn parse_row(record: &StringRecord, header: &[String]) -> Result<ArchiveRow> {
+  ensure!(
+    record.len() == header.len(),
+    "{} columns where {} are expected",
+    record.len(),
+    header.len()
+  );
+  let time = record[0]
+    .parse::<i64>()
+    .with_context(|| format!("md_time {:?} isn't a timestamp", &record[0]))?;
+  ensure!(time > 0, "md_time {} is before the epoch", time);
+  let values = record
+    .iter()
+    .zip(header)
+    .skip(1)
+    .map(|(field, column)| match field.parse::<f64>() {
+      Ok(value) if value.is_finite() => Ok(value),
+      _ => bail!("{} {:?} isn't a number", column, field),
+    })
+    .collect::<Result<Vec<_>>>()?;
+  Ok(ArchiveRow { time, values })
+}
+
+/// Read a recorded file, skipping the header rows every append re-emits.
+/// Rows that don't match the schema are logged and dropped, or fail the
+/// read when `strict`.
+pub fn read_file(
+  path: &Path,
+  data_type: &CsvDataType,
+  strict: bool,
+) -> Result<(Vec<ArchiveRow>, ConvertStats)> {
+  let header = archive_header(data_type)?;
+  let mut stats = ConvertStats::default();
+  let mut rows = vec![];
+  for record in RecordReader::new(vec![path.to_path_buf()], data_type) {
+    let record = record?;
+    stats.read += 1;
+    match parse_row(&record, &header) {
+      Ok(row) => rows.push(row),
+      Err(e) if strict => {
+        return Err(e.context(format!("Invalid row {} of {}", stats.read, path.display())))
+      }
+      Err(e) => {
+        log::warn!("Dropping row {} of {}: {:#}", stats.read, path.display(), e);
+        stats.invalid += 1;
+      }
+    }
+  }
+  Ok((rows, stats))
+}
+
+/// Sort rows by `md_time`, keeping recording order within a millisecond
+pub fn sort_rows(rows: &mut [ArchiveRow]) {
+  rows.sort_by_key(|row| row.time);
+}
+
+/// Drop rows repeating an earlier one of the same millisecond, as
+/// overlapping recorders write them, and return how many went. Trades
+/// carry no id, so distinct trades of the same price and quantity within
+/// a millisecond are taken for one. Rows have to be sorted.
+pub fn dedup_rows(rows: &mut Vec<ArchiveRow>) -> usize {
+  let before = rows.len();
+  let mut kept: Vec<ArchiveRow> = Vec::with_capacity(rows.len());
+  let mut millisecond_start = 0;
+  for row in rows.drain(..) {
+    if kept.last().is_none_or(|last| last.time != row.time) {
+      millisecond_start = kept.len();
+    }
+    if !kept[millisecond_start..].contains(&row) {
+      kept.push(row);
+    }
+  }
+  *rows = kept;
+  before - rows.len()
+}
+
+/// Write `rows` to `path` in `format`. The file is written next to it
+/// first and renamed over it once complete, so converting a file onto
+/// itself loses nothing if it fails midway.
+pub fn write_file(
+  path: &Path,
+  header: &[String],
+  rows: &[ArchiveRow],
+  format: ArchiveFormat,
+) -> Result<()> {
+  let partial = path.with_file_name(format!(
+    "{}.partial",
+    path
+      .file_name()
+      .map(|name| name.to_string_lossy())
+      .unwrap_or_default()
+  ));
+  let file =
+    File::create(&partial).with_context(|| format!("Failed to create {}", partial.display()))?;
+  match format {
+    ArchiveFormat::Raw => {
+      write_csv(file, header, rows)?;
+    }
+    ArchiveFormat::CsvGz => {
+      write_csv(GzEncoder::new(file, Compression::default()), header, rows)?.finish()?;
+    }
+    ArchiveFormat::Parquet => write_parquet(file, header, rows)?,
+  }
+  std::fs::rename(&partial, path)
+    .with_context(|| format!("Failed to move {} into place", partial.display()))?;
+  Ok(())
+}
+
+fn write_csv<W: Write>(writer: W, header: &[String], rows: &[ArchiveRow]) -> Result<W> {
+  let mut writer = csv::Writer::from_writer(writer);
+  writer.write_record(header)?;
+  for row in rows {
+    let mut record = Vec::with_capacity(header.len());
+    record.push(row.time.to_string());
+    record.extend(row.values.iter().map(|value| value.to_string()));
+    writer.write_record(&record)?;
+  }
+  writer.flush()?;
+  writer
+    .into_inner()
+    .map_err(|e| anyhow!("Failed to flush: {}", e.error()))
+}
+
+fn write_parquet(file: File, header: &[String], rows: &[ArchiveRow]) -> Result<()> {
+  let fields = header
+    .iter()
+    .enumerate()
+    .map(|(i, column)| {
+      let data_type = if i == 0 {
+        DataType::Int64
+      } else {
+        DataType::Float64
+      };
+      Field::new(column.as_str(), data_type, false)
+    })
+    .collect::<Vec<_>>();
+  let schema = Arc::new(Schema::new(fields));
+  let mut columns: Vec<ArrayRef> = vec![Arc::new(Int64Array::from_iter_values(
+    rows.iter().map(|row| row.time),
+  ))];
+  for i in 0..header.len() - 1 {
+    columns.push(Arc::new(Float64Array::from_iter_values(
+      rows.iter().map(|row| row.values[i]),
+    )));
+  }
+  let batch = RecordBatch::try_new(schema.clone(), columns)?;
+  let properties = WriterProperties::builder()
+    .set_compression(ParquetCompression::SNAPPY)
+    .build();
+  let mut writer = ArrowWriter::try_new(file, schema, Some(properties))?;
+  writer.write(&batch)?;
+  writer.close()?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use arrow_array::Array;
+  use flate2::read::GzDecoder;
+  use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+  use tempfile::TempDir;
+
+  fn header() -> Vec<String> {
+    archive_header(&CsvDataType::Trade).unwrap()
+  }
+
+  fn record(fields: &[&str]) -> StringRecord {
+    StringRecord::from(fields.to_vec())
+  }
+
+  fn row(time: i64, price: f64, amount: f64) -> ArchiveRow {
+    ArchiveRow {
+      time,
+      values: vec![price, amount],
+    }
+  }
+
+  fn rows() -> Vec<ArchiveRow> {
+    vec![row(1_000, 100.5, 0.25), row(1_001, 101.0, 1e-8)]
+  }
+
+  fn read_csv<R: std::io::Read>(reader: R) -> Vec<ArchiveRow> {
+    csv::Reader::from_reader(reader)
+      .records()
+      .map(|record| parse_row(&record.unwrap(), &header()).unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn parse_row_checks_the_schema() {
+    let header = header();
+    assert_eq!(
+      parse_row(&record(&["1000", "100.5", "0.25"]), &header).unwrap(),
+      row(1_000, 100.5, 0.25)
+    );
+    assert!(parse_row(&record(&["1000", "100.5"]), &header).is_err());
+    assert!(parse_row(&record(&["1000", "100.5", "0.25", "1"]), &header).is_err());
+    assert!(parse_row(&record(&["1000", "NaN", "0.25"]), &header).is_err());
+    assert!(parse_row(&record(&["1000", "100.5", "inf"]), &header).is_err());
+    assert!(parse_row(&record(&["1000", "", "0.25"]), &header).is_err());
+    assert!(parse_row(&record(&["1e3", "100.5", "0.25"]), &header).is_err());
+    assert!(parse_row(&record(&["0", "100.5", "0.25"]), &header).is_err());
+  }
+
+  #[test]
+  fn read_file_skips_headers_and_drops_invalid_rows() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("btcusdt_trade_20230601");
+    std::fs::write(
+      &path,
+      "md_time,price,amount\n1000,100.5,0.25\nmd_time,price,amount\n1001,NaN,1\n1001,101,1e-8\n",
+    )
+    .unwrap();
+    let (read, stats) = read_file(&path, &CsvDataType::Trade, false).unwrap();
+    assert_eq!(read, rows());
+    assert_eq!((stats.read, stats.invalid), (3, 1));
+    assert!(read_file(&path, &CsvDataType::Trade, true).is_err());
+  }
+
+  #[test]
+  fn dedup_drops_repeats_within_a_millisecond() {
+    let mut rows = vec![
+      row(1, 100.0, 1.0),
+      row(1, 101.0, 1.0),
+      row(1, 100.0, 1.0),
+      row(2, 100.0, 1.0),
+      row(2, 100.0, 1.0),
+      row(3, 101.0, 1.0),
+    ];
+    assert_eq!(dedup_rows(&mut rows), 2);
+    assert_eq!(
+      rows,
+      vec![
+        row(1, 100.0, 1.0),
+        row(1, 101.0, 1.0),
+        row(2, 100.0, 1.0),
+        row(3, 101.0, 1.0),
+      ]
+    );
+  }
+
+  #[test]
+  fn written_files_read_back() {
+    let dir = TempDir::new().unwrap();
+    let header = header();
+
+    let raw = dir.path().join("btcusdt_trade_20230601");
+    write_file(&raw, &header, &rows(), ArchiveFormat::Raw).unwrap();
+    assert_eq!(
+      read_file(&raw, &CsvDataType::Trade, true).unwrap().0,
+      rows()
+    );
+
+    let gz = dir.path().join("btcusdt_trade_20230601.csv.gz");
+    write_file(&gz, &header, &rows(), ArchiveFormat::CsvGz).unwrap();
+    assert_eq!(read_csv(GzDecoder::new(File::open(&gz).unwrap())), rows());
+
+    let parquet = dir.path().join("btcusdt_trade_20230601.parquet");
+    write_file(&parquet, &header, &rows(), ArchiveFormat::Parquet).unwrap();
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&parquet).unwrap())
+      .unwrap()
+      .build()
+      .unwrap();
+    let mut read = vec![];
+    for batch in reader {
+      let batch = batch.unwrap();
+      let schema = batch.schema();
+      let columns = schema
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .collect::<Vec<_>>();
+      assert_eq!(columns, header);
+      let times = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+      let values = (1..batch.num_columns())
+        .map(|i| {
+          batch
+            .column(i)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+      for i in 0..batch.num_rows() {
+        read.push(ArchiveRow {
+          time: times.value(i),
+          values: values.iter().map(|column| column.value(i)).collect(),
+        });
+      }
+    }
+    assert_eq!(read, rows());
+    // Nothing is left behind next to the converted files
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+  }
+}
@@ -0,0 +1,625 @@
+use super::{BacktestReport, Backtester, SimulatedExchange};
+use crate::binance::api::{OrderInput, OrderSide, OrderType, TimeInForce};
+use crate::oms::{OrderStatus, OrderUpdate};
+use crate::shared::config::DepthSetting;
+use crate::shared::reader::{MarketTrade, RecordedEvent};
+use crate::shared::utils::QTY_EPSILON;
+use crate::strategy::orderbook::OrderBook;
+use crate::strategy::Strategy;
+use anyhow::{ensure, Result};
+use std::collections::VecDeque;
+
+// Order prices go through f32, prices this close are the same level
+const PRICE_TOLERANCE: f64 = 1e-7;
+
+enum Action {
+  Submit(OrderInput),
+  Cancel(String),
+}
+
+struct InFlight {
+  arrival: i64,
+  action: Action,
+}
+
+// Order working on the book with what is ahead of it at its price
+struct QueuedOrder {
+  order: OrderInput,
+  price: f64, // limit price, 0 for market orders
+  remaining: f64,
+  queue_ahead: f64,
+  filled: f64,
+  filled_quote: f64,
+}
+
+impl QueuedOrder {
+  fn at_price(&self, price: f64) -> bool {
+    (price - self.price).abs() <= self.price * PRICE_TOLERANCE
+  }
+
+  // Whether `price` is past the order's, a trade there went through
+  // everything at its price and a level there on the other side crosses it
+  fn through(&self, price: f64) -> bool {
+    !self.at_price(price)
+      && match self.order.side {
+        OrderSide::Buy => price < self.price,
+        OrderSide::Sell => price > self.price,
+      }
+  }
+
+  // Whether the order trades with the other side at `price`
+  fn crosses(&self, price: f64) -> bool {
+    self.at_price(price) || self.through(price)
+  }
+
+  fn status(&self, status: OrderStatus, time: i64) -> OrderUpdate {
+    let mut update = OrderUpdate::status(&self.order, status, time);
+    update.cumulative_qty = self.filled;
+    update.cumulative_quote_qty = self.filled_quote;
+    update
+  }
+}
+
+/// Fills orders of one symbol on recorded depth. Orders and cancels
+/// reach the book `latency_ms` after they are sent. Marketable orders
+/// walk the levels of the latest snapshot, taking liquidity out of it
+/// until the next one. Resting orders join the back of the quantity
+/// shown at their price, scaled by `queue_position`, move up as that
+/// quantity shrinks or trades at their price, and fill from what
+/// trades past the queue, or in full once the market trades through.
+pub struct DepthFills {
+  setting: DepthSetting,
+  book: Option<OrderBook>, // latest snapshot less what was taken from it
+  in_flight: VecDeque<InFlight>,
+  resting: Vec<QueuedOrder>,
+}
+
+impl DepthFills {
+  pub fn new(setting: DepthSetting) -> Self {
+    Self {
+      setting,
+      book: None,
+      in_flight: VecDeque::new(),
+      resting: vec![],
+    }
+  }
+
+  /// Send an order registered at `time`
+  pub fn submit(&mut self, order: OrderInput, time: i64) {
+    self.send(Action::Submit(order), time);
+  }
+
+  pub fn cancel(&mut self, client_order_id: String, time: i64) {
+    self.send(Action::Cancel(client_order_id), time);
+  }
+
+  fn send(&mut self, action: Action, time: i64) {
+    self.in_flight.push_back(InFlight {
+      arrival: time + self.setting.latency_ms,
+      action,
+    });
+  }
+
+  /// Carry out orders and cancels reaching the book by `time`, in the
+  /// order they were sent
+  pub fn arrive(&mut self, exchange: &mut SimulatedExchange, time: i64) -> Vec<OrderUpdate> {
+    let mut updates = vec![];
+    while matches!(self.in_flight.front(), Some(next) if next.arrival <= time) {
+      let InFlight { arrival, action } = self.in_flight.pop_front().unwrap();
+      match action {
+        Action::Submit(order) => updates.extend(self.place(exchange, order, arrival)),
+        Action::Cancel(client_order_id) => {
+          let index = self
+            .resting
+            .iter()
+            .position(|queued| queued.order.new_client_order_id == client_order_id);
+          match index {
+            Some(index) => {
+              let queued = self.resting.remove(index);
+              updates.push(queued.status(OrderStatus::Canceled, arrival));
+            }
+            None => log::debug!("Order {} not resting, can't cancel", client_order_id),
+          }
+        }
+      }
+    }
+    updates
+  }
+
+  /// Take a new snapshot, resting orders it crossed fill at their price
+  /// and queues shrink with the quantity shown ahead of them
+  pub fn on_book(
+    &mut self,
+    exchange: &mut SimulatedExchange,
+    book: &OrderBook,
+  ) -> Vec<OrderUpdate> {
+    self.book = Some(book.clone());
+    let mut updates = vec![];
+    for mut queued in std::mem::take(&mut self.resting) {
+      let (touch, same_side) = match queued.order.side {
+        OrderSide::Buy => (book.best_ask(), &book.bids),
+        OrderSide::Sell => (book.best_bid(), &book.asks),
+      };
+      if matches!(touch, Some(touch) if queued.crosses(touch)) {
+        let (price, quantity) = (queued.price, queued.remaining);
+        updates.push(fill(
+          exchange,
+          &mut queued,
+          price,
+          quantity,
+          book.time,
+          true,
+        ));
+      } else if let Some(&(_, shown)) = same_side.iter().find(|level| queued.at_price(level.0)) {
+        queued.queue_ahead = queued.queue_ahead.min(shown);
+      } else if matches!(same_side.last(), Some(&(worst, _)) if queued.through(worst)) {
+        // Within the levels shown but not among them, nothing is ahead
+        queued.queue_ahead = 0.0;
+      }
+      if queued.remaining > QTY_EPSILON && !is_final(updates.last(), &queued) {
+        self.resting.push(queued);
+      }
+    }
+    updates
+  }
+
+  /// Fill resting orders from a trade at or through their price, trades
+  /// at their price go to the queue ahead first. Recorded trades carry no
+  /// aggressor side, a print is taken to have traded against every
+  /// resting order it reaches.
+  pub fn on_trade(
+    &mut self,
+    exchange: &mut SimulatedExchange,
+    trade: &MarketTrade,
+  ) -> Vec<OrderUpdate> {
+    let mut updates = vec![];
+    let mut left = trade.quantity;
+    for mut queued in std::mem::take(&mut self.resting) {
+      let quantity = if queued.through(trade.price) {
+        queued.remaining
+      } else if queued.at_price(trade.price) {
+        let ahead = queued.queue_ahead.min(left);
+        queued.queue_ahead -= ahead;
+        left -= ahead;
+        let quantity = queued.remaining.min(left);
+        left -= quantity;
+        quantity
+      } else {
+        0.0
+      };
+      if quantity > QTY_EPSILON {
+        let price = queued.price;
+        updates.push(fill(
+          exchange,
+          &mut queued,
+          price,
+          quantity,
+          trade.time,
+          true,
+        ));
+      }
+      if queued.remaining > QTY_EPSILON && !is_final(updates.last(), &queued) {
+        self.resting.push(queued);
+      }
+    }
+    updates
+  }
+
+  // An order reaching the book: whatever crosses walks the levels, the
+  // rest of a GTC limit order rests and of anything else expires
+  fn place(
+    &mut self,
+    exchange: &mut SimulatedExchange,
+    order: OrderInput,
+    time: i64,
+  ) -> Vec<OrderUpdate> {
+    let rejected = |order: &OrderInput, reason: &str| {
+      log::warn!(
+        "Simulated order {} rejected: {}",
+        order.new_client_order_id,
+        reason
+      );
+      vec![OrderUpdate::status(order, OrderStatus::Rejected, time)]
+    };
+    let book = match &self.book {
+      Some(book) => book,
+      None => return rejected(&order, "no book yet"),
+    };
+    let levels = match order.side {
+      OrderSide::Buy => &book.asks,
+      OrderSide::Sell => &book.bids,
+    };
+    let (limit, quantity) = match (&order.order_type, order.price, order.quantity) {
+      (OrderType::Market, _, Some(quantity)) => (None, quantity as f64),
+      (OrderType::Market, _, None) => match order.quote_order_qty {
+        Some(quote_qty) => (None, quantity_for_quote(levels, quote_qty as f64)),
+        None => return rejected(&order, "missing quantity or quote order qty"),
+      },
+      (OrderType::Limit, Some(price), Some(quantity))
+      | (OrderType::LimitMaker, Some(price), Some(quantity)) => {
+        (Some(price as f64), quantity as f64)
+      }
+      (OrderType::Limit, _, _) | (OrderType::LimitMaker, _, _) => {
+        return rejected(&order, "missing price or quantity")
+      }
+      (order_type, _, _) => {
+        return rejected(
+          &order,
+          &format!("{:?} not supported by simulator", order_type),
+        )
+      }
+    };
+    if quantity <= QTY_EPSILON {
+      return rejected(&order, "order quantity must be positive");
+    }
+    let mut queued = QueuedOrder {
+      price: limit.unwrap_or(0.0),
+      remaining: quantity,
+      queue_ahead: 0.0,
+      filled: 0.0,
+      filled_quote: 0.0,
+      order,
+    };
+    let marketable = levels
+      .iter()
+      .take_while(|level| limit.is_none() || queued.crosses(level.0))
+      .map(|level| level.1)
+      .sum::<f64>();
+    if let OrderType::LimitMaker = queued.order.order_type {
+      if marketable > QTY_EPSILON {
+        return rejected(&queued.order, "order would immediately match and take");
+      }
+    }
+    let rests =
+      limit.is_some() && matches!(queued.order.time_in_force, None | Some(TimeInForce::GTC));
+    let fill_or_kill = matches!(queued.order.time_in_force, Some(TimeInForce::FOK));
+    if fill_or_kill && marketable < quantity - QTY_EPSILON {
+      return vec![queued.status(OrderStatus::Expired, time)];
+    }
+
+    let mut updates = vec![];
+    if marketable > QTY_EPSILON {
+      updates = self.take(exchange, &mut queued, time);
+    }
+    if is_final(updates.last(), &queued) {
+      return updates;
+    }
+    if rests {
+      let same_side = match queued.order.side {
+        OrderSide::Buy => &self.book.as_ref().unwrap().bids,
+        OrderSide::Sell => &self.book.as_ref().unwrap().asks,
+      };
+      queued.queue_ahead = same_side
+        .iter()
+        .find(|level| queued.at_price(level.0))
+        .map_or(0.0, |level| level.1 * self.setting.queue_position);
+      if updates.is_empty() {
+        updates.push(queued.status(OrderStatus::New, time));
+      }
+      self.resting.push(queued);
+    } else {
+      if limit.is_none() {
+        log::warn!(
+          "{} left {} unfilled past the levels recorded",
+          queued.order.new_client_order_id,
+          queued.remaining
+        );
+      }
+      updates.push(queued.status(OrderStatus::Expired, time));
+    }
+    updates
+  }
+
+  // Fill from the other side of the book up to the order's limit price,
+  // one trade per level, taking the quantity out of the snapshot
+  fn take(
+    &mut self,
+    exchange: &mut SimulatedExchange,
+    queued: &mut QueuedOrder,
+    time: i64,
+  ) -> Vec<OrderUpdate> {
+    let book = self.book.as_mut().unwrap();
+    let levels = match queued.order.side {
+      OrderSide::Buy => &mut book.asks,
+      OrderSide::Sell => &mut book.bids,
+    };
+    let limited = queued.price > 0.0;
+    let mut updates = vec![];
+    for level in levels.iter_mut() {
+      if queued.remaining <= QTY_EPSILON || (limited && !queued.crosses(level.0)) {
+        break;
+      }
+      let quantity = queued.remaining.min(level.1);
+      if quantity <= QTY_EPSILON {
+        continue;
+      }
+      let update = fill(exchange, queued, level.0, quantity, time, false);
+      let failed = matches!(update.status, Some(OrderStatus::Expired));
+      updates.push(update);
+      if failed {
+        break;
+      }
+      level.1 -= quantity;
+    }
+    levels.retain(|level| level.1 > QTY_EPSILON);
+    updates
+  }
+}
+
+// Base quantity `quote_qty` buys or sells walking `levels`
+fn quantity_for_quote(levels: &[(f64, f64)], quote_qty: f64) -> f64 {
+  let mut quote_left = quote_qty;
+  let mut quantity = 0.0;
+  for (price, shown) in levels {
+    let taken = shown.min(quote_left / price);
+    quantity += taken;
+    quote_left -= taken * price;
+    if quote_left <= QTY_EPSILON {
+      break;
+    }
+  }
+  quantity
+}
+
+// Fill `quantity` of the order at `price`, an order the balances can't
+// cover expires with what it filled so far
+fn fill(
+  exchange: &mut SimulatedExchange,
+  queued: &mut QueuedOrder,
+  price: f64,
+  quantity: f64,
+  time: i64,
+  maker: bool,
+) -> OrderUpdate {
+  match exchange.fill(&queued.order, price, quantity, time, maker) {
+    Ok(trade) => {
+      queued.remaining -= quantity;
+      queued.filled += quantity;
+      queued.filled_quote += quantity * price;
+      let mut update = OrderUpdate::from_sim_trade(&trade);
+      update.cumulative_qty = queued.filled;
+      update.cumulative_quote_qty = queued.filled_quote;
+      update.status = Some(if queued.remaining > QTY_EPSILON {
+        OrderStatus::PartiallyFilled
+      } else {
+        OrderStatus::Filled
+      });
+      update
+    }
+    Err(e) => {
+      log::warn!(
+        "Expiring simulated order {}: {}",
+        queued.order.new_client_order_id,
+        e
+      );
+      queued.status(OrderStatus::Expired, time)
+    }
+  }
+}
+
+// Whether the last update ended the order
+fn is_final(update: Option<&OrderUpdate>, queued: &QueuedOrder) -> bool {
+  matches!(
+    update,
+    Some(update) if update.client_order_id == queued.order.new_client_order_id
+      && matches!(update.status, Some(status) if status.is_final())
+  )
+}
+
+impl<S: Strategy> Backtester<S> {
+  /// Replay recorded books and trades of a single symbol through
+  /// `DepthFills`, the strategy sees every book
+  pub fn run_depth<I>(mut self, events: I, setting: DepthSetting) -> Result<BacktestReport>
+  where
+    I: IntoIterator<Item = RecordedEvent>,
+  {
+    ensure!(
+      self.exchanges.len() == 1,
+      "Depth backtests trade a single symbol"
+    );
+    let mut fills = DepthFills::new(setting);
+    for event in events {
+      let time = event.time();
+      let updates = fills.arrive(&mut self.exchanges[0], time);
+      self.apply_updates(updates)?;
+      let book = match event {
+        RecordedEvent::Trade(trade) => {
+          let updates = fills.on_trade(&mut self.exchanges[0], &trade);
+          self.apply_updates(updates)?;
+          continue;
+        }
+        RecordedEvent::Book(book) => book,
+      };
+      let (best_bid, best_ask) = match (book.mid(), book.best_bid(), book.best_ask()) {
+        (Some(_), Some(best_bid), Some(best_ask)) => (best_bid, best_ask),
+        _ => {
+          log::warn!("Skipping empty or crossed book at {}", book.time);
+          continue;
+        }
+      };
+      self.touch.insert(book.symbol.clone(), (best_bid, best_ask));
+      if self.initial_equity.is_none() {
+        self.initial_equity = Some(self.equity());
+      }
+      let updates = fills.on_book(&mut self.exchanges[0], &book);
+      self.apply_updates(updates)?;
+
+      let orders = self.strategy.on_book(&book)?;
+      for client_order_id in self.strategy.take_cancels() {
+        fills.cancel(client_order_id, time);
+      }
+      for mut order in orders {
+        match self.oms.register(&mut order) {
+          Ok(_) => fills.submit(order, time),
+          Err(e) => {
+            log::warn!("Simulated order rejected: {}", e);
+            self.rejected_orders += 1;
+          }
+        }
+      }
+      // Without latency orders meet the book they were decided on
+      let updates = fills.arrive(&mut self.exchanges[0], time);
+      self.apply_updates(updates)?;
+      self.record_equity(time);
+    }
+    self.report()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn book(time: i64, bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) -> OrderBook {
+    OrderBook {
+      symbol: "BTCUSDT".to_string(),
+      time,
+      bids,
+      asks,
+    }
+  }
+
+  fn trade(time: i64, price: f64, quantity: f64) -> MarketTrade {
+    MarketTrade {
+      time,
+      price,
+      quantity,
+    }
+  }
+
+  fn order(id: &str, side: OrderSide, price: Option<f32>, quantity: f32) -> OrderInput {
+    OrderInput {
+      symbol: "BTCUSDT".to_string(),
+      side,
+      order_type: if price.is_some() {
+        OrderType::Limit
+      } else {
+        OrderType::Market
+      },
+      time_in_force: price.map(|_| TimeInForce::GTC),
+      quantity: Some(quantity),
+      quote_order_qty: None,
+      price,
+      new_client_order_id: id.to_string(),
+      stop_price: None,
+      iceberg_qty: None,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: 0,
+    }
+  }
+
+  fn setup(latency_ms: i64) -> (DepthFills, SimulatedExchange) {
+    let fills = DepthFills::new(DepthSetting {
+      latency_ms,
+      queue_position: 1.0,
+    });
+    let exchange = SimulatedExchange::new("BTCUSDT", 10.0, 100_000.0).unwrap();
+    (fills, exchange)
+  }
+
+  // Price and quantity of each fill
+  fn fills(updates: &[OrderUpdate]) -> Vec<(f64, f64)> {
+    updates
+      .iter()
+      .flat_map(|update| update.trades.iter())
+      .map(|trade| (trade.price, trade.quantity))
+      .collect()
+  }
+
+  #[test]
+  fn market_order_walks_the_levels() {
+    let (mut depth, mut exchange) = setup(0);
+    let asks = vec![(100.0, 1.0), (101.0, 1.0), (102.0, 5.0)];
+    depth.on_book(&mut exchange, &book(1, vec![(99.0, 1.0)], asks));
+    depth.submit(order("walk", OrderSide::Buy, None, 2.5), 1);
+    let updates = depth.arrive(&mut exchange, 1);
+    assert_eq!(
+      fills(&updates),
+      vec![(100.0, 1.0), (101.0, 1.0), (102.0, 0.5)]
+    );
+    let last = updates.last().unwrap();
+    assert_eq!(last.status, Some(OrderStatus::Filled));
+    assert_eq!(last.cumulative_qty, 2.5);
+    assert_eq!(last.cumulative_quote_qty, 252.0);
+
+    // What was taken stays out of the book until the next snapshot
+    depth.submit(order("next", OrderSide::Buy, None, 1.0), 2);
+    assert_eq!(fills(&depth.arrive(&mut exchange, 2)), vec![(102.0, 1.0)]);
+    assert!((exchange.base_balance - 13.5).abs() < 1e-9);
+  }
+
+  #[test]
+  fn trades_at_the_price_work_through_the_queue() {
+    let (mut depth, mut exchange) = setup(0);
+    depth.on_book(
+      &mut exchange,
+      &book(1, vec![(99.0, 3.0)], vec![(100.0, 1.0)]),
+    );
+    depth.submit(order("queued", OrderSide::Buy, Some(99.0), 1.0), 1);
+    let updates = depth.arrive(&mut exchange, 1);
+    assert_eq!(updates[0].status, Some(OrderStatus::New));
+    assert_eq!(depth.resting[0].queue_ahead, 3.0);
+
+    assert!(depth
+      .on_trade(&mut exchange, &trade(2, 99.0, 2.0))
+      .is_empty());
+    assert_eq!(depth.resting[0].queue_ahead, 1.0);
+    // A smaller snapshot quantity moves the order up
+    depth.on_book(
+      &mut exchange,
+      &book(3, vec![(99.0, 0.5)], vec![(100.0, 1.0)]),
+    );
+    assert_eq!(depth.resting[0].queue_ahead, 0.5);
+
+    let updates = depth.on_trade(&mut exchange, &trade(4, 99.0, 1.0));
+    assert_eq!(fills(&updates), vec![(99.0, 0.5)]);
+    assert_eq!(updates[0].status, Some(OrderStatus::PartiallyFilled));
+    assert_eq!(depth.resting[0].queue_ahead, 0.0);
+  }
+
+  #[test]
+  fn trade_through_the_price_fills_the_order() {
+    let (mut depth, mut exchange) = setup(0);
+    depth.on_book(
+      &mut exchange,
+      &book(1, vec![(99.0, 3.0)], vec![(100.0, 1.0)]),
+    );
+    depth.submit(order("through", OrderSide::Buy, Some(99.0), 1.0), 1);
+    depth.arrive(&mut exchange, 1);
+    let updates = depth.on_trade(&mut exchange, &trade(2, 98.5, 0.01));
+    assert_eq!(fills(&updates), vec![(99.0, 1.0)]);
+    assert_eq!(updates[0].status, Some(OrderStatus::Filled));
+    assert!(depth.resting.is_empty());
+  }
+
+  #[test]
+  fn orders_and_cancels_arrive_after_the_latency() {
+    let (mut depth, mut exchange) = setup(50);
+    depth.on_book(
+      &mut exchange,
+      &book(0, vec![(99.0, 3.0)], vec![(100.0, 1.0)]),
+    );
+    depth.submit(order("late", OrderSide::Sell, Some(101.0), 1.0), 0);
+    assert!(depth.arrive(&mut exchange, 49).is_empty());
+    let updates = depth.arrive(&mut exchange, 50);
+    assert_eq!(updates[0].status, Some(OrderStatus::New));
+
+    depth.cancel("late".to_string(), 100);
+    assert!(depth.arrive(&mut exchange, 149).is_empty());
+    // Still on the book until the cancel gets there
+    let updates = depth.on_trade(&mut exchange, &trade(120, 101.5, 0.4));
+    assert_eq!(fills(&updates), vec![(101.0, 1.0)]);
+    assert!(depth.arrive(&mut exchange, 150).is_empty());
+
+    depth.submit(order("canceled", OrderSide::Sell, Some(101.0), 1.0), 200);
+    depth.arrive(&mut exchange, 250);
+    depth.cancel("canceled".to_string(), 300);
+    let updates = depth.arrive(&mut exchange, 350);
+    assert_eq!(updates[0].status, Some(OrderStatus::Canceled));
+    assert_eq!(updates[0].cumulative_qty, 0.0);
+    assert!(depth
+      .on_trade(&mut exchange, &trade(400, 102.0, 1.0))
+      .is_empty());
+  }
+}
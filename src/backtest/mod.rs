@@ -0,0 +1,687 @@
+use crate::binance::api::{KlineResp, OrderInput, OrderSide, OrderType};
+use crate::fees::FeeRates;
+use crate::oms::{OrderManager, OrderStatus, OrderUpdate};
+use crate::shared::csv_schema::CsvDataType;
+use crate::shared::reader::{read_orderbooks, RecordReader};
+use crate::shared::utils::split_symbol;
+use crate::strategy::orderbook::OrderBook;
+use crate::strategy::{CandleStick, Strategy};
+use anyhow::{anyhow, bail, ensure, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+pub mod depth;
+
+const YEAR_MS: f64 = 365.0 * 86_400_000.0;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SimTrade {
+  pub time: i64,
+  pub symbol: String,
+  pub side: String,
+  pub price: f64,
+  pub quantity: f64,
+  pub client_order_id: String,
+  #[serde(default)]
+  pub commission: f64,
+  #[serde(default)]
+  pub commission_asset: String, // the quote asset, empty in files written before fees
+}
+
+/// Single symbol exchange simulator, shared by backtests and paper
+/// trading, strategies trading several symbols get one per symbol.
+/// Market orders fill at the given price, limit orders rest until a
+/// candle trades through them and hold the balance they need until
+/// then, orders it doesn't cover are rejected. Fills crossing the market pay the taker
+/// rate and resting ones the maker rate, in the quote asset so base
+/// quantities come out exact as when fees are paid in BNB.
+pub struct SimulatedExchange {
+  pub symbol: String,
+  pub base_asset: String,
+  pub quote_asset: String,
+  pub base_balance: f64,
+  pub quote_balance: f64,
+  pub fees: FeeRates,
+  resting_orders: Vec<OrderInput>,
+  pub trades: Vec<SimTrade>,
+}
+
+impl SimulatedExchange {
+  pub fn new(symbol: &str, base_balance: f64, quote_balance: f64) -> Result<Self> {
+    let (base_asset, quote_asset) = split_symbol(symbol)?;
+    Ok(Self {
+      symbol: symbol.to_uppercase(),
+      base_asset,
+      quote_asset,
+      base_balance,
+      quote_balance,
+      fees: FeeRates::default(),
+      resting_orders: vec![],
+      trades: vec![],
+    })
+  }
+
+  pub fn with_fees(mut self, fees: FeeRates) -> Self {
+    self.fees = fees;
+    self
+  }
+
+  /// Submit an order with `price` as the current market price
+  pub fn submit(&mut self, order: OrderInput, price: f64, time: i64) -> Result<Option<SimTrade>> {
+    match order.order_type {
+      OrderType::Market => {
+        let quantity = match (order.quantity, order.quote_order_qty) {
+          (Some(quantity), _) => quantity as f64,
+          (None, Some(quote_qty)) => quote_qty as f64 / price,
+          (None, None) => bail!("Missing Quantity or Quote Order Qty"),
+        };
+        self.fill(&order, price, quantity, time, false).map(Some)
+      }
+      OrderType::Limit | OrderType::LimitMaker => {
+        let limit_price = order.price.map(|p| p as f64);
+        ensure!(limit_price.is_some(), "Missing Price");
+        ensure!(order.quantity.is_some(), "Missing Quantity");
+        let limit_price = limit_price.unwrap();
+        let marketable = match order.side {
+          OrderSide::Buy => limit_price >= price,
+          OrderSide::Sell => limit_price <= price,
+        };
+        if marketable {
+          if let OrderType::LimitMaker = order.order_type {
+            bail!("Order would immediately match and take");
+          }
+          let quantity = order.quantity.unwrap() as f64;
+          return self.fill(&order, price, quantity, time, false).map(Some);
+        }
+        let quantity = order.quantity.unwrap() as f64;
+        self.check_available(&order.side, limit_price, quantity, self.fees.maker)?;
+        self.resting_orders.push(order);
+        Ok(None)
+      }
+      _ => bail!(
+        "Order type {:?} not supported by simulator",
+        order.order_type
+      ),
+    }
+  }
+
+  /// Fill resting limit orders the candle traded through, those the
+  /// balances no longer cover expire
+  pub fn match_resting(&mut self, candle: &CandleStick) -> Vec<OrderUpdate> {
+    self.match_orders(candle.close_time, |order, limit_price| match order.side {
+      OrderSide::Buy => candle.low <= limit_price,
+      OrderSide::Sell => candle.high >= limit_price,
+    })
+  }
+
+  /// Fill resting limit orders the other side of the book reached,
+  /// those the balances no longer cover expire
+  pub fn match_book(&mut self, book: &OrderBook) -> Vec<OrderUpdate> {
+    let (best_bid, best_ask) = (book.best_bid(), book.best_ask());
+    self.match_orders(book.time, |order, limit_price| match order.side {
+      OrderSide::Buy => matches!(best_ask, Some(ask) if ask <= limit_price),
+      OrderSide::Sell => matches!(best_bid, Some(bid) if bid >= limit_price),
+    })
+  }
+
+  // Fill resting orders `crossed` by the market at their limit price
+  fn match_orders<F>(&mut self, time: i64, crossed: F) -> Vec<OrderUpdate>
+  where
+    F: Fn(&OrderInput, f64) -> bool,
+  {
+    let mut updates = vec![];
+    let mut idx = 0;
+    while idx < self.resting_orders.len() {
+      let order = &self.resting_orders[idx];
+      let limit_price = order.price.unwrap() as f64;
+      if !crossed(order, limit_price) {
+        idx += 1;
+        continue;
+      }
+      // Out of the book first, the balance it held is its own
+      let order = self.resting_orders.remove(idx);
+      let quantity = order.quantity.unwrap() as f64;
+      match self.fill(&order, limit_price, quantity, time, true) {
+        Ok(trade) => updates.push(OrderUpdate::from_sim_trade(&trade)),
+        Err(e) => {
+          log::warn!(
+            "Expiring resting order {}: {}",
+            order.new_client_order_id,
+            e
+          );
+          updates.push(OrderUpdate::status(&order, OrderStatus::Expired, time));
+        }
+      }
+    }
+    updates
+  }
+
+  pub fn cancel(&mut self, client_order_id: &str) -> Option<OrderInput> {
+    let idx = self
+      .resting_orders
+      .iter()
+      .position(|order| order.new_client_order_id == client_order_id)?;
+    Some(self.resting_orders.remove(idx))
+  }
+
+  pub fn open_orders(&self) -> &[OrderInput] {
+    &self.resting_orders
+  }
+
+  /// Account value in quote asset
+  pub fn equity(&self, price: f64) -> f64 {
+    self.quote_balance + self.base_balance * price
+  }
+
+  /// Balances held by resting orders as (base, quote): the quantity of
+  /// sells and the cost of buys with maker fees
+  pub fn locked(&self) -> (f64, f64) {
+    self
+      .resting_orders
+      .iter()
+      .fold((0.0, 0.0), |(base, quote), order| {
+        let quantity = order.quantity.unwrap_or_default() as f64;
+        match order.side {
+          OrderSide::Buy => {
+            let price = order.price.unwrap_or_default() as f64;
+            (base, quote + price * quantity * (1.0 + self.fees.maker))
+          }
+          OrderSide::Sell => (base + quantity, quote),
+        }
+      })
+  }
+
+  // The balances left after what resting orders hold must cover
+  // `quantity` at `price`
+  fn check_available(
+    &self,
+    side: &OrderSide,
+    price: f64,
+    quantity: f64,
+    fee_rate: f64,
+  ) -> Result<()> {
+    let (locked_base, locked_quote) = self.locked();
+    match side {
+      OrderSide::Buy => ensure!(
+        self.quote_balance - locked_quote >= price * quantity * (1.0 + fee_rate),
+        "Insufficient {} balance",
+        self.quote_asset
+      ),
+      OrderSide::Sell => ensure!(
+        self.base_balance - locked_base >= quantity,
+        "Insufficient {} balance",
+        self.base_asset
+      ),
+    }
+    Ok(())
+  }
+
+  fn fill(
+    &mut self,
+    order: &OrderInput,
+    price: f64,
+    quantity: f64,
+    time: i64,
+    maker: bool,
+  ) -> Result<SimTrade> {
+    ensure!(quantity > 0.0, "Order quantity must be positive");
+    let commission = price * quantity * self.fees.rate(maker);
+    self.check_available(&order.side, price, quantity, self.fees.rate(maker))?;
+    match order.side {
+      OrderSide::Buy => {
+        self.quote_balance -= price * quantity + commission;
+        self.base_balance += quantity;
+      }
+      OrderSide::Sell => {
+        self.base_balance -= quantity;
+        self.quote_balance += price * quantity - commission;
+      }
+    }
+    let trade = SimTrade {
+      time,
+      symbol: self.symbol.clone(),
+      side: order.side.clone().into(),
+      price,
+      quantity,
+      client_order_id: order.new_client_order_id.clone(),
+      commission,
+      commission_asset: self.quote_asset.clone(),
+    };
+    self.trades.push(trade.clone());
+    Ok(trade)
+  }
+}
+
+pub struct BacktestReport {
+  pub initial_equity: f64,
+  pub final_equity: f64,
+  pub max_drawdown: f64, // fraction of the running peak
+  pub rejected_orders: usize,
+  pub trades: Vec<SimTrade>,
+  pub equity_curve: Vec<(i64, f64)>,
+}
+
+impl BacktestReport {
+  pub fn total_return(&self) -> f64 {
+    self.final_equity / self.initial_equity - 1.0
+  }
+
+  /// Sharpe ratio of the returns between equity samples, annualized by
+  /// their average spacing with no risk free rate, 0 without volatility
+  pub fn sharpe_ratio(&self) -> f64 {
+    let curve = &self.equity_curve;
+    if curve.len() < 3 {
+      return 0.0;
+    }
+    let returns = curve
+      .windows(2)
+      .filter(|pair| pair[0].1 > 0.0)
+      .map(|pair| pair[1].1 / pair[0].1 - 1.0)
+      .collect::<Vec<_>>();
+    let count = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / count;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / count;
+    let spacing = (curve.last().unwrap().0 - curve[0].0) as f64 / (curve.len() - 1) as f64;
+    if variance <= 0.0 || spacing <= 0.0 {
+      return 0.0;
+    }
+    mean / variance.sqrt() * (YEAR_MS / spacing).sqrt()
+  }
+}
+
+impl fmt::Display for BacktestReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "Initial equity:  {:.4}", self.initial_equity)?;
+    writeln!(f, "Final equity:    {:.4}", self.final_equity)?;
+    writeln!(f, "Total return:    {:.2}%", self.total_return() * 100.0)?;
+    writeln!(f, "Max drawdown:    {:.2}%", self.max_drawdown * 100.0)?;
+    writeln!(f, "Sharpe ratio:    {:.2}", self.sharpe_ratio())?;
+    writeln!(f, "Trades:          {}", self.trades.len())?;
+    write!(f, "Rejected orders: {}", self.rejected_orders)
+  }
+}
+
+/// Replays candles or recorded order books through a strategy against
+/// a `SimulatedExchange` per traded symbol, each with its own balances.
+/// Market orders fill at the close of the candle that produced them, or
+/// at the touch of the book. Fills reach the strategy through an
+/// `OrderManager` like they do live.
+pub struct Backtester<S: Strategy> {
+  strategy: S,
+  exchanges: Vec<SimulatedExchange>,
+  oms: OrderManager,
+  touch: HashMap<String, (f64, f64)>, // best bid and ask by symbol
+  initial_equity: Option<f64>,
+  peak: f64,
+  max_drawdown: f64,
+  rejected_orders: usize,
+  equity_curve: Vec<(i64, f64)>,
+}
+
+impl<S: Strategy> Backtester<S> {
+  pub fn new(strategy: S, exchange: SimulatedExchange) -> Self {
+    Self {
+      strategy,
+      exchanges: vec![exchange],
+      oms: OrderManager::new("bt"),
+      touch: HashMap::new(),
+      initial_equity: None,
+      peak: 0.0,
+      max_drawdown: 0.0,
+      rejected_orders: 0,
+      equity_curve: vec![],
+    }
+  }
+
+  /// Backtest a strategy trading every symbol of `exchanges`, equity
+  /// adds up the legs so they need the same quote asset
+  pub fn with_legs(strategy: S, exchanges: Vec<SimulatedExchange>) -> Result<Self> {
+    ensure!(!exchanges.is_empty(), "Backtest needs at least one symbol");
+    for (i, exchange) in exchanges.iter().enumerate() {
+      ensure!(
+        exchange.quote_asset == exchanges[0].quote_asset,
+        "{} is quoted in {}, not {} like {}",
+        exchange.symbol,
+        exchange.quote_asset,
+        exchanges[0].quote_asset,
+        exchanges[0].symbol
+      );
+      ensure!(
+        exchanges[..i]
+          .iter()
+          .all(|other| other.symbol != exchange.symbol),
+        "{} is traded twice",
+        exchange.symbol
+      );
+    }
+    let mut exchanges = exchanges.into_iter();
+    let mut backtester = Self::new(strategy, exchanges.next().unwrap());
+    backtester.exchanges.extend(exchanges);
+    Ok(backtester)
+  }
+
+  pub fn run<I>(mut self, candles: I) -> Result<BacktestReport>
+  where
+    I: IntoIterator<Item = CandleStick>,
+  {
+    for candle in candles {
+      self.open(std::slice::from_ref(&candle))?;
+      let time = candle.close_time;
+      let orders = self.strategy.on_candle(candle)?;
+      self.cancel_requested(time)?;
+      self.submit(orders, time)?;
+      self.record_equity(time);
+    }
+    self.report()
+  }
+
+  /// Replay lined up candles of every symbol, see `sync::CandleSync`
+  pub fn run_synced<I>(mut self, periods: I) -> Result<BacktestReport>
+  where
+    I: IntoIterator<Item = Vec<CandleStick>>,
+  {
+    for candles in periods {
+      self.open(&candles)?;
+      let time = match candles.iter().map(|candle| candle.close_time).max() {
+        Some(time) => time,
+        None => continue,
+      };
+      let orders = self.strategy.on_candles(&candles)?;
+      self.cancel_requested(time)?;
+      self.submit(orders, time)?;
+      self.record_equity(time);
+    }
+    self.report()
+  }
+
+  // Fill resting orders the candles traded through and mark their
+  // symbols at the close
+  fn open(&mut self, candles: &[CandleStick]) -> Result<()> {
+    for candle in candles {
+      self
+        .touch
+        .insert(candle.symbol.clone(), (candle.open, candle.open));
+    }
+    if self.initial_equity.is_none() {
+      self.initial_equity = Some(self.equity());
+    }
+    for candle in candles {
+      let updates = match self.exchange(&candle.symbol) {
+        Some(exchange) => exchange.match_resting(candle),
+        None => vec![],
+      };
+      self.apply_updates(updates)?;
+      self
+        .touch
+        .insert(candle.symbol.clone(), (candle.close, candle.close));
+    }
+    Ok(())
+  }
+
+  /// Replay order book snapshots, resting orders fill once the other
+  /// side of the book reaches them and marketable orders at the touch
+  pub fn run_books<I>(mut self, books: I) -> Result<BacktestReport>
+  where
+    I: IntoIterator<Item = OrderBook>,
+  {
+    for book in books {
+      let (best_bid, best_ask) = match (book.mid(), book.best_bid(), book.best_ask()) {
+        (Some(_), Some(best_bid), Some(best_ask)) => (best_bid, best_ask),
+        _ => {
+          log::warn!("Skipping empty or crossed book at {}", book.time);
+          continue;
+        }
+      };
+      self.touch.insert(book.symbol.clone(), (best_bid, best_ask));
+      if self.initial_equity.is_none() {
+        self.initial_equity = Some(self.equity());
+      }
+      let updates = match self.exchange(&book.symbol) {
+        Some(exchange) => exchange.match_book(&book),
+        None => vec![],
+      };
+      self.apply_updates(updates)?;
+      let orders = self.strategy.on_book(&book)?;
+      self.cancel_requested(book.time)?;
+      self.submit(orders, book.time)?;
+      self.record_equity(book.time);
+    }
+    self.report()
+  }
+
+  fn exchange(&mut self, symbol: &str) -> Option<&mut SimulatedExchange> {
+    self
+      .exchanges
+      .iter_mut()
+      .find(|exchange| exchange.symbol.eq_ignore_ascii_case(symbol))
+  }
+
+  // Legs valued at their mid, legs without a price yet at nothing
+  fn equity(&self) -> f64 {
+    self
+      .exchanges
+      .iter()
+      .map(|exchange| match self.touch.get(&exchange.symbol) {
+        Some((bid, ask)) => exchange.equity((bid + ask) / 2.0),
+        None => exchange.quote_balance,
+      })
+      .sum()
+  }
+
+  // Rejected orders count toward the report
+  fn apply_updates(&mut self, updates: Vec<OrderUpdate>) -> Result<()> {
+    for update in updates {
+      let events = match update.status {
+        Some(OrderStatus::Rejected) => {
+          self.rejected_orders += 1;
+          self.oms.reject(&update.client_order_id)
+        }
+        _ => self.oms.on_update(update),
+      };
+      events.dispatch(&mut self.strategy)?;
+    }
+    Ok(())
+  }
+
+  // Cancel the resting orders the strategy asked for, orders no longer
+  // resting already finished or never rested and finish in the OMS now
+  fn cancel_requested(&mut self, time: i64) -> Result<()> {
+    for client_order_id in self.strategy.take_cancels() {
+      let canceled = self
+        .exchanges
+        .iter_mut()
+        .find_map(|exchange| exchange.cancel(&client_order_id));
+      let canceled = match canceled {
+        Some(order) => OrderUpdate::status(&order, OrderStatus::Canceled, time),
+        None => {
+          log::debug!("Order {} not resting, can't cancel", client_order_id);
+          OrderUpdate::not_resting(&client_order_id, time)
+        }
+      };
+      let events = self.oms.on_update(canceled);
+      events.dispatch(&mut self.strategy)?;
+    }
+    Ok(())
+  }
+
+  // Send `orders` to the exchange of their symbol, market orders fill
+  // at the touch of their side
+  fn submit(&mut self, orders: Vec<OrderInput>, time: i64) -> Result<()> {
+    for mut order in orders {
+      let client_order_id = match self.oms.register(&mut order) {
+        Ok(client_order_id) => client_order_id,
+        Err(e) => {
+          log::warn!("Simulated order rejected: {}", e);
+          self.rejected_orders += 1;
+          continue;
+        }
+      };
+      let accepted = OrderUpdate::status(&order, OrderStatus::New, time);
+      let touch = self.touch.get(&order.symbol.to_uppercase()).copied();
+      let submitted = match (touch, self.exchange(&order.symbol)) {
+        (Some((bid, ask)), Some(exchange)) => {
+          let price = match order.side {
+            OrderSide::Buy => ask,
+            OrderSide::Sell => bid,
+          };
+          exchange.submit(order, price, time)
+        }
+        _ => Err(anyhow!("No {} market in the backtest", order.symbol)),
+      };
+      let events = match submitted {
+        Ok(Some(trade)) => self.oms.on_update(OrderUpdate::from_sim_trade(&trade)),
+        Ok(None) => self.oms.on_update(accepted),
+        Err(e) => {
+          log::warn!("Simulated order rejected: {}", e);
+          self.rejected_orders += 1;
+          self.oms.reject(&client_order_id)
+        }
+      };
+      events.dispatch(&mut self.strategy)?;
+    }
+    Ok(())
+  }
+
+  fn record_equity(&mut self, time: i64) {
+    let equity = self.equity();
+    if equity > self.peak {
+      self.peak = equity;
+    }
+    if self.peak > 0.0 && (self.peak - equity) / self.peak > self.max_drawdown {
+      self.max_drawdown = (self.peak - equity) / self.peak;
+    }
+    self.equity_curve.push((time, equity));
+  }
+
+  fn report(self) -> Result<BacktestReport> {
+    let initial_equity = match self.initial_equity {
+      Some(initial_equity) => initial_equity,
+      None => bail!("No market data to backtest on"),
+    };
+    let final_equity = self.equity_curve.last().unwrap().1;
+    let mut trades = self
+      .exchanges
+      .into_iter()
+      .flat_map(|exchange| exchange.trades)
+      .collect::<Vec<_>>();
+    trades.sort_by_key(|trade| trade.time);
+    Ok(BacktestReport {
+      initial_equity,
+      final_equity,
+      max_drawdown: self.max_drawdown,
+      rejected_orders: self.rejected_orders,
+      trades,
+      equity_curve: self.equity_curve,
+    })
+  }
+}
+
+/// Load klines written by `backfill` for every day between `start` and
+/// `end` inclusive, ordered by open time with duplicates removed
+pub fn load_klines(
+  csv_dir: &str,
+  symbol: &str,
+  interval: &str,
+  start: NaiveDate,
+  end: NaiveDate,
+) -> Result<Vec<KlineResp>> {
+  let data_type = CsvDataType::Kline(interval.to_string());
+  let mut klines = BTreeMap::new();
+  for record in RecordReader::open(csv_dir, symbol, &data_type, start, end) {
+    let kline = record?.deserialize::<KlineResp>(None)?;
+    klines.insert(kline.open_time, kline);
+  }
+  Ok(klines.into_values().collect())
+}
+
+/// Load the orderbook snapshots `record` wrote for every day between
+/// `start` and `end` inclusive, in recording order
+pub fn load_orderbooks(
+  csv_dir: &str,
+  symbol: &str,
+  start: NaiveDate,
+  end: NaiveDate,
+) -> Result<Vec<OrderBook>> {
+  read_orderbooks(csv_dir, symbol, start, end).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::binance::api::TimeInForce;
+
+  fn limit(id: &str, side: OrderSide, price: f32, quantity: f32) -> OrderInput {
+    OrderInput {
+      symbol: "BTCUSDT".to_string(),
+      side,
+      order_type: OrderType::Limit,
+      time_in_force: Some(TimeInForce::GTC),
+      quantity: Some(quantity),
+      quote_order_qty: None,
+      price: Some(price),
+      new_client_order_id: id.to_string(),
+      stop_price: None,
+      iceberg_qty: None,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: 0,
+    }
+  }
+
+  fn book(time: i64, bid: f64, ask: f64) -> OrderBook {
+    OrderBook {
+      symbol: "BTCUSDT".to_string(),
+      time,
+      bids: vec![(bid, 1.0)],
+      asks: vec![(ask, 1.0)],
+    }
+  }
+
+  #[test]
+  fn resting_orders_hold_their_balance() {
+    let mut exchange = SimulatedExchange::new("BTCUSDT", 1.0, 1000.0).unwrap();
+    exchange
+      .submit(limit("ask_1", OrderSide::Sell, 110.0, 1.0), 100.0, 1)
+      .unwrap();
+    assert!(exchange
+      .submit(limit("ask_2", OrderSide::Sell, 120.0, 0.5), 100.0, 1)
+      .is_err());
+    exchange
+      .submit(limit("bid_1", OrderSide::Buy, 90.0, 10.0), 100.0, 1)
+      .unwrap();
+    assert_eq!(exchange.locked(), (1.0, 900.0));
+    // Only 100 left to spend, the market buy needs 105
+    let mut market = limit("buy", OrderSide::Buy, 0.0, 1.05);
+    market.order_type = OrderType::Market;
+    assert!(exchange.submit(market, 100.0, 2).is_err());
+    assert_eq!(exchange.open_orders().len(), 2);
+
+    // The bid filling frees what it held and no more
+    let updates = exchange.match_book(&book(3, 85.0, 88.0));
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].status, Some(OrderStatus::Filled));
+    assert_eq!(exchange.locked(), (1.0, 0.0));
+    assert_eq!(
+      (exchange.base_balance, exchange.quote_balance),
+      (11.0, 100.0)
+    );
+  }
+
+  #[test]
+  fn resting_orders_the_balance_lost_expire() {
+    let mut exchange = SimulatedExchange::new("BTCUSDT", 1.0, 0.0).unwrap();
+    exchange
+      .submit(limit("ask", OrderSide::Sell, 110.0, 1.0), 100.0, 1)
+      .unwrap();
+    // Spent elsewhere, e.g. by hand on a paper account
+    exchange.base_balance = 0.0;
+    let updates = exchange.match_book(&book(2, 111.0, 112.0));
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].client_order_id, "ask");
+    assert_eq!(updates[0].status, Some(OrderStatus::Expired));
+    assert!(updates[0].trades.is_empty());
+    assert!(exchange.open_orders().is_empty());
+  }
+}
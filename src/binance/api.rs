@@ -1,28 +1,68 @@
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::convert::From;
+use std::fmt;
+use std::str::FromStr;
 
 /// Spot APIs
 /// API Spec: https://binance-docs.github.io/apidocs/spot/en/#spot-account-trade
 pub enum Spot {
   OpenOrders,
+  CancelOpenOrders,
+  UserDataStream,
   TestNewOrder,
   NewOrder,
+  CancelOrder,
+  QueryOrder,
+  NewOco,
+  NewOto,
+  NewOtoco,
+  CancelOrderList,
   AccountInfo,
+  AccountCommission,
 }
 
 impl From<Spot> for String {
   fn from(endpoint: Spot) -> Self {
     String::from(match endpoint {
-      Spot::OpenOrders => "/api/v3/openOrders",
+      Spot::OpenOrders | Spot::CancelOpenOrders => "/api/v3/openOrders",
+      Spot::UserDataStream => "/api/v3/userDataStream",
       Spot::TestNewOrder => "/api/v3/order/test",
-      Spot::NewOrder => "/api/v3/order",
+      Spot::NewOrder | Spot::CancelOrder | Spot::QueryOrder => "/api/v3/order",
+      Spot::NewOco => "/api/v3/orderList/oco",
+      Spot::NewOto => "/api/v3/orderList/oto",
+      Spot::NewOtoco => "/api/v3/orderList/otoco",
+      Spot::CancelOrderList => "/api/v3/orderList",
       Spot::AccountInfo => "/api/v3/account",
+      Spot::AccountCommission => "/api/v3/account/commission",
+    })
+  }
+}
+
+/// Cross Margin APIs
+/// API Spec: https://binance-docs.github.io/apidocs/spot/en/#margin-account-trade
+pub enum Margin {
+  BorrowRepay,
+  NewOrder,
+  Account,
+  MaxBorrowable,
+  InterestHistory,
+}
+
+impl From<Margin> for String {
+  fn from(endpoint: Margin) -> Self {
+    String::from(match endpoint {
+      Margin::BorrowRepay => "/sapi/v1/margin/borrow-repay",
+      Margin::NewOrder => "/sapi/v1/margin/order",
+      Margin::Account => "/sapi/v1/margin/account",
+      Margin::MaxBorrowable => "/sapi/v1/margin/maxBorrowable",
+      Margin::InterestHistory => "/sapi/v1/margin/interestHistory",
     })
   }
 }
 
 /// Enum Spec: https://binance-docs.github.io/apidocs/spot/en/#public-api-definitions
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum OrderSide {
   Buy,
   Sell,
@@ -37,6 +77,18 @@ impl From<OrderSide> for String {
   }
 }
 
+impl FromStr for OrderSide {
+  type Err = anyhow::Error;
+
+  fn from_str(side: &str) -> Result<Self> {
+    Ok(match side.to_uppercase().as_str() {
+      "BUY" => OrderSide::Buy,
+      "SELL" => OrderSide::Sell,
+      _ => bail!("Unknown order side: {}", side),
+    })
+  }
+}
+
 #[derive(Clone, Debug)]
 pub enum OrderType {
   Limit,
@@ -62,6 +114,23 @@ impl From<OrderType> for String {
   }
 }
 
+impl FromStr for OrderType {
+  type Err = anyhow::Error;
+
+  fn from_str(order_type: &str) -> Result<Self> {
+    Ok(match order_type.to_uppercase().replace('-', "_").as_str() {
+      "LIMIT" => OrderType::Limit,
+      "MARKET" => OrderType::Market,
+      "STOP_LOSS" => OrderType::StopLoss,
+      "STOP_LOSS_LIMIT" => OrderType::StopLossLimit,
+      "TAKE_PROFIT" => OrderType::TakeProfit,
+      "TAKE_PROFIT_LIMIT" => OrderType::TakeProfitLimit,
+      "LIMIT_MAKER" => OrderType::LimitMaker,
+      _ => bail!("Unknown order type: {}", order_type),
+    })
+  }
+}
+
 #[derive(Debug)]
 pub enum TimeInForce {
   // Good Until Canceled
@@ -82,6 +151,19 @@ impl From<TimeInForce> for String {
   }
 }
 
+impl FromStr for TimeInForce {
+  type Err = anyhow::Error;
+
+  fn from_str(time_in_force: &str) -> Result<Self> {
+    Ok(match time_in_force.to_uppercase().as_str() {
+      "GTC" => TimeInForce::GTC,
+      "IOC" => TimeInForce::IOC,
+      "FOK" => TimeInForce::FOK,
+      _ => bail!("Unknown time in force: {}", time_in_force),
+    })
+  }
+}
+
 #[derive(Debug)]
 pub enum OrderRespType {
   Ack,
@@ -116,6 +198,211 @@ pub struct OrderInput {
   pub timestamp: i64,
 }
 
+pub struct QueryOrderInput {
+  pub symbol: String,
+  pub order_id: Option<i64>,
+  pub orig_client_order_id: Option<String>,
+  pub recv_window: Option<u32>,
+  pub timestamp: i64,
+}
+
+pub struct CancelOrderInput {
+  pub symbol: String,
+  pub order_id: Option<i64>,
+  pub orig_client_order_id: Option<String>,
+  pub recv_window: Option<u32>,
+  pub timestamp: i64,
+}
+
+/// One order of an order list, the side and quantity are set on the list
+#[derive(Debug)]
+pub struct OrderListLeg {
+  pub order_type: OrderType,
+  pub price: Option<f32>,
+  pub stop_price: Option<f32>,
+  pub time_in_force: Option<TimeInForce>,
+  pub iceberg_qty: Option<f32>,
+  pub client_order_id: Option<String>,
+}
+
+/// One-Cancels-the-Other, the leg with the higher trigger price is
+/// `above`. A sell OCO protecting a long takes profit above and stops
+/// out below, a buy OCO covering a short is the mirror image.
+/// API Spec: https://binance-docs.github.io/apidocs/spot/en/#new-order-list-oco-trade
+#[derive(Debug)]
+pub struct OcoInput {
+  pub symbol: String,
+  pub list_client_order_id: Option<String>,
+  pub side: OrderSide,
+  pub quantity: f32,
+  pub above: OrderListLeg,
+  pub below: OrderListLeg,
+  pub new_order_resp_type: Option<OrderRespType>,
+  pub recv_window: Option<u32>,
+  pub timestamp: i64,
+}
+
+/// One-Triggers-the-Other, `pending` is placed once `working` fills
+/// API Spec: https://binance-docs.github.io/apidocs/spot/en/#new-order-list-oto-trade
+#[derive(Debug)]
+pub struct OtoInput {
+  pub symbol: String,
+  pub list_client_order_id: Option<String>,
+  pub working_side: OrderSide,
+  pub working_quantity: f32,
+  pub working: OrderListLeg,
+  pub pending_side: OrderSide,
+  pub pending_quantity: f32,
+  pub pending: OrderListLeg,
+  pub new_order_resp_type: Option<OrderRespType>,
+  pub recv_window: Option<u32>,
+  pub timestamp: i64,
+}
+
+/// One-Triggers-a-One-Cancels-the-Other, an entry order that places an
+/// OCO bracket once it fills
+/// API Spec: https://binance-docs.github.io/apidocs/spot/en/#new-order-list-otoco-trade
+#[derive(Debug)]
+pub struct OtocoInput {
+  pub symbol: String,
+  pub list_client_order_id: Option<String>,
+  pub working_side: OrderSide,
+  pub working_quantity: f32,
+  pub working: OrderListLeg,
+  pub pending_side: OrderSide,
+  pub pending_quantity: f32,
+  pub pending_above: OrderListLeg,
+  pub pending_below: OrderListLeg,
+  pub new_order_resp_type: Option<OrderRespType>,
+  pub recv_window: Option<u32>,
+  pub timestamp: i64,
+}
+
+pub struct CancelOrderListInput {
+  pub symbol: String,
+  pub order_list_id: Option<i64>,
+  pub list_client_order_id: Option<String>,
+  pub recv_window: Option<u32>,
+  pub timestamp: i64,
+}
+
+/// Error payload returned by Binance along with a non 2xx status
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiError {
+  pub code: i64,
+  pub msg: String,
+}
+
+/// Request Binance answered with an error status, `code` is missing if
+/// the body wasn't an `ApiError`
+#[derive(Debug)]
+pub struct RequestError {
+  pub status: u16,
+  pub code: Option<i64>,
+  pub msg: String,
+}
+
+impl RequestError {
+  pub fn new(status: u16, body: &str) -> Self {
+    match serde_json::from_str::<ApiError>(body) {
+      Ok(err) => Self {
+        status,
+        code: Some(err.code),
+        msg: err.msg,
+      },
+      Err(_) => Self {
+        status,
+        code: None,
+        msg: body.to_string(),
+      },
+    }
+  }
+
+  /// 5xx errors mean the request may or may not have been executed
+  pub fn is_unknown_outcome(&self) -> bool {
+    self.status >= 500
+  }
+}
+
+impl fmt::Display for RequestError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.code {
+      Some(code) => write!(f, "Binance error {} ({}): {}", code, self.status, self.msg),
+      None => write!(f, "Binance request failed ({}): {}", self.status, self.msg),
+    }
+  }
+}
+
+impl std::error::Error for RequestError {}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderFill {
+  pub price: String,
+  pub qty: String,
+  pub commission: String,
+  pub commission_asset: String,
+  pub trade_id: Option<i64>,
+}
+
+/// Shared by new order (ACK/RESULT/FULL), cancel order and open orders
+/// responses, fields missing from the lighter payloads are optional
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderResp {
+  pub symbol: String,
+  pub order_id: i64,
+  pub order_list_id: Option<i64>,
+  pub client_order_id: String,
+  pub orig_client_order_id: Option<String>,
+  pub transact_time: Option<i64>,
+  pub time: Option<i64>,
+  pub update_time: Option<i64>,
+  pub price: Option<String>,
+  pub orig_qty: Option<String>,
+  pub executed_qty: Option<String>,
+  pub cummulative_quote_qty: Option<String>,
+  pub status: Option<String>,
+  pub time_in_force: Option<String>,
+  #[serde(rename = "type")]
+  pub order_type: Option<String>,
+  pub side: Option<String>,
+  pub stop_price: Option<String>,
+  #[serde(default)]
+  pub fills: Vec<OrderFill>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderListOrder {
+  pub symbol: String,
+  pub order_id: i64,
+  pub client_order_id: String,
+}
+
+/// Response of placing or canceling an order list, `order_reports`
+/// holds the state of every order in the list
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderListResp {
+  pub order_list_id: i64,
+  pub contingency_type: String,
+  pub list_status_type: String,
+  pub list_order_status: String,
+  pub list_client_order_id: String,
+  pub transaction_time: i64,
+  pub symbol: String,
+  pub orders: Vec<OrderListOrder>,
+  #[serde(default)]
+  pub order_reports: Vec<OrderResp>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenKeyResp {
+  pub listen_key: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AccountBalanceInfo {
   pub asset: String,
@@ -137,19 +424,125 @@ pub struct AccountInfoResp {
   pub account_type: String,
   pub balances: Vec<AccountBalanceInfo>,
 }
+
+/// Commission rates as fractions, e.g. "0.00100000" for 10 bps
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissionRates {
+  pub maker: String,
+  pub taker: String,
+  pub buyer: String,
+  pub seller: String,
+}
+
+/// BNB discount of a symbol, `discount` is the share of the commission
+/// still paid, e.g. "0.75000000" for 25% off
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissionDiscount {
+  pub enabled_for_account: bool,
+  pub enabled_for_symbol: bool,
+  pub discount_asset: String,
+  pub discount: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissionResp {
+  pub symbol: String,
+  pub standard_commission: CommissionRates,
+  pub tax_commission: CommissionRates,
+  pub discount: CommissionDiscount,
+}
 /// Market Data Endpoints
 pub enum Market {
   Kline,
+  ExchangeInfo,
 }
 
 impl From<Market> for String {
   fn from(endpoint: Market) -> Self {
     String::from(match endpoint {
       Market::Kline => "/api/v3/klines",
+      Market::ExchangeInfo => "/api/v3/exchangeInfo",
     })
   }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExchangeInfoResp {
+  pub server_time: i64,
+  pub symbols: Vec<SymbolInfo>,
+}
+
+/// Trading rules of one symbol
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolInfo {
+  pub symbol: String,
+  pub status: String,
+  pub base_asset: String,
+  pub quote_asset: String,
+  pub filters: Vec<SymbolFilter>,
+}
+
+impl SymbolInfo {
+  /// Min quantity, max quantity and step size of the `LOT_SIZE` filter
+  pub fn lot_size(&self) -> Option<(f64, f64, f64)> {
+    self.filters.iter().find_map(|filter| match filter {
+      SymbolFilter::LotSize {
+        min_qty,
+        max_qty,
+        step_size,
+      } => Some((
+        min_qty.parse().ok()?,
+        max_qty.parse().ok()?,
+        step_size.parse().ok()?,
+      )),
+      _ => None,
+    })
+  }
+
+  /// Smallest order value in quote asset, 0 without a notional filter
+  pub fn min_notional(&self) -> f64 {
+    self
+      .filters
+      .iter()
+      .find_map(|filter| match filter {
+        SymbolFilter::Notional { min_notional } | SymbolFilter::MinNotional { min_notional } => {
+          min_notional.parse().ok()
+        }
+        _ => None,
+      })
+      .unwrap_or(0.0)
+  }
+}
+
+/// Symbol filters order sizing needs, others are skipped
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(tag = "filterType")]
+pub enum SymbolFilter {
+  #[serde(rename = "PRICE_FILTER", rename_all = "camelCase")]
+  PriceFilter {
+    min_price: String,
+    max_price: String,
+    tick_size: String,
+  },
+  #[serde(rename = "LOT_SIZE", rename_all = "camelCase")]
+  LotSize {
+    min_qty: String,
+    max_qty: String,
+    step_size: String,
+  },
+  #[serde(rename = "NOTIONAL", rename_all = "camelCase")]
+  Notional { min_notional: String },
+  #[serde(rename = "MIN_NOTIONAL", rename_all = "camelCase")]
+  MinNotional { min_notional: String },
+  #[serde(other)]
+  Other,
+}
+
 pub struct KlineInput {
   pub symbol: String,
   pub interval: String,
@@ -158,7 +551,7 @@ pub struct KlineInput {
   pub limit: Option<u64>, // Default 500, max 1000
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct KlineResp {
   pub open_time: i64,
   pub open: f64,
@@ -172,3 +565,133 @@ pub struct KlineResp {
   pub taker_buy_base_asset_vol: f64,
   pub taker_buy_quote_asset_vol: f64,
 }
+
+#[derive(Clone, Copy, Debug)]
+pub enum BorrowRepay {
+  Borrow,
+  Repay,
+}
+
+impl From<BorrowRepay> for String {
+  fn from(item: BorrowRepay) -> Self {
+    String::from(match item {
+      BorrowRepay::Borrow => "BORROW",
+      BorrowRepay::Repay => "REPAY",
+    })
+  }
+}
+
+/// What a margin order does to the loan, MARGIN_BUY borrows whatever the
+/// free balance doesn't cover and AUTO_REPAY repays debt with the proceeds
+#[derive(Clone, Copy, Debug)]
+pub enum SideEffectType {
+  NoSideEffect,
+  MarginBuy,
+  AutoRepay,
+}
+
+impl From<SideEffectType> for String {
+  fn from(item: SideEffectType) -> Self {
+    String::from(match item {
+      SideEffectType::NoSideEffect => "NO_SIDE_EFFECT",
+      SideEffectType::MarginBuy => "MARGIN_BUY",
+      SideEffectType::AutoRepay => "AUTO_REPAY",
+    })
+  }
+}
+
+impl FromStr for SideEffectType {
+  type Err = anyhow::Error;
+
+  fn from_str(side_effect: &str) -> Result<Self> {
+    Ok(
+      match side_effect.to_uppercase().replace('-', "_").as_str() {
+        "NO_SIDE_EFFECT" => SideEffectType::NoSideEffect,
+        "MARGIN_BUY" => SideEffectType::MarginBuy,
+        "AUTO_REPAY" => SideEffectType::AutoRepay,
+        _ => bail!("Unknown side effect type: {}", side_effect),
+      },
+    )
+  }
+}
+
+/// Spot order placed on the cross margin account
+#[derive(Debug)]
+pub struct MarginOrderInput {
+  pub order: OrderInput,
+  pub side_effect_type: Option<SideEffectType>,
+}
+
+pub struct InterestHistoryInput {
+  pub asset: Option<String>,
+  pub start_time: Option<i64>,
+  pub end_time: Option<i64>,
+  pub current: Option<u32>, // Page, starting from 1
+  pub size: Option<u32>,    // Default 10, max 100
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BorrowRepayResp {
+  pub tran_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginOrderResp {
+  #[serde(flatten)]
+  pub order: OrderResp,
+  pub margin_buy_borrow_amount: Option<String>,
+  pub margin_buy_borrow_asset: Option<String>,
+  pub is_isolated: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginAsset {
+  pub asset: String,
+  pub borrowed: String,
+  pub free: String,
+  pub interest: String,
+  pub locked: String,
+  pub net_asset: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginAccountResp {
+  pub borrow_enabled: bool,
+  pub margin_level: String,
+  pub total_asset_of_btc: String,
+  pub total_liability_of_btc: String,
+  pub total_net_asset_of_btc: String,
+  pub trade_enabled: bool,
+  pub transfer_enabled: bool,
+  pub user_assets: Vec<MarginAsset>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MaxBorrowableResp {
+  pub amount: String,
+  pub borrow_limit: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InterestRecord {
+  pub tx_id: i64,
+  pub interest_accured_time: i64, // Binance's spelling
+  pub asset: String,
+  pub principal: String,
+  pub interest: String,
+  pub interest_rate: String,
+  #[serde(rename = "type")]
+  pub interest_type: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InterestHistoryResp {
+  pub rows: Vec<InterestRecord>,
+  pub total: i64,
+}
@@ -1,70 +1,151 @@
-use crate::binance::api::AccountInfoResp;
+use crate::binance::api::{
+  AccountInfoResp, BorrowRepay, BorrowRepayResp, CancelOrderInput, CancelOrderListInput,
+  CommissionResp, InterestHistoryInput, InterestHistoryResp, ListenKeyResp, Margin,
+  MarginAccountResp, MarginOrderInput, MarginOrderResp, MaxBorrowableResp, OcoInput, OrderListResp,
+  OrderResp, OtoInput, OtocoInput, QueryOrderInput, RequestError,
+};
+use crate::binance::signer::Signer;
+use crate::shared::secret::Secret;
 use crate::shared::utils;
 use crate::{
-  binance::api::{KlineInput, KlineResp, Market, OrderInput, Spot},
+  binance::api::{ExchangeInfoResp, KlineInput, KlineResp, Market, OrderInput, Spot, SymbolInfo},
   shared::utils::{to_f64, to_i64},
 };
 use anyhow::Result;
-use hmac::{Hmac, Mac, NewMac};
 use reqwest::header::{self, HeaderValue};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
-use sha2::Sha256;
 use std::time::Duration;
 
 pub struct Client {
-  api_secret: String,
+  signer: Box<dyn Signer>,
   host: String,
   client: reqwest::Client,
 }
 
 impl Client {
   pub fn new(
-    api_key: String,
-    api_secret: String,
+    api_key: Secret,
+    signer: Box<dyn Signer>,
     host: String,
     proxy: Option<String>,
   ) -> Result<Self> {
-    let mut headers = header::HeaderMap::new();
-    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
-    headers.insert("X-MBX-APIKEY", HeaderValue::from_str(&api_key)?);
-    let mut client_builder = reqwest::Client::builder()
-      .connect_timeout(Duration::new(10, 0))
-      .default_headers(headers);
-    if let Some(proxy) = proxy {
-      client_builder = client_builder.proxy(reqwest::Proxy::https(proxy)?);
-    }
-    let client = client_builder.build()?;
-
     Ok(Self {
-      api_secret,
+      signer,
       host,
-      client,
+      client: http_client(api_key, proxy)?,
     })
   }
 
-  pub async fn new_order(&self, input: OrderInput, is_test: bool) -> Result<()> {
+  pub async fn new_order(&self, input: OrderInput) -> Result<OrderResp> {
     let query = utils::build_order_query(input)?;
-    let endpoint = match is_test {
-      true => Spot::TestNewOrder,
-      false => Spot::NewOrder,
-    };
-    let signed_req = self.sign_request(endpoint.into(), Some(query));
-    let res = self
-      .client
-      .post(signed_req)
-      .send()
-      .await
-      .unwrap()
-      .text()
-      .await
-      .unwrap();
-    println!("New Order Res: {:#?}", res);
+    let signed_req = self.sign_request(Spot::NewOrder.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<OrderResp>(res).await
+  }
+
+  /// Validates the order against the matching engine without placing it
+  pub async fn test_new_order(&self, input: OrderInput) -> Result<()> {
+    let query = utils::build_order_query(input)?;
+    let signed_req = self.sign_request(Spot::TestNewOrder.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<Value>(res).await?;
     Ok(())
   }
 
+  pub async fn cancel_order(&self, input: CancelOrderInput) -> Result<OrderResp> {
+    let query = utils::build_cancel_order_query(input)?;
+    let signed_req = self.sign_request(Spot::CancelOrder.into(), Some(query))?;
+    let res = self.client.delete(signed_req).send().await?;
+    parse_response::<OrderResp>(res).await
+  }
+
+  pub async fn new_oco(&self, input: OcoInput) -> Result<OrderListResp> {
+    let query = utils::build_oco_query(input)?;
+    let signed_req = self.sign_request(Spot::NewOco.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<OrderListResp>(res).await
+  }
+
+  pub async fn new_oto(&self, input: OtoInput) -> Result<OrderListResp> {
+    let query = utils::build_oto_query(input)?;
+    let signed_req = self.sign_request(Spot::NewOto.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<OrderListResp>(res).await
+  }
+
+  pub async fn new_otoco(&self, input: OtocoInput) -> Result<OrderListResp> {
+    let query = utils::build_otoco_query(input)?;
+    let signed_req = self.sign_request(Spot::NewOtoco.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<OrderListResp>(res).await
+  }
+
+  /// Cancel every order of an order list
+  pub async fn cancel_order_list(&self, input: CancelOrderListInput) -> Result<OrderListResp> {
+    let query = utils::build_cancel_order_list_query(input)?;
+    let signed_req = self.sign_request(Spot::CancelOrderList.into(), Some(query))?;
+    let res = self.client.delete(signed_req).send().await?;
+    parse_response::<OrderListResp>(res).await
+  }
+
+  /// Borrow `amount` of `asset` on the cross margin account
+  pub async fn margin_borrow(&self, asset: String, amount: f64) -> Result<BorrowRepayResp> {
+    let query = utils::build_borrow_repay_query(asset, amount, BorrowRepay::Borrow)?;
+    let signed_req = self.sign_request(Margin::BorrowRepay.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<BorrowRepayResp>(res).await
+  }
+
+  /// Repay `amount` of the `asset` loan, interest is repaid first
+  pub async fn margin_repay(&self, asset: String, amount: f64) -> Result<BorrowRepayResp> {
+    let query = utils::build_borrow_repay_query(asset, amount, BorrowRepay::Repay)?;
+    let signed_req = self.sign_request(Margin::BorrowRepay.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<BorrowRepayResp>(res).await
+  }
+
+  pub async fn margin_new_order(&self, input: MarginOrderInput) -> Result<MarginOrderResp> {
+    let query = utils::build_margin_order_query(input)?;
+    let signed_req = self.sign_request(Margin::NewOrder.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<MarginOrderResp>(res).await
+  }
+
+  pub async fn margin_account(&self) -> Result<MarginAccountResp> {
+    let query = utils::build_spot_account_info_query(None)?;
+    let signed_req = self.sign_request(Margin::Account.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<MarginAccountResp>(res).await
+  }
+
+  pub async fn margin_max_borrowable(&self, asset: String) -> Result<MaxBorrowableResp> {
+    let query = utils::build_max_borrowable_query(asset)?;
+    let signed_req = self.sign_request(Margin::MaxBorrowable.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<MaxBorrowableResp>(res).await
+  }
+
+  pub async fn margin_interest_history(
+    &self,
+    input: InterestHistoryInput,
+  ) -> Result<InterestHistoryResp> {
+    let query = utils::build_interest_history_query(input)?;
+    let signed_req = self.sign_request(Margin::InterestHistory.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<InterestHistoryResp>(res).await
+  }
+
+  pub async fn query_order(&self, input: QueryOrderInput) -> Result<OrderResp> {
+    let query = utils::build_query_order_query(input)?;
+    let signed_req = self.sign_request(Spot::QueryOrder.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<OrderResp>(res).await
+  }
+
   pub async fn spot_account_info(&self) -> Result<AccountInfoResp> {
     let query = utils::build_spot_account_info_query(None)?;
-    let signed_req = self.sign_request(Spot::AccountInfo.into(), Some(query));
+    let signed_req = self.sign_request(Spot::AccountInfo.into(), Some(query))?;
     Ok(
       self
         .client
@@ -80,6 +161,14 @@ impl Client {
     )
   }
 
+  /// Commission rates and BNB discount of `symbol` for this account
+  pub async fn account_commission(&self, symbol: String) -> Result<CommissionResp> {
+    let query = utils::build_account_commission_query(symbol)?;
+    let signed_req = self.sign_request(Spot::AccountCommission.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<CommissionResp>(res).await
+  }
+
   pub async fn kline(&self, input: KlineInput) -> Result<Vec<KlineResp>> {
     let query = utils::build_kline_query(input)?;
     let req_url = format!("{}{}?{}", self.host, String::from(Market::Kline), query);
@@ -110,39 +199,105 @@ impl Client {
     )
   }
 
-  pub async fn current_open_orders(&self, symbol: String) {
-    let timestamp = utils::get_timestamp();
-    let mut params = std::collections::BTreeMap::new();
-    params.insert("symbol".to_string(), symbol);
-    params.insert("timestamp".to_string(), timestamp.to_string());
-    let query = utils::construct_query(params);
-    let signed_req = self.sign_request(Spot::OpenOrders.into(), Some(query));
-    let res = self
-      .client
-      .get(signed_req)
-      .send()
-      .await
-      .unwrap()
-      .text()
-      .await
-      .unwrap();
-    println!("Current Open Order Res: {}", res);
-  }
-  fn sign_request(&self, endpoint: String, req: Option<String>) -> String {
-    match req {
-      Some(request) => {
-        let mut signed_key = Hmac::<Sha256>::new_from_slice(self.api_secret.as_bytes()).unwrap();
-        signed_key.update(request.as_bytes());
-        let signature = hex::encode(signed_key.finalize().into_bytes());
-        let request_body: String = format!("{}&signature={}", request, signature);
-        format!("{}{}?{}", self.host, endpoint, request_body)
-      }
-      None => {
-        let signed_key = Hmac::<Sha256>::new_from_slice(self.api_secret.as_bytes()).unwrap();
-        let signature = hex::encode(signed_key.finalize().into_bytes());
-        let request_body: String = format!("&signature={}", signature);
-        format!("{}{}?{}", self.host, endpoint, request_body)
-      }
-    }
+  /// Trading rules of `symbols`
+  pub async fn exchange_info(&self, symbols: &[String]) -> Result<Vec<SymbolInfo>> {
+    let symbols = symbols
+      .iter()
+      .map(|symbol| format!("\"{}\"", symbol.to_uppercase()))
+      .collect::<Vec<_>>()
+      .join(",");
+    let req_url = format!(
+      "{}{}?symbols={}",
+      self.host,
+      String::from(Market::ExchangeInfo),
+      utils::percent_encode(&format!("[{}]", symbols))
+    );
+    let res = self.client.get(req_url).send().await?;
+    Ok(parse_response::<ExchangeInfoResp>(res).await?.symbols)
+  }
+
+  /// Create a listen key for the user data stream, it expires after 60
+  /// minutes unless kept alive
+  pub async fn start_user_data_stream(&self) -> Result<String> {
+    let req_url = format!("{}{}", self.host, String::from(Spot::UserDataStream));
+    let res = self.client.post(req_url).send().await?;
+    Ok(parse_response::<ListenKeyResp>(res).await?.listen_key)
+  }
+
+  pub async fn keepalive_user_data_stream(&self, listen_key: &str) -> Result<()> {
+    let req_url = format!(
+      "{}{}?listenKey={}",
+      self.host,
+      String::from(Spot::UserDataStream),
+      listen_key
+    );
+    let res = self.client.put(req_url).send().await?;
+    parse_response::<Value>(res).await?;
+    Ok(())
+  }
+
+  pub async fn close_user_data_stream(&self, listen_key: &str) -> Result<()> {
+    let req_url = format!(
+      "{}{}?listenKey={}",
+      self.host,
+      String::from(Spot::UserDataStream),
+      listen_key
+    );
+    let res = self.client.delete(req_url).send().await?;
+    parse_response::<Value>(res).await?;
+    Ok(())
+  }
+
+  /// Cancel every open order of `symbol`, order lists included. Entries
+  /// are `OrderResp`s or order list reports.
+  pub async fn cancel_open_orders(&self, symbol: String) -> Result<Vec<Value>> {
+    let query = utils::build_open_orders_query(Some(symbol))?;
+    let signed_req = self.sign_request(Spot::CancelOpenOrders.into(), Some(query))?;
+    let res = self.client.delete(signed_req).send().await?;
+    parse_response::<Vec<Value>>(res).await
+  }
+
+  /// Open orders of one symbol, or of every symbol if none is given
+  pub async fn current_open_orders(&self, symbol: Option<String>) -> Result<Vec<OrderResp>> {
+    let query = utils::build_open_orders_query(symbol)?;
+    let signed_req = self.sign_request(Spot::OpenOrders.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<Vec<OrderResp>>(res).await
+  }
+
+  fn sign_request(&self, endpoint: String, req: Option<String>) -> Result<String> {
+    let request = req.unwrap_or_default();
+    let signature = utils::percent_encode(&self.signer.sign(&request)?);
+    Ok(format!(
+      "{}{}?{}&signature={}",
+      self.host, endpoint, request, signature
+    ))
+  }
+}
+
+/// HTTP client sending the API key with every request
+pub(crate) fn http_client(api_key: Secret, proxy: Option<String>) -> Result<reqwest::Client> {
+  let mut headers = header::HeaderMap::new();
+  headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+  let mut api_key_header = HeaderValue::from_str(api_key.expose())?;
+  api_key_header.set_sensitive(true);
+  headers.insert("X-MBX-APIKEY", api_key_header);
+  let mut client_builder = reqwest::Client::builder()
+    .connect_timeout(Duration::new(10, 0))
+    .default_headers(headers);
+  if let Some(proxy) = proxy {
+    client_builder = client_builder.proxy(reqwest::Proxy::https(proxy)?);
+  }
+  Ok(client_builder.build()?)
+}
+
+/// Deserialize a successful response, or surface the Binance error
+/// code and message
+pub(crate) async fn parse_response<T: DeserializeOwned>(res: reqwest::Response) -> Result<T> {
+  let status = res.status();
+  let body = res.text().await?;
+  if !status.is_success() {
+    return Err(RequestError::new(status.as_u16(), &body).into());
   }
+  Ok(serde_json::from_str::<T>(&body)?)
 }
@@ -0,0 +1,275 @@
+use crate::binance::api::{OrderInput, OrderRespType, OrderSide, OrderType, TimeInForce};
+use anyhow::{bail, ensure, Result};
+use serde::{Deserialize, Serialize};
+use std::convert::From;
+use std::str::FromStr;
+
+/// USD-M Futures APIs
+/// API Spec: https://binance-docs.github.io/apidocs/futures/en/#account-trades-endpoints
+pub enum Futures {
+  TestNewOrder,
+  NewOrder,
+  CancelOrder,
+  QueryOrder,
+  OpenOrders,
+  CancelAllOpenOrders,
+  PositionRisk,
+  Balance,
+  Leverage,
+  MarginType,
+  PremiumIndex,
+  FundingRate,
+}
+
+impl From<Futures> for String {
+  fn from(endpoint: Futures) -> Self {
+    String::from(match endpoint {
+      Futures::TestNewOrder => "/fapi/v1/order/test",
+      Futures::NewOrder | Futures::CancelOrder | Futures::QueryOrder => "/fapi/v1/order",
+      Futures::OpenOrders => "/fapi/v1/openOrders",
+      Futures::CancelAllOpenOrders => "/fapi/v1/allOpenOrders",
+      Futures::PositionRisk => "/fapi/v2/positionRisk",
+      Futures::Balance => "/fapi/v2/balance",
+      Futures::Leverage => "/fapi/v1/leverage",
+      Futures::MarginType => "/fapi/v1/marginType",
+      Futures::PremiumIndex => "/fapi/v1/premiumIndex",
+      Futures::FundingRate => "/fapi/v1/fundingRate",
+    })
+  }
+}
+
+/// Enum Spec: https://binance-docs.github.io/apidocs/futures/en/#public-endpoints-info
+#[derive(Clone, Debug)]
+pub enum FuturesOrderType {
+  Limit,
+  Market,
+  Stop,
+  StopMarket,
+  TakeProfit,
+  TakeProfitMarket,
+  TrailingStopMarket,
+}
+
+impl From<FuturesOrderType> for String {
+  fn from(item: FuturesOrderType) -> Self {
+    String::from(match item {
+      FuturesOrderType::Limit => "LIMIT",
+      FuturesOrderType::Market => "MARKET",
+      FuturesOrderType::Stop => "STOP",
+      FuturesOrderType::StopMarket => "STOP_MARKET",
+      FuturesOrderType::TakeProfit => "TAKE_PROFIT",
+      FuturesOrderType::TakeProfitMarket => "TAKE_PROFIT_MARKET",
+      FuturesOrderType::TrailingStopMarket => "TRAILING_STOP_MARKET",
+    })
+  }
+}
+
+impl FromStr for FuturesOrderType {
+  type Err = anyhow::Error;
+
+  fn from_str(order_type: &str) -> Result<Self> {
+    Ok(match order_type.to_uppercase().replace('-', "_").as_str() {
+      "LIMIT" => FuturesOrderType::Limit,
+      "MARKET" => FuturesOrderType::Market,
+      "STOP" => FuturesOrderType::Stop,
+      "STOP_MARKET" => FuturesOrderType::StopMarket,
+      "TAKE_PROFIT" => FuturesOrderType::TakeProfit,
+      "TAKE_PROFIT_MARKET" => FuturesOrderType::TakeProfitMarket,
+      "TRAILING_STOP_MARKET" => FuturesOrderType::TrailingStopMarket,
+      _ => bail!("Unknown futures order type: {}", order_type),
+    })
+  }
+}
+
+/// BOTH in one-way mode, LONG or SHORT in hedge mode
+#[derive(Clone, Debug)]
+pub enum PositionSide {
+  Both,
+  Long,
+  Short,
+}
+
+impl From<PositionSide> for String {
+  fn from(item: PositionSide) -> Self {
+    String::from(match item {
+      PositionSide::Both => "BOTH",
+      PositionSide::Long => "LONG",
+      PositionSide::Short => "SHORT",
+    })
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum MarginType {
+  Isolated,
+  Crossed,
+}
+
+impl From<MarginType> for String {
+  fn from(item: MarginType) -> Self {
+    String::from(match item {
+      MarginType::Isolated => "ISOLATED",
+      MarginType::Crossed => "CROSSED",
+    })
+  }
+}
+
+impl FromStr for MarginType {
+  type Err = anyhow::Error;
+
+  fn from_str(margin_type: &str) -> Result<Self> {
+    Ok(match margin_type.to_uppercase().as_str() {
+      "ISOLATED" => MarginType::Isolated,
+      "CROSSED" | "CROSS" => MarginType::Crossed,
+      _ => bail!("Unknown margin type: {}", margin_type),
+    })
+  }
+}
+
+#[derive(Debug)]
+pub struct FuturesOrderInput {
+  pub symbol: String,
+  pub side: OrderSide,
+  pub position_side: Option<PositionSide>,
+  pub order_type: FuturesOrderType,
+  pub time_in_force: Option<TimeInForce>,
+  pub quantity: Option<f32>,
+  pub reduce_only: Option<bool>,
+  pub price: Option<f32>,
+  pub new_client_order_id: String,
+  pub stop_price: Option<f32>,
+  pub close_position: Option<bool>, // Close the whole position when a stop triggers
+  pub callback_rate: Option<f32>,   // Trailing stop callback in percent, 0.1 to 5
+  pub new_order_resp_type: Option<OrderRespType>,
+  pub recv_window: Option<u32>,
+  pub timestamp: i64,
+}
+
+impl FuturesOrderInput {
+  /// Convert a spot order from a strategy, futures orders can't be sized
+  /// in quote asset so `quote_order_qty` is divided by `price`
+  pub fn from_spot(order: OrderInput, price: f64) -> Result<Self> {
+    let quantity = match (order.quantity, order.quote_order_qty) {
+      (Some(quantity), _) => quantity,
+      (None, Some(quote_qty)) => {
+        ensure!(price > 0.0, "Can't size {} without a price", quote_qty);
+        (quote_qty as f64 / price) as f32
+      }
+      (None, None) => bail!("Missing Quantity or Quote Order Qty"),
+    };
+    let order_type = match order.order_type {
+      OrderType::Limit => FuturesOrderType::Limit,
+      OrderType::Market => FuturesOrderType::Market,
+      OrderType::StopLoss => FuturesOrderType::StopMarket,
+      OrderType::StopLossLimit => FuturesOrderType::Stop,
+      OrderType::TakeProfit => FuturesOrderType::TakeProfitMarket,
+      OrderType::TakeProfitLimit => FuturesOrderType::TakeProfit,
+      OrderType::LimitMaker => bail!("Futures have no LIMIT_MAKER orders"),
+    };
+    Ok(Self {
+      symbol: order.symbol,
+      side: order.side,
+      position_side: None,
+      order_type,
+      time_in_force: order.time_in_force,
+      quantity: Some(quantity),
+      reduce_only: None,
+      price: order.price,
+      new_client_order_id: order.new_client_order_id,
+      stop_price: order.stop_price,
+      close_position: None,
+      callback_rate: None,
+      new_order_resp_type: order.new_order_resp_type,
+      recv_window: order.recv_window,
+      timestamp: order.timestamp,
+    })
+  }
+}
+
+pub struct FundingRateInput {
+  pub symbol: String,
+  pub start_time: Option<i64>,
+  pub end_time: Option<i64>,
+  pub limit: Option<u32>, // Default 100, max 1000
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesOrderResp {
+  pub symbol: String,
+  pub order_id: i64,
+  pub client_order_id: String,
+  pub status: String,
+  pub price: String,
+  pub avg_price: String,
+  pub orig_qty: String,
+  pub executed_qty: String,
+  pub cum_quote: String,
+  pub time_in_force: String,
+  #[serde(rename = "type")]
+  pub order_type: String,
+  pub reduce_only: bool,
+  pub close_position: bool,
+  pub side: String,
+  pub position_side: String,
+  pub stop_price: String,
+  pub update_time: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionRisk {
+  pub symbol: String,
+  pub position_amt: String, // Negative when short
+  pub entry_price: String,
+  pub mark_price: String,
+  pub un_realized_profit: String,
+  pub liquidation_price: String,
+  pub leverage: String,
+  pub margin_type: String,
+  pub isolated_margin: String,
+  pub position_side: String,
+  pub notional: String,
+  pub update_time: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesBalance {
+  pub asset: String,
+  pub balance: String,
+  pub cross_wallet_balance: String,
+  pub cross_un_pnl: String,
+  pub available_balance: String,
+  pub max_withdraw_amount: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LeverageResp {
+  pub symbol: String,
+  pub leverage: u32,
+  pub max_notional_value: String,
+}
+
+/// Mark price, index price and the funding rate of the current period
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PremiumIndex {
+  pub symbol: String,
+  pub mark_price: String,
+  pub index_price: String,
+  pub estimated_settle_price: String,
+  pub last_funding_rate: String,
+  pub next_funding_time: i64,
+  pub interest_rate: String,
+  pub time: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FundingRate {
+  pub symbol: String,
+  pub funding_rate: String,
+  pub funding_time: i64,
+}
@@ -0,0 +1,153 @@
+use crate::binance::api::{CancelOrderInput, QueryOrderInput, RequestError};
+use crate::binance::client::{http_client, parse_response};
+use crate::binance::futures::api::{
+  FundingRate, FundingRateInput, Futures, FuturesBalance, FuturesOrderInput, FuturesOrderResp,
+  LeverageResp, MarginType, PositionRisk, PremiumIndex,
+};
+use crate::binance::signer::Signer;
+use crate::shared::secret::Secret;
+use crate::shared::utils;
+use anyhow::Result;
+
+// Returned when the margin type is already the requested one
+const NO_NEED_TO_CHANGE_MARGIN_TYPE: i64 = -4046;
+
+/// Client for USD-M futures, `host` is e.g. `https://fapi.binance.com`.
+/// Positions are signed, a negative `position_amt` is a short.
+pub struct FuturesClient {
+  signer: Box<dyn Signer>,
+  host: String,
+  client: reqwest::Client,
+}
+
+impl FuturesClient {
+  pub fn new(
+    api_key: Secret,
+    signer: Box<dyn Signer>,
+    host: String,
+    proxy: Option<String>,
+  ) -> Result<Self> {
+    Ok(Self {
+      signer,
+      host,
+      client: http_client(api_key, proxy)?,
+    })
+  }
+
+  pub async fn new_order(&self, input: FuturesOrderInput) -> Result<FuturesOrderResp> {
+    let query = utils::build_futures_order_query(input)?;
+    let signed_req = self.sign_request(Futures::NewOrder.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<FuturesOrderResp>(res).await
+  }
+
+  /// Validates the order without placing it
+  pub async fn test_new_order(&self, input: FuturesOrderInput) -> Result<()> {
+    let query = utils::build_futures_order_query(input)?;
+    let signed_req = self.sign_request(Futures::TestNewOrder.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<serde_json::Value>(res).await?;
+    Ok(())
+  }
+
+  pub async fn cancel_order(&self, input: CancelOrderInput) -> Result<FuturesOrderResp> {
+    let query = utils::build_cancel_order_query(input)?;
+    let signed_req = self.sign_request(Futures::CancelOrder.into(), Some(query))?;
+    let res = self.client.delete(signed_req).send().await?;
+    parse_response::<FuturesOrderResp>(res).await
+  }
+
+  pub async fn query_order(&self, input: QueryOrderInput) -> Result<FuturesOrderResp> {
+    let query = utils::build_query_order_query(input)?;
+    let signed_req = self.sign_request(Futures::QueryOrder.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<FuturesOrderResp>(res).await
+  }
+
+  pub async fn cancel_all_open_orders(&self, symbol: String) -> Result<()> {
+    let query = utils::build_open_orders_query(Some(symbol))?;
+    let signed_req = self.sign_request(Futures::CancelAllOpenOrders.into(), Some(query))?;
+    let res = self.client.delete(signed_req).send().await?;
+    parse_response::<serde_json::Value>(res).await?;
+    Ok(())
+  }
+
+  /// Open orders of one symbol, or of every symbol if none is given
+  pub async fn open_orders(&self, symbol: Option<String>) -> Result<Vec<FuturesOrderResp>> {
+    let query = utils::build_open_orders_query(symbol)?;
+    let signed_req = self.sign_request(Futures::OpenOrders.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<Vec<FuturesOrderResp>>(res).await
+  }
+
+  /// Positions of one symbol, or of every symbol if none is given
+  pub async fn positions(&self, symbol: Option<String>) -> Result<Vec<PositionRisk>> {
+    let query = utils::build_open_orders_query(symbol)?;
+    let signed_req = self.sign_request(Futures::PositionRisk.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<Vec<PositionRisk>>(res).await
+  }
+
+  pub async fn balances(&self) -> Result<Vec<FuturesBalance>> {
+    let query = utils::build_spot_account_info_query(None)?;
+    let signed_req = self.sign_request(Futures::Balance.into(), Some(query))?;
+    let res = self.client.get(signed_req).send().await?;
+    parse_response::<Vec<FuturesBalance>>(res).await
+  }
+
+  pub async fn change_leverage(&self, symbol: String, leverage: u32) -> Result<LeverageResp> {
+    let query = utils::build_leverage_query(symbol, leverage)?;
+    let signed_req = self.sign_request(Futures::Leverage.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    parse_response::<LeverageResp>(res).await
+  }
+
+  /// Switch between isolated and cross margin, succeeds if the symbol
+  /// already uses `margin_type`
+  pub async fn change_margin_type(&self, symbol: String, margin_type: MarginType) -> Result<()> {
+    let query = utils::build_margin_type_query(symbol, margin_type)?;
+    let signed_req = self.sign_request(Futures::MarginType.into(), Some(query))?;
+    let res = self.client.post(signed_req).send().await?;
+    match parse_response::<serde_json::Value>(res).await {
+      Ok(_) => Ok(()),
+      Err(e) => match e.downcast_ref::<RequestError>() {
+        Some(err) if err.code == Some(NO_NEED_TO_CHANGE_MARGIN_TYPE) => Ok(()),
+        _ => Err(e),
+      },
+    }
+  }
+
+  /// Mark price and current funding rate
+  pub async fn premium_index(&self, symbol: String) -> Result<PremiumIndex> {
+    let req_url = format!(
+      "{}{}?symbol={}",
+      self.host,
+      String::from(Futures::PremiumIndex),
+      symbol
+    );
+    let res = self.client.get(req_url).send().await?;
+    parse_response::<PremiumIndex>(res).await
+  }
+
+  /// Funding rate history, oldest first
+  pub async fn funding_rate(&self, input: FundingRateInput) -> Result<Vec<FundingRate>> {
+    let query = utils::build_funding_rate_query(input)?;
+    let req_url = format!(
+      "{}{}?{}",
+      self.host,
+      String::from(Futures::FundingRate),
+      query
+    );
+    let res = self.client.get(req_url).send().await?;
+    parse_response::<Vec<FundingRate>>(res).await
+  }
+
+  fn sign_request(&self, endpoint: String, req: Option<String>) -> Result<String> {
+    let request = req.unwrap_or_default();
+    let signature = utils::percent_encode(&self.signer.sign(&request)?);
+    Ok(format!(
+      "{}{}?{}&signature={}",
+      self.host, endpoint, request, signature
+    ))
+  }
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+/// `<symbol>@markPrice@1s`, pushed every second
+pub fn mark_price_stream(symbol: &str) -> String {
+  format!("{}@markPrice@1s", symbol.to_lowercase())
+}
+
+/// `<symbol>@forceOrder`, the latest liquidation in each 1000ms window
+pub fn force_order_stream(symbol: &str) -> String {
+  format!("{}@forceOrder", symbol.to_lowercase())
+}
+
+/// `<symbol>@aggTrade`, trades aggregated by taker order and price
+pub fn agg_trade_stream(symbol: &str) -> String {
+  format!("{}@aggTrade", symbol.to_lowercase())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MarkPriceUpdate {
+  #[serde(rename = "e")]
+  pub event_type: String,
+  #[serde(rename = "E")]
+  pub event_time: i64,
+  #[serde(rename = "s")]
+  pub symbol: String,
+  #[serde(rename = "p")]
+  pub mark_price: String,
+  #[serde(rename = "i")]
+  pub index_price: String,
+  #[serde(rename = "P")]
+  pub estimated_settle_price: String,
+  #[serde(rename = "r")]
+  pub funding_rate: String,
+  #[serde(rename = "T")]
+  pub next_funding_time: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LiquidationOrder {
+  #[serde(rename = "s")]
+  pub symbol: String,
+  #[serde(rename = "S")]
+  pub side: String,
+  #[serde(rename = "o")]
+  pub order_type: String,
+  #[serde(rename = "f")]
+  pub time_in_force: String,
+  #[serde(rename = "q")]
+  pub orig_qty: String,
+  #[serde(rename = "p")]
+  pub price: String,
+  #[serde(rename = "ap")]
+  pub avg_price: String,
+  #[serde(rename = "X")]
+  pub status: String,
+  #[serde(rename = "l")]
+  pub last_filled_qty: String,
+  #[serde(rename = "z")]
+  pub filled_qty: String,
+  #[serde(rename = "T")]
+  pub trade_time: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ForceOrder {
+  #[serde(rename = "e")]
+  pub event_type: String,
+  #[serde(rename = "E")]
+  pub event_time: i64,
+  #[serde(rename = "o")]
+  pub order: LiquidationOrder,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AggTrade {
+  #[serde(rename = "e")]
+  pub event_type: String,
+  #[serde(rename = "E")]
+  pub event_time: i64,
+  #[serde(rename = "s")]
+  pub symbol: String,
+  #[serde(rename = "a")]
+  pub agg_trade_id: u64,
+  #[serde(rename = "p")]
+  pub price: String,
+  #[serde(rename = "q")]
+  pub quantity: String,
+  #[serde(rename = "f")]
+  pub first_trade_id: u64,
+  #[serde(rename = "l")]
+  pub last_trade_id: u64,
+  #[serde(rename = "T")]
+  pub trade_time: i64,
+  #[serde(rename = "m")]
+  pub is_buyer_maker: bool, // The seller was the taker
+}
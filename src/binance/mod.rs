@@ -1,4 +1,7 @@
 pub mod api;
 pub mod client;
 pub mod data_stream;
+pub mod futures;
+pub mod signer;
 pub mod websocket;
+pub mod ws_api;
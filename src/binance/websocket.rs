@@ -72,6 +72,23 @@ pub struct StreamTrade {
   pub trade_time: i64, // Time of transaction
 }
 
+/// Best bid and ask pushed on every change of the top of the book
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StreamBookTicker {
+  #[serde(rename = "u")]
+  pub update_id: u64,
+  #[serde(rename = "s")]
+  pub symbol: String,
+  #[serde(rename = "b")]
+  pub bid_price: String,
+  #[serde(rename = "B")]
+  pub bid_qty: String,
+  #[serde(rename = "a")]
+  pub ask_price: String,
+  #[serde(rename = "A")]
+  pub ask_qty: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct StreamOrderbook {
@@ -79,3 +96,56 @@ pub struct StreamOrderbook {
   pub bids: Vec<Vec<String>>,
   pub asks: Vec<Vec<String>>,
 }
+
+/// Order update pushed on the user data stream, quantities are
+/// cumulative except `last_executed_qty`, `last_executed_price` and
+/// `commission` which belong to the trade of this report
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExecutionReport {
+  #[serde(rename = "e")]
+  pub event_type: String,
+  #[serde(rename = "E")]
+  pub event_time: i64,
+  #[serde(rename = "s")]
+  pub symbol: String,
+  #[serde(rename = "c")]
+  pub client_order_id: String,
+  #[serde(rename = "S")]
+  pub side: String,
+  #[serde(rename = "o")]
+  pub order_type: String,
+  #[serde(rename = "f")]
+  pub time_in_force: String,
+  #[serde(rename = "q")]
+  pub quantity: String,
+  #[serde(rename = "p")]
+  pub price: String,
+  #[serde(rename = "P")]
+  pub stop_price: String,
+  #[serde(rename = "C")]
+  pub orig_client_order_id: String, // Order being canceled, empty otherwise
+  #[serde(rename = "x")]
+  pub execution_type: String,
+  #[serde(rename = "X")]
+  pub order_status: String,
+  #[serde(rename = "r")]
+  pub reject_reason: String,
+  #[serde(rename = "i")]
+  pub order_id: i64,
+  #[serde(rename = "l")]
+  pub last_executed_qty: String,
+  #[serde(rename = "z")]
+  pub cumulative_filled_qty: String,
+  #[serde(rename = "L")]
+  pub last_executed_price: String,
+  #[serde(rename = "n")]
+  pub commission: String,
+  #[serde(rename = "N")]
+  pub commission_asset: Option<String>,
+  #[serde(rename = "T")]
+  pub transaction_time: i64,
+  #[serde(rename = "t")]
+  pub trade_id: i64, // -1 unless the report is a trade
+  #[serde(rename = "Z")]
+  pub cumulative_quote_qty: String,
+}
@@ -0,0 +1,471 @@
+use crate::binance::api::{
+  AccountInfoResp, ApiError, CancelOrderInput, OrderInput, OrderResp, QueryOrderInput, RequestError,
+};
+use crate::binance::signer::{KeyType, Signer};
+use crate::shared::secret::Secret;
+use crate::shared::utils;
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{stream::StreamExt, SinkExt};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>;
+
+// Params sent as JSON numbers, everything else is sent as a string
+const INTEGER_PARAMS: [&str; 5] = [
+  "timestamp",
+  "recvWindow",
+  "orderId",
+  "strategyId",
+  "strategyType",
+];
+
+/// Response envelope of the WebSocket API
+/// API Spec: https://binance-docs.github.io/apidocs/websocket_api/en/#response-format
+#[derive(Deserialize, Debug)]
+struct WsApiResp {
+  status: u16,
+  result: Option<Value>,
+  error: Option<ApiError>,
+}
+
+// Delay before the first reconnect attempt, doubled after every failure
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Client for the Binance WebSocket API. Requests share one persistent
+/// connection and are matched to their responses by id, so several can
+/// be in flight at once.
+///
+/// Ed25519 keys log the session on once and requests are sent unsigned
+/// afterwards, HMAC and RSA keys sign every request.
+///
+/// A dropped connection is reopened in the background with backoff and
+/// logged on again. Requests made while it's down fail right away.
+pub struct WsApiClient {
+  session: Arc<Session>,
+}
+
+// What the client and the task reconnecting it share
+struct Session {
+  endpoint: String,
+  api_key: Secret,
+  signer: Box<dyn Signer>,
+  timeout: Duration,
+  next_id: AtomicU64,
+  pending: Pending,
+  sender: Arc<Mutex<Option<mpsc::UnboundedSender<Message>>>>, // None while disconnected
+  logged_on: Arc<AtomicBool>,
+}
+
+impl WsApiClient {
+  /// Connect to `endpoint`, e.g. `wss://ws-api.binance.com:443/ws-api/v3`.
+  /// `timeout` bounds how long each request waits for its response.
+  pub async fn connect(
+    endpoint: &str,
+    api_key: Secret,
+    signer: Box<dyn Signer>,
+    timeout: Duration,
+  ) -> Result<Self> {
+    let session = Arc::new(Session {
+      endpoint: endpoint.to_string(),
+      api_key,
+      signer,
+      timeout,
+      next_id: AtomicU64::new(1),
+      pending: Arc::new(Mutex::new(HashMap::new())),
+      sender: Arc::new(Mutex::new(None)),
+      logged_on: Arc::new(AtomicBool::new(false)),
+    });
+    let closed = session.open().await?;
+    if session.signer.key_type() == KeyType::Ed25519 {
+      session.logon().await?;
+    }
+    tokio::spawn(reconnect(Arc::downgrade(&session), closed));
+    Ok(Self { session })
+  }
+
+  /// Whether the connection is up, requests fail while it's reopened
+  pub fn is_connected(&self) -> bool {
+    self.session.connection().is_some()
+  }
+
+  pub async fn place_order(&self, input: OrderInput) -> Result<OrderResp> {
+    let params = utils::build_order_params(input)?;
+    self.session.signed_request("order.place", params).await
+  }
+
+  /// Validates the order against the matching engine without placing it
+  pub async fn test_order(&self, input: OrderInput) -> Result<()> {
+    let params = utils::build_order_params(input)?;
+    self
+      .session
+      .signed_request::<Value>("order.test", params)
+      .await?;
+    Ok(())
+  }
+
+  pub async fn cancel_order(&self, input: CancelOrderInput) -> Result<OrderResp> {
+    let params = utils::build_cancel_order_params(input)?;
+    self.session.signed_request("order.cancel", params).await
+  }
+
+  /// Cancel every open order of `symbol`, order lists included
+  pub async fn cancel_open_orders(&self, symbol: String) -> Result<Vec<Value>> {
+    let params = utils::build_open_orders_params(Some(symbol))?;
+    self
+      .session
+      .signed_request("openOrders.cancelAll", params)
+      .await
+  }
+
+  pub async fn order_status(&self, input: QueryOrderInput) -> Result<OrderResp> {
+    let params = utils::build_query_order_params(input)?;
+    self.session.signed_request("order.status", params).await
+  }
+
+  pub async fn account_status(&self) -> Result<AccountInfoResp> {
+    let mut params = BTreeMap::new();
+    params.insert("timestamp".into(), utils::get_timestamp().to_string());
+    self.session.signed_request("account.status", params).await
+  }
+}
+
+impl Session {
+  /// Open the connection, the returned receiver resolves once it drops
+  async fn open(&self) -> Result<oneshot::Receiver<()>> {
+    log::info!("Connecting to {}", self.endpoint);
+    let (stream, resp) = connect_async(self.endpoint.as_str())
+      .await
+      .with_context(|| format!("Failed to connect to {}", self.endpoint))?;
+    log::debug!("Websocket server response: {:#?}", resp);
+    let (mut write, mut read) = stream.split();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+
+    tokio::spawn(async move {
+      while let Some(msg) = receiver.recv().await {
+        if let Err(e) = write.send(msg).await {
+          log::error!("Failed to send to WebSocket API: {:#?}", e);
+          break;
+        }
+      }
+    });
+
+    *self
+      .sender
+      .lock()
+      .map_err(|_| anyhow!("WebSocket API sender poisoned"))? = Some(sender);
+
+    // The reader doesn't keep the sender alive, so dropping the client
+    // closes the connection
+    let pending = self.pending.clone();
+    let slot = Arc::downgrade(&self.sender);
+    let logged_on = self.logged_on.clone();
+    let (closed_sender, closed) = oneshot::channel();
+    tokio::spawn(async move {
+      while let Some(item) = read.next().await {
+        match item {
+          Ok(Message::Text(data)) => dispatch(&pending, &data),
+          Ok(Message::Ping(ping)) => {
+            let sender = slot.upgrade().and_then(|slot| slot.lock().ok()?.clone());
+            match sender {
+              Some(sender) if sender.send(Message::Pong(ping)).is_ok() => {}
+              _ => break,
+            }
+          }
+          Ok(Message::Close(frame)) => {
+            log::warn!("WebSocket API closed the connection: {:?}", frame);
+            break;
+          }
+          Ok(_) => {}
+          Err(e) => {
+            log::error!("Failed to get message from WebSocket API: {:#?}", e);
+            break;
+          }
+        }
+      }
+      if let Some(slot) = slot.upgrade() {
+        if let Ok(mut sender) = slot.lock() {
+          *sender = None;
+        }
+      }
+      logged_on.store(false, Ordering::Relaxed);
+      // Dropping the response senders fails every request still waiting
+      if let Ok(mut pending) = pending.lock() {
+        pending.clear();
+      }
+      let _ = closed_sender.send(());
+    });
+    Ok(closed)
+  }
+
+  fn connection(&self) -> Option<mpsc::UnboundedSender<Message>> {
+    self.sender.lock().ok()?.clone()
+  }
+
+  /// Authenticate the connection so later requests don't carry the API
+  /// key and signature, only available for Ed25519 keys
+  async fn logon(&self) -> Result<()> {
+    let mut params = BTreeMap::new();
+    params.insert("timestamp".into(), utils::get_timestamp().to_string());
+    self
+      .request::<Value>("session.logon", self.sign(params)?)
+      .await
+      .context("WebSocket API session logon failed")?;
+    self.logged_on.store(true, Ordering::Relaxed);
+    log::info!("WebSocket API session logged on");
+    Ok(())
+  }
+
+  async fn signed_request<T: DeserializeOwned>(
+    &self,
+    method: &str,
+    params: BTreeMap<String, String>,
+  ) -> Result<T> {
+    let params = if self.logged_on.load(Ordering::Relaxed) {
+      params
+    } else {
+      self.sign(params)?
+    };
+    self.request(method, params).await
+  }
+
+  /// Add the API key and the signature over the sorted params
+  fn sign(&self, mut params: BTreeMap<String, String>) -> Result<BTreeMap<String, String>> {
+    params.insert("apiKey".into(), self.api_key.expose().to_string());
+    let signature = self.signer.sign(&utils::construct_query(params.clone()))?;
+    params.insert("signature".into(), signature);
+    Ok(params)
+  }
+
+  async fn request<T: DeserializeOwned>(
+    &self,
+    method: &str,
+    params: BTreeMap<String, String>,
+  ) -> Result<T> {
+    let sender = match self.connection() {
+      Some(sender) => sender,
+      None => bail!("WebSocket API is disconnected, {} not sent", method),
+    };
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let params: Map<String, Value> = params
+      .into_iter()
+      .map(|(key, val)| {
+        let val = match val.parse::<i64>() {
+          Ok(num) if INTEGER_PARAMS.contains(&key.as_str()) => json!(num),
+          _ => Value::String(val),
+        };
+        (key, val)
+      })
+      .collect();
+    let request = json!({ "id": id, "method": method, "params": params });
+
+    let (resp_sender, resp_receiver) = oneshot::channel();
+    self
+      .pending
+      .lock()
+      .map_err(|_| anyhow!("WebSocket API pending requests poisoned"))?
+      .insert(id, resp_sender);
+    if sender.send(Message::Text(request.to_string())).is_err() {
+      self.forget(id);
+      bail!("WebSocket API connection is closed");
+    }
+
+    let resp = match tokio::time::timeout(self.timeout, resp_receiver).await {
+      Ok(Ok(resp)) => resp,
+      Ok(Err(_)) => bail!(
+        "WebSocket API connection closed before {} {} returned",
+        method,
+        id
+      ),
+      Err(_) => {
+        self.forget(id);
+        bail!(
+          "WebSocket API {} {} timed out after {:?}",
+          method,
+          id,
+          self.timeout
+        )
+      }
+    };
+    let resp: WsApiResp = serde_json::from_value(resp)?;
+    if let Some(err) = resp.error {
+      return Err(
+        RequestError {
+          status: resp.status,
+          code: Some(err.code),
+          msg: err.msg,
+        }
+        .into(),
+      );
+    }
+    let result = resp
+      .result
+      .ok_or_else(|| anyhow!("WebSocket API {} {} returned no result", method, id))?;
+    Ok(serde_json::from_value::<T>(result)?)
+  }
+
+  fn forget(&self, id: u64) {
+    if let Ok(mut pending) = self.pending.lock() {
+      pending.remove(&id);
+    }
+  }
+}
+
+/// Reopen the session's connection whenever it drops, until the client
+/// is gone
+async fn reconnect(session: Weak<Session>, mut closed: oneshot::Receiver<()>) {
+  loop {
+    let _ = (&mut closed).await;
+    let mut delay = RECONNECT_DELAY;
+    closed = loop {
+      let session = match session.upgrade() {
+        Some(session) => session,
+        None => return,
+      };
+      log::warn!("WebSocket API disconnected, reconnecting in {:?}", delay);
+      tokio::time::sleep(delay).await;
+      match session.open().await {
+        Ok(closed) => {
+          // Without a session requests are signed one by one
+          if session.signer.key_type() == KeyType::Ed25519 {
+            if let Err(e) = session.logon().await {
+              log::error!("{:#}, signing every request", e);
+            }
+          }
+          break closed;
+        }
+        Err(e) => {
+          log::error!("{:#}", e);
+          delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+      }
+    };
+  }
+}
+
+/// Hand a response to the request waiting on its id
+fn dispatch(pending: &Pending, data: &str) {
+  let resp = match serde_json::from_str::<Value>(data) {
+    Ok(resp) => resp,
+    Err(e) => {
+      log::error!("Malformed WebSocket API message {}: {:#?}", data, e);
+      return;
+    }
+  };
+  let waiting = resp["id"]
+    .as_u64()
+    .and_then(|id| pending.lock().ok()?.remove(&id));
+  match waiting {
+    Some(sender) => {
+      // The request already timed out if the receiver is gone
+      let _ = sender.send(resp);
+    }
+    None => log::warn!("Unmatched WebSocket API response: {}", data),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::binance::signer::{Ed25519Signer, HmacSigner};
+  use std::time::Instant;
+  use tokio::net::TcpListener;
+
+  // Answers every request with an empty list and passes it on with the
+  // number of its connection. The first connection is closed after its
+  // first answer.
+  async fn serve(listener: TcpListener, requests: mpsc::UnboundedSender<(usize, Value)>) {
+    let mut connections = 0;
+    loop {
+      let (stream, _) = listener.accept().await.unwrap();
+      let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+      connections += 1;
+      while let Some(Ok(Message::Text(data))) = ws.next().await {
+        let request = serde_json::from_str::<Value>(&data).unwrap();
+        let resp = json!({ "id": request["id"], "status": 200, "result": [] });
+        ws.send(Message::Text(resp.to_string())).await.unwrap();
+        let _ = requests.send((connections, request));
+        if connections == 1 {
+          ws.close(None).await.unwrap();
+          break;
+        }
+      }
+    }
+  }
+
+  async fn connect(
+    signer: Box<dyn Signer>,
+  ) -> (WsApiClient, mpsc::UnboundedReceiver<(usize, Value)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("ws://{}", listener.local_addr().unwrap());
+    let (sender, requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, sender));
+    let api_key = Secret::new("key".to_string());
+    let client = WsApiClient::connect(&endpoint, api_key, signer, Duration::from_secs(10))
+      .await
+      .unwrap();
+    (client, requests)
+  }
+
+  async fn wait_for(client: &WsApiClient, connected: bool) {
+    let start = Instant::now();
+    while client.is_connected() != connected {
+      assert!(start.elapsed() < Duration::from_secs(5), "Timed out");
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+  }
+
+  #[tokio::test]
+  async fn reconnects_and_fails_fast_while_disconnected() {
+    let signer = Box::new(HmacSigner::new(Secret::new("secret".to_string())));
+    let (client, _requests) = connect(signer).await;
+    client.cancel_open_orders("BTCUSDT".into()).await.unwrap();
+    wait_for(&client, false).await;
+
+    let start = Instant::now();
+    let e = client
+      .cancel_open_orders("BTCUSDT".into())
+      .await
+      .unwrap_err();
+    assert!(e.to_string().contains("disconnected"), "{}", e);
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    wait_for(&client, true).await;
+    client.cancel_open_orders("BTCUSDT".into()).await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn ed25519_sessions_log_on_again() {
+    let (client, mut requests) = connect(Box::new(Ed25519Signer::from_bytes(&[7; 32]))).await;
+    let (connection, logon) = requests.recv().await.unwrap();
+    assert_eq!(
+      (connection, logon["method"].as_str()),
+      (1, Some("session.logon"))
+    );
+    assert!(logon["params"]["signature"].is_string());
+    wait_for(&client, false).await;
+
+    wait_for(&client, true).await;
+    let (connection, logon) = requests.recv().await.unwrap();
+    assert_eq!(
+      (connection, logon["method"].as_str()),
+      (2, Some("session.logon"))
+    );
+    // Requests are signed until the logon response is in
+    while !client.session.logged_on.load(Ordering::Relaxed) {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    client.cancel_open_orders("BTCUSDT".into()).await.unwrap();
+    let (_, request) = requests.recv().await.unwrap();
+    assert_eq!(request["method"], "openOrders.cancelAll");
+    assert!(request["params"].get("signature").is_none());
+  }
+}
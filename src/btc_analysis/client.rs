@@ -1,8 +1,8 @@
 use crate::btc_analysis::structs::Block;
 
-use anyhow::{Result};
+use anyhow::Result;
 use jsonrpc::{Client, Response};
-use serde_json;
+
 pub struct ChainRpc {
   rpc_client: Client,
 }
@@ -15,7 +15,7 @@ impl ChainRpc {
       .unwrap()
       .build();
     let rpc_client = Client::with_transport(transport);
-    return Self { rpc_client };
+    Self { rpc_client }
   }
 
   fn send_request(
@@ -31,7 +31,7 @@ impl ChainRpc {
     let method = "getbestblockhash";
     let block_hash = self
       .send_request(method, &[])
-      .map_err(|e| anyhow::Error::new(e))?
+      .map_err(anyhow::Error::new)?
       .result::<String>()?;
 
     Ok(block_hash)
@@ -41,9 +41,8 @@ impl ChainRpc {
     let method = "getblock";
     let res = self
       .send_request(method, &[jsonrpc::arg(hash), jsonrpc::arg(verbosity)])
-      .unwrap()
-      .result::<Block>()
-      .unwrap();
+      .map_err(anyhow::Error::new)?
+      .result::<Block>()?;
     Ok(res)
   }
 }
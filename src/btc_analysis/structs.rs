@@ -3,8 +3,8 @@ use serde::{Deserialize, Serialize};
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub struct Block {
   pub hash: String,
-  pub confirmations: i64, 
-  pub size: i64, 
+  pub confirmations: i64,
+  pub size: i64,
   pub strippedsize: i64,
   pub weight: i64,
   pub height: i64,
@@ -12,7 +12,7 @@ pub struct Block {
   #[serde(rename = "versionHex")]
   pub version_hex: String,
   pub merkleroot: String,
-  pub tx: Vec<String>, 
+  pub tx: Vec<String>,
   pub time: i64,
   pub mediantime: i64,
   pub nonce: i64,
@@ -22,5 +22,5 @@ pub struct Block {
   #[serde(rename = "nTx")]
   pub n_tx: i64,
   pub previousblockhash: String,
-  pub nextblockhash: Option<String>
-}
\ No newline at end of file
+  pub nextblockhash: Option<String>,
+}
@@ -0,0 +1,69 @@
+use super::{binance_client, CommonOpt};
+use anyhow::Result;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct AccountOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// List open orders of every symbol instead of only --symbol
+  #[structopt(long)]
+  pub all_symbols: bool,
+}
+
+pub async fn run(opt: AccountOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let client = binance_client(&config)?;
+
+  let account_info = client.spot_account_info().await?;
+  println!(
+    "Account type: {} Can trade: {}",
+    account_info.account_type, account_info.can_trade
+  );
+  let commission = client.account_commission(symbol.to_uppercase()).await?;
+  let (rates, discount) = (&commission.standard_commission, &commission.discount);
+  println!(
+    "{} commission: maker {} taker {} BNB discount: {}",
+    commission.symbol,
+    rates.maker,
+    rates.taker,
+    match discount.enabled_for_account && discount.enabled_for_symbol {
+      true => format!("pays {} of it", discount.discount),
+      false => "off".to_string(),
+    }
+  );
+  println!("{:<10} {:>20} {:>20}", "Asset", "Free", "Locked");
+  for balance in &account_info.balances {
+    let free = balance.free.parse::<f64>()?;
+    let locked = balance.locked.parse::<f64>()?;
+    if free == 0.0 && locked == 0.0 {
+      continue;
+    }
+    println!(
+      "{:<10} {:>20} {:>20}",
+      balance.asset, balance.free, balance.locked
+    );
+  }
+
+  let symbol = match opt.all_symbols {
+    true => None,
+    false => Some(symbol.to_uppercase()),
+  };
+  let open_orders = client.current_open_orders(symbol).await?;
+  println!("\nOpen orders: {}", open_orders.len());
+  for order in open_orders {
+    println!(
+      "{} {} {} {} price: {} qty: {} filled: {} status: {} client id: {}",
+      order.symbol,
+      order.order_id,
+      order.side.unwrap_or_default(),
+      order.order_type.unwrap_or_default(),
+      order.price.unwrap_or_default(),
+      order.orig_qty.unwrap_or_default(),
+      order.executed_qty.unwrap_or_default(),
+      order.status.unwrap_or_default(),
+      order.client_order_id
+    );
+  }
+  Ok(())
+}
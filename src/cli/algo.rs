@@ -0,0 +1,178 @@
+use super::trade::{connect, ExecutorOpt, Trader};
+use super::CommonOpt;
+use anyhow::{bail, Result};
+use chrono::{Duration as ChronoDuration, Utc};
+use crossbeam_channel::select;
+use crypto_trading::backtest::load_klines;
+use crypto_trading::binance::api::OrderSide;
+use crypto_trading::binance::data_stream::MarketStream;
+use crypto_trading::binance::websocket::StreamTrade;
+use crypto_trading::execution::{AlgoKind, ExecutionAlgo, ParentOrder, VolumeProfile};
+use crypto_trading::risk::RiskManager;
+use crypto_trading::shared::utils::parse_interval;
+use crypto_trading::strategy::CandleStick;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct AlgoOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// buy or sell
+  #[structopt(long)]
+  pub side: OrderSide,
+  /// Parent order quantity in base asset
+  #[structopt(long)]
+  pub quantity: f64,
+  /// How long to work the order for, e.g. 30m or 2h. Ignored by iceberg
+  #[structopt(long, default_value = "30m")]
+  pub duration: String,
+  /// Lot size step of the symbol, child quantities are rounded down to it
+  #[structopt(long, default_value = "0.00001")]
+  pub step_size: f64,
+  /// Smallest child order quantity
+  #[structopt(long, default_value = "0.00001")]
+  pub min_qty: f64,
+  #[structopt(flatten)]
+  pub executor: ExecutorOpt,
+  #[structopt(subcommand)]
+  pub algo: AlgoKindOpt,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum AlgoKindOpt {
+  /// Equal slices at evenly spaced times
+  Twap {
+    #[structopt(long, default_value = "10")]
+    slices: u32,
+  },
+  /// Slices following the volume profile of recorded klines
+  Vwap {
+    /// Days of klines before today to build the profile from
+    #[structopt(long, default_value = "7")]
+    days: i64,
+    /// Interval of the recorded klines, one profile bucket each
+    #[structopt(long, default_value = "1m")]
+    interval: String,
+  },
+  /// Keep executed quantity at a share of the market volume
+  Pov {
+    /// Share of the volume traded since the start, e.g. 0.1
+    #[structopt(long)]
+    rate: f64,
+  },
+  /// Show one limit order of --display at a time
+  Iceberg {
+    #[structopt(long)]
+    display: f64,
+    #[structopt(long)]
+    price: f64,
+  },
+}
+
+pub async fn run(opt: AlgoOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  if opt.executor.dry_run {
+    // Test orders never fill, the algo would send them again and again
+    bail!("--dry-run can't work an order, use --profile paper instead");
+  }
+  let now = Utc::now().timestamp_millis();
+  let kind = match opt.algo {
+    AlgoKindOpt::Twap { slices } => AlgoKind::Twap { slices },
+    AlgoKindOpt::Vwap { days, interval } => {
+      let today = Utc::now().date_naive();
+      let klines = load_klines(
+        &config.recorder()?.csv_dir,
+        &symbol,
+        &interval,
+        today - ChronoDuration::days(days),
+        today - ChronoDuration::days(1),
+      )?;
+      AlgoKind::Vwap {
+        profile: VolumeProfile::from_klines(&klines)?,
+      }
+    }
+    AlgoKindOpt::Pov { rate } => AlgoKind::Pov {
+      participation: rate,
+    },
+    AlgoKindOpt::Iceberg { display, price } => AlgoKind::Iceberg {
+      display_qty: display,
+      price,
+    },
+  };
+  let parent = ParentOrder {
+    symbol: symbol.to_uppercase(),
+    side: opt.side,
+    quantity: opt.quantity,
+    start_time: now,
+    end_time: now + parse_interval(&opt.duration)?,
+    step_size: opt.step_size,
+    min_qty: opt.min_qty,
+  };
+  let algo = ExecutionAlgo::new(parent, kind, &format!("algo_{}", now))?;
+
+  let (executor, portfolio, user_stream) =
+    connect(&config, std::slice::from_ref(&symbol), &opt.executor).await?;
+  let risk = RiskManager::new(config.risk.clone(), portfolio);
+  let mut trader = Trader::new(&symbol, algo, executor, risk);
+
+  let (sender, receiver) = crossbeam_channel::unbounded();
+  let ws_base = config.market_data(config.profile)?.ws_base.clone();
+  let stream = format!("{}@trade", symbol);
+  tokio::spawn(async move {
+    let market_stream = MarketStream::new(ws_base);
+    market_stream.subscribe(stream, sender).await
+  });
+
+  let mut last_price = None;
+  while !trader.strategy.is_done() {
+    select! {
+      recv(receiver) -> msg => {
+        let msg = match msg {
+          Ok(msg) => msg,
+          Err(_) => break,
+        };
+        let trade = serde_json::from_str::<StreamTrade>(&msg)?;
+        let (price, quantity) = (trade.price.parse::<f64>()?, trade.quantity.parse::<f64>()?);
+        last_price = Some(price);
+        trader.on_market(&trade_candle(&trade, price, quantity)).await;
+        let orders = trader.strategy.on_trade(price, quantity, trade.trade_time);
+        trader.submit(orders, price, trade.trade_time).await;
+      }
+      recv(user_stream) -> msg => {
+        if let Ok(msg) = msg {
+          if let Err(e) = trader.on_user_event(&msg) {
+            log::error!("Failed to handle user stream event: {:#?}", e);
+          }
+        }
+      }
+      // Slices are due even when the market is quiet
+      default(Duration::from_secs(1)) => {
+        if let Some(price) = last_price {
+          let now = Utc::now().timestamp_millis();
+          let orders = trader.strategy.on_timer(now);
+          trader.submit(orders, price, now).await;
+        }
+      }
+    }
+    trader.check_halted().await?;
+  }
+  println!("{}", trader.strategy.report());
+  Ok(())
+}
+
+// A single trade as a candle, to mark the market and match resting
+// paper orders
+fn trade_candle(trade: &StreamTrade, price: f64, quantity: f64) -> CandleStick {
+  CandleStick {
+    symbol: trade.symbol.clone(),
+    open_time: trade.trade_time,
+    open: price,
+    high: price,
+    low: price,
+    close: price,
+    volume: quantity,
+    close_time: trade.trade_time,
+    num_trades: 1,
+  }
+}
@@ -0,0 +1,112 @@
+use super::{parse_date, CommonOpt};
+use anyhow::{bail, ensure, Result};
+use crypto_trading::archive::{
+  archive_header, dedup_rows, read_file, sort_rows, write_file, ArchiveFormat, ConvertStats,
+};
+use crypto_trading::shared::csv_schema::CsvDataType;
+use crypto_trading::shared::reader::csv_path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct ArchiveOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// First day to convert, YYYY-MM-DD
+  #[structopt(long)]
+  pub start: String,
+  /// Last day to convert, YYYY-MM-DD
+  #[structopt(long)]
+  pub end: String,
+  /// Directory to write to, the CSV directory itself normalizes raw files in place
+  #[structopt(long)]
+  pub output: String,
+  /// raw, csv.gz or parquet
+  #[structopt(long, default_value = "parquet")]
+  pub format: ArchiveFormat,
+  /// trade or orderbook, both without it
+  #[structopt(long)]
+  pub data: Option<RecordedData>,
+  /// Directory of the recorded files, defaults to `recorder.csv_dir` in the config
+  #[structopt(long)]
+  pub csv_dir: Option<String>,
+  /// Fail on the first row that doesn't match the schema instead of dropping it
+  #[structopt(long)]
+  pub strict: bool,
+  /// Keep rows repeated within a millisecond
+  #[structopt(long)]
+  pub keep_duplicates: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum RecordedData {
+  Trade,
+  OrderBook,
+}
+
+impl FromStr for RecordedData {
+  type Err = anyhow::Error;
+
+  fn from_str(data: &str) -> Result<Self> {
+    Ok(match data.to_lowercase().as_str() {
+      "trade" | "trades" => RecordedData::Trade,
+      "orderbook" | "orderbooks" => RecordedData::OrderBook,
+      _ => bail!("Unknown data {}, expected trade or orderbook", data),
+    })
+  }
+}
+
+impl From<RecordedData> for CsvDataType {
+  fn from(data: RecordedData) -> Self {
+    match data {
+      RecordedData::Trade => CsvDataType::Trade,
+      RecordedData::OrderBook => CsvDataType::OrderBook,
+    }
+  }
+}
+
+pub fn run(opt: ArchiveOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let csv_dir = match &opt.csv_dir {
+    Some(csv_dir) => csv_dir.clone(),
+    None => config.recorder()?.csv_dir.clone(),
+  };
+  let (start, end) = (parse_date(&opt.start)?, parse_date(&opt.end)?);
+  ensure!(start <= end, "Start date is after end date");
+  std::fs::create_dir_all(&opt.output)?;
+
+  let data = match opt.data {
+    Some(data) => vec![data],
+    None => vec![RecordedData::Trade, RecordedData::OrderBook],
+  };
+  for data in data {
+    let data_type = CsvDataType::from(data);
+    let header = archive_header(&data_type)?;
+    let mut total = ConvertStats::default();
+    let mut files = 0;
+    for date in start.iter_days().take_while(|date| *date <= end) {
+      let input = csv_path(&csv_dir, &symbol, &data_type, date);
+      let mut output = csv_path(&opt.output, &symbol, &data_type, date).into_os_string();
+      output.push(opt.format.extension());
+      let output = PathBuf::from(output);
+      if !input.exists() {
+        log::warn!("Missing {}", input.display());
+        continue;
+      }
+
+      let (mut rows, mut stats) = read_file(&input, &data_type, opt.strict)?;
+      sort_rows(&mut rows);
+      if !opt.keep_duplicates {
+        stats.duplicates = dedup_rows(&mut rows);
+      }
+      write_file(&output, &header, &rows, opt.format)?;
+      stats.written = rows.len();
+      log::info!("{} -> {}: {}", input.display(), output.display(), stats);
+      total.add(&stats);
+      files += 1;
+    }
+    println!("{} {} files: {}", files, String::from(data_type), total);
+  }
+  Ok(())
+}
@@ -0,0 +1,84 @@
+use super::{market_client, parse_date, CommonOpt};
+use anyhow::{anyhow, ensure, Result};
+use chrono::{DateTime, Days, NaiveTime};
+use crypto_trading::binance::api::KlineInput;
+use crypto_trading::shared::{csv_schema::CsvDataType, utils::get_csv_writer};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct BackfillOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// Kline interval to download
+  #[structopt(short, long, default_value = "1d")]
+  pub interval: String,
+  /// First day to download, YYYY-MM-DD
+  #[structopt(long)]
+  pub start: String,
+  /// Last day to download, YYYY-MM-DD
+  #[structopt(long)]
+  pub end: String,
+}
+
+pub async fn run(opt: BackfillOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let csv_dir = config.recorder()?.csv_dir.clone();
+  let start = parse_date(&opt.start)?;
+  let end = parse_date(&opt.end)?;
+  ensure!(start <= end, "Start date is after end date");
+
+  let client = market_client(&config)?;
+  let mut start_time = start.and_time(NaiveTime::MIN).and_utc().timestamp_millis();
+  let end_time = (end + Days::new(1))
+    .and_time(NaiveTime::MIN)
+    .and_utc()
+    .timestamp_millis()
+    - 1;
+
+  let mut written = 0;
+  let mut writer_date = String::new();
+  let mut writer = None;
+  while start_time <= end_time {
+    let klines = client
+      .kline(KlineInput {
+        symbol: symbol.to_uppercase(),
+        interval: opt.interval.clone(),
+        start_time: Some(start_time),
+        end_time: Some(end_time),
+        limit: Some(1000),
+      })
+      .await?;
+    if klines.is_empty() {
+      break;
+    }
+    start_time = klines.last().unwrap().close_time + 1;
+    for kline in klines {
+      // One file per day, same layout as the recorder
+      let date = DateTime::from_timestamp_millis(kline.open_time)
+        .ok_or_else(|| anyhow!("Kline open time {} out of range", kline.open_time))?
+        .format("%Y%m%d")
+        .to_string();
+      if date != writer_date {
+        writer_date = date;
+        writer = Some(get_csv_writer(
+          &csv_dir,
+          &symbol,
+          CsvDataType::Kline(opt.interval.clone()),
+          &writer_date,
+        ));
+      }
+      writer.as_mut().unwrap().serialize(kline)?;
+      written += 1;
+    }
+    if let Some(writer) = writer.as_mut() {
+      writer.flush()?;
+    }
+  }
+  log::info!(
+    "Backfilled {} {} klines of {}",
+    written,
+    opt.interval,
+    symbol
+  );
+  Ok(())
+}
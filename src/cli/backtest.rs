@@ -0,0 +1,110 @@
+use super::trade::StrategyKind;
+use super::{parse_date, CommonOpt};
+use anyhow::{ensure, Result};
+use crypto_trading::backtest::{load_klines, Backtester, SimTrade, SimulatedExchange};
+use crypto_trading::fees::FeeModel;
+use crypto_trading::portfolio::Portfolio;
+use crypto_trading::shared::reader::read_trades;
+use crypto_trading::shared::utils::split_symbol;
+use crypto_trading::strategy::aggregator::{aggregate_trades, BarType};
+use crypto_trading::strategy::CandleStick;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct BacktestOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// turtle or mean-reversion
+  #[structopt(long, default_value = "turtle")]
+  pub strategy: StrategyKind,
+  /// Kline interval of the backfilled data, defaults to the strategy's `interval` in the config
+  #[structopt(short, long)]
+  pub interval: Option<String>,
+  /// Build the candles out of recorded trades instead of backfilled klines, as
+  /// 1s, 5m, volume:10, dollar:1000000 or tick:100 bars
+  #[structopt(long, conflicts_with = "interval")]
+  pub bars: Option<BarType>,
+  #[structopt(flatten)]
+  pub replay: ReplayOpt,
+}
+
+/// Date range and starting balances of a backtest on recorded data
+#[derive(StructOpt, Debug)]
+pub struct ReplayOpt {
+  /// First day to load, YYYY-MM-DD
+  #[structopt(long)]
+  pub start: String,
+  /// Last day to load, YYYY-MM-DD
+  #[structopt(long)]
+  pub end: String,
+  /// Starting base asset balance
+  #[structopt(long, default_value = "0")]
+  pub base_balance: f64,
+  /// Starting quote asset balance
+  #[structopt(long, default_value = "10000")]
+  pub quote_balance: f64,
+  /// Write the results to this CSV file, the simulated trades of a backtest
+  #[structopt(short, long)]
+  pub output: Option<String>,
+}
+
+pub fn run(opt: BacktestOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let csv_dir = &config.recorder()?.csv_dir;
+  let (default_interval, warmup) = opt.strategy.interval_and_warmup(&config);
+  let (start, end) = (parse_date(&opt.replay.start)?, parse_date(&opt.replay.end)?);
+  let mut klines = match &opt.bars {
+    Some(bar_type) => {
+      let trades = read_trades(csv_dir, &symbol, start, end);
+      let candles = aggregate_trades(symbol.to_uppercase(), bar_type.clone(), trades)?;
+      ensure!(
+        candles.len() > warmup,
+        "Only {} bars built, run record first",
+        candles.len()
+      );
+      log::info!("Built {} bars out of recorded trades", candles.len());
+      candles.iter().map(CandleStick::to_kline).collect()
+    }
+    None => {
+      let interval = opt.interval.clone().unwrap_or(default_interval);
+      let klines = load_klines(csv_dir, &symbol, &interval, start, end)?;
+      ensure!(
+        klines.len() > warmup,
+        "Only {} klines found, run backfill first",
+        klines.len()
+      );
+      log::info!("Loaded {} klines", klines.len());
+      klines
+    }
+  };
+
+  let replay = klines.split_off(warmup);
+  let (base_asset, quote_asset) = split_symbol(&symbol)?;
+  let mut portfolio = Portfolio::new(&quote_asset);
+  portfolio.set_balance(&base_asset, opt.replay.base_balance, 0.0);
+  portfolio.set_balance(&quote_asset, opt.replay.quote_balance, 0.0);
+  let strategy = opt.strategy.build(&config, &symbol, klines, portfolio)?;
+  let exchange =
+    SimulatedExchange::new(&symbol, opt.replay.base_balance, opt.replay.quote_balance)?
+      .with_fees(FeeModel::new(&config.fees).rates(&symbol));
+  let candles = replay
+    .iter()
+    .map(|kline| CandleStick::from_kline(symbol.to_uppercase(), kline));
+  let report = Backtester::new(strategy, exchange).run(candles)?;
+  println!("{}", report);
+
+  if let Some(output) = opt.replay.output {
+    write_trades(&output, &report.trades)?;
+  }
+  Ok(())
+}
+
+/// Write simulated trades to a CSV file at `path`
+pub(super) fn write_trades(path: &str, trades: &[SimTrade]) -> Result<()> {
+  let mut writer = csv::Writer::from_path(path)?;
+  for trade in trades {
+    writer.serialize(trade)?;
+  }
+  writer.flush()?;
+  Ok(())
+}
@@ -0,0 +1,57 @@
+use anyhow::Result;
+use crypto_trading::btc_analysis::client::ChainRpc;
+use crypto_trading::shared::config::get_config;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct ChainOpt {
+  /// Path to the TOML config file
+  #[structopt(short, long, default_value = "setting.toml")]
+  pub config: String,
+  /// bitcoind JSON-RPC endpoint, defaults to `bitcoind.url` in the config
+  #[structopt(long)]
+  pub rpc_url: Option<String>,
+  /// Defaults to `bitcoind.user` in the config
+  #[structopt(long)]
+  pub rpc_user: Option<String>,
+  /// Defaults to `bitcoind.password` in the config
+  #[structopt(long)]
+  pub rpc_password: Option<String>,
+  #[structopt(subcommand)]
+  pub query: ChainQuery,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ChainQuery {
+  /// Hash of the tip of the best chain
+  BestBlockHash,
+  /// Block header and transaction ids of a block
+  Block {
+    hash: String,
+    #[structopt(long, default_value = "1")]
+    verbosity: i64,
+  },
+}
+
+pub fn run(opt: ChainOpt) -> Result<()> {
+  let rpc = match (opt.rpc_url, opt.rpc_user, opt.rpc_password) {
+    (Some(url), Some(user), Some(password)) => ChainRpc::new(user, password, url),
+    (url, user, password) => {
+      let config = get_config(&opt.config)?;
+      let bitcoind = config.bitcoind()?;
+      ChainRpc::new(
+        user.unwrap_or_else(|| bitcoind.user.clone()),
+        match password {
+          Some(password) => password,
+          None => bitcoind.password.resolve()?.expose().to_string(),
+        },
+        url.unwrap_or_else(|| bitcoind.url.clone()),
+      )
+    }
+  };
+  match opt.query {
+    ChainQuery::BestBlockHash => println!("{}", rpc.get_best_block_hash()?),
+    ChainQuery::Block { hash, verbosity } => println!("{:#?}", rpc.get_block(hash, verbosity)?),
+  }
+  Ok(())
+}
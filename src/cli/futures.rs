@@ -0,0 +1,213 @@
+use super::{futures_client, CommonOpt};
+use anyhow::{anyhow, bail, Result};
+use crypto_trading::binance::data_stream::MarketStream;
+use crypto_trading::binance::futures::api::{FundingRateInput, MarginType};
+use crypto_trading::binance::futures::client::FuturesClient;
+use crypto_trading::binance::futures::websocket::{
+  agg_trade_stream, force_order_stream, mark_price_stream, AggTrade, ForceOrder, MarkPriceUpdate,
+};
+use crypto_trading::binance::signer::HmacSigner;
+use crypto_trading::shared::config::Setting;
+use crypto_trading::shared::secret::Secret;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub enum FuturesOpt {
+  /// Show open positions, negative amounts are shorts
+  Positions(CommonOpt),
+  /// Show futures wallet balances
+  Balance(CommonOpt),
+  /// Set the initial leverage of --symbol
+  Leverage(LeverageOpt),
+  /// Set the margin type of --symbol to isolated or crossed
+  MarginType(MarginTypeOpt),
+  /// Show the mark price and the current funding rate
+  MarkPrice(CommonOpt),
+  /// Show recent funding rates
+  Funding(FundingOpt),
+  /// Print a market stream: mark-price, force-order or agg-trade
+  Stream(StreamOpt),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct LeverageOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  pub leverage: u32,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct MarginTypeOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  pub margin_type: MarginType,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct FundingOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  #[structopt(long, default_value = "10")]
+  pub limit: u32,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct StreamOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  pub stream: String,
+}
+
+pub async fn run(opt: FuturesOpt) -> Result<()> {
+  match opt {
+    FuturesOpt::Positions(common) => {
+      let (config, symbol) = common.load()?;
+      let positions = futures_client(&config)?
+        .positions(Some(symbol.to_uppercase()))
+        .await?;
+      println!(
+        "{:<12} {:>6} {:>16} {:>16} {:>16} {:>16}",
+        "Symbol", "Side", "Amount", "Entry", "Mark", "Unrealized"
+      );
+      for position in positions {
+        if position.position_amt.parse::<f64>()? == 0.0 {
+          continue;
+        }
+        println!(
+          "{:<12} {:>6} {:>16} {:>16} {:>16} {:>16}",
+          position.symbol,
+          position.position_side,
+          position.position_amt,
+          position.entry_price,
+          position.mark_price,
+          position.un_realized_profit
+        );
+      }
+    }
+    FuturesOpt::Balance(common) => {
+      let (config, _) = common.load()?;
+      println!("{:<10} {:>20} {:>20}", "Asset", "Balance", "Available");
+      for balance in futures_client(&config)?.balances().await? {
+        if balance.balance.parse::<f64>()? == 0.0 {
+          continue;
+        }
+        println!(
+          "{:<10} {:>20} {:>20}",
+          balance.asset, balance.balance, balance.available_balance
+        );
+      }
+    }
+    FuturesOpt::Leverage(LeverageOpt { common, leverage }) => {
+      let (config, symbol) = common.load()?;
+      let res = futures_client(&config)?
+        .change_leverage(symbol.to_uppercase(), leverage)
+        .await?;
+      println!("{:#?}", res);
+    }
+    FuturesOpt::MarginType(MarginTypeOpt {
+      common,
+      margin_type,
+    }) => {
+      let (config, symbol) = common.load()?;
+      futures_client(&config)?
+        .change_margin_type(symbol.to_uppercase(), margin_type)
+        .await?;
+      println!("{} margin type is {:?}", symbol.to_uppercase(), margin_type);
+    }
+    FuturesOpt::MarkPrice(common) => {
+      let (config, symbol) = common.load()?;
+      let res = public_client(&config)?
+        .premium_index(symbol.to_uppercase())
+        .await?;
+      println!("{:#?}", res);
+    }
+    FuturesOpt::Funding(FundingOpt { common, limit }) => {
+      let (config, symbol) = common.load()?;
+      let rates = public_client(&config)?
+        .funding_rate(FundingRateInput {
+          symbol: symbol.to_uppercase(),
+          start_time: None,
+          end_time: None,
+          limit: Some(limit),
+        })
+        .await?;
+      for rate in rates {
+        println!("{} {}", rate.funding_time, rate.funding_rate);
+      }
+    }
+    FuturesOpt::Stream(StreamOpt { common, stream }) => {
+      let (config, symbol) = common.load()?;
+      print_stream(&config, &symbol, &stream).await?;
+    }
+  }
+  Ok(())
+}
+
+/// Client for public futures market data, works without API keys
+fn public_client(config: &Setting) -> Result<FuturesClient> {
+  let exchange = config.market_data(config.profile)?;
+  let host = exchange
+    .futures_host
+    .clone()
+    .ok_or_else(|| anyhow!("No futures_host for {} {}", exchange.name, exchange.profile))?;
+  FuturesClient::new(
+    Secret::default(),
+    Box::new(HmacSigner::new(Secret::default())),
+    host,
+    exchange.proxy.clone(),
+  )
+}
+
+async fn print_stream(config: &Setting, symbol: &str, stream: &str) -> Result<()> {
+  let exchange = config.market_data(config.profile)?;
+  let ws_base = exchange.futures_ws_base.clone().ok_or_else(|| {
+    anyhow!(
+      "No futures_ws_base for {} {}",
+      exchange.name,
+      exchange.profile
+    )
+  })?;
+  let stream_name = match stream {
+    "mark-price" => mark_price_stream(symbol),
+    "force-order" => force_order_stream(symbol),
+    "agg-trade" => agg_trade_stream(symbol),
+    _ => bail!(
+      "Unknown stream {}, expected mark-price, force-order or agg-trade",
+      stream
+    ),
+  };
+
+  let (sender, receiver) = crossbeam_channel::unbounded();
+  tokio::spawn(async move {
+    let market_stream = MarketStream::new(ws_base);
+    market_stream.subscribe(stream_name, sender).await
+  });
+
+  // Liquidations can be minutes apart, so wait until the stream closes
+  while let Ok(msg) = receiver.recv() {
+    match stream {
+      "mark-price" => {
+        let update = serde_json::from_str::<MarkPriceUpdate>(&msg)?;
+        println!(
+          "{} mark {} index {} funding {}",
+          update.event_time, update.mark_price, update.index_price, update.funding_rate
+        );
+      }
+      "force-order" => {
+        let liquidation = serde_json::from_str::<ForceOrder>(&msg)?.order;
+        println!(
+          "{} liquidation {} {} @ {}",
+          liquidation.trade_time, liquidation.side, liquidation.orig_qty, liquidation.avg_price
+        );
+      }
+      _ => {
+        let trade = serde_json::from_str::<AggTrade>(&msg)?;
+        println!(
+          "{} {} @ {} buyer maker {}",
+          trade.trade_time, trade.quantity, trade.price, trade.is_buyer_maker
+        );
+      }
+    }
+  }
+  Ok(())
+}
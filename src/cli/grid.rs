@@ -0,0 +1,89 @@
+use super::backtest::{write_trades, ReplayOpt};
+use super::trade::{connect, run_strategy, ExecutorOpt, Trader};
+use super::{parse_date, CommonOpt};
+use anyhow::{ensure, Result};
+use crypto_trading::backtest::{load_klines, Backtester, SimulatedExchange};
+use crypto_trading::fees::FeeModel;
+use crypto_trading::risk::RiskManager;
+use crypto_trading::shared::utils::split_symbol;
+use crypto_trading::strategy::grid::Grid;
+use crypto_trading::strategy::CandleStick;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct GridOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  #[structopt(subcommand)]
+  pub mode: GridMode,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum GridMode {
+  /// Trade the grid on the `strategy.grid.interval` kline stream
+  Live(ExecutorOpt),
+  /// Replay backfilled klines of `strategy.grid.interval`
+  Backtest(ReplayOpt),
+}
+
+pub async fn run(opt: GridOpt) -> Result<()> {
+  match opt.mode {
+    GridMode::Live(executor) => live(opt.common, executor).await,
+    GridMode::Backtest(backtest) => replay(opt.common, backtest),
+  }
+}
+
+async fn live(common: CommonOpt, opt: ExecutorOpt) -> Result<()> {
+  let (config, symbol) = common.load()?;
+  let setting = config.strategy.grid.clone();
+  let stream = format!("{}@kline_{}", symbol, setting.interval);
+  let (executor, portfolio, user_stream) =
+    connect(&config, std::slice::from_ref(&symbol), &opt).await?;
+  let mut grid = Grid::new(&symbol, setting)?;
+  // Futures sells open shorts, spot ones need the base asset
+  if !opt.futures {
+    let (base_asset, _) = split_symbol(&symbol)?;
+    grid = grid.with_inventory(portfolio.balance(&base_asset).free);
+  }
+  let risk = RiskManager::new(config.risk.clone(), portfolio);
+  let mut trader = Trader::new(&symbol, grid, executor, risk);
+
+  let ws_base = config.market_data(config.profile)?.ws_base.clone();
+  run_strategy(ws_base, stream, user_stream, &mut trader).await?;
+  // Nothing tracks the ladder once the strategy is gone
+  trader.kill().await;
+  log::info!(
+    "Grid profit: {} over {} round trips",
+    trader.strategy.grid_profit(),
+    trader.strategy.round_trips()
+  );
+  Ok(())
+}
+
+fn replay(common: CommonOpt, opt: ReplayOpt) -> Result<()> {
+  let (config, symbol) = common.load()?;
+  let setting = config.strategy.grid.clone();
+  let klines = load_klines(
+    &config.recorder()?.csv_dir,
+    &symbol,
+    &setting.interval,
+    parse_date(&opt.start)?,
+    parse_date(&opt.end)?,
+  )?;
+  ensure!(!klines.is_empty(), "No klines found, run backfill first");
+  log::info!("Loaded {} klines", klines.len());
+
+  let grid = Grid::new(&symbol, setting)?.with_inventory(opt.base_balance);
+  let exchange = SimulatedExchange::new(&symbol, opt.base_balance, opt.quote_balance)?
+    .with_fees(FeeModel::new(&config.fees).rates(&symbol));
+  let candles = klines
+    .iter()
+    .map(|kline| CandleStick::from_kline(symbol.to_uppercase(), kline));
+  let report = Backtester::new(grid, exchange).run(candles)?;
+  println!("{}", report);
+
+  if let Some(output) = opt.output {
+    write_trades(&output, &report.trades)?;
+  }
+  Ok(())
+}
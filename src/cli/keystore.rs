@@ -0,0 +1,94 @@
+use anyhow::{ensure, Context, Result};
+use crypto_trading::shared::secret::{read_passphrase, Keystore, Secret};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Read};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub enum KeystoreOpt {
+  /// Create an empty keystore protected by a new passphrase
+  Init { path: String },
+  /// Add or replace an entry, the secret is read from stdin or `--file`
+  /// until the end, so multi-line PEM keys go in whole
+  Set {
+    path: String,
+    entry: String,
+    /// Read the secret from this file instead of stdin
+    #[structopt(long)]
+    file: Option<String>,
+  },
+  /// Delete an entry
+  Remove { path: String, entry: String },
+  /// List entry names without their secrets
+  List { path: String },
+}
+
+pub fn run(opt: KeystoreOpt) -> Result<()> {
+  match opt {
+    KeystoreOpt::Init { path } => {
+      ensure!(
+        !std::path::Path::new(&path).exists(),
+        "{} already exists",
+        path
+      );
+      let passphrase = read_passphrase(&path)?;
+      let confirm = Secret::new(rpassword::read_password_from_tty(Some(
+        "Confirm passphrase: ",
+      ))?);
+      ensure!(
+        passphrase.expose() == confirm.expose(),
+        "Passphrases don't match"
+      );
+      Keystore::encrypt(&BTreeMap::new(), &passphrase)?.save(&path)?;
+      println!("Created keystore {}", path);
+    }
+    KeystoreOpt::Set { path, entry, file } => {
+      let passphrase = read_passphrase(&path)?;
+      let mut entries = Keystore::load(&path)?.decrypt(&passphrase)?;
+      let secret = read_secret(&entry, file.as_deref())?;
+      ensure!(!secret.is_empty(), "Secret can't be empty");
+      entries.insert(entry, secret);
+      Keystore::encrypt(&entries, &passphrase)?.save(&path)?;
+    }
+    KeystoreOpt::Remove { path, entry } => {
+      let passphrase = read_passphrase(&path)?;
+      let mut entries = Keystore::load(&path)?.decrypt(&passphrase)?;
+      ensure!(
+        entries.remove(&entry).is_some(),
+        "Keystore has no entry {}",
+        entry
+      );
+      Keystore::encrypt(&entries, &passphrase)?.save(&path)?;
+    }
+    KeystoreOpt::List { path } => {
+      let passphrase = read_passphrase(&path)?;
+      for entry in Keystore::load(&path)?.decrypt(&passphrase)?.keys() {
+        println!("{}", entry);
+      }
+    }
+  }
+  Ok(())
+}
+
+// Whole secret of `file` or stdin, a single line loses its line ending as
+// `echo` and terminals add one
+fn read_secret(entry: &str, file: Option<&str>) -> Result<Secret> {
+  let mut secret = match file {
+    Some(file) => {
+      std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?
+    }
+    None => {
+      let mut stdin = std::io::stdin();
+      if stdin.is_terminal() {
+        eprintln!("Secret for {}, end with Ctrl-D:", entry);
+      }
+      let mut secret = String::new();
+      stdin.read_to_string(&mut secret)?;
+      secret
+    }
+  };
+  if secret.trim_end().lines().count() <= 1 {
+    secret.truncate(secret.trim_end_matches(&['\r', '\n'][..]).len());
+  }
+  Ok(Secret::new(secret))
+}
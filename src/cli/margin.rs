@@ -0,0 +1,122 @@
+use super::{binance_client, CommonOpt};
+use anyhow::Result;
+use crypto_trading::binance::api::InterestHistoryInput;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub enum MarginOpt {
+  /// Show the margin level and non-zero cross margin assets
+  Account(CommonOpt),
+  /// Borrow an asset on the cross margin account
+  Borrow(LoanOpt),
+  /// Repay a cross margin loan
+  Repay(LoanOpt),
+  /// Show how much of an asset can still be borrowed
+  MaxBorrowable(AssetOpt),
+  /// Show interest charged on cross margin loans
+  Interest(InterestOpt),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct LoanOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// Asset such as btc or usdt
+  pub asset: String,
+  pub amount: f64,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct AssetOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  pub asset: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct InterestOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// Only show interest of this asset
+  #[structopt(long)]
+  pub asset: Option<String>,
+  #[structopt(long, default_value = "10")]
+  pub size: u32,
+}
+
+pub async fn run(opt: MarginOpt) -> Result<()> {
+  match opt {
+    MarginOpt::Account(common) => {
+      let (config, _) = common.load()?;
+      let account = binance_client(&config)?.margin_account().await?;
+      println!(
+        "Margin level: {} Net asset: {} BTC Liability: {} BTC",
+        account.margin_level, account.total_net_asset_of_btc, account.total_liability_of_btc
+      );
+      println!(
+        "{:<10} {:>16} {:>16} {:>16} {:>16}",
+        "Asset", "Free", "Borrowed", "Interest", "Net"
+      );
+      for asset in &account.user_assets {
+        if asset.net_asset.parse::<f64>()? == 0.0 && asset.borrowed.parse::<f64>()? == 0.0 {
+          continue;
+        }
+        println!(
+          "{:<10} {:>16} {:>16} {:>16} {:>16}",
+          asset.asset, asset.free, asset.borrowed, asset.interest, asset.net_asset
+        );
+      }
+    }
+    MarginOpt::Borrow(opt) => {
+      let (config, _) = opt.common.load()?;
+      let res = binance_client(&config)?
+        .margin_borrow(opt.asset.to_uppercase(), opt.amount)
+        .await?;
+      println!(
+        "Borrowed {} {}, tran id {}",
+        opt.amount, opt.asset, res.tran_id
+      );
+    }
+    MarginOpt::Repay(opt) => {
+      let (config, _) = opt.common.load()?;
+      let res = binance_client(&config)?
+        .margin_repay(opt.asset.to_uppercase(), opt.amount)
+        .await?;
+      println!(
+        "Repaid {} {}, tran id {}",
+        opt.amount, opt.asset, res.tran_id
+      );
+    }
+    MarginOpt::MaxBorrowable(opt) => {
+      let (config, _) = opt.common.load()?;
+      let res = binance_client(&config)?
+        .margin_max_borrowable(opt.asset.to_uppercase())
+        .await?;
+      println!(
+        "{} {} (limit {})",
+        res.amount,
+        opt.asset.to_uppercase(),
+        res.borrow_limit
+      );
+    }
+    MarginOpt::Interest(opt) => {
+      let (config, _) = opt.common.load()?;
+      let res = binance_client(&config)?
+        .margin_interest_history(InterestHistoryInput {
+          asset: opt.asset.map(|asset| asset.to_uppercase()),
+          start_time: None,
+          end_time: None,
+          current: None,
+          size: Some(opt.size),
+        })
+        .await?;
+      for row in res.rows {
+        println!(
+          "{} {} {} on {} at {}",
+          row.interest_accured_time, row.interest, row.asset, row.principal, row.interest_rate
+        );
+      }
+    }
+  }
+  Ok(())
+}
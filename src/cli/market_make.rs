@@ -0,0 +1,141 @@
+use super::backtest::{write_trades, ReplayOpt};
+use super::trade::{connect, ExecutorOpt, Trader};
+use super::{parse_date, CommonOpt};
+use anyhow::{ensure, Result};
+use chrono::Utc;
+use crossbeam_channel::select;
+use crypto_trading::backtest::{load_orderbooks, Backtester, SimulatedExchange};
+use crypto_trading::binance::data_stream::MarketStream;
+use crypto_trading::binance::websocket::StreamOrderbook;
+use crypto_trading::fees::FeeModel;
+use crypto_trading::risk::RiskManager;
+use crypto_trading::shared::reader::{merge_events, read_trades};
+use crypto_trading::shared::utils::split_symbol;
+use crypto_trading::strategy::market_maker::MarketMaker;
+use crypto_trading::strategy::orderbook::OrderBook;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct MarketMakeOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  #[structopt(subcommand)]
+  pub mode: MarketMakeMode,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum MarketMakeMode {
+  /// Quote on the 10 level depth stream
+  Live(ExecutorOpt),
+  /// Replay the orderbook snapshots written by `record`
+  Backtest(BookReplayOpt),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct BookReplayOpt {
+  #[structopt(flatten)]
+  pub replay: ReplayOpt,
+  /// Walk the book, queue on recorded trades and delay orders as set in `[depth]`,
+  /// instead of filling at the touch
+  #[structopt(long)]
+  pub depth: bool,
+  /// Latency of orders and cancels with --depth, defaults to `depth.latency_ms`
+  #[structopt(long)]
+  pub latency_ms: Option<i64>,
+}
+
+pub async fn run(opt: MarketMakeOpt) -> Result<()> {
+  match opt.mode {
+    MarketMakeMode::Live(executor) => live(opt.common, executor).await,
+    MarketMakeMode::Backtest(backtest) => replay(opt.common, backtest),
+  }
+}
+
+async fn live(common: CommonOpt, opt: ExecutorOpt) -> Result<()> {
+  let (config, symbol) = common.load()?;
+  let mut market_maker = MarketMaker::new(&symbol, config.strategy.market_maker.clone());
+  let (executor, portfolio, user_stream) =
+    connect(&config, std::slice::from_ref(&symbol), &opt).await?;
+  // Futures asks open shorts, spot ones need the base asset
+  if !opt.futures {
+    let (base_asset, _) = split_symbol(&symbol)?;
+    market_maker = market_maker.with_inventory(portfolio.balance(&base_asset).free);
+  }
+  let risk = RiskManager::new(config.risk.clone(), portfolio);
+  let mut trader = Trader::new(&symbol, market_maker, executor, risk);
+
+  let (sender, receiver) = crossbeam_channel::unbounded();
+  let ws_base = config.market_data(config.profile)?.ws_base.clone();
+  let stream = format!("{}@depth10@100ms", symbol);
+  tokio::spawn(async move {
+    let market_stream = MarketStream::new(ws_base);
+    market_stream.subscribe(stream, sender).await
+  });
+
+  loop {
+    select! {
+      recv(receiver) -> msg => {
+        let msg = match msg {
+          Ok(msg) => msg,
+          Err(_) => break,
+        };
+        let book = serde_json::from_str::<StreamOrderbook>(&msg)?;
+        let book = OrderBook::from_stream(&symbol, Utc::now().timestamp_millis(), &book)?;
+        trader.on_book(&book).await;
+      }
+      recv(user_stream) -> msg => {
+        if let Ok(msg) = msg {
+          if let Err(e) = trader.on_user_event(&msg) {
+            log::error!("Failed to handle user stream event: {:#?}", e);
+          }
+        }
+      }
+      default(Duration::new(5, 0)) => break,
+    }
+    trader.check_halted().await?;
+  }
+  // Quotes left behind would keep trading without the strategy
+  trader.kill().await;
+  log::info!("Inventory: {}", trader.strategy.inventory());
+  Ok(())
+}
+
+fn replay(common: CommonOpt, opt: BookReplayOpt) -> Result<()> {
+  let (config, symbol) = common.load()?;
+  let (start, end) = (parse_date(&opt.replay.start)?, parse_date(&opt.replay.end)?);
+  let csv_dir = &config.recorder()?.csv_dir;
+  let books = load_orderbooks(csv_dir, &symbol, start, end)?;
+  ensure!(
+    !books.is_empty(),
+    "No orderbook snapshots found, run record first"
+  );
+  let snapshots = books.len();
+  log::info!("Loaded {} orderbook snapshots", snapshots);
+
+  let market_maker = MarketMaker::new(&symbol, config.strategy.market_maker.clone())
+    .with_inventory(opt.replay.base_balance);
+  let exchange =
+    SimulatedExchange::new(&symbol, opt.replay.base_balance, opt.replay.quote_balance)?
+      .with_fees(FeeModel::new(&config.fees).rates(&symbol));
+  let backtester = Backtester::new(market_maker, exchange);
+  let report = if opt.depth {
+    let trades = read_trades(csv_dir, &symbol, start, end);
+    let mut setting = config.depth.clone();
+    if let Some(latency_ms) = opt.latency_ms {
+      ensure!(latency_ms >= 0, "--latency-ms can't be negative");
+      setting.latency_ms = latency_ms;
+    }
+    let events = merge_events(books.into_iter().map(Ok), trades).collect::<Result<Vec<_>>>()?;
+    log::info!("Merged {} trades", events.len() - snapshots);
+    backtester.run_depth(events, setting)?
+  } else {
+    backtester.run_books(books)?
+  };
+  println!("{}", report);
+
+  if let Some(output) = opt.replay.output {
+    write_trades(&output, &report.trades)?;
+  }
+  Ok(())
+}
@@ -0,0 +1,218 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use crypto_trading::binance::client::Client;
+use crypto_trading::binance::futures::client::FuturesClient;
+use crypto_trading::binance::signer::{new_signer, HmacSigner};
+use crypto_trading::binance::ws_api::WsApiClient;
+use crypto_trading::fees::FeeModel;
+use crypto_trading::shared::config::{get_config, Profile, Setting};
+use crypto_trading::shared::secret::Secret;
+use std::time::Duration;
+use structopt::StructOpt;
+
+pub mod account;
+pub mod algo;
+pub mod archive;
+pub mod backfill;
+pub mod backtest;
+pub mod chain;
+pub mod futures;
+pub mod grid;
+pub mod keystore;
+pub mod margin;
+pub mod market_make;
+pub mod montecarlo;
+pub mod optimize;
+pub mod order;
+pub mod pairs;
+pub mod record;
+pub mod spread;
+pub mod trade;
+pub mod triangle;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+  name = "crypto_trading",
+  about = "Binance market data recorder and trading bot"
+)]
+pub enum Command {
+  /// Record the trade stream and 10 level orderbook snapshots to daily CSV files
+  Record(record::RecordOpt),
+  /// Record the basis between Binance and another venue to daily CSV files
+  Spread(spread::SpreadOpt),
+  /// Run a candle strategy on mainnet, the spot testnet or paper traded
+  Trade(trade::TradeOpt),
+  /// Work a parent order with TWAP, VWAP, POV or iceberg child orders
+  Algo(algo::AlgoOpt),
+  /// Quote both sides of the book with the market maker, live or on recorded books
+  MarketMake(market_make::MarketMakeOpt),
+  /// Run a ladder of limit orders between two prices, live or on backfilled klines
+  Grid(grid::GridOpt),
+  /// Trade the spread between two symbols, live or on backfilled klines
+  Pairs(pairs::PairsOpt),
+  /// Watch bookTicker streams for triangular arbitrage, trading it with --execute
+  Triangle(triangle::TriangleOpt),
+  /// Replay backfilled klines through a candle strategy
+  Backtest(backtest::BacktestOpt),
+  /// Search turtle parameters on backfilled klines, optionally walk-forward
+  Optimize(optimize::OptimizeOpt),
+  /// Distributions of backtest results over resampled trades or synthetic klines
+  MonteCarlo(montecarlo::MonteCarloOpt),
+  /// Download historical klines into the CSV directory
+  Backfill(backfill::BackfillOpt),
+  /// Sort, deduplicate and validate recorded files into Parquet, gzipped or raw CSV
+  Archive(archive::ArchiveOpt),
+  /// Show account balances and open orders
+  Account(account::AccountOpt),
+  /// Place or cancel an order manually
+  Order(order::OrderOpt),
+  /// USD-M futures positions, leverage, margin type, funding and streams
+  Futures(futures::FuturesOpt),
+  /// Cross margin account, loans and interest
+  Margin(margin::MarginOpt),
+  /// Query a bitcoind node over JSON-RPC
+  Chain(chain::ChainOpt),
+  /// Create and edit the encrypted keystore holding API secrets
+  Keystore(keystore::KeystoreOpt),
+}
+
+/// Flags shared by every subcommand
+#[derive(StructOpt, Debug)]
+pub struct CommonOpt {
+  /// Path to the TOML config file
+  #[structopt(short, long, default_value = "setting.toml")]
+  pub config: String,
+  /// Trading pair such as btcusdt, defaults to `symbol` in the config
+  #[structopt(short, long)]
+  pub symbol: Option<String>,
+  /// mainnet (or live), testnet or paper, defaults to `profile` in the config
+  #[structopt(long)]
+  pub profile: Option<Profile>,
+}
+
+impl CommonOpt {
+  pub fn load(&self) -> Result<(Setting, String)> {
+    let mut config = get_config(&self.config)?;
+    if let Some(profile) = self.profile {
+      config.profile = profile;
+      config.validate()?;
+    }
+    let symbol = self
+      .symbol
+      .clone()
+      .unwrap_or_else(|| config.symbol.clone())
+      .to_lowercase();
+    Ok((config, symbol))
+  }
+}
+
+/// REST client for the account of the selected profile
+pub fn binance_client(config: &Setting) -> Result<Client> {
+  let exchange = config.exchange(config.profile)?;
+  let (api_key, api_secret) = exchange.credentials()?;
+  Client::new(
+    api_key,
+    new_signer(exchange.key_type, api_secret)?,
+    exchange.host.clone(),
+    exchange.proxy.clone(),
+  )
+}
+
+/// USD-M futures client for the account of the selected profile
+pub fn futures_client(config: &Setting) -> Result<FuturesClient> {
+  let exchange = config.exchange(config.profile)?;
+  let host = exchange
+    .futures_host
+    .clone()
+    .ok_or_else(|| anyhow!("No futures_host for {} {}", exchange.name, exchange.profile))?;
+  let (api_key, api_secret) = exchange.credentials()?;
+  FuturesClient::new(
+    api_key,
+    new_signer(exchange.key_type, api_secret)?,
+    host,
+    exchange.proxy.clone(),
+  )
+}
+
+/// WebSocket API client for the account of the selected profile
+pub async fn ws_api_client(config: &Setting) -> Result<WsApiClient> {
+  let exchange = config.exchange(config.profile)?;
+  let endpoint = exchange.ws_api.as_ref().ok_or_else(|| {
+    anyhow!(
+      "No ws_api endpoint for {} {}",
+      exchange.name,
+      exchange.profile
+    )
+  })?;
+  let (api_key, api_secret) = exchange.credentials()?;
+  WsApiClient::connect(
+    endpoint,
+    api_key,
+    new_signer(exchange.key_type, api_secret)?,
+    Duration::from_secs(10),
+  )
+  .await
+}
+
+/// REST client for public market data, works without API keys
+pub fn market_client(config: &Setting) -> Result<Client> {
+  let exchange = config.market_data(config.profile)?;
+  Client::new(
+    Secret::default(),
+    Box::new(HmacSigner::new(Secret::default())),
+    exchange.host.clone(),
+    exchange.proxy.clone(),
+  )
+}
+
+/// Fee model of the config, with the account's own rates for `symbols`
+/// if `fees.from_account` is set and the profile has an account
+pub async fn fee_model(config: &Setting, symbols: &[String]) -> Result<FeeModel> {
+  let mut fees = FeeModel::new(&config.fees);
+  if config.fees.from_account && config.profile != Profile::Paper {
+    let client = binance_client(config)?;
+    fees.set_account(&client.spot_account_info().await?);
+    for symbol in symbols {
+      fees.set_commission(&client.account_commission(symbol.to_uppercase()).await?)?;
+    }
+  }
+  for symbol in symbols {
+    let rates = fees.rates(symbol);
+    log::info!(
+      "{} fees: maker {:.2} bps, taker {:.2} bps",
+      symbol.to_uppercase(),
+      rates.maker * 1e4,
+      rates.taker * 1e4
+    );
+  }
+  Ok(fees)
+}
+
+/// Parse `YYYY-MM-DD` command line dates
+pub fn parse_date(date: &str) -> Result<NaiveDate> {
+  Ok(NaiveDate::parse_from_str(date, "%Y-%m-%d")?)
+}
+
+pub async fn run(command: Command) -> Result<()> {
+  match command {
+    Command::Record(opt) => record::run(opt).await,
+    Command::Spread(opt) => spread::run(opt).await,
+    Command::Trade(opt) => trade::run(opt).await,
+    Command::Algo(opt) => algo::run(opt).await,
+    Command::MarketMake(opt) => market_make::run(opt).await,
+    Command::Grid(opt) => grid::run(opt).await,
+    Command::Pairs(opt) => pairs::run(opt).await,
+    Command::Triangle(opt) => triangle::run(opt).await,
+    Command::Backtest(opt) => backtest::run(opt),
+    Command::Optimize(opt) => optimize::run(opt),
+    Command::MonteCarlo(opt) => montecarlo::run(opt),
+    Command::Backfill(opt) => backfill::run(opt).await,
+    Command::Archive(opt) => archive::run(opt),
+    Command::Account(opt) => account::run(opt).await,
+    Command::Order(opt) => order::run(opt).await,
+    Command::Futures(opt) => futures::run(opt).await,
+    Command::Margin(opt) => margin::run(opt).await,
+    Command::Chain(opt) => chain::run(opt),
+    Command::Keystore(opt) => keystore::run(opt),
+  }
+}
@@ -0,0 +1,210 @@
+use super::backtest::ReplayOpt;
+use super::trade::StrategyKind;
+use super::{parse_date, CommonOpt};
+use anyhow::{ensure, Context, Result};
+use crypto_trading::backtest::{load_klines, Backtester, SimTrade, SimulatedExchange};
+use crypto_trading::binance::api::KlineResp;
+use crypto_trading::fees::FeeModel;
+use crypto_trading::montecarlo::{
+  block_bootstrap, bootstrap_trades, closed_trades, shuffle_trades, trades_per_year, Distribution,
+  PathStats,
+};
+use crypto_trading::optimize::par_map;
+use crypto_trading::portfolio::Portfolio;
+use crypto_trading::shared::config::Setting;
+use crypto_trading::shared::utils::split_symbol;
+use crypto_trading::strategy::CandleStick;
+use rand::{rngs::StdRng, SeedableRng};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub enum MonteCarloOpt {
+  /// Resample and reorder the closed trades of a backtest's trade CSV
+  Trades(TradesOpt),
+  /// Rerun a strategy on block-bootstrapped synthetic klines
+  Paths(PathsOpt),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TradesOpt {
+  /// Trade CSV written by backtest --output
+  #[structopt(long)]
+  pub trades: String,
+  /// Quote asset equity the trades started from
+  #[structopt(long, default_value = "10000")]
+  pub initial_equity: f64,
+  #[structopt(long, default_value = "1000")]
+  pub runs: usize,
+  /// Seed of the resampling, drawn at random without it
+  #[structopt(long)]
+  pub seed: Option<u64>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PathsOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// turtle or mean-reversion
+  #[structopt(long, default_value = "turtle")]
+  pub strategy: StrategyKind,
+  /// Kline interval of the backfilled data, defaults to the strategy's `interval` in the config
+  #[structopt(short, long)]
+  pub interval: Option<String>,
+  #[structopt(flatten)]
+  pub replay: ReplayOpt,
+  #[structopt(long, default_value = "200")]
+  pub runs: usize,
+  /// Consecutive historical klines in each bootstrapped block
+  #[structopt(long, default_value = "20")]
+  pub block: usize,
+  /// Seed of the first path, the others count up from it
+  #[structopt(long)]
+  pub seed: Option<u64>,
+  /// Threads to backtest on, defaults to the number of cores
+  #[structopt(long)]
+  pub threads: Option<usize>,
+}
+
+pub fn run(opt: MonteCarloOpt) -> Result<()> {
+  match opt {
+    MonteCarloOpt::Trades(opt) => run_trades(opt),
+    MonteCarloOpt::Paths(opt) => run_paths(opt),
+  }
+}
+
+fn run_trades(opt: TradesOpt) -> Result<()> {
+  let mut reader = csv::Reader::from_path(&opt.trades)
+    .with_context(|| format!("Failed to open {}", opt.trades))?;
+  let trades = reader.deserialize().collect::<Result<Vec<SimTrade>, _>>()?;
+  let closed = closed_trades(&trades);
+  ensure!(
+    closed.len() > 1,
+    "{} closes {} positions, Monte Carlo needs at least 2",
+    opt.trades,
+    closed.len()
+  );
+  let pnls = closed.iter().map(|trade| trade.pnl).collect::<Vec<_>>();
+  let per_year = trades_per_year(&closed);
+  let seed = opt.seed.unwrap_or_else(rand::random);
+  log::info!(
+    "Resampling {} closed trades of {} fills with seed {}",
+    closed.len(),
+    trades.len(),
+    seed
+  );
+  let mut rng = StdRng::seed_from_u64(seed);
+
+  let historical = PathStats::of_pnls(&pnls, opt.initial_equity, per_year);
+  println!("Historical order");
+  print_path(&historical);
+  println!("\nBootstrapped trades");
+  let bootstrapped = bootstrap_trades(&pnls, opt.initial_equity, per_year, opt.runs, &mut rng);
+  println!("{}", Distribution::of(&bootstrapped));
+  println!("\nShuffled trade order");
+  let shuffled = shuffle_trades(&pnls, opt.initial_equity, per_year, opt.runs, &mut rng);
+  println!("{}", Distribution::of(&shuffled));
+  Ok(())
+}
+
+fn run_paths(opt: PathsOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let (default_interval, warmup) = opt.strategy.interval_and_warmup(&config);
+  let interval = opt.interval.clone().unwrap_or(default_interval);
+  let mut klines = load_klines(
+    &config.recorder()?.csv_dir,
+    &symbol,
+    &interval,
+    parse_date(&opt.replay.start)?,
+    parse_date(&opt.replay.end)?,
+  )?;
+  ensure!(
+    klines.len() > warmup + 1,
+    "Only {} klines found, run backfill first",
+    klines.len()
+  );
+  let replay = klines.split_off(warmup);
+  let threads = opt
+    .threads
+    .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |threads| threads.get()));
+  let seed = opt.seed.unwrap_or_else(rand::random);
+  log::info!(
+    "Backtesting {} synthetic paths of {} klines with seed {} on {} threads",
+    opt.runs,
+    replay.len(),
+    seed,
+    threads
+  );
+
+  let historical = backtest(&opt, &config, &symbol, &klines, &replay)?;
+  println!("Historical klines");
+  print_path(&historical);
+
+  // Every path warms up on the real klines and continues from their last close
+  let start_close = klines[warmup - 1].close;
+  let start_time = replay[0].open_time;
+  let runs = (0..opt.runs as u64).collect::<Vec<_>>();
+  let results = par_map(&runs, threads, |run| {
+    let mut rng = StdRng::seed_from_u64(seed.wrapping_add(*run));
+    let path = block_bootstrap(
+      &replay,
+      opt.block,
+      replay.len(),
+      start_close,
+      start_time,
+      &mut rng,
+    );
+    backtest(&opt, &config, &symbol, &klines, &path)
+  });
+  let mut paths = vec![];
+  for (run, result) in runs.iter().zip(results) {
+    match result {
+      Ok(path) => paths.push(path),
+      Err(e) => log::warn!("Path {} failed: {:#}", run, e),
+    }
+  }
+  ensure!(!paths.is_empty(), "Every synthetic path failed");
+  println!("\nBlock-bootstrapped paths of {} klines", opt.block);
+  println!("{}", Distribution::of(&paths));
+
+  if let Some(output) = &opt.replay.output {
+    let mut writer = csv::Writer::from_path(output)?;
+    for path in &paths {
+      writer.serialize(path)?;
+    }
+    writer.flush()?;
+  }
+  Ok(())
+}
+
+// Backtest the strategy warmed up on `warmup` over `replay`
+fn backtest(
+  opt: &PathsOpt,
+  config: &Setting,
+  symbol: &str,
+  warmup: &[KlineResp],
+  replay: &[KlineResp],
+) -> Result<PathStats> {
+  let (base_asset, quote_asset) = split_symbol(symbol)?;
+  let mut portfolio = Portfolio::new(&quote_asset);
+  portfolio.set_balance(&base_asset, opt.replay.base_balance, 0.0);
+  portfolio.set_balance(&quote_asset, opt.replay.quote_balance, 0.0);
+  let strategy = opt
+    .strategy
+    .build(config, symbol, warmup.to_vec(), portfolio)?;
+  let exchange = SimulatedExchange::new(symbol, opt.replay.base_balance, opt.replay.quote_balance)?
+    .with_fees(FeeModel::new(&config.fees).rates(symbol));
+  let candles = replay
+    .iter()
+    .map(|kline| CandleStick::from_kline(symbol.to_uppercase(), kline));
+  let report = Backtester::new(strategy, exchange).run(candles)?;
+  Ok(PathStats::from(&report))
+}
+
+fn print_path(path: &PathStats) {
+  println!(
+    "Final equity {:.4}, max drawdown {:.2}%, Sharpe ratio {:.4}",
+    path.final_equity,
+    path.max_drawdown * 100.0,
+    path.sharpe
+  );
+}
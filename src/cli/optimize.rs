@@ -0,0 +1,250 @@
+use super::backtest::ReplayOpt;
+use super::{parse_date, CommonOpt};
+use anyhow::{bail, ensure, Result};
+use crypto_trading::backtest::load_klines;
+use crypto_trading::fees::FeeModel;
+use crypto_trading::optimize::{
+  parse_counts, parse_values, sweep, walk_forward, walk_forward_folds, Evaluation, Metric,
+  ParamSpace, ResultRow, TurtleBacktest,
+};
+use crypto_trading::shared::config::TurtleParams;
+use rand::{rngs::StdRng, SeedableRng};
+use structopt::StructOpt;
+
+/// Parameters are searched over a list like `-p breakout_window=10,20,55`
+/// or a range like `-p stop_n=1:3:0.5`, the others stay at their
+/// `strategy.turtle.params` value
+#[derive(StructOpt, Debug)]
+pub struct OptimizeOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// Kline interval of the backfilled data, defaults to `strategy.turtle.interval`
+  #[structopt(short, long)]
+  pub interval: Option<String>,
+  #[structopt(flatten)]
+  pub replay: ReplayOpt,
+  /// Values of a parameter as name=values, repeated for each one searched
+  #[structopt(short, long = "param")]
+  pub params: Vec<String>,
+  /// Backtest this many random combinations instead of every one
+  #[structopt(long)]
+  pub random: Option<usize>,
+  /// Seed of the random search, drawn at random without it
+  #[structopt(long)]
+  pub seed: Option<u64>,
+  /// return, sharpe, calmar or drawdown
+  #[structopt(long, default_value = "sharpe")]
+  pub metric: Metric,
+  /// In-sample klines of each walk-forward fold, the whole range is one sample without it
+  #[structopt(long)]
+  pub train: Option<usize>,
+  /// Out-of-sample klines of each walk-forward fold, defaults to a quarter of --train
+  #[structopt(long)]
+  pub test: Option<usize>,
+  /// Threads to backtest on, defaults to the number of cores
+  #[structopt(long)]
+  pub threads: Option<usize>,
+  /// Rows of the ranking to print
+  #[structopt(long, default_value = "10")]
+  pub top: usize,
+}
+
+impl OptimizeOpt {
+  fn space(&self, params: &TurtleParams) -> Result<ParamSpace> {
+    let mut space = ParamSpace::fixed(params);
+    for param in &self.params {
+      let (name, spec) = match param.split_once('=') {
+        Some((name, spec)) => (name.trim(), spec.trim()),
+        None => bail!("--param {} needs to be name=values", param),
+      };
+      match name {
+        "breakout_window" => space.breakout_window = parse_counts(spec)?,
+        "atr_period" => space.atr_period = parse_counts(spec)?,
+        "stop_n" => space.stop_n = parse_values(spec)?,
+        "max_units" => space.max_units = parse_counts(spec)?,
+        "take_profit" => space.take_profit = parse_values(spec)?,
+        _ => bail!(
+          "Unknown parameter {}, expected breakout_window, atr_period, stop_n, max_units or take_profit",
+          name
+        ),
+      }
+    }
+    Ok(space)
+  }
+}
+
+pub fn run(opt: OptimizeOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let setting = &config.strategy.turtle;
+  let space = opt.space(&setting.params)?;
+  let candidates = match opt.random {
+    Some(count) => {
+      let seed = opt.seed.unwrap_or_else(rand::random);
+      log::info!("Drawing {} combinations with seed {}", count, seed);
+      space.sample(count, &mut StdRng::seed_from_u64(seed))
+    }
+    None => space.grid(),
+  };
+  ensure!(!candidates.is_empty(), "No valid parameter combination");
+  let threads = opt
+    .threads
+    .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |threads| threads.get()));
+
+  let interval = opt
+    .interval
+    .clone()
+    .unwrap_or_else(|| setting.interval.clone());
+  let klines = load_klines(
+    &config.recorder()?.csv_dir,
+    &symbol,
+    &interval,
+    parse_date(&opt.replay.start)?,
+    parse_date(&opt.replay.end)?,
+  )?;
+  // Every combination warms up on as many klines, so they trade the same range
+  let warmup = setting.warmup.max(space.max_lookback() + 1);
+  ensure!(
+    klines.len() > warmup,
+    "Only {} klines found, run backfill first",
+    klines.len()
+  );
+  log::info!(
+    "Backtesting {} of {} combinations on {} klines with {} threads",
+    candidates.len(),
+    space.size(),
+    klines.len(),
+    threads
+  );
+  let fees = FeeModel::new(&config.fees).rates(&symbol);
+  let backtest = TurtleBacktest {
+    symbol,
+    klines,
+    warmup,
+    base_balance: opt.replay.base_balance,
+    quote_balance: opt.replay.quote_balance,
+    fees,
+  };
+
+  let mut rows = vec![];
+  match opt.train {
+    None => {
+      let range = warmup..backtest.klines.len();
+      let ranking = sweep(&backtest, &candidates, range.clone(), opt.metric, threads);
+      print_ranking(&ranking, opt.top);
+      for (rank, evaluation) in ranking.iter().enumerate() {
+        rows.push(ResultRow::new(
+          1,
+          "in",
+          rank + 1,
+          &backtest.klines[range.clone()],
+          &evaluation.params,
+          &evaluation.metrics,
+        ));
+      }
+    }
+    Some(train) => {
+      let test = opt.test.unwrap_or((train / 4).max(1));
+      let folds = walk_forward_folds(backtest.klines.len(), warmup, train, test)?;
+      let results = walk_forward(&backtest, &candidates, &folds, opt.metric, threads);
+      let mut compounded = 1.0;
+      println!(
+        "{:>4} {:>7} {:>7} {:>6} {:>5} {:>5} {:>10} {:>10} {:>10} {:>8}",
+        "fold",
+        "window",
+        "atr",
+        "stop",
+        "units",
+        "tp",
+        "in score",
+        "out ret%",
+        "out dd%",
+        "out shp"
+      );
+      for (i, result) in results.iter().enumerate() {
+        let best = match result.ranking.first() {
+          Some(best) => best,
+          None => {
+            println!("{:>4} no successful run", i + 1);
+            continue;
+          }
+        };
+        let params = &best.params;
+        match &result.out_of_sample {
+          Some(metrics) => {
+            compounded *= 1.0 + metrics.total_return;
+            println!(
+              "{:>4} {:>7} {:>7} {:>6.2} {:>5} {:>5.2} {:>10.4} {:>10.2} {:>10.2} {:>8.2}",
+              i + 1,
+              params.breakout_window,
+              params.atr_period,
+              params.stop_n,
+              params.max_units,
+              params.take_profit,
+              best.metrics.score(opt.metric),
+              metrics.total_return * 100.0,
+              metrics.max_drawdown * 100.0,
+              metrics.sharpe
+            );
+            rows.push(ResultRow::new(
+              i + 1,
+              "out",
+              1,
+              &backtest.klines[result.fold.out_of_sample.clone()],
+              params,
+              metrics,
+            ));
+          }
+          None => println!("{:>4} out of sample run failed", i + 1),
+        }
+        for (rank, evaluation) in result.ranking.iter().enumerate() {
+          rows.push(ResultRow::new(
+            i + 1,
+            "in",
+            rank + 1,
+            &backtest.klines[result.fold.in_sample.clone()],
+            &evaluation.params,
+            &evaluation.metrics,
+          ));
+        }
+      }
+      println!(
+        "Out of sample return over {} folds: {:.2}%",
+        results.len(),
+        (compounded - 1.0) * 100.0
+      );
+    }
+  }
+
+  if let Some(output) = opt.replay.output {
+    let mut writer = csv::Writer::from_path(&output)?;
+    for row in rows {
+      writer.serialize(row)?;
+    }
+    writer.flush()?;
+  }
+  Ok(())
+}
+
+fn print_ranking(ranking: &[Evaluation], top: usize) {
+  println!(
+    "{:>4} {:>7} {:>7} {:>6} {:>5} {:>5} {:>9} {:>8} {:>7} {:>7} {:>6}",
+    "rank", "window", "atr", "stop", "units", "tp", "return%", "dd%", "sharpe", "calmar", "trades"
+  );
+  for (rank, evaluation) in ranking.iter().take(top).enumerate() {
+    let (params, metrics) = (&evaluation.params, &evaluation.metrics);
+    println!(
+      "{:>4} {:>7} {:>7} {:>6.2} {:>5} {:>5.2} {:>9.2} {:>8.2} {:>7.2} {:>7.2} {:>6}",
+      rank + 1,
+      params.breakout_window,
+      params.atr_period,
+      params.stop_n,
+      params.max_units,
+      params.take_profit,
+      metrics.total_return * 100.0,
+      metrics.max_drawdown * 100.0,
+      metrics.sharpe,
+      metrics.calmar,
+      metrics.trades
+    );
+  }
+}
@@ -0,0 +1,238 @@
+use super::{binance_client, ws_api_client, CommonOpt};
+use anyhow::Result;
+use crypto_trading::binance::api::{
+  CancelOrderInput, CancelOrderListInput, MarginOrderInput, OcoInput, OrderInput, OrderListLeg,
+  OrderRespType, OrderSide, OrderType, SideEffectType, TimeInForce,
+};
+use crypto_trading::shared::utils::get_timestamp;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub enum OrderOpt {
+  /// Place a new order
+  Place(PlaceOpt),
+  /// Cancel an open order by order id or client order id
+  Cancel(CancelOpt),
+  /// Place a take-profit and a stop-loss where one fill cancels the other
+  Oco(OcoOpt),
+  /// Cancel every order of an order list
+  CancelList(CancelListOpt),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PlaceOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// buy or sell
+  #[structopt(long)]
+  pub side: OrderSide,
+  /// limit, market, stop_loss, stop_loss_limit, take_profit, take_profit_limit or limit_maker
+  #[structopt(long = "type", default_value = "limit")]
+  pub order_type: OrderType,
+  /// Quantity in base asset
+  #[structopt(short, long)]
+  pub quantity: Option<f32>,
+  /// Quantity in quote asset, market orders only
+  #[structopt(long)]
+  pub quote_order_qty: Option<f32>,
+  #[structopt(short, long)]
+  pub price: Option<f32>,
+  #[structopt(long)]
+  pub stop_price: Option<f32>,
+  /// GTC, IOC or FOK
+  #[structopt(long)]
+  pub time_in_force: Option<TimeInForce>,
+  /// Defaults to manual_<timestamp>
+  #[structopt(long)]
+  pub client_order_id: Option<String>,
+  /// Validate with the test order endpoint without placing the order
+  #[structopt(long)]
+  pub test: bool,
+  /// Send the order over the WebSocket API instead of REST
+  #[structopt(long, conflicts_with = "margin")]
+  pub ws_api: bool,
+  /// Place the order on the cross margin account, there's no test endpoint
+  #[structopt(long, conflicts_with = "test")]
+  pub margin: bool,
+  /// Margin orders only: no_side_effect, margin_buy or auto_repay
+  #[structopt(long, requires = "margin")]
+  pub side_effect: Option<SideEffectType>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CancelOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  #[structopt(long)]
+  pub order_id: Option<i64>,
+  #[structopt(long)]
+  pub client_order_id: Option<String>,
+  /// Cancel over the WebSocket API instead of REST
+  #[structopt(long)]
+  pub ws_api: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct OcoOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// sell closes a long, buy closes a short
+  #[structopt(long)]
+  pub side: OrderSide,
+  #[structopt(short, long)]
+  pub quantity: f32,
+  /// Limit maker price of the take-profit leg
+  #[structopt(long)]
+  pub take_profit: f32,
+  /// Trigger price of the stop-loss leg
+  #[structopt(long)]
+  pub stop_price: f32,
+  /// Limit price once the stop triggers, a market stop if not set
+  #[structopt(long)]
+  pub stop_limit_price: Option<f32>,
+  #[structopt(long)]
+  pub list_client_order_id: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CancelListOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  #[structopt(long)]
+  pub order_list_id: Option<i64>,
+  #[structopt(long)]
+  pub list_client_order_id: Option<String>,
+}
+
+pub async fn run(opt: OrderOpt) -> Result<()> {
+  match opt {
+    OrderOpt::Place(opt) => place(opt).await,
+    OrderOpt::Cancel(opt) => cancel(opt).await,
+    OrderOpt::Oco(opt) => oco(opt).await,
+    OrderOpt::CancelList(opt) => cancel_list(opt).await,
+  }
+}
+
+async fn place(opt: PlaceOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let now = get_timestamp();
+  let order = OrderInput {
+    symbol: symbol.to_uppercase(),
+    side: opt.side,
+    order_type: opt.order_type,
+    time_in_force: opt.time_in_force,
+    quantity: opt.quantity,
+    quote_order_qty: opt.quote_order_qty,
+    price: opt.price,
+    new_client_order_id: opt
+      .client_order_id
+      .unwrap_or_else(|| format!("manual_{}", now)),
+    stop_price: opt.stop_price,
+    iceberg_qty: None,
+    new_order_resp_type: Some(OrderRespType::Full),
+    recv_window: None,
+    timestamp: now,
+  };
+  if opt.margin {
+    let res = binance_client(&config)?
+      .margin_new_order(MarginOrderInput {
+        order,
+        side_effect_type: opt.side_effect,
+      })
+      .await?;
+    println!("{:#?}", res);
+  } else if opt.ws_api {
+    let client = ws_api_client(&config).await?;
+    if opt.test {
+      client.test_order(order).await?;
+      println!("Test order accepted");
+    } else {
+      println!("{:#?}", client.place_order(order).await?);
+    }
+  } else {
+    let client = binance_client(&config)?;
+    if opt.test {
+      client.test_new_order(order).await?;
+      println!("Test order accepted");
+    } else {
+      println!("{:#?}", client.new_order(order).await?);
+    }
+  }
+  Ok(())
+}
+
+async fn cancel(opt: CancelOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let input = CancelOrderInput {
+    symbol: symbol.to_uppercase(),
+    order_id: opt.order_id,
+    orig_client_order_id: opt.client_order_id,
+    recv_window: None,
+    timestamp: get_timestamp(),
+  };
+  let res = if opt.ws_api {
+    ws_api_client(&config).await?.cancel_order(input).await?
+  } else {
+    binance_client(&config)?.cancel_order(input).await?
+  };
+  println!("{:#?}", res);
+  Ok(())
+}
+
+async fn oco(opt: OcoOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let client = binance_client(&config)?;
+  let take_profit = OrderListLeg {
+    order_type: OrderType::LimitMaker,
+    price: Some(opt.take_profit),
+    stop_price: None,
+    time_in_force: None,
+    iceberg_qty: None,
+    client_order_id: None,
+  };
+  let stop_loss = OrderListLeg {
+    order_type: match opt.stop_limit_price {
+      Some(_) => OrderType::StopLossLimit,
+      None => OrderType::StopLoss,
+    },
+    price: opt.stop_limit_price,
+    stop_price: Some(opt.stop_price),
+    time_in_force: opt.stop_limit_price.map(|_| TimeInForce::GTC),
+    iceberg_qty: None,
+    client_order_id: None,
+  };
+  let (above, below) = match opt.side {
+    OrderSide::Sell => (take_profit, stop_loss),
+    OrderSide::Buy => (stop_loss, take_profit),
+  };
+  let res = client
+    .new_oco(OcoInput {
+      symbol: symbol.to_uppercase(),
+      list_client_order_id: opt.list_client_order_id,
+      side: opt.side,
+      quantity: opt.quantity,
+      above,
+      below,
+      new_order_resp_type: Some(OrderRespType::Full),
+      recv_window: None,
+      timestamp: get_timestamp(),
+    })
+    .await?;
+  println!("{:#?}", res);
+  Ok(())
+}
+
+async fn cancel_list(opt: CancelListOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let res = binance_client(&config)?
+    .cancel_order_list(CancelOrderListInput {
+      symbol: symbol.to_uppercase(),
+      order_list_id: opt.order_list_id,
+      list_client_order_id: opt.list_client_order_id,
+      recv_window: None,
+      timestamp: get_timestamp(),
+    })
+    .await?;
+  println!("{:#?}", res);
+  Ok(())
+}
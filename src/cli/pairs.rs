@@ -0,0 +1,193 @@
+use super::backtest::{write_trades, ReplayOpt};
+use super::trade::{connect, ExecutorOpt, Trader};
+use super::{market_client, parse_date, CommonOpt};
+use anyhow::{anyhow, ensure, Result};
+use chrono::Utc;
+use crossbeam_channel::select;
+use crypto_trading::backtest::{load_klines, Backtester, SimulatedExchange};
+use crypto_trading::binance::{api::KlineInput, data_stream::MarketStream, websocket::Kline};
+use crypto_trading::fees::FeeModel;
+use crypto_trading::risk::RiskManager;
+use crypto_trading::shared::utils::parse_interval;
+use crypto_trading::strategy::pairs::Pairs;
+use crypto_trading::strategy::sync::CandleSync;
+use crypto_trading::strategy::CandleStick;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct PairsOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  #[structopt(subcommand)]
+  pub mode: PairsMode,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum PairsMode {
+  /// Trade on the `strategy.pairs.interval` kline streams of both symbols
+  Live(ExecutorOpt),
+  /// Replay backfilled klines of both symbols
+  Backtest(PairsReplayOpt),
+}
+
+/// Each symbol trades its own balances, the quote balance goes to both
+#[derive(StructOpt, Debug)]
+pub struct PairsReplayOpt {
+  #[structopt(flatten)]
+  pub replay: ReplayOpt,
+  /// Starting base asset balance of the hedge symbol
+  #[structopt(long, default_value = "0")]
+  pub hedge_base_balance: f64,
+}
+
+pub async fn run(opt: PairsOpt) -> Result<()> {
+  match opt.mode {
+    PairsMode::Live(executor) => live(opt.common, executor).await,
+    PairsMode::Backtest(backtest) => replay(opt.common, backtest),
+  }
+}
+
+async fn live(common: CommonOpt, opt: ExecutorOpt) -> Result<()> {
+  let (config, symbol) = common.load()?;
+  let setting = config.strategy.pairs.clone();
+  let interval = setting.interval.clone();
+  let symbols = vec![symbol.clone(), setting.hedge_symbol.to_lowercase()];
+
+  // Closed candles of both symbols to fit the first hedge ratio on
+  let market_client = market_client(&config)?;
+  let now = Utc::now().timestamp_millis();
+  let warmup = setting.window as i64 + 1;
+  let mut klines = vec![];
+  for symbol in &symbols {
+    let kline_req = KlineInput {
+      symbol: symbol.to_uppercase(),
+      interval: interval.clone(),
+      start_time: Some(now - warmup * parse_interval(&interval)?),
+      end_time: Some(now),
+      limit: None,
+    };
+    let mut history = market_client.kline(kline_req).await?;
+    history.retain(|kline| kline.close_time < now);
+    klines.push(history);
+  }
+  let history = CandleSync::align(&symbols, &klines);
+  log::info!("Candles of both symbols: {}", history.len());
+  // Orders the exchange takes are whole steps of LOT_SIZE
+  let infos = market_client.exchange_info(&symbols).await?;
+  let step_size = |symbol: &str| {
+    infos
+      .iter()
+      .find(|info| info.symbol.eq_ignore_ascii_case(symbol))
+      .and_then(|info| info.lot_size())
+      .map(|(_, _, step_size)| step_size)
+      .ok_or_else(|| anyhow!("No LOT_SIZE filter for {}", symbol))
+  };
+  let step_sizes = (step_size(&symbols[0])?, step_size(&symbols[1])?);
+  let pairs = Pairs::new(&symbol, setting, &history)?.with_step_sizes(step_sizes.0, step_sizes.1);
+
+  let (executor, portfolio, user_stream) = connect(&config, &symbols, &opt).await?;
+  let risk = RiskManager::new(config.risk.clone(), portfolio);
+  let mut trader = Trader::new(&symbols[0], pairs, executor, risk).with_symbol(&symbols[1]);
+
+  let (sender, receiver) = crossbeam_channel::unbounded();
+  let ws_base = config.market_data(config.profile)?.ws_base.clone();
+  let streams = symbols
+    .iter()
+    .map(|symbol| format!("{}@kline_{}", symbol, interval))
+    .collect::<Vec<_>>();
+  let stream = format!("stream?streams={}", streams.join("/"));
+  tokio::spawn(async move {
+    let market_stream = MarketStream::new(ws_base);
+    market_stream.subscribe(stream, sender).await
+  });
+
+  let mut sync = CandleSync::new(&symbols);
+  loop {
+    select! {
+      recv(receiver) -> msg => {
+        let msg = match msg {
+          Ok(msg) => msg,
+          Err(_) => break,
+        };
+        // Combined streams wrap every message with the name of its stream
+        let msg = serde_json::from_str::<serde_json::Value>(&msg)?;
+        let kline = serde_json::from_value::<Kline>(msg["data"].clone())?;
+        let closed = kline.candle.closed;
+        let candle: CandleStick = kline.candle.into();
+        trader.on_market(&candle).await;
+        if !closed {
+          continue;
+        }
+        if let Some(candles) = sync.push(candle) {
+          trader.on_candles(&candles).await;
+        }
+      }
+      recv(user_stream) -> msg => {
+        if let Ok(msg) = msg {
+          if let Err(e) = trader.on_user_event(&msg) {
+            log::error!("Failed to handle user stream event: {:#?}", e);
+          }
+        }
+      }
+      default(Duration::new(5, 0)) => break,
+    }
+    trader.check_halted().await?;
+  }
+  if let Some((traded, hedge)) = trader.strategy.position() {
+    log::warn!(
+      "Pair left open: {} {} and {} {}",
+      traded,
+      symbols[0],
+      hedge,
+      symbols[1]
+    );
+  }
+  Ok(())
+}
+
+fn replay(common: CommonOpt, opt: PairsReplayOpt) -> Result<()> {
+  let (config, symbol) = common.load()?;
+  let setting = config.strategy.pairs.clone();
+  let symbols = vec![symbol.clone(), setting.hedge_symbol.to_lowercase()];
+  let csv_dir = &config.recorder()?.csv_dir;
+  let (start, end) = (parse_date(&opt.replay.start)?, parse_date(&opt.replay.end)?);
+  let klines = symbols
+    .iter()
+    .map(|symbol| load_klines(csv_dir, symbol, &setting.interval, start, end))
+    .collect::<Result<Vec<_>>>()?;
+  let mut history = CandleSync::align(&symbols, &klines);
+  ensure!(
+    history.len() > setting.window,
+    "Only {} candles of both {} and {} found, run backfill first",
+    history.len(),
+    symbols[0],
+    symbols[1]
+  );
+  log::info!("Loaded {} candles of both symbols", history.len());
+
+  let replay = history.split_off(setting.window);
+  let pairs = Pairs::new(&symbol, setting, &history)?;
+  let fees = FeeModel::new(&config.fees);
+  let exchanges = vec![
+    SimulatedExchange::new(
+      &symbols[0],
+      opt.replay.base_balance,
+      opt.replay.quote_balance,
+    )?
+    .with_fees(fees.rates(&symbols[0])),
+    SimulatedExchange::new(
+      &symbols[1],
+      opt.hedge_base_balance,
+      opt.replay.quote_balance,
+    )?
+    .with_fees(fees.rates(&symbols[1])),
+  ];
+  let report = Backtester::with_legs(pairs, exchanges)?.run_synced(replay)?;
+  println!("{}", report);
+
+  if let Some(output) = opt.replay.output {
+    write_trades(&output, &report.trades)?;
+  }
+  Ok(())
+}
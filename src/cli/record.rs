@@ -0,0 +1,100 @@
+use super::CommonOpt;
+use anyhow::Result;
+use crypto_trading::binance::{data_stream::MarketStream, websocket::StreamOrderbook};
+use crypto_trading::shared::{
+  csv_schema::{CsvDataType, Trade},
+  utils::get_csv_writer,
+};
+use serde_json::Value;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct RecordOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// Directory for the CSV files, defaults to `recorder.csv_dir` in the config
+  #[structopt(long)]
+  pub csv_dir: Option<String>,
+}
+
+pub async fn run(opt: RecordOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let recorder = config.recorder()?;
+  let csv_dir = opt.csv_dir.unwrap_or_else(|| recorder.csv_dir.clone());
+  let symbol = match (&opt.common.symbol, &recorder.symbol) {
+    (None, Some(recorder_symbol)) => recorder_symbol.to_lowercase(),
+    _ => symbol,
+  };
+
+  let (sender, receiver) = crossbeam_channel::unbounded();
+  let trade_stream = format!("{}@trade", symbol);
+  let orderbook_stream = format!("{}@depth20@100ms", symbol);
+  let stream = format!("stream?streams={}/{}", trade_stream, orderbook_stream);
+  let ws_base = config.market_data(config.profile)?.ws_base.clone();
+
+  // Websocket stream receiver.
+  // This will run in another thread
+  tokio::spawn(async move {
+    let market_stream = MarketStream::new(ws_base);
+    market_stream.subscribe(stream, sender).await
+  });
+
+  let mut dump_date = chrono::Utc::now().format("%Y%m%d").to_string();
+  let mut trade_csv_writer = get_csv_writer(&csv_dir, &symbol, CsvDataType::Trade, &dump_date);
+  let mut orderbook_csv_writer =
+    get_csv_writer(&csv_dir, &symbol, CsvDataType::OrderBook, &dump_date);
+
+  while let Ok(msg) = receiver.recv_timeout(std::time::Duration::new(5, 0)) {
+    let curr_date = chrono::Utc::now().format("%Y%m%d").to_string();
+    if curr_date != dump_date {
+      dump_date = curr_date;
+      trade_csv_writer = get_csv_writer(&csv_dir, &symbol, CsvDataType::Trade, &dump_date);
+      orderbook_csv_writer = get_csv_writer(&csv_dir, &symbol, CsvDataType::OrderBook, &dump_date);
+    }
+    let raw_value = serde_json::from_str::<Value>(&msg)?;
+    let mut stream = raw_value
+      .get("stream")
+      .expect("Multi stream should contain stream tag")
+      .to_string();
+    // Get rid of the quotation mark(")
+    stream.pop();
+    stream.remove(0);
+    let data = raw_value
+      .get("data")
+      .expect("Multi stream should contain data")
+      .to_owned();
+    if stream == trade_stream {
+      let trade_record = serde_json::from_value::<Trade>(data)?;
+      log::debug!("{:#?}", trade_record);
+      trade_csv_writer.serialize(trade_record)?;
+    } else if stream == orderbook_stream {
+      let orderbook = serde_json::from_value::<StreamOrderbook>(data)?;
+      if orderbook.bids.len() < 10 || orderbook.asks.len() < 10 {
+        log::error!("Orderbook data malformed, not enough length");
+        continue;
+      }
+      let record = (0..10).fold(
+        vec![chrono::Utc::now().timestamp_millis().to_string()],
+        |mut record, i| {
+          let bid = orderbook.bids[i][0].parse::<f64>().unwrap();
+          let bid_amount = orderbook.bids[i][1].parse::<f64>().unwrap();
+          let ask = orderbook.asks[i][0].parse::<f64>().unwrap();
+          let ask_amount = orderbook.asks[i][1].parse::<f64>().unwrap();
+          let mid = (bid + ask) / 2.0;
+          record.append(&mut vec![
+            bid.to_string(),
+            ask.to_string(),
+            bid_amount.to_string(),
+            ask_amount.to_string(),
+            mid.to_string(),
+          ]);
+          record
+        },
+      );
+      orderbook_csv_writer.write_record(record)?;
+    }
+  }
+  trade_csv_writer.flush()?;
+  orderbook_csv_writer.flush()?;
+  Ok(())
+}
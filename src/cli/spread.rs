@@ -0,0 +1,200 @@
+use super::CommonOpt;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::select;
+use crypto_trading::binance::data_stream::MarketStream;
+use crypto_trading::shared::config::Venue;
+use crypto_trading::shared::{csv_schema::CsvDataType, utils::get_csv_writer};
+use crypto_trading::spread::{SpreadMonitor, SpreadRecord};
+use crypto_trading::venue::coinbase::{self, CoinbaseClient, CoinbaseStream};
+use crypto_trading::venue::MarketEvent;
+use csv::Writer;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Venue and sampling come from the `[spread]` section
+#[derive(StructOpt, Debug)]
+pub struct SpreadOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// Directory for the CSV files, defaults to `recorder.csv_dir` in the config
+  #[structopt(long)]
+  pub csv_dir: Option<String>,
+  /// Also append every raw message to this file, to be read back with --replay
+  #[structopt(long)]
+  pub capture: Option<String>,
+  /// Read messages captured with --capture instead of connecting
+  #[structopt(long)]
+  pub replay: Option<String>,
+}
+
+// Daily spread CSV files, rolled over on the record's date
+struct SpreadWriter {
+  csv_dir: String,
+  symbol: String,
+  venue: Venue,
+  date: String,
+  writer: Option<Writer<File>>,
+  written: usize,
+}
+
+impl SpreadWriter {
+  fn write(&mut self, record: &SpreadRecord) -> Result<()> {
+    log::debug!("{:?}", record);
+    let date = DateTime::from_timestamp_millis(record.md_time)
+      .ok_or_else(|| anyhow!("Spread time {} out of range", record.md_time))?
+      .format("%Y%m%d")
+      .to_string();
+    if date != self.date || self.writer.is_none() {
+      self.flush()?;
+      self.date = date;
+      self.writer = Some(get_csv_writer(
+        &self.csv_dir,
+        &self.symbol,
+        CsvDataType::Spread(self.venue.to_string()),
+        &self.date,
+      ));
+    }
+    self.writer.as_mut().unwrap().serialize(record)?;
+    self.written += 1;
+    Ok(())
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    if let Some(writer) = self.writer.as_mut() {
+      writer.flush()?;
+    }
+    Ok(())
+  }
+}
+
+pub async fn run(opt: SpreadOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let setting = config.spread.clone();
+  let csv_dir = match opt.csv_dir {
+    Some(csv_dir) => csv_dir,
+    None => config.recorder()?.csv_dir.clone(),
+  };
+  let mut writer = SpreadWriter {
+    csv_dir,
+    symbol: symbol.clone(),
+    venue: setting.venue,
+    date: String::new(),
+    writer: None,
+    written: 0,
+  };
+  let mut monitor = SpreadMonitor::new(setting.clone());
+
+  if let Some(replay) = &opt.replay {
+    let file = File::open(replay).with_context(|| format!("Failed to open {}", replay))?;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+      let line = line?;
+      let mut fields = line.splitn(3, '\t');
+      let (time, source, msg) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(time), Some(source), Some(msg)) => (time.parse::<i64>()?, source, msg),
+        _ => bail!("{}:{} is not a captured message", replay, number + 1),
+      };
+      if let Some(record) = on_message(&mut monitor, setting.venue, source, msg, time)? {
+        writer.write(&record)?;
+      }
+    }
+    writer.flush()?;
+    log::info!("Wrote {} spread records from {}", writer.written, replay);
+    return Ok(());
+  }
+
+  let venue = config.venue(setting.venue)?;
+  let product_id = match &setting.venue_symbol {
+    Some(venue_symbol) => venue_symbol.to_uppercase(),
+    None => coinbase::product_id(&symbol)?,
+  };
+  log::info!("Comparing {} with {} {}", symbol, setting.venue, product_id);
+
+  // The venue's ticker only moves with trades, start from its current book
+  let client = CoinbaseClient::new(venue.host.clone(), venue.proxy.clone())?;
+  let now = Utc::now().timestamp_millis();
+  if let Some(trade) = client.trades(&product_id).await?.into_iter().next() {
+    monitor.on_venue(&MarketEvent::Trade(trade), now)?;
+  }
+  let ticker = client.book_ticker(&product_id).await?;
+  monitor.on_venue(&MarketEvent::BookTicker(ticker), now)?;
+
+  let (binance_sender, binance_receiver) = crossbeam_channel::unbounded();
+  let ws_base = config.market_data(config.profile)?.ws_base.clone();
+  let stream = format!("stream?streams={}@trade/{}@bookTicker", symbol, symbol);
+  tokio::spawn(async move {
+    let market_stream = MarketStream::new(ws_base);
+    market_stream.subscribe(stream, binance_sender).await
+  });
+  let (venue_sender, venue_receiver) = crossbeam_channel::unbounded();
+  let venue_stream = CoinbaseStream::new(venue.ws_base.clone());
+  tokio::spawn(async move {
+    if let Err(e) = venue_stream.subscribe(vec![product_id], venue_sender).await {
+      log::error!("Venue stream failed: {:#}", e);
+    }
+  });
+
+  let mut capture = match &opt.capture {
+    Some(capture) => Some(
+      std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(capture)
+        .with_context(|| format!("Failed to open {}", capture))?,
+    ),
+    None => None,
+  };
+  let venue_name = setting.venue.to_string();
+  loop {
+    let (source, msg) = select! {
+      recv(binance_receiver) -> msg => match msg {
+        Ok(msg) => ("binance", msg),
+        Err(_) => break,
+      },
+      recv(venue_receiver) -> msg => match msg {
+        Ok(msg) => (venue_name.as_str(), msg),
+        Err(_) => break,
+      },
+      default(Duration::new(5, 0)) => break,
+    };
+    let time = Utc::now().timestamp_millis();
+    if let Some(capture) = capture.as_mut() {
+      writeln!(capture, "{}\t{}\t{}", time, source, msg)?;
+    }
+    if let Some(record) = on_message(&mut monitor, setting.venue, source, &msg, time)? {
+      log::info!(
+        "Basis {:.2} bps, buy binance {:.2} bps, buy {} {:.2} bps",
+        record.basis_bps,
+        record.buy_binance_bps,
+        venue_name,
+        record.buy_venue_bps
+      );
+      writer.write(&record)?;
+    }
+  }
+  writer.flush()?;
+  Ok(())
+}
+
+// `source` is binance or the venue's name, as in captured files
+fn on_message(
+  monitor: &mut SpreadMonitor,
+  venue: Venue,
+  source: &str,
+  msg: &str,
+  time: i64,
+) -> Result<Option<SpreadRecord>> {
+  if source == "binance" {
+    match MarketEvent::from_binance(msg)? {
+      Some(event) => monitor.on_binance(&event, time),
+      None => Ok(None),
+    }
+  } else {
+    match MarketEvent::from_venue(venue, msg)? {
+      Some(event) => monitor.on_venue(&event, time),
+      None => Ok(None),
+    }
+  }
+}
@@ -0,0 +1,831 @@
+use super::{binance_client, fee_model, futures_client, market_client, ws_api_client, CommonOpt};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use crossbeam_channel::{never, select, Receiver};
+use crypto_trading::backtest::SimulatedExchange;
+use crypto_trading::binance::{
+  api::{
+    CancelOrderInput, KlineInput, KlineResp, OrderInput, OrderResp, OrderSide, QueryOrderInput,
+    RequestError,
+  },
+  client::Client,
+  data_stream::MarketStream,
+  futures::{api::FuturesOrderInput, client::FuturesClient},
+  websocket::{ExecutionReport, Kline},
+  ws_api::WsApiClient,
+};
+use crypto_trading::fees::FeeModel;
+use crypto_trading::oms::{OrderEvents, OrderManager, OrderStatus, OrderUpdate};
+use crypto_trading::portfolio::Portfolio;
+use crypto_trading::risk::RiskManager;
+use crypto_trading::shared::{
+  config::{Profile, Setting},
+  utils::{parse_interval, split_symbol},
+};
+use crypto_trading::strategy::mean_reversion::MeanReversion;
+use crypto_trading::strategy::orderbook::OrderBook;
+use crypto_trading::strategy::turtle_trade::Turtle;
+use crypto_trading::strategy::{CandleStick, Strategy};
+use std::str::FromStr;
+use std::time::Duration;
+use structopt::StructOpt;
+
+// Returned when querying an order the exchange never received
+const NO_SUCH_ORDER: i64 = -2013;
+// Returned when canceling an order that isn't open, or all open orders
+// of a symbol that has none
+const CANCEL_REJECTED: i64 = -2011;
+// Listen keys expire after 60 minutes without a keepalive
+const LISTEN_KEY_KEEPALIVE: Duration = Duration::from_secs(30 * 60);
+
+#[derive(StructOpt, Debug)]
+pub struct TradeOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// turtle or mean-reversion
+  #[structopt(long, default_value = "turtle")]
+  pub strategy: StrategyKind,
+  /// Kline interval the strategy trades on, defaults to its `interval` in the config
+  #[structopt(short, long)]
+  pub interval: Option<String>,
+  #[structopt(flatten)]
+  pub executor: ExecutorOpt,
+}
+
+/// Candle strategies the trade and backtest commands can run
+#[derive(Clone, Copy, Debug)]
+pub enum StrategyKind {
+  Turtle,
+  MeanReversion,
+}
+
+impl FromStr for StrategyKind {
+  type Err = anyhow::Error;
+
+  fn from_str(kind: &str) -> Result<Self> {
+    Ok(match kind.to_lowercase().as_str() {
+      "turtle" => StrategyKind::Turtle,
+      "mean-reversion" | "mean_reversion" => StrategyKind::MeanReversion,
+      _ => bail!(
+        "Unknown strategy {}, expected turtle or mean-reversion",
+        kind
+      ),
+    })
+  }
+}
+
+impl StrategyKind {
+  /// Kline interval from the config and the candles needed to warm up
+  pub(super) fn interval_and_warmup(&self, config: &Setting) -> (String, usize) {
+    match self {
+      StrategyKind::Turtle => {
+        let setting = &config.strategy.turtle;
+        (setting.interval.clone(), setting.warmup)
+      }
+      StrategyKind::MeanReversion => {
+        let setting = &config.strategy.mean_reversion;
+        (setting.interval.clone(), setting.warmup())
+      }
+    }
+  }
+
+  /// Build the strategy warmed up on `klines`, `portfolio` holds the
+  /// starting balances
+  pub(super) fn build(
+    &self,
+    config: &Setting,
+    symbol: &str,
+    mut klines: Vec<KlineResp>,
+    portfolio: Portfolio,
+  ) -> Result<Box<dyn Strategy>> {
+    // The forming candle comes in again from the stream
+    let now = Utc::now().timestamp_millis();
+    klines.retain(|kline| kline.close_time < now);
+    Ok(match self {
+      StrategyKind::Turtle => {
+        let params = config.strategy.turtle.params.clone();
+        Box::new(Turtle::new(symbol, klines, params, portfolio)?)
+      }
+      StrategyKind::MeanReversion => {
+        let setting = config.strategy.mean_reversion.clone();
+        Box::new(MeanReversion::new(symbol, klines, setting, portfolio)?)
+      }
+    })
+  }
+}
+
+/// Where orders go, shared by every command that trades
+#[derive(StructOpt, Debug)]
+pub struct ExecutorOpt {
+  /// Validate orders with the test order endpoint instead of placing them
+  #[structopt(long)]
+  pub dry_run: bool,
+  /// Send orders over the WebSocket API instead of REST
+  #[structopt(long)]
+  pub ws_api: bool,
+  /// Trade USD-M futures so short entries open real short positions
+  #[structopt(long, conflicts_with = "ws-api")]
+  pub futures: bool,
+  /// Starting base asset balance in paper mode
+  #[structopt(long, default_value = "0")]
+  pub paper_base: f64,
+  /// Starting quote asset balance in paper mode
+  #[structopt(long, default_value = "10000")]
+  pub paper_quote: f64,
+}
+
+/// Where the strategy's orders end up
+pub(super) enum Executor {
+  Exchange {
+    client: Client,
+    dry_run: bool,
+  },
+  WsApi {
+    client: WsApiClient,
+    dry_run: bool,
+  },
+  Futures {
+    client: FuturesClient,
+    dry_run: bool,
+  },
+  // One per traded symbol, each with its own balances
+  Paper(Vec<SimulatedExchange>),
+}
+
+impl Executor {
+  /// Send the order at the current market `price` and `time`, returning
+  /// what the response tells about it
+  async fn execute(
+    &mut self,
+    order: OrderInput,
+    price: f64,
+    time: i64,
+  ) -> Result<Vec<OrderUpdate>> {
+    // Dry run orders are validated but never placed
+    let not_placed = OrderUpdate::status(&order, OrderStatus::Expired, time);
+    match self {
+      Executor::Exchange {
+        client,
+        dry_run: true,
+      } => {
+        client.test_new_order(order).await?;
+        Ok(vec![not_placed])
+      }
+      Executor::Exchange { client, .. } => {
+        let res = client.new_order(order).await?;
+        log::info!("New Order Res: {:#?}", res);
+        Ok(vec![OrderUpdate::from_order_resp(&res)])
+      }
+      Executor::WsApi {
+        client,
+        dry_run: true,
+      } => {
+        client.test_order(order).await?;
+        Ok(vec![not_placed])
+      }
+      Executor::WsApi { client, .. } => {
+        let res = client.place_order(order).await?;
+        log::info!("New Order Res: {:#?}", res);
+        Ok(vec![OrderUpdate::from_order_resp(&res)])
+      }
+      Executor::Futures { client, dry_run } => {
+        let order = FuturesOrderInput::from_spot(order, price)?;
+        if *dry_run {
+          client.test_new_order(order).await?;
+          return Ok(vec![not_placed]);
+        }
+        let res = client.new_order(order).await?;
+        log::info!("New Futures Order Res: {:#?}", res);
+        Ok(vec![OrderUpdate::from_futures_order_resp(&res)])
+      }
+      Executor::Paper(exchanges) => {
+        let exchange = match exchanges
+          .iter_mut()
+          .find(|exchange| exchange.symbol.eq_ignore_ascii_case(&order.symbol))
+        {
+          Some(exchange) => exchange,
+          None => bail!("Not paper trading {}", order.symbol),
+        };
+        let resting = OrderUpdate::status(&order, OrderStatus::New, time);
+        let update = match exchange.submit(order, price, time)? {
+          Some(trade) => {
+            log::info!("Paper fill: {:?}", trade);
+            OrderUpdate::from_sim_trade(&trade)
+          }
+          None => resting,
+        };
+        log::info!(
+          "Paper balances: {} {} {} {}",
+          exchange.base_balance,
+          exchange.base_asset,
+          exchange.quote_balance,
+          exchange.quote_asset
+        );
+        Ok(vec![update])
+      }
+    }
+  }
+
+  /// Fills of resting paper orders the candle traded through and
+  /// expiries of those the paper balances no longer cover
+  fn poll(&mut self, candle: &CandleStick) -> Vec<OrderUpdate> {
+    match self {
+      Executor::Paper(exchanges) => exchanges
+        .iter_mut()
+        .filter(|exchange| exchange.symbol == candle.symbol)
+        .flat_map(|exchange| exchange.match_resting(candle))
+        .collect(),
+      _ => vec![],
+    }
+  }
+
+  /// Fills of resting paper orders the other side of the book reached
+  /// and expiries of those the paper balances no longer cover
+  fn poll_book(&mut self, book: &OrderBook) -> Vec<OrderUpdate> {
+    match self {
+      Executor::Paper(exchanges) => exchanges
+        .iter_mut()
+        .filter(|exchange| exchange.symbol == book.symbol)
+        .flat_map(|exchange| exchange.match_book(book))
+        .collect(),
+      _ => vec![],
+    }
+  }
+
+  /// Cancel one order, nothing comes back for an order that is no
+  /// longer open, its fill or cancel is reported on its own. Paper
+  /// orders are all in memory, one that isn't resting is finished.
+  async fn cancel(
+    &mut self,
+    symbol: &str,
+    client_order_id: &str,
+    time: i64,
+  ) -> Result<Vec<OrderUpdate>> {
+    let input = CancelOrderInput {
+      symbol: symbol.to_uppercase(),
+      order_id: None,
+      orig_client_order_id: Some(client_order_id.to_string()),
+      recv_window: None,
+      timestamp: Utc::now().timestamp_millis(),
+    };
+    let res = match self {
+      Executor::Exchange { dry_run: true, .. }
+      | Executor::WsApi { dry_run: true, .. }
+      | Executor::Futures { dry_run: true, .. } => return Ok(vec![]),
+      Executor::Exchange { client, .. } => client
+        .cancel_order(input)
+        .await
+        .map(|res| OrderUpdate::from_order_resp(&res)),
+      Executor::WsApi { client, .. } => client
+        .cancel_order(input)
+        .await
+        .map(|res| OrderUpdate::from_order_resp(&res)),
+      Executor::Futures { client, .. } => client
+        .cancel_order(input)
+        .await
+        .map(|res| OrderUpdate::from_futures_order_resp(&res)),
+      Executor::Paper(exchanges) => {
+        return Ok(vec![exchanges
+          .iter_mut()
+          .find_map(|exchange| exchange.cancel(client_order_id))
+          .map(|order| OrderUpdate::status(&order, OrderStatus::Canceled, time))
+          .unwrap_or_else(|| OrderUpdate::not_resting(client_order_id, time))]);
+      }
+    };
+    match res {
+      Ok(update) => Ok(vec![update]),
+      Err(e) => match e.downcast_ref::<RequestError>() {
+        Some(err) if err.code == Some(CANCEL_REJECTED) => Ok(vec![]),
+        _ => Err(e),
+      },
+    }
+  }
+
+  /// Cancel every open order of `symbol` on the account
+  async fn cancel_all(&mut self, symbol: &str, time: i64) -> Result<Vec<OrderUpdate>> {
+    let symbol = symbol.to_uppercase();
+    let res = match self {
+      Executor::Exchange { dry_run: true, .. }
+      | Executor::WsApi { dry_run: true, .. }
+      | Executor::Futures { dry_run: true, .. } => return Ok(vec![]),
+      Executor::Exchange { client, .. } => client.cancel_open_orders(symbol).await,
+      Executor::WsApi { client, .. } => client.cancel_open_orders(symbol).await,
+      // The response has no orders, cancels only show up in queries
+      Executor::Futures { client, .. } => {
+        client.cancel_all_open_orders(symbol).await.map(|_| vec![])
+      }
+      Executor::Paper(exchanges) => {
+        let mut updates = vec![];
+        for exchange in exchanges.iter_mut().filter(|e| e.symbol == symbol) {
+          let ids = exchange
+            .open_orders()
+            .iter()
+            .map(|order| order.new_client_order_id.clone())
+            .collect::<Vec<_>>();
+          updates.extend(
+            ids
+              .iter()
+              .filter_map(|id| exchange.cancel(id))
+              .map(|order| OrderUpdate::status(&order, OrderStatus::Canceled, time)),
+          );
+        }
+        return Ok(updates);
+      }
+    };
+    match res {
+      // Order list reports don't parse as orders, their legs are
+      // canceled all the same
+      Ok(canceled) => Ok(
+        canceled
+          .into_iter()
+          .filter_map(|order| serde_json::from_value::<OrderResp>(order).ok())
+          .map(|res| OrderUpdate::from_order_resp(&res))
+          .collect(),
+      ),
+      Err(e) => match e.downcast_ref::<RequestError>() {
+        Some(err) if err.code == Some(CANCEL_REJECTED) => Ok(vec![]),
+        _ => Err(e),
+      },
+    }
+  }
+
+  /// Look up an order whose submission had an unknown outcome, None if
+  /// the exchange never received it
+  async fn query(&self, symbol: &str, client_order_id: &str) -> Result<Option<OrderUpdate>> {
+    let input = QueryOrderInput {
+      symbol: symbol.to_string(),
+      order_id: None,
+      orig_client_order_id: Some(client_order_id.to_string()),
+      recv_window: None,
+      timestamp: Utc::now().timestamp_millis(),
+    };
+    let res = match self {
+      Executor::Exchange { client, .. } => client
+        .query_order(input)
+        .await
+        .map(|res| OrderUpdate::from_order_resp(&res)),
+      Executor::WsApi { client, .. } => client
+        .order_status(input)
+        .await
+        .map(|res| OrderUpdate::from_order_resp(&res)),
+      Executor::Futures { client, .. } => client
+        .query_order(input)
+        .await
+        .map(|res| OrderUpdate::from_futures_order_resp(&res)),
+      Executor::Paper(_) => return Ok(None),
+    };
+    match res {
+      Ok(update) => Ok(Some(update)),
+      Err(e) => match e.downcast_ref::<RequestError>() {
+        Some(err) if err.code == Some(NO_SUCH_ORDER) => Ok(None),
+        _ => Err(e),
+      },
+    }
+  }
+}
+
+pub async fn run(opt: TradeOpt) -> Result<()> {
+  let (config, symbol) = opt.common.load()?;
+  let (default_interval, warmup) = opt.strategy.interval_and_warmup(&config);
+  let interval = opt.interval.clone().unwrap_or(default_interval);
+
+  let market_client = market_client(&config)?;
+  let now = Utc::now().timestamp_millis();
+  let warmup = warmup as i64;
+  let kline_req = KlineInput {
+    symbol: symbol.to_uppercase(),
+    interval: interval.clone(),
+    start_time: Some(now - warmup * parse_interval(&interval)?),
+    end_time: Some(now),
+    limit: None,
+  };
+  let klines = market_client.kline(kline_req).await?;
+  log::info!("Klines length: {:#?}", klines.len());
+
+  let (executor, portfolio, user_stream) =
+    connect(&config, std::slice::from_ref(&symbol), &opt.executor).await?;
+  let risk = RiskManager::new(config.risk.clone(), portfolio.clone());
+  let strategy = opt.strategy.build(&config, &symbol, klines, portfolio)?;
+  let stream = format!("{}@kline_{}", symbol, interval);
+  let ws_base = config.market_data(config.profile)?.ws_base.clone();
+  let mut trader = Trader::new(&symbol, strategy, executor, risk);
+  run_strategy(ws_base, stream, user_stream, &mut trader).await
+}
+
+/// Set up the executor `opt` asks for. Returns it with the account's
+/// balances of the assets of `symbols` and the user data stream, which
+/// never yields for paper, dry runs and futures.
+pub(super) async fn connect(
+  config: &Setting,
+  symbols: &[String],
+  opt: &ExecutorOpt,
+) -> Result<(Executor, Portfolio, Receiver<String>)> {
+  let mut assets: Vec<String> = vec![];
+  for symbol in symbols {
+    let (base_asset, quote_asset) = split_symbol(symbol)?;
+    for asset in [base_asset, quote_asset].iter() {
+      if !assets.contains(asset) {
+        assets.push(asset.clone());
+      }
+    }
+  }
+  let (_, quote_asset) = split_symbol(&symbols[0])?;
+  let ws_base = config.market_data(config.profile)?.ws_base.clone();
+  // Futures trade at their own rates, only the configured ones apply
+  let fees = if opt.futures {
+    FeeModel::new(&config.fees)
+  } else {
+    fee_model(config, symbols).await?
+  };
+  // Futures fills are only known from order responses and queries
+  let mut user_stream = never();
+  let (executor, balances) = match config.profile {
+    Profile::Paper => {
+      let exchanges = symbols
+        .iter()
+        .map(|symbol| {
+          SimulatedExchange::new(symbol, opt.paper_base, opt.paper_quote)
+            .map(|exchange| exchange.with_fees(fees.rates(symbol)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+      // Every symbol trades its own balances, the account holds their sum
+      let balances = assets
+        .iter()
+        .map(|asset| {
+          let balance = exchanges
+            .iter()
+            .map(|exchange| match asset {
+              asset if *asset == exchange.base_asset => exchange.base_balance,
+              asset if *asset == exchange.quote_asset => exchange.quote_balance,
+              _ => 0.0,
+            })
+            .sum::<f64>();
+          (asset.clone(), balance)
+        })
+        .collect::<Vec<_>>();
+      (Executor::Paper(exchanges), balances)
+    }
+    Profile::Mainnet | Profile::Testnet if opt.futures => {
+      let client = futures_client(config)?;
+      let margin = client.balances().await?;
+      // Positions start flat, the strategy's shorts are real shorts
+      let mut balances = vec![];
+      for asset in &assets {
+        let balance = match margin.iter().find(|b| b.asset == *asset) {
+          Some(balance) if *asset == quote_asset => balance.available_balance.parse::<f64>()?,
+          _ => 0.0,
+        };
+        balances.push((asset.clone(), balance));
+      }
+      let executor = Executor::Futures {
+        client,
+        dry_run: opt.dry_run,
+      };
+      (executor, balances)
+    }
+    Profile::Mainnet | Profile::Testnet => {
+      let client = binance_client(config)?;
+      let account_info = client.spot_account_info().await?;
+      let mut balances = vec![];
+      for asset in &assets {
+        let balance = match account_info.balances.iter().find(|b| b.asset == *asset) {
+          Some(balance) => balance.free.parse::<f64>()?,
+          None => 0.0,
+        };
+        balances.push((asset.clone(), balance));
+      }
+      if !opt.dry_run {
+        user_stream = start_user_stream(config, ws_base).await?;
+      }
+      let executor = if opt.ws_api {
+        Executor::WsApi {
+          client: ws_api_client(config).await?,
+          dry_run: opt.dry_run,
+        }
+      } else {
+        Executor::Exchange {
+          client,
+          dry_run: opt.dry_run,
+        }
+      };
+      (executor, balances)
+    }
+  };
+
+  let mut portfolio = Portfolio::new(&quote_asset);
+  for (asset, balance) in &balances {
+    log::info!("{} Balance: {}", asset, balance);
+    portfolio.set_balance(asset, *balance, 0.0);
+  }
+  portfolio.set_fee_model(fees);
+  Ok((executor, portfolio, user_stream))
+}
+
+/// Subscribe to the account's user data stream and keep its listen key
+/// alive, execution reports arrive on the returned receiver
+async fn start_user_stream(config: &Setting, ws_base: String) -> Result<Receiver<String>> {
+  let client = binance_client(config)?;
+  let listen_key = client.start_user_data_stream().await?;
+  let (sender, receiver) = crossbeam_channel::unbounded();
+
+  let stream_key = listen_key.clone();
+  tokio::spawn(async move {
+    let user_stream = MarketStream::new(ws_base);
+    user_stream.subscribe(stream_key, sender).await
+  });
+  tokio::spawn(async move {
+    loop {
+      tokio::time::sleep(LISTEN_KEY_KEEPALIVE).await;
+      if let Err(e) = client.keepalive_user_data_stream(&listen_key).await {
+        log::error!("Failed to keep the user data stream alive: {:#}", e);
+      }
+    }
+  });
+  Ok(receiver)
+}
+
+/// Feed `trader` candles from the kline `stream` and its orders' updates
+/// from `user_stream` until the market stream goes quiet
+pub(super) async fn run_strategy<S: Strategy>(
+  wss_endpoint: String,
+  stream: String,
+  user_stream: Receiver<String>,
+  trader: &mut Trader<S>,
+) -> Result<()> {
+  let (sender, receiver) = crossbeam_channel::unbounded();
+
+  tokio::spawn(async move {
+    let market_stream = MarketStream::new(wss_endpoint);
+    market_stream.subscribe(stream, sender).await
+  });
+
+  loop {
+    select! {
+      recv(receiver) -> msg => {
+        let msg = match msg {
+          Ok(msg) => msg,
+          Err(_) => break,
+        };
+        let curr_candle: CandleStick = serde_json::from_str::<Kline>(&msg)?.candle.into();
+        trader.on_candle(curr_candle).await;
+      }
+      recv(user_stream) -> msg => {
+        if let Ok(msg) = msg {
+          if let Err(e) = trader.on_user_event(&msg) {
+            log::error!("Failed to handle user stream event: {:#?}", e);
+          }
+        }
+      }
+      default(Duration::new(5, 0)) => break,
+    }
+    trader.check_halted().await?;
+  }
+  Ok(())
+}
+
+/// Everything an order goes through between the strategy and the
+/// exchange: risk checks, the executor and order tracking
+pub(super) struct Trader<S: Strategy> {
+  symbols: Vec<String>,
+  pub(super) strategy: S,
+  executor: Executor,
+  oms: OrderManager,
+  risk: RiskManager,
+}
+
+impl<S: Strategy> Trader<S> {
+  pub(super) fn new(symbol: &str, strategy: S, executor: Executor, risk: RiskManager) -> Self {
+    Self {
+      symbols: vec![symbol.to_uppercase()],
+      strategy,
+      executor,
+      oms: OrderManager::new("trade"),
+      risk,
+    }
+  }
+
+  /// Also trade `symbol`, for strategies trading several symbols
+  pub(super) fn with_symbol(mut self, symbol: &str) -> Self {
+    self.symbols.push(symbol.to_uppercase());
+    self
+  }
+
+  async fn on_candle(&mut self, curr_candle: CandleStick) {
+    self.on_market(&curr_candle).await;
+    if self.risk.halted().is_some() {
+      return;
+    }
+    let orders = match self.strategy.on_candle(curr_candle.clone()) {
+      Ok(orders) => orders,
+      Err(e) => {
+        log::error!("Error executing strategy: {:#?}", e);
+        return;
+      }
+    };
+    self.cancel_requested(curr_candle.close_time).await;
+    self
+      .submit(orders, curr_candle.close, curr_candle.close_time)
+      .await;
+  }
+
+  /// Mark the market at the book's mid, fill resting paper orders and
+  /// let the strategy requote
+  pub(super) async fn on_book(&mut self, book: &OrderBook) {
+    let (mid, best_bid, best_ask) = match (book.mid(), book.best_bid(), book.best_ask()) {
+      (Some(mid), Some(best_bid), Some(best_ask)) => (mid, best_bid, best_ask),
+      _ => return,
+    };
+    self.risk.on_price(&book.symbol, mid, book.time);
+    for update in self.executor.poll_book(book) {
+      self.apply_update(update);
+    }
+    self.reconcile().await;
+    if self.risk.halted().is_some() {
+      return;
+    }
+    let orders = match self.strategy.on_book(book) {
+      Ok(orders) => orders,
+      Err(e) => {
+        log::error!("Error executing strategy: {:#?}", e);
+        return;
+      }
+    };
+    self.cancel_requested(book.time).await;
+    // Marketable orders would trade at the touch of their side
+    let (buys, sells) = orders
+      .into_iter()
+      .partition(|order| matches!(order.side, OrderSide::Buy));
+    self.submit(buys, best_ask, book.time).await;
+    self.submit(sells, best_bid, book.time).await;
+  }
+
+  /// Let a strategy trading several symbols act on lined up candles,
+  /// `on_market` already saw each of them
+  pub(super) async fn on_candles(&mut self, candles: &[CandleStick]) {
+    if self.risk.halted().is_some() {
+      return;
+    }
+    let mut orders = match self.strategy.on_candles(candles) {
+      Ok(orders) => orders,
+      Err(e) => {
+        log::error!("Error executing strategy: {:#?}", e);
+        return;
+      }
+    };
+    let time = candles.iter().map(|candle| candle.close_time).max();
+    let time = time.unwrap_or_else(|| Utc::now().timestamp_millis());
+    self.cancel_requested(time).await;
+    // Market orders of every symbol go out at its own close
+    for candle in candles {
+      let (own, rest): (Vec<_>, Vec<_>) = orders
+        .into_iter()
+        .partition(|order| order.symbol.eq_ignore_ascii_case(&candle.symbol));
+      orders = rest;
+      self.submit(own, candle.close, time).await;
+    }
+    for order in orders {
+      log::error!(
+        "No {} candle to price order {} at",
+        order.symbol,
+        order.new_client_order_id
+      );
+    }
+  }
+
+  async fn cancel_requested(&mut self, time: i64) {
+    for client_order_id in self.strategy.take_cancels() {
+      let symbol = match self.oms.get(&client_order_id) {
+        Some(order) => order.symbol.clone(),
+        None => self.symbols[0].clone(),
+      };
+      match self.executor.cancel(&symbol, &client_order_id, time).await {
+        Ok(updates) => {
+          for update in updates {
+            self.apply_update(update);
+          }
+        }
+        Err(e) => log::error!("Failed to cancel order {}: {:#?}", client_order_id, e),
+      }
+    }
+  }
+
+  /// Mark the market at the candle's close, fill resting paper orders
+  /// and reconcile orders in an unknown state
+  pub(super) async fn on_market(&mut self, candle: &CandleStick) {
+    self
+      .risk
+      .on_price(&candle.symbol, candle.close, candle.close_time);
+    for update in self.executor.poll(candle) {
+      self.apply_update(update);
+    }
+    self.reconcile().await;
+  }
+
+  /// Risk check and send `orders`, `price` and `time` are the current
+  /// market price and time
+  pub(super) async fn submit(&mut self, orders: Vec<OrderInput>, price: f64, time: i64) {
+    for mut order in orders {
+      let client_order_id = match self.oms.register(&mut order) {
+        Ok(client_order_id) => client_order_id,
+        Err(e) => {
+          log::error!("Failed to execute order: {:#?}", e);
+          continue;
+        }
+      };
+      // Candle times run ahead of the clock, rate limits use the clock
+      let now = Utc::now().timestamp_millis();
+      let open_orders = self.oms.open_orders().count() - 1;
+      if let Err(e) = self.risk.check(&order, open_orders, now) {
+        log::error!("{:#}", e);
+        let events = self.oms.reject(&client_order_id);
+        self.dispatch(events);
+        continue;
+      }
+      let res = self.executor.execute(order, price, time).await;
+      self.risk.on_order_result(&res);
+      match res {
+        Ok(updates) => {
+          for update in updates {
+            self.apply_update(update);
+          }
+        }
+        Err(e) => {
+          log::error!("Failed to execute order: {:#?}", e);
+          let events = self.oms.on_submit_error(&client_order_id, &e);
+          self.dispatch(events);
+        }
+      }
+    }
+  }
+
+  pub(super) fn on_user_event(&mut self, msg: &str) -> Result<()> {
+    let event = serde_json::from_str::<serde_json::Value>(msg)?;
+    // Balance and list status events are not tracked
+    if event["e"] != "executionReport" {
+      return Ok(());
+    }
+    let report = serde_json::from_value::<ExecutionReport>(event)?;
+    self.apply_update(OrderUpdate::from_execution_report(&report));
+    Ok(())
+  }
+
+  fn apply_update(&mut self, update: OrderUpdate) {
+    let events = self.oms.on_update(update);
+    self.dispatch(events);
+  }
+
+  fn dispatch(&mut self, events: OrderEvents) {
+    for fill in &events.fills {
+      if let Err(e) = self.risk.on_fill(fill) {
+        log::error!("Failed to record fill: {:#?}", e);
+      }
+    }
+    if let Err(e) = events.dispatch(&mut self.strategy) {
+      log::error!("Error handling order update: {:#?}", e);
+    }
+  }
+
+  /// Query orders whose submission failed without a clear answer
+  async fn reconcile(&mut self) {
+    for client_order_id in self.oms.unresolved() {
+      let symbol = match self.oms.get(&client_order_id) {
+        Some(order) => order.symbol.clone(),
+        None => continue,
+      };
+      match self.executor.query(&symbol, &client_order_id).await {
+        Ok(Some(update)) => self.apply_update(update),
+        Ok(None) => {
+          let events = self.oms.reject(&client_order_id);
+          self.dispatch(events);
+        }
+        Err(e) => log::warn!("Failed to query order {}: {:#}", client_order_id, e),
+      }
+    }
+  }
+
+  /// Cancel open orders and fail once the kill switch tripped
+  pub(super) async fn check_halted(&mut self) -> Result<()> {
+    if let Some(reason) = self.risk.halted() {
+      let reason = reason.to_string();
+      self.kill().await;
+      bail!("Trading halted: {}", reason);
+    }
+    Ok(())
+  }
+
+  /// Cancel everything left open
+  pub(super) async fn kill(&mut self) {
+    let now = Utc::now().timestamp_millis();
+    for symbol in self.symbols.clone() {
+      match self.executor.cancel_all(&symbol, now).await {
+        Ok(updates) => {
+          log::warn!("Canceled {} open {} orders", updates.len(), symbol);
+          for update in updates {
+            self.apply_update(update);
+          }
+        }
+        Err(e) => log::error!("Failed to cancel open {} orders: {:#?}", symbol, e),
+      }
+    }
+  }
+}
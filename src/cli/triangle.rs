@@ -0,0 +1,226 @@
+use super::{binance_client, fee_model, market_client, CommonOpt};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use crossbeam_channel::select;
+use crypto_trading::arbitrage::{BookTicker, Market, Opportunity, TriangleArbitrage};
+use crypto_trading::binance::api::{OrderInput, OrderResp, OrderSide};
+use crypto_trading::binance::client::Client;
+use crypto_trading::binance::{data_stream::MarketStream, websocket::StreamBookTicker};
+use crypto_trading::oms::{OrderManager, OrderUpdate};
+use crypto_trading::portfolio::Portfolio;
+use crypto_trading::risk::RiskManager;
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Symbols and thresholds come from `strategy.triangle`
+#[derive(StructOpt, Debug)]
+pub struct TriangleOpt {
+  #[structopt(flatten)]
+  pub common: CommonOpt,
+  /// Trade opportunities with IOC orders, they are only logged without it
+  #[structopt(long)]
+  pub execute: bool,
+}
+
+pub async fn run(opt: TriangleOpt) -> Result<()> {
+  let (config, _) = opt.common.load()?;
+  let setting = config.strategy.triangle.clone();
+
+  let market_client = market_client(&config)?;
+  let markets = market_client
+    .exchange_info(&setting.symbols)
+    .await?
+    .iter()
+    .filter(|info| {
+      if info.status != "TRADING" {
+        log::warn!("{} is {}, leaving it out", info.symbol, info.status);
+      }
+      info.status == "TRADING"
+    })
+    .map(Market::from_info)
+    .collect::<Result<Vec<_>>>()?;
+  let mut assets: Vec<String> = vec![];
+  for market in &markets {
+    for asset in [&market.base_asset, &market.quote_asset].iter() {
+      if !assets.contains(asset) {
+        assets.push(asset.to_string());
+      }
+    }
+  }
+  // Equity is valued in the asset cycles start from
+  let quote_currency = match (setting.start_assets.first(), markets.first()) {
+    (Some(asset), _) => asset.to_uppercase(),
+    (None, Some(market)) => market.quote_asset.clone(),
+    (None, None) => String::new(),
+  };
+  let fees = fee_model(&config, &setting.symbols).await?;
+  let mut arbitrage = TriangleArbitrage::new(markets, setting.clone(), &fees)?;
+  for cycle in arbitrage.cycles() {
+    log::info!("Watching {}", cycle);
+  }
+  // Watching needs no keys, trading does
+  let mut trader = if opt.execute {
+    let client = binance_client(&config)?;
+    let account_info = client.spot_account_info().await?;
+    let mut portfolio = Portfolio::new(&quote_currency);
+    for asset in &assets {
+      let balance = match account_info.balances.iter().find(|b| b.asset == *asset) {
+        Some(balance) => balance.free.parse::<f64>()?,
+        None => 0.0,
+      };
+      portfolio.set_balance(asset, balance, 0.0);
+    }
+    Some(LegTrader {
+      client,
+      oms: OrderManager::new("tri"),
+      risk: RiskManager::new(config.risk.clone(), portfolio),
+    })
+  } else {
+    log::info!("Dry run, pass --execute to trade");
+    None
+  };
+
+  let (sender, receiver) = crossbeam_channel::unbounded();
+  let ws_base = config.market_data(config.profile)?.ws_base.clone();
+  let streams = setting
+    .symbols
+    .iter()
+    .map(|symbol| format!("{}@bookTicker", symbol.to_lowercase()))
+    .collect::<Vec<_>>();
+  let stream = format!("stream?streams={}", streams.join("/"));
+  tokio::spawn(async move {
+    let market_stream = MarketStream::new(ws_base);
+    market_stream.subscribe(stream, sender).await
+  });
+
+  loop {
+    select! {
+      recv(receiver) -> msg => {
+        let msg = match msg {
+          Ok(msg) => msg,
+          Err(_) => break,
+        };
+        // Combined streams wrap every message with the name of its stream
+        let msg = serde_json::from_str::<serde_json::Value>(&msg)?;
+        let ticker = serde_json::from_value::<StreamBookTicker>(msg["data"].clone())?;
+        let ticker = BookTicker::from_stream(&ticker, Utc::now().timestamp_millis())?;
+        if let Some(trader) = &mut trader {
+          let mid = (ticker.bid_price + ticker.ask_price) / 2.0;
+          trader.risk.on_price(&ticker.symbol, mid, ticker.time);
+        }
+        for opportunity in arbitrage.on_book_ticker(ticker) {
+          log::info!("Opportunity {}", opportunity);
+          if let Some(trader) = &mut trader {
+            if let Err(e) = execute(trader, &opportunity).await {
+              log::error!("Failed to trade {}: {:#}", opportunity.route, e);
+            }
+          }
+        }
+      }
+      default(Duration::new(5, 0)) => break,
+    }
+    // IOC legs never rest, there is nothing to cancel
+    if let Some(reason) = trader.as_ref().and_then(|trader| trader.risk.halted()) {
+      bail!("Trading halted: {}", reason);
+    }
+  }
+  Ok(())
+}
+
+// Legs go through the risk checks and order tracking strategy orders get
+struct LegTrader {
+  client: Client,
+  oms: OrderManager,
+  risk: RiskManager,
+}
+
+impl LegTrader {
+  // Send one leg, its fills are recorded before the response comes back
+  async fn send(&mut self, mut order: OrderInput) -> Result<OrderResp> {
+    let client_order_id = self.oms.register(&mut order)?;
+    let now = Utc::now().timestamp_millis();
+    let open_orders = self.oms.open_orders().count() - 1;
+    if let Err(e) = self.risk.check(&order, open_orders, now) {
+      self.oms.reject(&client_order_id);
+      return Err(e);
+    }
+    let res = self.client.new_order(order).await;
+    self.risk.on_order_result(&res);
+    let events = match &res {
+      Ok(resp) => self.oms.on_update(OrderUpdate::from_order_resp(resp)),
+      Err(e) => self.oms.on_submit_error(&client_order_id, e),
+    };
+    for fill in &events.fills {
+      if let Err(e) = self.risk.on_fill(fill) {
+        log::error!("Failed to record fill: {:#?}", e);
+      }
+    }
+    res
+  }
+}
+
+// Legs go out one after the other, each sized on what the previous one
+// actually received. A leg that doesn't fill leaves the cycle holding
+// the intermediate asset.
+async fn execute(trader: &mut LegTrader, opportunity: &Opportunity) -> Result<()> {
+  let mut amount = opportunity.start_amount;
+  for (index, leg) in opportunity.legs.iter().enumerate() {
+    let quantity = match index {
+      0 => leg.quantity,
+      _ => leg.quantity_for(amount),
+    };
+    let asset = match leg.side {
+      OrderSide::Buy => &leg.market.quote_asset,
+      OrderSide::Sell => &leg.market.base_asset,
+    };
+    if !leg.tradable(quantity) {
+      log::warn!(
+        "Holding {} {}, too little for {}",
+        amount,
+        asset,
+        leg.market.symbol
+      );
+      return Ok(());
+    }
+    let order = leg.order(
+      quantity,
+      format!("tri_{}_{}", opportunity.time, index),
+      Utc::now().timestamp_millis(),
+    );
+    let res = trader.send(order).await?;
+    let executed = parse_qty(&res.executed_qty)?;
+    let quote = parse_qty(&res.cummulative_quote_qty)?;
+    if executed <= 0.0 {
+      log::warn!(
+        "{} leg of {} didn't fill, holding {} {}",
+        leg.market.symbol,
+        opportunity.route,
+        amount,
+        asset
+      );
+      return Ok(());
+    }
+    if executed < quantity {
+      log::warn!(
+        "{} leg of {} filled {} of {}",
+        leg.market.symbol,
+        opportunity.route,
+        executed,
+        quantity
+      );
+    }
+    amount = leg.proceeds(executed, quote);
+  }
+  log::info!(
+    "Traded {}: {} {} back for the {} planned",
+    opportunity.route,
+    amount,
+    opportunity.start_asset,
+    opportunity.end_amount
+  );
+  Ok(())
+}
+
+fn parse_qty(qty: &Option<String>) -> Result<f64> {
+  Ok(qty.as_deref().unwrap_or("0").parse()?)
+}
@@ -0,0 +1,375 @@
+use crate::binance::api::{KlineResp, OrderInput, OrderSide, OrderType, TimeInForce};
+use crate::oms::{Fill, OrderStatus};
+use crate::strategy::{CandleStick, Strategy};
+use anyhow::{ensure, Result};
+use std::collections::HashMap;
+use std::fmt;
+
+const DAY_MS: i64 = 86_400_000;
+
+/// Order to be worked over time by an `ExecutionAlgo`, `quantity` is in
+/// base asset. Child quantities are rounded down to `step_size` and
+/// never go below `min_qty`.
+#[derive(Clone, Debug)]
+pub struct ParentOrder {
+  pub symbol: String,
+  pub side: OrderSide,
+  pub quantity: f64,
+  pub start_time: i64,
+  pub end_time: i64, // Everything left is sent at the end, iceberg ignores it
+  pub step_size: f64,
+  pub min_qty: f64,
+}
+
+/// How the parent order is sliced
+#[derive(Clone, Debug)]
+pub enum AlgoKind {
+  /// Equal market orders at evenly spaced times
+  Twap { slices: u32 },
+  /// Market orders following the historical volume of each time bucket
+  Vwap { profile: VolumeProfile },
+  /// Market orders keeping executed quantity at a share of the volume
+  /// traded since the start
+  Pov { participation: f64 },
+  /// One limit order of `display_qty` at a time, the next one goes out
+  /// once it's done
+  Iceberg { display_qty: f64, price: f64 },
+}
+
+/// Share of the daily volume traded in each bucket of the day, built
+/// from recorded klines
+#[derive(Clone, Debug)]
+pub struct VolumeProfile {
+  bucket_ms: i64,
+  weights: Vec<f64>,
+}
+
+impl VolumeProfile {
+  /// Buckets are as long as the kline interval, days are averaged
+  pub fn from_klines(klines: &[KlineResp]) -> Result<Self> {
+    ensure!(
+      klines.len() > 1,
+      "Need at least 2 klines for a volume profile"
+    );
+    let bucket_ms = klines[1].open_time - klines[0].open_time;
+    ensure!(
+      bucket_ms > 0 && DAY_MS % bucket_ms == 0,
+      "Kline interval of {} ms doesn't divide a day",
+      bucket_ms
+    );
+    let mut weights = vec![0.0; (DAY_MS / bucket_ms) as usize];
+    for kline in klines {
+      let bucket = kline.open_time.rem_euclid(DAY_MS) / bucket_ms;
+      weights[bucket as usize] += kline.volume;
+    }
+    let total = weights.iter().sum::<f64>();
+    ensure!(total > 0.0, "Klines have no volume");
+    weights.iter_mut().for_each(|weight| *weight /= total);
+    Ok(Self { bucket_ms, weights })
+  }
+
+  // Weight of [from, to), buckets cut in two count pro rata
+  fn volume_between(&self, from: i64, to: i64) -> f64 {
+    let mut volume = 0.0;
+    let mut time = from;
+    while time < to {
+      let bucket_start = time - time.rem_euclid(self.bucket_ms);
+      let segment_end = to.min(bucket_start + self.bucket_ms);
+      let bucket = (bucket_start.rem_euclid(DAY_MS) / self.bucket_ms) as usize;
+      volume += self.weights[bucket] * (segment_end - time) as f64 / self.bucket_ms as f64;
+      time = segment_end;
+    }
+    volume
+  }
+
+  /// Share of the volume between `start` and `end` expected by `now`,
+  /// linear in time if the profile has no volume in the window
+  pub fn fraction(&self, start: i64, end: i64, now: i64) -> f64 {
+    let now = now.max(start).min(end);
+    let total = self.volume_between(start, end);
+    if total <= 0.0 {
+      return (now - start) as f64 / (end - start) as f64;
+    }
+    self.volume_between(start, now) / total
+  }
+}
+
+#[derive(Debug)]
+struct ChildOrder {
+  quantity: f64,
+  filled: f64,
+  open: bool,
+}
+
+/// Result of working a parent order. Slippage is against the first
+/// trade price seen once the algo started, positive when it cost money.
+#[derive(Debug)]
+pub struct AlgoReport {
+  pub symbol: String,
+  pub side: OrderSide,
+  pub target_qty: f64,
+  pub filled_qty: f64,
+  pub avg_price: Option<f64>,
+  pub arrival_price: Option<f64>,
+  pub slippage_bps: Option<f64>,
+  pub child_orders: usize,
+}
+
+impl fmt::Display for AlgoReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(
+      f,
+      "{} {:?}: {} of {} filled in {} child orders",
+      self.symbol, self.side, self.filled_qty, self.target_qty, self.child_orders
+    )?;
+    match (self.avg_price, self.arrival_price, self.slippage_bps) {
+      (Some(avg_price), Some(arrival_price), Some(slippage)) => write!(
+        f,
+        "Average price {:.8}, arrival price {:.8}, slippage {:.2} bps",
+        avg_price, arrival_price, slippage
+      ),
+      _ => write!(f, "Nothing filled"),
+    }
+  }
+}
+
+/// Slices a parent order into child orders. It only decides what to
+/// send: the caller feeds it market trades, timer ticks, fills and final
+/// order states, and sends the orders it returns.
+pub struct ExecutionAlgo {
+  parent: ParentOrder,
+  kind: AlgoKind,
+  id_prefix: String,
+  next_seq: u64,
+  children: HashMap<String, ChildOrder>,
+  filled_qty: f64,
+  filled_quote: f64,
+  arrival_price: Option<f64>,
+  last_price: Option<f64>,
+  market_volume: f64, // traded since the start, for POV
+}
+
+impl ExecutionAlgo {
+  /// Child client order ids are `<id_prefix>_<seq>`
+  pub fn new(parent: ParentOrder, kind: AlgoKind, id_prefix: &str) -> Result<Self> {
+    ensure!(parent.quantity > 0.0, "Parent quantity must be positive");
+    ensure!(parent.step_size > 0.0, "Step size must be positive");
+    ensure!(
+      parent.min_qty <= parent.quantity,
+      "Parent quantity is below the minimum order quantity"
+    );
+    match &kind {
+      AlgoKind::Twap { slices } => ensure!(*slices > 0, "TWAP needs at least one slice"),
+      AlgoKind::Pov { participation } => ensure!(
+        *participation > 0.0 && *participation <= 1.0,
+        "Participation must be in (0, 1], got {}",
+        participation
+      ),
+      AlgoKind::Iceberg { display_qty, price } => {
+        ensure!(
+          *display_qty >= parent.min_qty && *display_qty > 0.0,
+          "Display quantity is below the minimum order quantity"
+        );
+        ensure!(*price > 0.0, "Iceberg price must be positive");
+      }
+      AlgoKind::Vwap { .. } => {}
+    }
+    if !matches!(kind, AlgoKind::Iceberg { .. }) {
+      ensure!(
+        parent.end_time > parent.start_time,
+        "End time must be after start time"
+      );
+    }
+    Ok(Self {
+      parent,
+      kind,
+      id_prefix: id_prefix.to_string(),
+      next_seq: 0,
+      children: HashMap::new(),
+      filled_qty: 0.0,
+      filled_quote: 0.0,
+      arrival_price: None,
+      last_price: None,
+      market_volume: 0.0,
+    })
+  }
+
+  /// A trade on the market, returns child orders to send
+  pub fn on_trade(&mut self, price: f64, quantity: f64, time: i64) -> Vec<OrderInput> {
+    self.last_price = Some(price);
+    if time >= self.parent.start_time {
+      self.arrival_price.get_or_insert(price);
+      self.market_volume += quantity;
+    }
+    self.on_timer(time)
+  }
+
+  /// Time passed, returns child orders to send
+  pub fn on_timer(&mut self, now: i64) -> Vec<OrderInput> {
+    if self.is_done() || now < self.parent.start_time || self.last_price.is_none() {
+      return vec![];
+    }
+    let parent = &self.parent;
+    if let AlgoKind::Iceberg { display_qty, price } = self.kind {
+      if self.children.values().any(|child| child.open) {
+        return vec![];
+      }
+      let quantity = self.round(display_qty.min(parent.quantity - self.filled_qty));
+      if quantity < parent.min_qty || quantity <= 0.0 {
+        return vec![];
+      }
+      return vec![self.child_order(quantity, Some(price), now)];
+    }
+
+    let target = if now >= parent.end_time {
+      parent.quantity
+    } else {
+      let elapsed = (now - parent.start_time) as f64;
+      let duration = (parent.end_time - parent.start_time) as f64;
+      match &self.kind {
+        AlgoKind::Twap { slices } => {
+          let slice = ((elapsed / duration * *slices as f64).floor() + 1.0).min(*slices as f64);
+          parent.quantity * slice / *slices as f64
+        }
+        AlgoKind::Vwap { profile } => {
+          parent.quantity * profile.fraction(parent.start_time, parent.end_time, now)
+        }
+        AlgoKind::Pov { participation } => {
+          (self.market_volume * participation).min(parent.quantity)
+        }
+        AlgoKind::Iceberg { .. } => unreachable!(),
+      }
+    };
+    let working = self
+      .children
+      .values()
+      .filter(|child| child.open)
+      .map(|child| child.quantity - child.filled)
+      .sum::<f64>();
+    let quantity = self.round(target - self.filled_qty - working);
+    if quantity < parent.min_qty || quantity <= 0.0 {
+      return vec![];
+    }
+    vec![self.child_order(quantity, None, now)]
+  }
+
+  pub fn on_fill(&mut self, fill: &Fill) {
+    if let Some(child) = self.children.get_mut(&fill.client_order_id) {
+      child.filled += fill.quantity;
+      self.filled_qty += fill.quantity;
+      self.filled_quote += fill.quantity * fill.price;
+    }
+  }
+
+  /// Unfilled quantity of canceled, rejected or expired children goes
+  /// back into the pool
+  pub fn on_order_finished(&mut self, client_order_id: &str, status: OrderStatus) {
+    if let Some(child) = self.children.get_mut(client_order_id) {
+      child.open = false;
+      if status != OrderStatus::Filled {
+        log::warn!(
+          "Child order {} {:?} with {} of {} filled",
+          client_order_id,
+          status,
+          child.filled,
+          child.quantity
+        );
+      }
+    }
+  }
+
+  /// Done once what's left can't make a child order and nothing is working
+  pub fn is_done(&self) -> bool {
+    let left = self.round(self.parent.quantity - self.filled_qty);
+    (left < self.parent.min_qty || left <= 0.0) && !self.children.values().any(|child| child.open)
+  }
+
+  pub fn report(&self) -> AlgoReport {
+    let avg_price = if self.filled_qty > 0.0 {
+      Some(self.filled_quote / self.filled_qty)
+    } else {
+      None
+    };
+    let slippage_bps = match (avg_price, self.arrival_price) {
+      (Some(avg_price), Some(arrival_price)) => {
+        let slippage = (avg_price - arrival_price) / arrival_price * 10_000.0;
+        Some(match self.parent.side {
+          OrderSide::Buy => slippage,
+          OrderSide::Sell => -slippage,
+        })
+      }
+      _ => None,
+    };
+    AlgoReport {
+      symbol: self.parent.symbol.clone(),
+      side: self.parent.side.clone(),
+      target_qty: self.parent.quantity,
+      filled_qty: self.filled_qty,
+      avg_price,
+      arrival_price: self.arrival_price,
+      slippage_bps,
+      child_orders: self.children.len(),
+    }
+  }
+
+  fn round(&self, quantity: f64) -> f64 {
+    // Order quantities are f32, the tolerance keeps fills that came back
+    // a hair over the child quantity from costing a whole step
+    (quantity / self.parent.step_size + 1e-4).floor() * self.parent.step_size
+  }
+
+  fn child_order(&mut self, quantity: f64, price: Option<f64>, now: i64) -> OrderInput {
+    self.next_seq += 1;
+    let client_order_id = format!("{}_{}", self.id_prefix, self.next_seq);
+    self.children.insert(
+      client_order_id.clone(),
+      ChildOrder {
+        quantity,
+        filled: 0.0,
+        open: true,
+      },
+    );
+    log::info!(
+      "Child order {} {:?} {} @ {:?}",
+      client_order_id,
+      self.parent.side,
+      quantity,
+      price
+    );
+    OrderInput {
+      symbol: self.parent.symbol.to_uppercase(),
+      side: self.parent.side.clone(),
+      order_type: match price {
+        Some(_) => OrderType::Limit,
+        None => OrderType::Market,
+      },
+      time_in_force: price.map(|_| TimeInForce::GTC),
+      quantity: Some(quantity as f32),
+      quote_order_qty: None,
+      price: price.map(|price| price as f32),
+      new_client_order_id: client_order_id,
+      stop_price: None,
+      iceberg_qty: None,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: now,
+    }
+  }
+}
+
+/// Candles drive the algo like a trade at the close with the candle's
+/// volume, so it runs in the backtester and the trade loop
+impl Strategy for ExecutionAlgo {
+  fn on_candle(&mut self, candle: CandleStick) -> Result<Vec<OrderInput>> {
+    Ok(self.on_trade(candle.close, candle.volume, candle.close_time))
+  }
+
+  fn on_fill(&mut self, fill: &Fill) -> Result<()> {
+    ExecutionAlgo::on_fill(self, fill);
+    Ok(())
+  }
+
+  fn on_order_finished(&mut self, client_order_id: &str, status: OrderStatus) {
+    ExecutionAlgo::on_order_finished(self, client_order_id, status)
+  }
+}
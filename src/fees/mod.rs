@@ -0,0 +1,139 @@
+use crate::binance::api::{AccountInfoResp, CommissionResp};
+use crate::shared::config::FeeSetting;
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// Spot maker and taker rates of VIP 0 to 9, in basis points
+pub const VIP_TIERS: [(f64, f64); 10] = [
+  (10.0, 10.0),
+  (9.0, 10.0),
+  (8.0, 10.0),
+  (4.2, 6.0),
+  (4.2, 5.4),
+  (3.6, 4.8),
+  (3.0, 4.2),
+  (2.4, 3.6),
+  (1.8, 3.0),
+  (1.2, 2.4),
+];
+
+/// Maker and taker commission as fractions of the notional
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeRates {
+  pub maker: f64,
+  pub taker: f64,
+}
+
+impl FeeRates {
+  pub fn from_bps(maker_bps: f64, taker_bps: f64) -> Self {
+    Self {
+      maker: maker_bps / 1e4,
+      taker: taker_bps / 1e4,
+    }
+  }
+
+  /// Rate of a fill that rested on the book if `maker`, that crossed it otherwise
+  pub fn rate(&self, maker: bool) -> f64 {
+    if maker {
+      self.maker
+    } else {
+      self.taker
+    }
+  }
+
+  fn discounted(&self, discount: f64) -> Self {
+    Self {
+      maker: self.maker * (1.0 - discount),
+      taker: self.taker * (1.0 - discount),
+    }
+  }
+}
+
+/// Commission rates by symbol. Configured overrides come first, then
+/// rates read from the account's commission endpoint, then the account
+/// or VIP tier wide rates. Paying in BNB takes the discount off whichever
+/// applies, the commission endpoint tells whether the account does.
+#[derive(Clone, Debug, Default)]
+pub struct FeeModel {
+  pub default: FeeRates,
+  pub bnb_discount: Option<f64>, // fraction taken off, None when fees aren't paid in BNB
+  account: HashMap<String, (FeeRates, Option<f64>)>, // rates and discount by symbol
+  overrides: HashMap<String, FeeRates>,
+}
+
+impl FeeModel {
+  pub fn new(setting: &FeeSetting) -> Self {
+    let (tier_maker, tier_taker) = VIP_TIERS[setting.vip_tier];
+    let default = FeeRates::from_bps(
+      setting.maker_bps.unwrap_or(tier_maker),
+      setting.taker_bps.unwrap_or(tier_taker),
+    );
+    let overrides = setting
+      .symbols
+      .iter()
+      .map(|(symbol, rates)| {
+        let rates = FeeRates::from_bps(
+          rates.maker_bps.unwrap_or(default.maker * 1e4),
+          rates.taker_bps.unwrap_or(default.taker * 1e4),
+        );
+        (symbol.to_uppercase(), rates)
+      })
+      .collect();
+    Self {
+      default,
+      bnb_discount: if setting.bnb_discount {
+        Some(setting.bnb_discount_pct / 100.0)
+      } else {
+        None
+      },
+      account: HashMap::new(),
+      overrides,
+    }
+  }
+
+  /// Take the account wide rates, given in basis points
+  pub fn set_account(&mut self, account: &AccountInfoResp) {
+    self.default = FeeRates::from_bps(
+      account.maker_commission as f64,
+      account.taker_commission as f64,
+    );
+  }
+
+  /// Take the rates and BNB discount of one symbol, taxes included
+  pub fn set_commission(&mut self, commission: &CommissionResp) -> Result<()> {
+    let (standard, tax) = (&commission.standard_commission, &commission.tax_commission);
+    let rates = FeeRates {
+      maker: standard.maker.parse::<f64>()? + tax.maker.parse::<f64>()?,
+      taker: standard.taker.parse::<f64>()? + tax.taker.parse::<f64>()?,
+    };
+    let discount = &commission.discount;
+    let discount = if discount.enabled_for_account && discount.enabled_for_symbol {
+      Some(1.0 - discount.discount.parse::<f64>()?)
+    } else {
+      None
+    };
+    self
+      .account
+      .insert(commission.symbol.to_uppercase(), (rates, discount));
+    Ok(())
+  }
+
+  /// Rates `symbol` trades at, net of the BNB discount
+  pub fn rates(&self, symbol: &str) -> FeeRates {
+    let symbol = symbol.to_uppercase();
+    let (rates, discount) = match (self.overrides.get(&symbol), self.account.get(&symbol)) {
+      (Some(rates), _) => (*rates, self.bnb_discount),
+      (None, Some((rates, discount))) => (*rates, *discount),
+      (None, None) => (self.default, self.bnb_discount),
+    };
+    match discount {
+      Some(discount) => rates.discounted(discount),
+      None => rates,
+    }
+  }
+
+  /// Commission of a fill of `notional` quote asset
+  pub fn fee(&self, symbol: &str, maker: bool, notional: f64) -> f64 {
+    self.rates(symbol).rate(maker) * notional
+  }
+}
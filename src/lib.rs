@@ -1,3 +1,16 @@
+pub mod arbitrage;
+pub mod archive;
+pub mod backtest;
 pub mod binance;
+pub mod btc_analysis;
+pub mod execution;
+pub mod fees;
+pub mod montecarlo;
+pub mod oms;
+pub mod optimize;
+pub mod portfolio;
+pub mod risk;
 pub mod shared;
+pub mod spread;
 pub mod strategy;
+pub mod venue;
@@ -0,0 +1,404 @@
+use crate::backtest::{BacktestReport, SimTrade};
+use crate::binance::api::KlineResp;
+use crate::shared::utils::QTY_EPSILON;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+const YEAR_MS: f64 = 365.0 * 86_400_000.0;
+
+/// Profit of a fill that reduced a position, against the average cost
+/// of the position
+#[derive(Clone, Debug)]
+pub struct ClosedTrade {
+  pub time: i64,
+  pub symbol: String,
+  pub pnl: f64, // in quote asset
+}
+
+/// Realized profit net of commissions of every fill that reduced a
+/// position, fills adding to a position move its average cost. Shorts
+/// are negative positions.
+pub fn closed_trades(trades: &[SimTrade]) -> Vec<ClosedTrade> {
+  let mut positions: HashMap<&str, (f64, f64)> = HashMap::new(); // quantity and average cost
+  let mut closed = vec![];
+  for trade in trades {
+    // Commission moves what the fill cost or brought in per unit
+    let per_unit_fee = trade.commission / trade.quantity;
+    let (signed, price) = match trade.side.as_str() {
+      "BUY" => (trade.quantity, trade.price + per_unit_fee),
+      _ => (-trade.quantity, trade.price - per_unit_fee),
+    };
+    let (position, cost) = positions.entry(&trade.symbol).or_insert((0.0, 0.0));
+    if position.abs() < QTY_EPSILON || position.signum() == signed.signum() {
+      let quantity = position.abs() + trade.quantity;
+      *cost = (position.abs() * *cost + trade.quantity * price) / quantity;
+      *position += signed;
+      continue;
+    }
+    let reduced = trade.quantity.min(position.abs());
+    closed.push(ClosedTrade {
+      time: trade.time,
+      symbol: trade.symbol.clone(),
+      pnl: reduced * (price - *cost) * position.signum(),
+    });
+    *position += signed;
+    // What is left over after flipping sides was bought or sold at this price
+    if trade.quantity > reduced {
+      *cost = price;
+    }
+  }
+  closed
+}
+
+/// Final equity, max drawdown and Sharpe ratio of one path
+#[derive(Clone, Debug, Serialize)]
+pub struct PathStats {
+  pub final_equity: f64,
+  pub max_drawdown: f64, // fraction of the running peak
+  pub sharpe: f64,
+}
+
+impl From<&BacktestReport> for PathStats {
+  fn from(report: &BacktestReport) -> Self {
+    Self {
+      final_equity: report.final_equity,
+      max_drawdown: report.max_drawdown,
+      sharpe: report.sharpe_ratio(),
+    }
+  }
+}
+
+impl PathStats {
+  /// Stats of the equity `pnls` take `initial_equity` through, one trade
+  /// at a time. The Sharpe ratio is of the returns of each trade on the
+  /// equity before it, annualized at `trades_per_year`.
+  pub fn of_pnls(pnls: &[f64], initial_equity: f64, trades_per_year: f64) -> Self {
+    let mut equity = initial_equity;
+    let mut peak = initial_equity;
+    let mut max_drawdown: f64 = 0.0;
+    let mut returns = Vec::with_capacity(pnls.len());
+    for pnl in pnls {
+      if equity > 0.0 {
+        returns.push(pnl / equity);
+      }
+      equity += pnl;
+      peak = peak.max(equity);
+      if peak > 0.0 {
+        max_drawdown = max_drawdown.max((peak - equity) / peak);
+      }
+    }
+    Self {
+      final_equity: equity,
+      max_drawdown,
+      sharpe: sharpe(&returns, trades_per_year),
+    }
+  }
+}
+
+// Mean over standard deviation scaled by the square root of `per_year`,
+// 0 without volatility
+fn sharpe(returns: &[f64], per_year: f64) -> f64 {
+  if returns.len() < 2 {
+    return 0.0;
+  }
+  let count = returns.len() as f64;
+  let mean = returns.iter().sum::<f64>() / count;
+  let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / count;
+  if variance <= 0.0 {
+    return 0.0;
+  }
+  mean / variance.sqrt() * per_year.sqrt()
+}
+
+/// Trades per year of `trades`, 1 when they span no time so the Sharpe
+/// ratio stays per trade
+pub fn trades_per_year(trades: &[ClosedTrade]) -> f64 {
+  match (trades.first(), trades.last()) {
+    (Some(first), Some(last)) if last.time > first.time => {
+      trades.len() as f64 / ((last.time - first.time) as f64 / YEAR_MS)
+    }
+    _ => 1.0,
+  }
+}
+
+/// `runs` paths of as many trades as `pnls` drawn with replacement
+pub fn bootstrap_trades<R: Rng>(
+  pnls: &[f64],
+  initial_equity: f64,
+  trades_per_year: f64,
+  runs: usize,
+  rng: &mut R,
+) -> Vec<PathStats> {
+  (0..runs)
+    .map(|_| {
+      let sample = (0..pnls.len())
+        .map(|_| pnls[rng.gen_range(0..pnls.len())])
+        .collect::<Vec<_>>();
+      PathStats::of_pnls(&sample, initial_equity, trades_per_year)
+    })
+    .collect()
+}
+
+/// `runs` paths of the same trades in random order, final equity stays
+/// put while drawdown and Sharpe move with the order
+pub fn shuffle_trades<R: Rng>(
+  pnls: &[f64],
+  initial_equity: f64,
+  trades_per_year: f64,
+  runs: usize,
+  rng: &mut R,
+) -> Vec<PathStats> {
+  let mut order = pnls.to_vec();
+  (0..runs)
+    .map(|_| {
+      order.shuffle(rng);
+      PathStats::of_pnls(&order, initial_equity, trades_per_year)
+    })
+    .collect()
+}
+
+/// Synthetic klines stitched from random runs of `block` consecutive
+/// historical candles, so volatility clusters and short term trends
+/// survive. Each candle keeps its open, high, low and close relative to
+/// the close before it, the path starts from `start_close` and its
+/// candles follow each other from `start_time` at the historical spacing.
+pub fn block_bootstrap<R: Rng>(
+  history: &[KlineResp],
+  block: usize,
+  len: usize,
+  start_close: f64,
+  start_time: i64,
+  rng: &mut R,
+) -> Vec<KlineResp> {
+  if history.len() < 2 {
+    return vec![];
+  }
+  let interval = history[1].open_time - history[0].open_time;
+  let steps = history
+    .windows(2)
+    .map(|pair| (pair[0].close, &pair[1]))
+    .collect::<Vec<_>>();
+  let block = block.clamp(1, steps.len());
+  let mut klines = Vec::with_capacity(len);
+  let mut close = start_close;
+  while klines.len() < len {
+    let start = rng.gen_range(0..=steps.len() - block);
+    for (prev_close, kline) in &steps[start..start + block] {
+      if klines.len() == len {
+        break;
+      }
+      let ratio = close / prev_close;
+      let open_time = start_time + klines.len() as i64 * interval;
+      klines.push(KlineResp {
+        open_time,
+        open: kline.open * ratio,
+        high: kline.high * ratio,
+        low: kline.low * ratio,
+        close: kline.close * ratio,
+        close_time: open_time + interval - 1,
+        ..(*kline).clone()
+      });
+      close = kline.close * ratio;
+    }
+  }
+  klines
+}
+
+/// 5th, 25th, 50th, 75th and 95th percentile, linearly interpolated
+#[derive(Clone, Debug)]
+pub struct Percentiles {
+  pub p5: f64,
+  pub p25: f64,
+  pub p50: f64,
+  pub p75: f64,
+  pub p95: f64,
+}
+
+impl Percentiles {
+  pub fn of(values: &[f64]) -> Self {
+    let mut sorted = values
+      .iter()
+      .copied()
+      .filter(|v| !v.is_nan())
+      .collect::<Vec<_>>();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let at = |q: f64| {
+      if sorted.is_empty() {
+        return f64::NAN;
+      }
+      let rank = q * (sorted.len() - 1) as f64;
+      let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+      sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+    };
+    Self {
+      p5: at(0.05),
+      p25: at(0.25),
+      p50: at(0.5),
+      p75: at(0.75),
+      p95: at(0.95),
+    }
+  }
+}
+
+/// Percentiles of every stat over a set of paths
+#[derive(Clone, Debug)]
+pub struct Distribution {
+  pub runs: usize,
+  pub final_equity: Percentiles,
+  pub max_drawdown: Percentiles,
+  pub sharpe: Percentiles,
+}
+
+impl Distribution {
+  pub fn of(paths: &[PathStats]) -> Self {
+    let stat = |f: fn(&PathStats) -> f64| paths.iter().map(f).collect::<Vec<_>>();
+    Self {
+      runs: paths.len(),
+      final_equity: Percentiles::of(&stat(|path| path.final_equity)),
+      max_drawdown: Percentiles::of(&stat(|path| path.max_drawdown * 100.0)),
+      sharpe: Percentiles::of(&stat(|path| path.sharpe)),
+    }
+  }
+}
+
+impl fmt::Display for Distribution {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(
+      f,
+      "{:<16} {:>12} {:>12} {:>12} {:>12} {:>12}",
+      format!("{} runs", self.runs),
+      "5%",
+      "25%",
+      "50%",
+      "75%",
+      "95%"
+    )?;
+    let rows = [
+      ("Final equity", &self.final_equity),
+      ("Max drawdown %", &self.max_drawdown),
+      ("Sharpe ratio", &self.sharpe),
+    ];
+    for (i, (name, p)) in rows.iter().enumerate() {
+      write!(
+        f,
+        "{:<16} {:>12.4} {:>12.4} {:>12.4} {:>12.4} {:>12.4}",
+        name, p.p5, p.p25, p.p50, p.p75, p.p95
+      )?;
+      if i + 1 < rows.len() {
+        writeln!(f)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::rngs::StdRng;
+  use rand::SeedableRng;
+
+  fn trade(side: &str, price: f64, quantity: f64, commission: f64) -> SimTrade {
+    SimTrade {
+      time: 0,
+      symbol: "BTCUSDT".into(),
+      side: side.into(),
+      price,
+      quantity,
+      client_order_id: String::new(),
+      commission,
+      commission_asset: "USDT".into(),
+    }
+  }
+
+  fn kline(minute: i64, close: f64) -> KlineResp {
+    KlineResp {
+      open_time: minute * 60_000,
+      open: close * 0.99,
+      high: close * 1.01,
+      low: close * 0.98,
+      close,
+      volume: 1.0,
+      close_time: (minute + 1) * 60_000 - 1,
+      quote_asset_vol: close,
+      num_trades: 1,
+      taker_buy_base_asset_vol: 0.5,
+      taker_buy_quote_asset_vol: close / 2.0,
+    }
+  }
+
+  fn assert_close(a: f64, b: f64) {
+    assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+  }
+
+  #[test]
+  fn closed_trades_follow_the_average_cost() {
+    let pnls = |trades: &[SimTrade]| {
+      closed_trades(trades)
+        .iter()
+        .map(|trade| trade.pnl)
+        .collect::<Vec<_>>()
+    };
+    let trades = [
+      trade("BUY", 100.0, 1.0, 0.0),
+      trade("BUY", 120.0, 1.0, 0.0),
+      trade("SELL", 130.0, 1.0, 0.0),
+      // Closes the last unit and goes short 2 at 90
+      trade("SELL", 90.0, 3.0, 0.0),
+      trade("BUY", 80.0, 2.0, 0.0),
+    ];
+    let closed = pnls(&trades);
+    assert_eq!(closed.len(), 3);
+    assert_close(closed[0], 20.0);
+    assert_close(closed[1], -20.0);
+    assert_close(closed[2], 20.0);
+
+    // Commissions come out of both sides
+    let closed = pnls(&[
+      trade("BUY", 100.0, 2.0, 2.0),
+      trade("SELL", 110.0, 2.0, 2.0),
+    ]);
+    assert_eq!(closed.len(), 1);
+    assert_close(closed[0], 16.0);
+  }
+
+  #[test]
+  fn percentiles_interpolate_between_values() {
+    let p = Percentiles::of(&[5.0, f64::NAN, 1.0, 4.0, 2.0, 3.0]);
+    assert_close(p.p5, 1.2);
+    assert_close(p.p25, 2.0);
+    assert_close(p.p50, 3.0);
+    assert_close(p.p75, 4.0);
+    assert_close(p.p95, 4.8);
+    assert!(Percentiles::of(&[]).p50.is_nan());
+  }
+
+  #[test]
+  fn bootstrapped_closes_carry_on_across_blocks() {
+    let history = [100.0, 110.0, 99.0, 108.9]
+      .iter()
+      .enumerate()
+      .map(|(minute, close)| kline(minute as i64, *close))
+      .collect::<Vec<_>>();
+    let mut rng = StdRng::seed_from_u64(1);
+    // One block spans every step, so each block replays the history
+    let path = block_bootstrap(&history, 3, 6, 50.0, 1_000, &mut rng);
+    assert_eq!(path.len(), 6);
+    let ratios = [1.1, 0.9, 1.1];
+    let mut close = 50.0;
+    for (i, kline) in path.iter().enumerate() {
+      let ratio = ratios[i % 3];
+      assert_close(kline.close, close * ratio);
+      // The rest of the candle moves with its close
+      assert_close(kline.open, close * ratio * 0.99);
+      assert_close(kline.high, close * ratio * 1.01);
+      assert_eq!(kline.open_time, 1_000 + i as i64 * 60_000);
+      assert_eq!(kline.close_time, kline.open_time + 59_999);
+      close = kline.close;
+    }
+    assert!(block_bootstrap(&history[..1], 3, 6, 50.0, 0, &mut rng).is_empty());
+  }
+}
@@ -0,0 +1,807 @@
+use crate::backtest::SimTrade;
+use crate::binance::api::{OrderInput, OrderResp, OrderSide, RequestError};
+use crate::binance::futures::api::FuturesOrderResp;
+use crate::binance::websocket::ExecutionReport;
+use crate::shared::utils::QTY_EPSILON;
+use crate::strategy::Strategy;
+use anyhow::{bail, ensure, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+
+// Finished orders kept around to match late updates against
+const MAX_FINISHED_ORDERS: usize = 1000;
+// Binance rejects client order ids longer than this
+const MAX_CLIENT_ORDER_ID_LEN: usize = 36;
+
+/// Order state, `Unknown` is ours: the request failed in a way that
+/// doesn't tell whether the order reached the exchange
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrderStatus {
+  Unknown,
+  PendingNew,
+  New,
+  PartiallyFilled,
+  PendingCancel,
+  Filled,
+  Canceled,
+  Rejected,
+  Expired,
+}
+
+impl OrderStatus {
+  pub fn is_final(self) -> bool {
+    matches!(
+      self,
+      OrderStatus::Filled | OrderStatus::Canceled | OrderStatus::Rejected | OrderStatus::Expired
+    )
+  }
+
+  // Order of the states in an order's life, updates never move back
+  fn rank(self) -> u8 {
+    match self {
+      OrderStatus::Unknown | OrderStatus::PendingNew => 0,
+      OrderStatus::New => 1,
+      OrderStatus::PartiallyFilled => 2,
+      OrderStatus::PendingCancel => 3,
+      _ => 4,
+    }
+  }
+}
+
+impl FromStr for OrderStatus {
+  type Err = anyhow::Error;
+
+  fn from_str(status: &str) -> Result<Self> {
+    Ok(match status {
+      "PENDING_NEW" => OrderStatus::PendingNew,
+      "NEW" => OrderStatus::New,
+      "PARTIALLY_FILLED" => OrderStatus::PartiallyFilled,
+      "PENDING_CANCEL" => OrderStatus::PendingCancel,
+      "FILLED" => OrderStatus::Filled,
+      "CANCELED" => OrderStatus::Canceled,
+      "REJECTED" => OrderStatus::Rejected,
+      "EXPIRED" | "EXPIRED_IN_MATCH" => OrderStatus::Expired,
+      _ => bail!("Unknown order status: {}", status),
+    })
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Fill {
+  pub client_order_id: String,
+  pub order_id: Option<i64>,
+  pub trade_id: Option<i64>,
+  pub symbol: String,
+  pub side: OrderSide,
+  pub price: f64,
+  pub quantity: f64,
+  pub commission: f64,
+  pub commission_asset: Option<String>,
+  pub time: i64,
+  // Quantity of fills synthesized for trades an update didn't list that
+  // this fill's trade turned out to be. Such a fill adds no quantity and
+  // only carries the commission reported for the trade.
+  pub replaces_qty: f64,
+}
+
+/// One trade reported along with an order update
+#[derive(Clone, Debug)]
+pub struct TradeUpdate {
+  pub trade_id: Option<i64>,
+  pub price: f64,
+  pub quantity: f64,
+  pub commission: f64,
+  pub commission_asset: Option<String>,
+}
+
+/// Order state from a REST response, an execution report or the
+/// simulator. `cumulative_qty` is authoritative, trades it covers that
+/// aren't in `trades` are filled in at the average price.
+#[derive(Clone, Debug)]
+pub struct OrderUpdate {
+  pub client_order_id: String,
+  pub order_id: Option<i64>,
+  pub symbol: String,
+  pub side: Option<OrderSide>,
+  pub status: Option<OrderStatus>,
+  pub cumulative_qty: f64,
+  pub cumulative_quote_qty: f64,
+  pub trades: Vec<TradeUpdate>,
+  pub time: i64,
+}
+
+fn parse_status(status: &str) -> Option<OrderStatus> {
+  match status.parse::<OrderStatus>() {
+    Ok(status) => Some(status),
+    Err(e) => {
+      log::warn!("{}, keeping the previous status", e);
+      None
+    }
+  }
+}
+
+fn parse_qty(qty: &Option<String>) -> f64 {
+  qty
+    .as_ref()
+    .and_then(|qty| qty.parse::<f64>().ok())
+    .unwrap_or_default()
+}
+
+impl OrderUpdate {
+  pub fn from_order_resp(resp: &OrderResp) -> Self {
+    let trades = resp
+      .fills
+      .iter()
+      .map(|fill| TradeUpdate {
+        trade_id: fill.trade_id,
+        price: fill.price.parse::<f64>().unwrap_or_default(),
+        quantity: fill.qty.parse::<f64>().unwrap_or_default(),
+        commission: fill.commission.parse::<f64>().unwrap_or_default(),
+        commission_asset: Some(fill.commission_asset.clone()),
+      })
+      .collect();
+    Self {
+      // Cancel responses carry the id of the cancel request in
+      // client_order_id and the order's own id in orig_client_order_id
+      client_order_id: resp
+        .orig_client_order_id
+        .clone()
+        .unwrap_or_else(|| resp.client_order_id.clone()),
+      order_id: Some(resp.order_id),
+      symbol: resp.symbol.clone(),
+      side: resp.side.as_deref().and_then(|side| side.parse().ok()),
+      // ACK responses have no status, the order was accepted
+      status: match &resp.status {
+        Some(status) => parse_status(status),
+        None => Some(OrderStatus::New),
+      },
+      cumulative_qty: parse_qty(&resp.executed_qty),
+      cumulative_quote_qty: parse_qty(&resp.cummulative_quote_qty),
+      trades,
+      time: resp
+        .transact_time
+        .or(resp.update_time)
+        .or(resp.time)
+        .unwrap_or_else(crate::shared::utils::get_timestamp),
+    }
+  }
+
+  pub fn from_execution_report(report: &ExecutionReport) -> Self {
+    let mut trades = vec![];
+    if report.execution_type == "TRADE" {
+      trades.push(TradeUpdate {
+        trade_id: Some(report.trade_id),
+        price: report.last_executed_price.parse().unwrap_or_default(),
+        quantity: report.last_executed_qty.parse().unwrap_or_default(),
+        commission: report.commission.parse().unwrap_or_default(),
+        commission_asset: report.commission_asset.clone(),
+      });
+    }
+    Self {
+      client_order_id: if report.orig_client_order_id.is_empty() {
+        report.client_order_id.clone()
+      } else {
+        report.orig_client_order_id.clone()
+      },
+      order_id: Some(report.order_id),
+      symbol: report.symbol.clone(),
+      side: report.side.parse().ok(),
+      status: parse_status(&report.order_status),
+      cumulative_qty: report.cumulative_filled_qty.parse().unwrap_or_default(),
+      cumulative_quote_qty: report.cumulative_quote_qty.parse().unwrap_or_default(),
+      trades,
+      time: report.transaction_time,
+    }
+  }
+
+  /// Futures responses only carry totals, fills come at the average price
+  pub fn from_futures_order_resp(resp: &FuturesOrderResp) -> Self {
+    Self {
+      client_order_id: resp.client_order_id.clone(),
+      order_id: Some(resp.order_id),
+      symbol: resp.symbol.clone(),
+      side: resp.side.parse().ok(),
+      status: parse_status(&resp.status),
+      cumulative_qty: resp.executed_qty.parse().unwrap_or_default(),
+      cumulative_quote_qty: resp.cum_quote.parse().unwrap_or_default(),
+      trades: vec![],
+      time: resp.update_time,
+    }
+  }
+
+  /// Simulated trades always fill the whole order
+  pub fn from_sim_trade(trade: &SimTrade) -> Self {
+    Self {
+      client_order_id: trade.client_order_id.clone(),
+      order_id: None,
+      symbol: trade.symbol.clone(),
+      side: trade.side.parse().ok(),
+      status: Some(OrderStatus::Filled),
+      cumulative_qty: trade.quantity,
+      cumulative_quote_qty: trade.quantity * trade.price,
+      trades: vec![TradeUpdate {
+        trade_id: None,
+        price: trade.price,
+        quantity: trade.quantity,
+        commission: trade.commission,
+        commission_asset: Some(trade.commission_asset.clone()).filter(|asset| !asset.is_empty()),
+      }],
+      time: trade.time,
+    }
+  }
+
+  /// Cancel of an order the simulator doesn't hold, it finishes the
+  /// order if nothing else did and is ignored after a fill
+  pub fn not_resting(client_order_id: &str, time: i64) -> Self {
+    Self {
+      client_order_id: client_order_id.to_string(),
+      order_id: None,
+      symbol: String::new(),
+      side: None,
+      status: Some(OrderStatus::Canceled),
+      cumulative_qty: 0.0,
+      cumulative_quote_qty: 0.0,
+      trades: vec![],
+      time,
+    }
+  }
+
+  /// Status only update, e.g. a resting simulated order or a dry run
+  pub fn status(order: &OrderInput, status: OrderStatus, time: i64) -> Self {
+    Self {
+      client_order_id: order.new_client_order_id.clone(),
+      order_id: None,
+      symbol: order.symbol.clone(),
+      side: Some(order.side.clone()),
+      status: Some(status),
+      cumulative_qty: 0.0,
+      cumulative_quote_qty: 0.0,
+      trades: vec![],
+      time,
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct ManagedOrder {
+  pub client_order_id: String,
+  pub order_id: Option<i64>,
+  pub symbol: String,
+  pub side: OrderSide,
+  pub orig_qty: Option<f64>,
+  pub status: OrderStatus,
+  pub executed_qty: f64,
+  pub cumulative_quote_qty: f64,
+  pub commission: HashMap<String, f64>, // by asset
+  pub fills: Vec<Fill>,
+  pub external: bool, // Not submitted through this manager
+  pub created_at: i64,
+  pub updated_at: i64,
+  trade_ids: HashSet<i64>,
+  synthesized_qty: f64, // filled in without a trade, not reported since
+}
+
+impl ManagedOrder {
+  pub fn avg_price(&self) -> Option<f64> {
+    if self.executed_qty > QTY_EPSILON {
+      Some(self.cumulative_quote_qty / self.executed_qty)
+    } else {
+      None
+    }
+  }
+}
+
+/// What an update changed, for the strategy that placed the order
+#[derive(Debug, Default)]
+pub struct OrderEvents {
+  pub fills: Vec<Fill>,
+  // Set when the update moved the order into a final state
+  pub finished: Option<(String, OrderStatus)>,
+}
+
+impl OrderEvents {
+  /// Pass fills and the final state on to the strategy
+  pub fn dispatch<S: Strategy + ?Sized>(&self, strategy: &mut S) -> Result<()> {
+    for fill in &self.fills {
+      strategy.on_fill(fill)?;
+    }
+    if let Some((client_order_id, status)) = &self.finished {
+      strategy.on_order_finished(client_order_id, *status);
+    }
+    Ok(())
+  }
+}
+
+/// Tracks orders from submission until they are filled, canceled,
+/// rejected or expire, merging REST responses and user stream execution
+/// reports that can arrive in any order
+pub struct OrderManager {
+  prefix: String,
+  next_seq: u64,
+  orders: HashMap<String, ManagedOrder>,
+  finished: VecDeque<String>,
+}
+
+impl OrderManager {
+  /// Generated client order ids are `<prefix>_<start time>_<seq>` so
+  /// they don't collide with ids of a previous run
+  pub fn new(prefix: &str) -> Self {
+    Self {
+      prefix: format!("{}_{}", prefix, crate::shared::utils::get_timestamp()),
+      next_seq: 0,
+      orders: HashMap::new(),
+      finished: VecDeque::new(),
+    }
+  }
+
+  /// Start tracking `order`, assigning a client order id if it has none.
+  /// Returns the client order id.
+  pub fn register(&mut self, order: &mut OrderInput) -> Result<String> {
+    if order.new_client_order_id.is_empty() {
+      self.next_seq += 1;
+      order.new_client_order_id = format!("{}_{}", self.prefix, self.next_seq);
+    }
+    let client_order_id = order.new_client_order_id.clone();
+    ensure!(
+      client_order_id.len() <= MAX_CLIENT_ORDER_ID_LEN,
+      "Client order id {} is longer than {} characters",
+      client_order_id,
+      MAX_CLIENT_ORDER_ID_LEN
+    );
+    ensure!(
+      client_order_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || ".:/_-".contains(c)),
+      "Client order id {} has invalid characters",
+      client_order_id
+    );
+    if let Some(existing) = self.orders.get(&client_order_id) {
+      ensure!(
+        existing.status.is_final(),
+        "Client order id {} is already used by an open order",
+        client_order_id
+      );
+    }
+    self.orders.insert(
+      client_order_id.clone(),
+      ManagedOrder {
+        client_order_id: client_order_id.clone(),
+        order_id: None,
+        symbol: order.symbol.clone(),
+        side: order.side.clone(),
+        orig_qty: order.quantity.map(|qty| qty as f64),
+        status: OrderStatus::PendingNew,
+        executed_qty: 0.0,
+        cumulative_quote_qty: 0.0,
+        commission: HashMap::new(),
+        fills: vec![],
+        external: false,
+        created_at: order.timestamp,
+        updated_at: order.timestamp,
+        trade_ids: HashSet::new(),
+        synthesized_qty: 0.0,
+      },
+    );
+    Ok(client_order_id)
+  }
+
+  /// Record a failed submission. Errors Binance answered with a 4xx
+  /// reject the order, anything else (timeouts, 5xx, dropped
+  /// connections) leaves it `Unknown` until it's reconciled.
+  pub fn on_submit_error(&mut self, client_order_id: &str, err: &anyhow::Error) -> OrderEvents {
+    let rejected = match err.downcast_ref::<RequestError>() {
+      Some(err) => !err.is_unknown_outcome(),
+      None => false,
+    };
+    if rejected {
+      log::warn!("Order {} rejected: {:#}", client_order_id, err);
+      return self.reject(client_order_id);
+    }
+    log::warn!(
+      "Order {} is in an unknown state: {:#}",
+      client_order_id,
+      err
+    );
+    if let Some(order) = self.orders.get_mut(client_order_id) {
+      if order.status == OrderStatus::PendingNew {
+        order.status = OrderStatus::Unknown;
+      }
+    }
+    OrderEvents::default()
+  }
+
+  /// Mark an order that never reached the exchange as rejected
+  pub fn reject(&mut self, client_order_id: &str) -> OrderEvents {
+    match self.orders.get_mut(client_order_id) {
+      Some(order) if !order.status.is_final() => {
+        order.status = OrderStatus::Rejected;
+        self.finish(client_order_id);
+        OrderEvents {
+          fills: vec![],
+          finished: Some((client_order_id.to_string(), OrderStatus::Rejected)),
+        }
+      }
+      _ => OrderEvents::default(),
+    }
+  }
+
+  /// Apply an update and return the new fills. Stale updates never move
+  /// an order back to an earlier state, but fills are always recorded.
+  pub fn on_update(&mut self, update: OrderUpdate) -> OrderEvents {
+    if !self.orders.contains_key(&update.client_order_id) {
+      match &update.side {
+        Some(side) => {
+          log::info!(
+            "Tracking external order {} {}",
+            update.symbol,
+            update.client_order_id
+          );
+          self.orders.insert(
+            update.client_order_id.clone(),
+            ManagedOrder {
+              client_order_id: update.client_order_id.clone(),
+              order_id: update.order_id,
+              symbol: update.symbol.clone(),
+              side: side.clone(),
+              orig_qty: None,
+              status: OrderStatus::Unknown,
+              executed_qty: 0.0,
+              cumulative_quote_qty: 0.0,
+              commission: HashMap::new(),
+              fills: vec![],
+              external: true,
+              created_at: update.time,
+              updated_at: update.time,
+              trade_ids: HashSet::new(),
+              synthesized_qty: 0.0,
+            },
+          );
+        }
+        None => {
+          log::warn!(
+            "Dropping update of unknown order {} without a side",
+            update.client_order_id
+          );
+          return OrderEvents::default();
+        }
+      }
+    }
+
+    let order = self.orders.get_mut(&update.client_order_id).unwrap();
+    let was_final = order.status.is_final();
+    order.order_id = order.order_id.or(update.order_id);
+    order.updated_at = order.updated_at.max(update.time);
+
+    let mut fills = vec![];
+    for trade in &update.trades {
+      if let Some(trade_id) = trade.trade_id {
+        if order.trade_ids.contains(&trade_id) {
+          continue;
+        }
+      }
+      // Already covered by an earlier update that only had totals, its
+      // commission is all that's new
+      if order.executed_qty + trade.quantity > update.cumulative_qty + QTY_EPSILON {
+        if order.synthesized_qty > QTY_EPSILON {
+          if let Some(trade_id) = trade.trade_id {
+            order.trade_ids.insert(trade_id);
+          }
+          fills.push(Self::replace_synthesized(order, trade.clone(), update.time));
+        } else {
+          log::debug!(
+            "Skipping trade {:?} of {}, already accounted for",
+            trade.trade_id,
+            order.client_order_id
+          );
+        }
+        continue;
+      }
+      if let Some(trade_id) = trade.trade_id {
+        order.trade_ids.insert(trade_id);
+      }
+      fills.push(Self::record_fill(order, trade.clone(), update.time));
+    }
+    // Trades the update doesn't list, e.g. RESULT responses or missed
+    // execution reports
+    let missing_qty = update.cumulative_qty - order.executed_qty;
+    if missing_qty > QTY_EPSILON {
+      let missing_quote = update.cumulative_quote_qty - order.cumulative_quote_qty;
+      let trade = TradeUpdate {
+        trade_id: None,
+        price: if missing_quote > 0.0 {
+          missing_quote / missing_qty
+        } else {
+          0.0
+        },
+        quantity: missing_qty,
+        commission: 0.0,
+        commission_asset: None,
+      };
+      order.synthesized_qty += missing_qty;
+      fills.push(Self::record_fill(order, trade, update.time));
+    }
+
+    if let Some(status) = update.status {
+      if !order.status.is_final() && status.rank() >= order.status.rank() {
+        order.status = status;
+      } else if status != order.status {
+        log::debug!(
+          "Ignoring late status {:?} of {}, already {:?}",
+          status,
+          order.client_order_id,
+          order.status
+        );
+      }
+    }
+
+    let finished = if !was_final && order.status.is_final() {
+      log::info!(
+        "Order {} {:?}: {} filled at {:?}",
+        order.client_order_id,
+        order.status,
+        order.executed_qty,
+        order.avg_price()
+      );
+      Some((order.client_order_id.clone(), order.status))
+    } else {
+      None
+    };
+    if finished.is_some() {
+      self.finish(&update.client_order_id);
+    }
+    OrderEvents { fills, finished }
+  }
+
+  pub fn get(&self, client_order_id: &str) -> Option<&ManagedOrder> {
+    self.orders.get(client_order_id)
+  }
+
+  /// Orders that are not in a final state
+  pub fn open_orders(&self) -> impl Iterator<Item = &ManagedOrder> {
+    self
+      .orders
+      .values()
+      .filter(|order| !order.status.is_final())
+  }
+
+  /// Client order ids of orders whose submission had an unknown outcome,
+  /// to be queried from the exchange
+  pub fn unresolved(&self) -> Vec<String> {
+    self
+      .orders
+      .values()
+      .filter(|order| order.status == OrderStatus::Unknown && !order.external)
+      .map(|order| order.client_order_id.clone())
+      .collect()
+  }
+
+  fn record_fill(order: &mut ManagedOrder, trade: TradeUpdate, time: i64) -> Fill {
+    order.executed_qty += trade.quantity;
+    order.cumulative_quote_qty += trade.quantity * trade.price;
+    if let Some(asset) = &trade.commission_asset {
+      *order.commission.entry(asset.clone()).or_insert(0.0) += trade.commission;
+    }
+    let fill = Fill {
+      client_order_id: order.client_order_id.clone(),
+      order_id: order.order_id,
+      trade_id: trade.trade_id,
+      symbol: order.symbol.clone(),
+      side: order.side.clone(),
+      price: trade.price,
+      quantity: trade.quantity,
+      commission: trade.commission,
+      commission_asset: trade.commission_asset,
+      time,
+      replaces_qty: 0.0,
+    };
+    order.fills.push(fill.clone());
+    fill
+  }
+
+  // Report of a trade an earlier synthesized fill stood in for
+  fn replace_synthesized(order: &mut ManagedOrder, trade: TradeUpdate, time: i64) -> Fill {
+    let replaces_qty = trade.quantity.min(order.synthesized_qty);
+    order.synthesized_qty -= replaces_qty;
+    if let Some(asset) = &trade.commission_asset {
+      *order.commission.entry(asset.clone()).or_insert(0.0) += trade.commission;
+    }
+    let fill = Fill {
+      client_order_id: order.client_order_id.clone(),
+      order_id: order.order_id,
+      trade_id: trade.trade_id,
+      symbol: order.symbol.clone(),
+      side: order.side.clone(),
+      price: trade.price,
+      quantity: 0.0,
+      commission: trade.commission,
+      commission_asset: trade.commission_asset,
+      time,
+      replaces_qty,
+    };
+    order.fills.push(fill.clone());
+    fill
+  }
+
+  // Forget the oldest finished orders once there are too many
+  fn finish(&mut self, client_order_id: &str) {
+    self.finished.push_back(client_order_id.to_string());
+    while self.finished.len() > MAX_FINISHED_ORDERS {
+      if let Some(oldest) = self.finished.pop_front() {
+        if matches!(self.orders.get(&oldest), Some(order) if order.status.is_final()) {
+          self.orders.remove(&oldest);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::binance::api::OrderType;
+
+  const ID: &str = "test_1";
+
+  fn manager() -> (OrderManager, String) {
+    let mut oms = OrderManager::new("test");
+    let mut order = OrderInput {
+      symbol: "BTCUSDT".to_string(),
+      side: OrderSide::Buy,
+      order_type: OrderType::Market,
+      time_in_force: None,
+      quantity: Some(3.0),
+      quote_order_qty: None,
+      price: None,
+      new_client_order_id: ID.to_string(),
+      stop_price: None,
+      iceberg_qty: None,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: 1,
+    };
+    let client_order_id = oms.register(&mut order).unwrap();
+    (oms, client_order_id)
+  }
+
+  fn update(status: OrderStatus, cumulative_qty: f64, trades: Vec<TradeUpdate>) -> OrderUpdate {
+    OrderUpdate {
+      client_order_id: ID.to_string(),
+      order_id: Some(42),
+      symbol: "BTCUSDT".to_string(),
+      side: Some(OrderSide::Buy),
+      status: Some(status),
+      cumulative_qty,
+      cumulative_quote_qty: cumulative_qty * 100.0,
+      trades,
+      time: 2,
+    }
+  }
+
+  fn trade(trade_id: i64, quantity: f64) -> TradeUpdate {
+    TradeUpdate {
+      trade_id: Some(trade_id),
+      price: 100.0,
+      quantity,
+      commission: 0.001 * quantity,
+      commission_asset: Some("BNB".to_string()),
+    }
+  }
+
+  #[test]
+  fn duplicate_trade_ids_are_recorded_once() {
+    let (mut oms, id) = manager();
+    let report = update(OrderStatus::PartiallyFilled, 1.0, vec![trade(7, 1.0)]);
+    assert_eq!(oms.on_update(report.clone()).fills.len(), 1);
+    let events = oms.on_update(report);
+    assert!(events.fills.is_empty());
+    assert!(events.finished.is_none());
+    let order = oms.get(&id).unwrap();
+    assert_eq!(order.executed_qty, 1.0);
+    assert_eq!(order.fills.len(), 1);
+    assert_eq!(order.commission["BNB"], 0.001);
+  }
+
+  #[test]
+  fn cumulative_qty_jump_is_synthesized_then_replaced() {
+    let (mut oms, id) = manager();
+    // A response with totals only, the trades come in reports after it
+    let events = oms.on_update(update(OrderStatus::PartiallyFilled, 2.0, vec![]));
+    assert_eq!(events.fills.len(), 1);
+    let fill = &events.fills[0];
+    assert_eq!(
+      (fill.quantity, fill.price, fill.trade_id),
+      (2.0, 100.0, None)
+    );
+    assert_eq!(
+      (fill.commission, fill.commission_asset.as_deref()),
+      (0.0, None)
+    );
+
+    for (trade_id, cumulative_qty) in [(1, 1.0), (2, 2.0)].iter() {
+      let report = update(
+        OrderStatus::PartiallyFilled,
+        *cumulative_qty,
+        vec![trade(*trade_id, 1.0)],
+      );
+      let events = oms.on_update(report);
+      assert_eq!(events.fills.len(), 1);
+      let fill = &events.fills[0];
+      assert_eq!((fill.quantity, fill.replaces_qty), (0.0, 1.0));
+      assert_eq!(
+        (fill.commission, fill.commission_asset.as_deref()),
+        (0.001, Some("BNB"))
+      );
+    }
+    assert!((oms.get(&id).unwrap().commission["BNB"] - 0.002).abs() < 1e-12);
+
+    let events = oms.on_update(update(OrderStatus::Filled, 3.0, vec![trade(3, 1.0)]));
+    assert_eq!(events.fills.len(), 1);
+    assert_eq!(
+      (events.fills[0].quantity, events.fills[0].replaces_qty),
+      (1.0, 0.0)
+    );
+    assert_eq!(events.finished, Some((id.clone(), OrderStatus::Filled)));
+    let order = oms.get(&id).unwrap();
+    assert_eq!(order.executed_qty, 3.0);
+    assert_eq!(order.avg_price(), Some(100.0));
+    assert!((order.commission["BNB"] - 0.003).abs() < 1e-12);
+  }
+
+  #[test]
+  fn late_new_does_not_reopen_a_filled_order() {
+    let (mut oms, id) = manager();
+    let events = oms.on_update(update(OrderStatus::Filled, 3.0, vec![trade(1, 3.0)]));
+    assert_eq!(events.finished, Some((id.clone(), OrderStatus::Filled)));
+    let events = oms.on_update(update(OrderStatus::New, 0.0, vec![]));
+    assert!(events.fills.is_empty());
+    assert!(events.finished.is_none());
+    let order = oms.get(&id).unwrap();
+    assert_eq!(order.status, OrderStatus::Filled);
+    assert_eq!(order.executed_qty, 3.0);
+    assert_eq!(oms.open_orders().count(), 0);
+  }
+
+  #[test]
+  fn unknown_orders_are_reconciled() {
+    let (mut oms, id) = manager();
+    let events = oms.on_submit_error(&id, &anyhow::anyhow!("Connection reset"));
+    assert!(events.finished.is_none());
+    assert_eq!(oms.get(&id).unwrap().status, OrderStatus::Unknown);
+    assert_eq!(oms.unresolved(), vec![id.clone()]);
+
+    // The query finds it filled, without the trades
+    let events = oms.on_update(update(OrderStatus::Filled, 3.0, vec![]));
+    assert_eq!(events.fills.len(), 1);
+    assert_eq!(events.fills[0].quantity, 3.0);
+    assert_eq!(events.finished, Some((id.clone(), OrderStatus::Filled)));
+    assert!(oms.unresolved().is_empty());
+    assert_eq!(oms.get(&id).unwrap().order_id, Some(42));
+  }
+
+  #[test]
+  fn client_errors_reject_the_order() {
+    let (mut oms, id) = manager();
+    let err = anyhow::Error::new(RequestError::new(
+      400,
+      r#"{"code":-2010,"msg":"Account has insufficient balance"}"#,
+    ));
+    let events = oms.on_submit_error(&id, &err);
+    assert_eq!(events.finished, Some((id.clone(), OrderStatus::Rejected)));
+    assert!(oms.unresolved().is_empty());
+  }
+
+  #[test]
+  fn cancels_of_orders_not_resting_finish_open_ones_only() {
+    let (mut oms, id) = manager();
+    oms.on_update(update(OrderStatus::New, 0.0, vec![]));
+    let events = oms.on_update(OrderUpdate::not_resting(&id, 3));
+    assert_eq!(events.finished, Some((id.clone(), OrderStatus::Canceled)));
+    assert_eq!(oms.open_orders().count(), 0);
+
+    let (mut oms, id) = manager();
+    oms.on_update(update(OrderStatus::Filled, 3.0, vec![trade(1, 3.0)]));
+    let events = oms.on_update(OrderUpdate::not_resting(&id, 3));
+    assert!(events.finished.is_none());
+    assert_eq!(oms.get(&id).unwrap().status, OrderStatus::Filled);
+  }
+}
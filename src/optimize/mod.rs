@@ -0,0 +1,556 @@
+use crate::backtest::{BacktestReport, Backtester, SimulatedExchange};
+use crate::binance::api::KlineResp;
+use crate::fees::FeeRates;
+use crate::portfolio::Portfolio;
+use crate::shared::config::TurtleParams;
+use crate::shared::utils::split_symbol;
+use crate::strategy::turtle_trade::Turtle;
+use crate::strategy::CandleStick;
+use anyhow::{anyhow, bail, ensure, Result};
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Parse the values of a parameter, a list like `10,20,55` or an
+/// inclusive range with a step like `1.5:3:0.5`
+pub fn parse_values(spec: &str) -> Result<Vec<f64>> {
+  let parts = spec.split(':').map(str::trim).collect::<Vec<_>>();
+  let values = match parts.as_slice() {
+    [start, end, step] => {
+      let (start, end, step) = (
+        start.parse::<f64>()?,
+        end.parse::<f64>()?,
+        step.parse::<f64>()?,
+      );
+      ensure!(
+        step > 0.0 && start <= end,
+        "Range {} needs start <= end and a positive step",
+        spec
+      );
+      let steps = ((end - start) / step + 1e-9).floor() as usize;
+      (0..=steps).map(|i| start + i as f64 * step).collect()
+    }
+    [list] => list
+      .split(',')
+      .map(|value| Ok(value.trim().parse::<f64>()?))
+      .collect::<Result<Vec<_>>>()?,
+    _ => bail!("{} is neither a list nor a start:end:step range", spec),
+  };
+  ensure!(!values.is_empty(), "No values in {}", spec);
+  Ok(values)
+}
+
+/// `parse_values` for whole numbers
+pub fn parse_counts(spec: &str) -> Result<Vec<usize>> {
+  parse_values(spec)?
+    .into_iter()
+    .map(|value| {
+      let count = value.round();
+      ensure!(
+        count >= 0.0 && (value - count).abs() < 1e-9,
+        "{} in {} is not a whole number",
+        value,
+        spec
+      );
+      Ok(count as usize)
+    })
+    .collect()
+}
+
+/// Values each turtle parameter is searched over
+#[derive(Clone, Debug)]
+pub struct ParamSpace {
+  pub breakout_window: Vec<usize>,
+  pub atr_period: Vec<usize>,
+  pub stop_n: Vec<f64>,
+  pub max_units: Vec<usize>,
+  pub take_profit: Vec<f64>,
+}
+
+impl ParamSpace {
+  /// Every parameter fixed at its value in `params`
+  pub fn fixed(params: &TurtleParams) -> Self {
+    Self {
+      breakout_window: vec![params.breakout_window],
+      atr_period: vec![params.atr_period],
+      stop_n: vec![params.stop_n],
+      max_units: vec![params.max_units],
+      take_profit: vec![params.take_profit],
+    }
+  }
+
+  /// Number of combinations
+  pub fn size(&self) -> usize {
+    self.breakout_window.len()
+      * self.atr_period.len()
+      * self.stop_n.len()
+      * self.max_units.len()
+      * self.take_profit.len()
+  }
+
+  /// Longest lookback of any combination, runs warm up on more klines
+  pub fn max_lookback(&self) -> usize {
+    self
+      .breakout_window
+      .iter()
+      .chain(&self.atr_period)
+      .copied()
+      .max()
+      .unwrap_or(0)
+  }
+
+  /// Every combination, invalid ones left out
+  pub fn grid(&self) -> Vec<TurtleParams> {
+    (0..self.size())
+      .map(|index| self.at(index))
+      .filter(|params| params.validate().is_ok())
+      .collect()
+  }
+
+  /// Up to `count` distinct combinations drawn at random, every valid
+  /// one when the space is that small
+  pub fn sample<R: Rng>(&self, count: usize, rng: &mut R) -> Vec<TurtleParams> {
+    let size = self.size();
+    if count >= size {
+      return self.grid();
+    }
+    let mut drawn = HashSet::new();
+    let mut sample = vec![];
+    // Invalid draws count against the attempts, a mostly invalid space
+    // yields fewer combinations rather than spinning
+    for _ in 0..count * 10 {
+      if sample.len() == count {
+        break;
+      }
+      let index = rng.gen_range(0..size);
+      if !drawn.insert(index) {
+        continue;
+      }
+      let params = self.at(index);
+      if params.validate().is_ok() {
+        sample.push(params);
+      }
+    }
+    sample
+  }
+
+  // Combination number `index`, take_profit varying fastest
+  fn at(&self, mut index: usize) -> TurtleParams {
+    let mut pick = |len: usize| {
+      let value = index % len;
+      index /= len;
+      value
+    };
+    let take_profit = self.take_profit[pick(self.take_profit.len())];
+    let max_units = self.max_units[pick(self.max_units.len())];
+    let stop_n = self.stop_n[pick(self.stop_n.len())];
+    let atr_period = self.atr_period[pick(self.atr_period.len())];
+    let breakout_window = self.breakout_window[pick(self.breakout_window.len())];
+    TurtleParams {
+      breakout_window,
+      atr_period,
+      stop_n,
+      max_units,
+      take_profit,
+    }
+  }
+}
+
+/// What runs are ranked by, higher is better except for drawdown
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+  Return,
+  Sharpe,
+  Calmar,
+  Drawdown,
+}
+
+impl FromStr for Metric {
+  type Err = anyhow::Error;
+
+  fn from_str(metric: &str) -> Result<Self> {
+    Ok(match metric.to_lowercase().as_str() {
+      "return" => Metric::Return,
+      "sharpe" => Metric::Sharpe,
+      "calmar" => Metric::Calmar,
+      "drawdown" => Metric::Drawdown,
+      _ => bail!(
+        "Unknown metric {}, expected return, sharpe, calmar or drawdown",
+        metric
+      ),
+    })
+  }
+}
+
+/// Summary of one backtest run
+#[derive(Clone, Debug)]
+pub struct Metrics {
+  pub total_return: f64,
+  pub max_drawdown: f64,
+  pub sharpe: f64,
+  pub calmar: f64, // total return over max drawdown, not annualized
+  pub trades: usize,
+  pub rejected_orders: usize,
+}
+
+impl From<&BacktestReport> for Metrics {
+  fn from(report: &BacktestReport) -> Self {
+    let total_return = report.total_return();
+    let calmar = match report.max_drawdown {
+      drawdown if drawdown > 0.0 => total_return / drawdown,
+      _ if total_return > 0.0 => f64::INFINITY,
+      _ => 0.0,
+    };
+    Self {
+      total_return,
+      max_drawdown: report.max_drawdown,
+      sharpe: report.sharpe_ratio(),
+      calmar,
+      trades: report.trades.len(),
+      rejected_orders: report.rejected_orders,
+    }
+  }
+}
+
+impl Metrics {
+  pub fn score(&self, metric: Metric) -> f64 {
+    match metric {
+      Metric::Return => self.total_return,
+      Metric::Sharpe => self.sharpe,
+      Metric::Calmar => self.calmar,
+      Metric::Drawdown => -self.max_drawdown,
+    }
+  }
+}
+
+/// Klines and starting balances every run of the turtle replays
+pub struct TurtleBacktest {
+  pub symbol: String,
+  pub klines: Vec<KlineResp>,
+  pub warmup: usize, // klines before a run's range it warms up on
+  pub base_balance: f64,
+  pub quote_balance: f64,
+  pub fees: FeeRates,
+}
+
+impl TurtleBacktest {
+  /// Trade `klines[range]` with the turtle warmed up on the `warmup`
+  /// klines before the range
+  pub fn run(&self, params: &TurtleParams, range: Range<usize>) -> Result<BacktestReport> {
+    ensure!(
+      range.start >= self.warmup && range.start < range.end && range.end <= self.klines.len(),
+      "Klines {:?} out of the {} loaded with {} to warm up on",
+      range,
+      self.klines.len(),
+      self.warmup
+    );
+    let (base_asset, quote_asset) = split_symbol(&self.symbol)?;
+    let mut portfolio = Portfolio::new(&quote_asset);
+    portfolio.set_balance(&base_asset, self.base_balance, 0.0);
+    portfolio.set_balance(&quote_asset, self.quote_balance, 0.0);
+    let warmup = self.klines[range.start - self.warmup..range.start].to_vec();
+    let turtle = Turtle::new(&self.symbol, warmup, params.clone(), portfolio)?;
+    let exchange = SimulatedExchange::new(&self.symbol, self.base_balance, self.quote_balance)?
+      .with_fees(self.fees);
+    let symbol = self.symbol.to_uppercase();
+    let candles = self.klines[range]
+      .iter()
+      .map(|kline| CandleStick::from_kline(symbol.clone(), kline));
+    Backtester::new(turtle, exchange).run(candles)
+  }
+}
+
+/// Run `job` on every item on up to `threads` threads, results come
+/// back in item order
+pub fn par_map<T, R, F>(items: &[T], threads: usize, job: F) -> Vec<R>
+where
+  T: Sync,
+  R: Send,
+  F: Fn(&T) -> R + Sync,
+{
+  let next = AtomicUsize::new(0);
+  let results = Mutex::new(Vec::with_capacity(items.len()));
+  std::thread::scope(|scope| {
+    for _ in 0..threads.clamp(1, items.len().max(1)) {
+      scope.spawn(|| loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        if index >= items.len() {
+          break;
+        }
+        let result = job(&items[index]);
+        results.lock().unwrap().push((index, result));
+      });
+    }
+  });
+  let mut results = results.into_inner().unwrap();
+  results.sort_by_key(|(index, _)| *index);
+  results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct Evaluation {
+  pub params: TurtleParams,
+  pub metrics: Metrics,
+}
+
+/// Backtest every candidate on `range`, best first by `metric`. Runs
+/// that fail are logged and left out.
+pub fn sweep(
+  backtest: &TurtleBacktest,
+  candidates: &[TurtleParams],
+  range: Range<usize>,
+  metric: Metric,
+  threads: usize,
+) -> Vec<Evaluation> {
+  let results = par_map(candidates, threads, |params| {
+    backtest
+      .run(params, range.clone())
+      .map(|report| Metrics::from(&report))
+  });
+  let mut ranking = candidates
+    .iter()
+    .zip(results)
+    .filter_map(|(params, result)| match result {
+      Ok(metrics) => Some(Evaluation {
+        params: params.clone(),
+        metrics,
+      }),
+      Err(e) => {
+        log::warn!("Backtest of {:?} failed: {:#}", params, e);
+        None
+      }
+    })
+    .collect::<Vec<_>>();
+  ranking.sort_by(|a, b| {
+    let (a, b) = (a.metrics.score(metric), b.metrics.score(metric));
+    b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+  });
+  ranking
+}
+
+/// In-sample and out-of-sample kline ranges of a walk-forward fold
+#[derive(Clone, Debug)]
+pub struct Fold {
+  pub in_sample: Range<usize>,
+  pub out_of_sample: Range<usize>,
+}
+
+/// Folds of `train` in-sample klines followed by `test` out-of-sample
+/// ones, rolling forward `test` klines at a time so the out-of-sample
+/// ranges line up back to back. The first fold starts after `warmup`.
+pub fn walk_forward_folds(
+  len: usize,
+  warmup: usize,
+  train: usize,
+  test: usize,
+) -> Result<Vec<Fold>> {
+  ensure!(
+    train > 0 && test > 0,
+    "Folds need in and out of sample klines"
+  );
+  let mut folds = vec![];
+  let mut start = warmup;
+  while start + train + test <= len {
+    folds.push(Fold {
+      in_sample: start..start + train,
+      out_of_sample: start + train..start + train + test,
+    });
+    start += test;
+  }
+  if folds.is_empty() {
+    return Err(anyhow!(
+      "{} klines can't fit {} to warm up, {} in sample and {} out of sample",
+      len,
+      warmup,
+      train,
+      test
+    ));
+  }
+  Ok(folds)
+}
+
+/// Every candidate ranked in sample, and the best one replayed on the
+/// klines that follow
+#[derive(Clone, Debug)]
+pub struct FoldResult {
+  pub fold: Fold,
+  pub ranking: Vec<Evaluation>,
+  pub out_of_sample: Option<Metrics>,
+}
+
+pub fn walk_forward(
+  backtest: &TurtleBacktest,
+  candidates: &[TurtleParams],
+  folds: &[Fold],
+  metric: Metric,
+  threads: usize,
+) -> Vec<FoldResult> {
+  folds
+    .iter()
+    .enumerate()
+    .map(|(i, fold)| {
+      let ranking = sweep(
+        backtest,
+        candidates,
+        fold.in_sample.clone(),
+        metric,
+        threads,
+      );
+      let out_of_sample = ranking.first().and_then(|best| {
+        match backtest.run(&best.params, fold.out_of_sample.clone()) {
+          Ok(report) => Some(Metrics::from(&report)),
+          Err(e) => {
+            log::warn!("Out of sample run of fold {} failed: {:#}", i + 1, e);
+            None
+          }
+        }
+      });
+      FoldResult {
+        fold: fold.clone(),
+        ranking,
+        out_of_sample,
+      }
+    })
+    .collect()
+}
+
+/// Row of the results table, `sample` is `in` or `out` of sample and
+/// `rank` the in-sample rank of the parameters
+#[derive(Serialize, Debug)]
+pub struct ResultRow {
+  pub fold: usize,
+  pub sample: &'static str,
+  pub rank: usize,
+  pub start_time: i64,
+  pub end_time: i64,
+  pub breakout_window: usize,
+  pub atr_period: usize,
+  pub stop_n: f64,
+  pub max_units: usize,
+  pub take_profit: f64,
+  pub total_return: f64,
+  pub max_drawdown: f64,
+  pub sharpe: f64,
+  pub calmar: f64,
+  pub trades: usize,
+  pub rejected_orders: usize,
+}
+
+impl ResultRow {
+  pub fn new(
+    fold: usize,
+    sample: &'static str,
+    rank: usize,
+    klines: &[KlineResp],
+    params: &TurtleParams,
+    metrics: &Metrics,
+  ) -> Self {
+    Self {
+      fold,
+      sample,
+      rank,
+      start_time: klines.first().map_or(0, |kline| kline.open_time),
+      end_time: klines.last().map_or(0, |kline| kline.close_time),
+      breakout_window: params.breakout_window,
+      atr_period: params.atr_period,
+      stop_n: params.stop_n,
+      max_units: params.max_units,
+      take_profit: params.take_profit,
+      total_return: metrics.total_return,
+      max_drawdown: metrics.max_drawdown,
+      sharpe: metrics.sharpe,
+      calmar: metrics.calmar,
+      trades: metrics.trades,
+      rejected_orders: metrics.rejected_orders,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::rngs::StdRng;
+  use rand::SeedableRng;
+
+  fn space() -> ParamSpace {
+    ParamSpace {
+      breakout_window: vec![10, 20],
+      atr_period: vec![14],
+      stop_n: vec![1.0, 2.0],
+      max_units: vec![1],
+      take_profit: vec![1.5, 2.0, 3.0],
+    }
+  }
+
+  #[test]
+  fn parse_values_reads_lists_and_ranges() {
+    assert_eq!(parse_values("10, 20,55").unwrap(), vec![10.0, 20.0, 55.0]);
+    assert_eq!(parse_values("1.5:3:0.5").unwrap(), vec![1.5, 2.0, 2.5, 3.0]);
+    // The end is only kept when a whole step lands on it
+    assert_eq!(parse_values("1:2:0.3").unwrap().len(), 4);
+    assert_eq!(parse_values("2:2:1").unwrap(), vec![2.0]);
+    assert!(parse_values("3:1:1").is_err());
+    assert!(parse_values("1:3:0").is_err());
+    assert!(parse_values("1:3").is_err());
+    assert!(parse_values("1,x").is_err());
+    assert_eq!(parse_counts("10:30:10").unwrap(), vec![10, 20, 30]);
+    assert!(parse_counts("1.5").is_err());
+  }
+
+  #[test]
+  fn at_varies_take_profit_fastest() {
+    let space = space();
+    assert_eq!(space.size(), 12);
+    let first = space.at(0);
+    assert_eq!(
+      (first.breakout_window, first.stop_n, first.take_profit),
+      (10, 1.0, 1.5)
+    );
+    assert_eq!(space.at(1).take_profit, 2.0);
+    let fourth = space.at(3);
+    assert_eq!((fourth.stop_n, fourth.take_profit), (2.0, 1.5));
+    let last = space.at(11);
+    assert_eq!(
+      (last.breakout_window, last.stop_n, last.take_profit),
+      (20, 2.0, 3.0)
+    );
+  }
+
+  #[test]
+  fn sample_draws_distinct_valid_combinations() {
+    let mut space = space();
+    let mut rng = StdRng::seed_from_u64(7);
+    let sample = space.sample(5, &mut rng);
+    assert_eq!(sample.len(), 5);
+    let mut distinct = sample
+      .iter()
+      .map(|p| format!("{:?}", p))
+      .collect::<Vec<_>>();
+    distinct.sort();
+    distinct.dedup();
+    assert_eq!(distinct.len(), 5);
+    // Asking for the whole space returns the grid, invalid ones left out
+    space.take_profit.push(1.0);
+    assert_eq!(space.sample(100, &mut rng).len(), 12);
+    assert!(space
+      .sample(10, &mut rng)
+      .iter()
+      .all(|params| params.validate().is_ok()));
+  }
+
+  #[test]
+  fn folds_roll_forward_by_the_out_of_sample_length() {
+    let folds = walk_forward_folds(100, 10, 40, 20).unwrap();
+    let ranges = folds
+      .iter()
+      .map(|fold| (fold.in_sample.clone(), fold.out_of_sample.clone()))
+      .collect::<Vec<_>>();
+    assert_eq!(ranges, vec![(10..50, 50..70), (30..70, 70..90)]);
+    assert!(walk_forward_folds(60, 10, 40, 20).is_err());
+    assert!(walk_forward_folds(100, 0, 40, 0).is_err());
+  }
+}
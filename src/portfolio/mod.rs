@@ -0,0 +1,435 @@
+use crate::binance::api::OrderSide;
+use crate::fees::FeeModel;
+use crate::oms::Fill;
+use crate::shared::utils::{split_symbol, QTY_EPSILON};
+use anyhow::{ensure, Result};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Balance {
+  pub free: f64, // Negative when the asset is owed, e.g. a spot short
+  pub locked: f64,
+}
+
+impl Balance {
+  pub fn total(&self) -> f64 {
+    self.free + self.locked
+  }
+}
+
+/// Net position in one symbol, `quantity` is negative when short
+#[derive(Clone, Debug)]
+pub struct Position {
+  pub symbol: String,
+  pub base_asset: String,
+  pub quote_asset: String,
+  pub quantity: f64,
+  pub avg_entry_price: f64,
+  pub realized_pnl: f64, // in quote asset, before fees
+  pub fees: f64,         // in quote asset, at the price when paid
+}
+
+impl Position {
+  fn new(symbol: &str, base_asset: String, quote_asset: String) -> Self {
+    Self {
+      symbol: symbol.to_string(),
+      base_asset,
+      quote_asset,
+      quantity: 0.0,
+      avg_entry_price: 0.0,
+      realized_pnl: 0.0,
+      fees: 0.0,
+    }
+  }
+
+  pub fn is_flat(&self) -> bool {
+    self.quantity.abs() < QTY_EPSILON
+  }
+
+  pub fn unrealized_pnl(&self, mark_price: f64) -> f64 {
+    self.quantity * (mark_price - self.avg_entry_price)
+  }
+
+  // Fills in the direction of the position average into the entry
+  // price, the rest close it at a profit or loss and may flip it
+  fn apply(&mut self, side: &OrderSide, price: f64, quantity: f64) {
+    let signed_qty = match side {
+      OrderSide::Buy => quantity,
+      OrderSide::Sell => -quantity,
+    };
+    if self.is_flat() || self.quantity.signum() == signed_qty.signum() {
+      let total = self.quantity.abs() + quantity;
+      self.avg_entry_price =
+        (self.quantity.abs() * self.avg_entry_price + quantity * price) / total;
+      self.quantity += signed_qty;
+      return;
+    }
+    let closed = self.quantity.abs().min(quantity);
+    self.realized_pnl += closed * (price - self.avg_entry_price) * self.quantity.signum();
+    self.quantity += signed_qty;
+    if self.is_flat() {
+      self.quantity = 0.0;
+      self.avg_entry_price = 0.0;
+    } else if quantity > closed {
+      self.avg_entry_price = price;
+    }
+  }
+}
+
+/// Balances, positions and PnL of an account, updated from fills and
+/// price ticks and valued in `quote_currency`
+#[derive(Clone)]
+pub struct Portfolio {
+  pub quote_currency: String,
+  balances: HashMap<String, Balance>,
+  positions: HashMap<String, Position>,
+  fees_paid: HashMap<String, f64>,             // by commission asset
+  prices: HashMap<String, f64>,                // last price by symbol
+  fee_model: Option<FeeModel>,                 // estimates commissions fills don't report
+  estimated_fees: HashMap<String, (f64, f64)>, // quantity and quote fee by client order id
+}
+
+impl Portfolio {
+  pub fn new(quote_currency: &str) -> Self {
+    Self {
+      quote_currency: quote_currency.to_uppercase(),
+      balances: HashMap::new(),
+      positions: HashMap::new(),
+      fees_paid: HashMap::new(),
+      prices: HashMap::new(),
+      fee_model: None,
+      estimated_fees: HashMap::new(),
+    }
+  }
+
+  /// Charge fills that come without a commission, e.g. trades missing
+  /// from an order response, the taker rate of `fees` in quote asset
+  pub fn set_fee_model(&mut self, fees: FeeModel) {
+    self.fee_model = Some(fees);
+  }
+
+  pub fn set_balance(&mut self, asset: &str, free: f64, locked: f64) {
+    self
+      .balances
+      .insert(asset.to_uppercase(), Balance { free, locked });
+  }
+
+  pub fn balance(&self, asset: &str) -> Balance {
+    self
+      .balances
+      .get(&asset.to_uppercase())
+      .cloned()
+      .unwrap_or_default()
+  }
+
+  pub fn balances(&self) -> &HashMap<String, Balance> {
+    &self.balances
+  }
+
+  /// Move `amount` of free balance into locked, e.g. for a resting order
+  pub fn lock(&mut self, asset: &str, amount: f64) -> Result<()> {
+    let balance = self.balances.entry(asset.to_uppercase()).or_default();
+    ensure!(
+      balance.free >= amount,
+      "Can't lock {} {}, only {} free",
+      amount,
+      asset,
+      balance.free
+    );
+    balance.free -= amount;
+    balance.locked += amount;
+    Ok(())
+  }
+
+  pub fn unlock(&mut self, asset: &str, amount: f64) -> Result<()> {
+    let balance = self.balances.entry(asset.to_uppercase()).or_default();
+    ensure!(
+      balance.locked >= amount,
+      "Can't unlock {} {}, only {} locked",
+      amount,
+      asset,
+      balance.locked
+    );
+    balance.locked -= amount;
+    balance.free += amount;
+    Ok(())
+  }
+
+  pub fn position(&self, symbol: &str) -> Option<&Position> {
+    self.positions.get(&symbol.to_uppercase())
+  }
+
+  pub fn positions(&self) -> impl Iterator<Item = &Position> {
+    self.positions.values()
+  }
+
+  pub fn fees_paid(&self) -> &HashMap<String, f64> {
+    &self.fees_paid
+  }
+
+  /// Last traded or mark price of `symbol`
+  pub fn price(&self, symbol: &str) -> Option<f64> {
+    self.prices.get(&symbol.to_uppercase()).copied()
+  }
+
+  /// Record the last traded or mark price of `symbol`
+  pub fn on_price(&mut self, symbol: &str, price: f64) {
+    self.prices.insert(symbol.to_uppercase(), price);
+  }
+
+  pub fn on_fill(&mut self, fill: &Fill) -> Result<()> {
+    let symbol = fill.symbol.to_uppercase();
+    let (base_asset, quote_asset) = split_symbol(&symbol)?;
+    let refund = self.refund_estimate(fill);
+    if refund > 0.0 {
+      self.balances.entry(quote_asset.clone()).or_default().free += refund;
+      *self.fees_paid.entry(quote_asset.clone()).or_insert(0.0) -= refund;
+    }
+    let notional = fill.quantity * fill.price;
+    let (base_change, quote_change) = match fill.side {
+      OrderSide::Buy => (fill.quantity, -notional),
+      OrderSide::Sell => (-fill.quantity, notional),
+    };
+    self.balances.entry(base_asset.clone()).or_default().free += base_change;
+    self.balances.entry(quote_asset.clone()).or_default().free += quote_change;
+
+    // Commission comes out of the base, the quote or BNB
+    let (commission, commission_asset) = match (&fill.commission_asset, &self.fee_model) {
+      (None, Some(fees)) if fill.quantity > 0.0 => {
+        let fee = fees.fee(&symbol, false, notional);
+        let estimate = self
+          .estimated_fees
+          .entry(fill.client_order_id.clone())
+          .or_insert((0.0, 0.0));
+        estimate.0 += fill.quantity;
+        estimate.1 += fee;
+        (fee, Some(quote_asset.clone()))
+      }
+      _ => (fill.commission, fill.commission_asset.clone()),
+    };
+    let mut fee = 0.0;
+    if let Some(asset) = &commission_asset {
+      if commission > 0.0 {
+        let asset = asset.to_uppercase();
+        self.balances.entry(asset.clone()).or_default().free -= commission;
+        *self.fees_paid.entry(asset.clone()).or_insert(0.0) += commission;
+        fee = if asset == quote_asset {
+          commission
+        } else if asset == base_asset {
+          commission * fill.price
+        } else {
+          match self.price_in(&asset, &quote_asset) {
+            Some(price) => commission * price,
+            None => {
+              log::warn!(
+                "No {} price in {}, leaving fee out of PnL",
+                asset,
+                quote_asset
+              );
+              0.0
+            }
+          }
+        };
+      }
+    }
+
+    let position = self
+      .positions
+      .entry(symbol.clone())
+      .or_insert_with(|| Position::new(&symbol, base_asset, quote_asset));
+    if fill.quantity > 0.0 {
+      position.apply(&fill.side, fill.price, fill.quantity);
+    }
+    position.fees += fee - refund;
+    self.prices.insert(symbol, fill.price);
+    Ok(())
+  }
+
+  // Take back the fee estimated for the part of a synthesized fill that
+  // `fill` reports, as it carries the actual commission
+  fn refund_estimate(&mut self, fill: &Fill) -> f64 {
+    if fill.replaces_qty <= 0.0 {
+      return 0.0;
+    }
+    let (qty, fee) = match self.estimated_fees.get_mut(&fill.client_order_id) {
+      Some(estimate) => estimate,
+      None => return 0.0,
+    };
+    let replaced = fill.replaces_qty.min(*qty);
+    let refund = *fee * replaced / *qty;
+    *qty -= replaced;
+    *fee -= refund;
+    if *qty <= 1e-9 {
+      self.estimated_fees.remove(&fill.client_order_id);
+    }
+    refund
+  }
+
+  /// Unrealized PnL of `symbol` at its last price, in its quote asset
+  pub fn unrealized_pnl(&self, symbol: &str) -> f64 {
+    let symbol = symbol.to_uppercase();
+    match (self.positions.get(&symbol), self.prices.get(&symbol)) {
+      (Some(position), Some(price)) => position.unrealized_pnl(*price),
+      _ => 0.0,
+    }
+  }
+
+  /// Realized PnL of `symbol` net of fees, in its quote asset
+  pub fn realized_pnl(&self, symbol: &str) -> f64 {
+    match self.positions.get(&symbol.to_uppercase()) {
+      Some(position) => position.realized_pnl - position.fees,
+      None => 0.0,
+    }
+  }
+
+  /// Mark to market value of every balance in `quote_currency`. Assets
+  /// without a known price are left out, see `unpriced_assets`.
+  pub fn equity(&self) -> f64 {
+    self
+      .balances
+      .iter()
+      .filter_map(|(asset, balance)| {
+        self
+          .price_in(asset, &self.quote_currency)
+          .map(|price| balance.total() * price)
+      })
+      .sum()
+  }
+
+  /// Assets with a balance but no price in `quote_currency`
+  pub fn unpriced_assets(&self) -> Vec<String> {
+    self
+      .balances
+      .iter()
+      .filter(|(asset, balance)| {
+        balance.total() != 0.0 && self.price_in(asset, &self.quote_currency).is_none()
+      })
+      .map(|(asset, _)| asset.clone())
+      .collect()
+  }
+
+  // Price of one `asset` in `quote`, directly or through the inverse pair
+  fn price_in(&self, asset: &str, quote: &str) -> Option<f64> {
+    if asset == quote {
+      return Some(1.0);
+    }
+    if let Some(price) = self.prices.get(&format!("{}{}", asset, quote)) {
+      return Some(*price);
+    }
+    match self.prices.get(&format!("{}{}", quote, asset)) {
+      Some(price) if *price > 0.0 => Some(1.0 / price),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::shared::config::FeeSetting;
+
+  fn fill(quantity: f64, commission_asset: Option<&str>, replaces_qty: f64) -> Fill {
+    Fill {
+      client_order_id: "test_1".to_string(),
+      order_id: Some(42),
+      trade_id: None,
+      symbol: "BTCUSDT".to_string(),
+      side: OrderSide::Buy,
+      price: 100.0,
+      quantity,
+      commission: if commission_asset.is_some() {
+        0.001
+      } else {
+        0.0
+      },
+      commission_asset: commission_asset.map(String::from),
+      time: 1,
+      replaces_qty,
+    }
+  }
+
+  #[test]
+  fn reported_commission_replaces_the_estimate() {
+    let mut portfolio = Portfolio::new("USDT");
+    portfolio.set_balance("USDT", 1000.0, 0.0);
+    portfolio.set_fee_model(FeeModel::new(&FeeSetting::default()));
+    // Synthesized fill, charged the 10 bps taker rate
+    portfolio.on_fill(&fill(2.0, None, 0.0)).unwrap();
+    assert!((portfolio.fees_paid()["USDT"] - 0.2).abs() < 1e-12);
+
+    portfolio.on_fill(&fill(0.0, Some("BNB"), 1.0)).unwrap();
+    assert!((portfolio.fees_paid()["USDT"] - 0.1).abs() < 1e-12);
+    assert_eq!(portfolio.fees_paid()["BNB"], 0.001);
+    assert!((portfolio.balance("USDT").free - 799.9).abs() < 1e-9);
+    assert_eq!(portfolio.balance("BTC").free, 2.0);
+    let position = portfolio.position("BTCUSDT").unwrap();
+    assert_eq!((position.quantity, position.avg_entry_price), (2.0, 100.0));
+  }
+
+  fn trade(side: OrderSide, price: f64, quantity: f64, commission: f64) -> Fill {
+    Fill {
+      side,
+      price,
+      quantity,
+      commission,
+      commission_asset: Some("USDT".to_string()),
+      ..fill(quantity, None, 0.0)
+    }
+  }
+
+  #[test]
+  fn positions_average_in_close_and_flip() {
+    let mut position = Position::new("BTCUSDT", "BTC".into(), "USDT".into());
+    position.apply(&OrderSide::Buy, 100.0, 1.0);
+    position.apply(&OrderSide::Buy, 120.0, 1.0);
+    assert_eq!((position.quantity, position.avg_entry_price), (2.0, 110.0));
+    position.apply(&OrderSide::Sell, 130.0, 0.5);
+    assert_eq!(position.realized_pnl, 10.0);
+    assert_eq!(position.unrealized_pnl(120.0), 15.0);
+
+    // Closes 1.5 at a loss and goes short 1 from the fill price
+    position.apply(&OrderSide::Sell, 100.0, 2.5);
+    assert_eq!(position.realized_pnl, -5.0);
+    assert_eq!((position.quantity, position.avg_entry_price), (-1.0, 100.0));
+    assert_eq!(position.unrealized_pnl(90.0), 10.0);
+
+    position.apply(&OrderSide::Buy, 95.0, 1.0);
+    assert_eq!(position.realized_pnl, 0.0);
+    assert!(position.is_flat());
+    assert_eq!(position.avg_entry_price, 0.0);
+  }
+
+  #[test]
+  fn pnl_is_net_of_fees_and_marked_at_the_last_price() {
+    let mut portfolio = Portfolio::new("USDT");
+    portfolio.set_balance("USDT", 1000.0, 0.0);
+    portfolio
+      .on_fill(&trade(OrderSide::Buy, 100.0, 2.0, 0.2))
+      .unwrap();
+    portfolio
+      .on_fill(&trade(OrderSide::Sell, 110.0, 1.0, 0.1))
+      .unwrap();
+    assert!((portfolio.realized_pnl("BTCUSDT") - 9.7).abs() < 1e-9);
+    assert_eq!(portfolio.unrealized_pnl("btcusdt"), 10.0);
+    portfolio.on_price("BTCUSDT", 90.0);
+    assert_eq!(portfolio.unrealized_pnl("BTCUSDT"), -10.0);
+    assert!((portfolio.equity() - (1000.0 - 200.2 + 109.9 + 90.0)).abs() < 1e-9);
+  }
+
+  #[test]
+  fn equity_prices_assets_through_the_inverse_pair() {
+    let mut portfolio = Portfolio::new("BTC");
+    portfolio.set_balance("BTC", 1.0, 0.0);
+    portfolio.set_balance("USDT", 15000.0, 5000.0);
+    portfolio.set_balance("ETH", 2.0, 0.0);
+    assert_eq!(portfolio.equity(), 1.0);
+    let mut unpriced = portfolio.unpriced_assets();
+    unpriced.sort();
+    assert_eq!(unpriced, vec!["ETH", "USDT"]);
+
+    portfolio.on_price("BTCUSDT", 20000.0);
+    portfolio.on_price("ETHBTC", 0.05);
+    assert!((portfolio.equity() - 2.1).abs() < 1e-12);
+    assert!(portfolio.unpriced_assets().is_empty());
+  }
+}
@@ -0,0 +1,373 @@
+use crate::binance::api::{OrderInput, OrderSide};
+use crate::oms::Fill;
+use crate::portfolio::Portfolio;
+use crate::shared::config::RiskSetting;
+use anyhow::{bail, Result};
+use std::collections::VecDeque;
+use std::fmt;
+
+const MINUTE_MS: i64 = 60_000;
+const DAY_MS: i64 = 86_400_000;
+
+/// Order refused by the pre-trade checks, with every limit it breaks
+#[derive(Debug)]
+pub struct RiskViolation {
+  pub client_order_id: String,
+  pub reasons: Vec<String>,
+}
+
+impl fmt::Display for RiskViolation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Order {} rejected by risk checks: {}",
+      self.client_order_id,
+      self.reasons.join("; ")
+    )
+  }
+}
+
+impl std::error::Error for RiskViolation {}
+
+/// Checks orders against the `[risk]` limits before they're sent and
+/// trips a kill switch on too large a daily loss or too many failed
+/// orders in a row. Once halted every order is refused, the caller is
+/// expected to cancel open orders and stop the strategy.
+pub struct RiskManager {
+  setting: RiskSetting,
+  portfolio: Portfolio,
+  order_times: VecDeque<i64>, // accepted orders within the last minute
+  consecutive_errors: usize,
+  day: Option<i64>, // days since epoch, UTC
+  day_start_equity: f64,
+  halted: Option<String>,
+}
+
+impl RiskManager {
+  /// `portfolio` holds the starting balances, the risk manager keeps it
+  /// up to date from fills and prices on its own
+  pub fn new(setting: RiskSetting, portfolio: Portfolio) -> Self {
+    Self {
+      setting,
+      portfolio,
+      order_times: VecDeque::new(),
+      consecutive_errors: 0,
+      day: None,
+      day_start_equity: 0.0,
+      halted: None,
+    }
+  }
+
+  pub fn portfolio(&self) -> &Portfolio {
+    &self.portfolio
+  }
+
+  /// Reason the kill switch tripped, if it did
+  pub fn halted(&self) -> Option<&str> {
+    self.halted.as_deref()
+  }
+
+  /// Trip the kill switch, later calls keep the first reason
+  pub fn halt(&mut self, reason: String) {
+    if self.halted.is_none() {
+      log::error!("Kill switch tripped: {}", reason);
+      self.halted = Some(reason);
+    }
+  }
+
+  /// Check `order` with `open_orders` already working, `now` in ms.
+  /// Accepted orders count towards the per minute limit.
+  pub fn check(&mut self, order: &OrderInput, open_orders: usize, now: i64) -> Result<()> {
+    if let Some(reason) = &self.halted {
+      bail!(RiskViolation {
+        client_order_id: order.new_client_order_id.clone(),
+        reasons: vec![format!("trading halted: {}", reason)],
+      });
+    }
+    while let Some(time) = self.order_times.front() {
+      if now - time < MINUTE_MS {
+        break;
+      }
+      self.order_times.pop_front();
+    }
+
+    let setting = &self.setting;
+    let mut reasons = vec![];
+    let last_price = self.portfolio.price(&order.symbol);
+    let price = order.price.map(|price| price as f64).or(last_price);
+
+    let quantity = match (order.quantity, order.quote_order_qty, price) {
+      (Some(quantity), _, _) => Some(quantity as f64),
+      (None, Some(quote_qty), Some(price)) if price > 0.0 => Some(quote_qty as f64 / price),
+      _ => None,
+    };
+    match (quantity, price) {
+      (Some(quantity), Some(price)) => {
+        let notional = quantity * price;
+        if notional > setting.max_order_notional {
+          reasons.push(format!(
+            "notional {:.2} exceeds max_order_notional {}",
+            notional, setting.max_order_notional
+          ));
+        }
+        let signed_qty = match order.side {
+          OrderSide::Buy => quantity,
+          OrderSide::Sell => -quantity,
+        };
+        let position = self
+          .portfolio
+          .position(&order.symbol)
+          .map_or(0.0, |position| position.quantity);
+        let position_notional = ((position + signed_qty) * price).abs();
+        // Orders that shrink the position are always allowed through
+        if position_notional > setting.max_position_notional
+          && position_notional > (position * price).abs()
+        {
+          reasons.push(format!(
+            "position notional {:.2} would exceed max_position_notional {}",
+            position_notional, setting.max_position_notional
+          ));
+        }
+      }
+      _ => reasons.push(format!("no price to value the {} order at", order.symbol)),
+    }
+
+    if let (Some(limit_price), Some(last_price)) = (order.price, last_price) {
+      let deviation = (limit_price as f64 - last_price).abs() / last_price * 100.0;
+      if deviation > setting.price_collar_pct {
+        reasons.push(format!(
+          "price {} is {:.2}% away from last trade {}, collar is {}%",
+          limit_price, deviation, last_price, setting.price_collar_pct
+        ));
+      }
+    }
+    if open_orders >= setting.max_open_orders {
+      reasons.push(format!(
+        "{} open orders, max_open_orders is {}",
+        open_orders, setting.max_open_orders
+      ));
+    }
+    if self.order_times.len() >= setting.max_orders_per_minute {
+      reasons.push(format!(
+        "{} orders in the last minute, max_orders_per_minute is {}",
+        self.order_times.len(),
+        setting.max_orders_per_minute
+      ));
+    }
+
+    if !reasons.is_empty() {
+      bail!(RiskViolation {
+        client_order_id: order.new_client_order_id.clone(),
+        reasons,
+      });
+    }
+    self.order_times.push_back(now);
+    Ok(())
+  }
+
+  /// Record the outcome of sending an order, enough failures in a row
+  /// trip the kill switch
+  pub fn on_order_result<T>(&mut self, result: &Result<T>) {
+    match result {
+      Ok(_) => self.consecutive_errors = 0,
+      Err(e) => {
+        self.consecutive_errors += 1;
+        if self.consecutive_errors >= self.setting.max_consecutive_errors {
+          self.halt(format!(
+            "{} orders failed in a row, last error: {:#}",
+            self.consecutive_errors, e
+          ));
+        }
+      }
+    }
+  }
+
+  pub fn on_fill(&mut self, fill: &Fill) -> Result<()> {
+    self.portfolio.on_fill(fill)?;
+    self.check_daily_loss();
+    Ok(())
+  }
+
+  /// Mark `symbol` at `price`, `time` in ms decides the trading day
+  pub fn on_price(&mut self, symbol: &str, price: f64, time: i64) {
+    self.portfolio.on_price(symbol, price);
+    let day = time.div_euclid(DAY_MS);
+    if self.day != Some(day) {
+      self.day = Some(day);
+      self.day_start_equity = self.portfolio.equity();
+      log::info!(
+        "Start of day equity: {} {}",
+        self.day_start_equity,
+        self.portfolio.quote_currency
+      );
+    }
+    self.check_daily_loss();
+  }
+
+  /// Loss since the start of the UTC day, negative when in profit
+  pub fn daily_loss(&self) -> f64 {
+    match self.day {
+      Some(_) => self.day_start_equity - self.portfolio.equity(),
+      None => 0.0,
+    }
+  }
+
+  fn check_daily_loss(&mut self) {
+    let loss = self.daily_loss();
+    if loss > self.setting.max_daily_loss {
+      self.halt(format!(
+        "daily loss {:.2} exceeds max_daily_loss {}",
+        loss, self.setting.max_daily_loss
+      ));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::binance::api::OrderType;
+
+  const SYMBOL: &str = "BTCUSDT";
+
+  fn manager(setting: RiskSetting) -> RiskManager {
+    let mut portfolio = Portfolio::new("USDT");
+    portfolio.set_balance("USDT", 10_000.0, 0.0);
+    let mut risk = RiskManager::new(setting, portfolio);
+    risk.on_price(SYMBOL, 100.0, 0);
+    risk
+  }
+
+  fn limit(side: OrderSide, price: f32, quantity: f32) -> OrderInput {
+    OrderInput {
+      symbol: SYMBOL.to_string(),
+      side,
+      order_type: OrderType::Limit,
+      time_in_force: None,
+      quantity: Some(quantity),
+      quote_order_qty: None,
+      price: Some(price),
+      new_client_order_id: "test_1".to_string(),
+      stop_price: None,
+      iceberg_qty: None,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: 0,
+    }
+  }
+
+  fn buy(quantity: f64, price: f64) -> Fill {
+    Fill {
+      client_order_id: "test_0".to_string(),
+      order_id: None,
+      trade_id: None,
+      symbol: SYMBOL.to_string(),
+      side: OrderSide::Buy,
+      price,
+      quantity,
+      commission: 0.0,
+      commission_asset: None,
+      time: 0,
+      replaces_qty: 0.0,
+    }
+  }
+
+  #[test]
+  fn orders_per_minute_leave_the_window() {
+    let mut risk = manager(RiskSetting {
+      max_orders_per_minute: 2,
+      ..Default::default()
+    });
+    let order = limit(OrderSide::Buy, 100.0, 1.0);
+    risk.check(&order, 0, 0).unwrap();
+    risk.check(&order, 0, 1_000).unwrap();
+    assert!(risk.check(&order, 0, 59_999).is_err());
+    // The first one is a minute old, the refused one never counted
+    risk.check(&order, 0, 60_000).unwrap();
+    assert!(risk.check(&order, 0, 60_500).is_err());
+  }
+
+  #[test]
+  fn orders_shrinking_the_position_pass_its_limit() {
+    let mut risk = manager(RiskSetting {
+      max_order_notional: 20_000.0,
+      ..Default::default()
+    });
+    // 6000 worth, already past max_position_notional
+    risk.on_fill(&buy(60.0, 100.0)).unwrap();
+    assert!(risk
+      .check(&limit(OrderSide::Buy, 100.0, 1.0), 0, 0)
+      .is_err());
+    risk
+      .check(&limit(OrderSide::Sell, 100.0, 5.0), 0, 0)
+      .unwrap();
+    risk
+      .check(&limit(OrderSide::Sell, 100.0, 115.0), 0, 0)
+      .unwrap();
+    // Selling through flat into a larger short isn't shrinking
+    let err = risk
+      .check(&limit(OrderSide::Sell, 100.0, 125.0), 0, 0)
+      .unwrap_err();
+    assert!(err.to_string().contains("max_position_notional"));
+  }
+
+  #[test]
+  fn limit_prices_stay_within_the_collar() {
+    let mut risk = manager(RiskSetting::default());
+    risk
+      .check(&limit(OrderSide::Buy, 104.9, 1.0), 0, 0)
+      .unwrap();
+    risk
+      .check(&limit(OrderSide::Sell, 95.1, 1.0), 0, 0)
+      .unwrap();
+    let err = risk
+      .check(&limit(OrderSide::Buy, 106.0, 1.0), 0, 0)
+      .unwrap_err();
+    assert!(err.to_string().contains("collar"));
+    assert!(risk
+      .check(&limit(OrderSide::Sell, 94.0, 1.0), 0, 0)
+      .is_err());
+  }
+
+  #[test]
+  fn consecutive_errors_trip_the_kill_switch_once() {
+    let mut risk = manager(RiskSetting {
+      max_consecutive_errors: 2,
+      ..Default::default()
+    });
+    let failed: Result<()> = Err(anyhow::anyhow!("timeout"));
+    risk.on_order_result(&failed);
+    risk.on_order_result(&Ok(()));
+    risk.on_order_result(&failed);
+    assert!(risk.halted().is_none());
+    risk.on_order_result(&failed);
+    let reason = risk.halted().unwrap().to_string();
+    assert!(reason.starts_with("2 orders failed in a row"));
+
+    risk.halt("daily loss".to_string());
+    risk.on_order_result(&failed);
+    assert_eq!(risk.halted(), Some(reason.as_str()));
+    let err = risk
+      .check(&limit(OrderSide::Buy, 100.0, 1.0), 0, 0)
+      .unwrap_err();
+    assert!(err.to_string().contains("trading halted"));
+  }
+
+  #[test]
+  fn daily_loss_starts_over_each_utc_day() {
+    let mut risk = manager(RiskSetting {
+      max_daily_loss: 500.0,
+      ..Default::default()
+    });
+    risk.on_fill(&buy(10.0, 100.0)).unwrap();
+    risk.on_price(SYMBOL, 60.0, DAY_MS - 1);
+    assert!((risk.daily_loss() - 400.0).abs() < 1e-9);
+    // The next day measures from 9600, another 400 is still within it
+    risk.on_price(SYMBOL, 60.0, DAY_MS);
+    assert_eq!(risk.daily_loss(), 0.0);
+    risk.on_price(SYMBOL, 20.0, DAY_MS + 1);
+    assert!(risk.halted().is_none());
+    risk.on_price(SYMBOL, 9.0, DAY_MS + 2);
+    assert!(risk.halted().unwrap().contains("max_daily_loss"));
+  }
+}
@@ -1,27 +1,825 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
+use crate::binance::signer::KeyType;
+use crate::fees::VIP_TIERS;
+use crate::shared::secret::{Secret, SecretSource};
+use crate::shared::utils::parse_interval;
+
+/// Prefix of environment variables overriding config values, nested keys
+/// are separated by `__`, e.g. `CRYPTO_TRADING__RISK__MAX_DAILY_LOSS=500`
+pub const ENV_PREFIX: &str = "CRYPTO_TRADING_";
+
+/// Which environment orders are routed to. Paper trading reads mainnet
+/// market data and fills orders locally.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+  Mainnet,
+  Testnet,
+  Paper,
+}
+
+impl FromStr for Profile {
+  type Err = anyhow::Error;
+
+  fn from_str(profile: &str) -> Result<Self> {
+    Ok(match profile.to_lowercase().as_str() {
+      "mainnet" | "live" => Profile::Mainnet,
+      "testnet" => Profile::Testnet,
+      "paper" => Profile::Paper,
+      _ => bail!(
+        "Unknown profile {}, expected mainnet, testnet or paper",
+        profile
+      ),
+    })
+  }
+}
+
+impl fmt::Display for Profile {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Profile::Mainnet => "mainnet",
+      Profile::Testnet => "testnet",
+      Profile::Paper => "paper",
+    })
+  }
+}
+
+/// One `[[exchanges]]` entry, keys are only needed by commands that
+/// touch the account. See `SecretSource` for how keys can be supplied.
 #[derive(Clone, Debug, Deserialize)]
-pub struct BinanceSetting {
-  pub spot_test_api_key: String,
-  pub spot_test_api_secret: String,
-  pub api_key: String,
-  pub api_secret: String,
-  pub test_host: String,
+pub struct ExchangeSetting {
+  pub name: String,
+  pub profile: Profile,
   pub host: String,
   pub ws_base: String,
+  // WebSocket API endpoint for order entry, optional
+  pub ws_api: Option<String>,
+  // USD-M futures REST and stream endpoints, optional
+  pub futures_host: Option<String>,
+  pub futures_ws_base: Option<String>,
+  // hmac takes the API secret, ed25519 and rsa the PEM private key
+  #[serde(default)]
+  pub key_type: KeyType,
+  pub api_key: Option<SecretSource>,
+  pub api_secret: Option<SecretSource>,
   pub proxy: Option<String>,
-  pub csv_dir: Option<String>,
-  pub symbol: String
+}
+
+impl ExchangeSetting {
+  /// API key and secret, or an error naming the missing one
+  pub fn credentials(&self) -> Result<(Secret, Secret)> {
+    let key = |source: &Option<SecretSource>, name: &str| {
+      let context = || format!("{} of {} {}", name, self.name, self.profile);
+      match source {
+        Some(SecretSource::Plain(secret)) if secret.is_empty() => None,
+        Some(source) => Some(source.resolve().with_context(context)),
+        None => None,
+      }
+      .unwrap_or_else(|| Err(anyhow!("{} is required but is not set", context())))
+    };
+    Ok((
+      key(&self.api_key, "api_key")?,
+      key(&self.api_secret, "api_secret")?,
+    ))
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RecorderSetting {
+  // Directory of recorded and backfilled CSV files
+  pub csv_dir: String,
+  // Defaults to the top level symbol
+  pub symbol: Option<String>,
+}
+
+/// Rules of the turtle strategy, the classic system's by default
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TurtleParams {
+  pub breakout_window: usize, // candles in the rolling high and low entries break out of
+  pub atr_period: usize,      // candles N, the average true range, is smoothed over
+  pub stop_n: f64,            // exit once price moves this many N against the last entry
+  pub max_units: usize,       // entries per side
+  pub take_profit: f64,       // close everything once equity reaches this multiple of the start
+}
+
+impl Default for TurtleParams {
+  fn default() -> Self {
+    Self {
+      breakout_window: 20,
+      atr_period: 20,
+      stop_n: 2.0,
+      max_units: 4,
+      take_profit: 1.5,
+    }
+  }
+}
+
+impl TurtleParams {
+  pub fn validate(&self) -> Result<()> {
+    ensure!(
+      self.breakout_window >= 2 && self.atr_period >= 1,
+      "Turtle needs a breakout_window of at least 2 and an atr_period of at least 1"
+    );
+    ensure!(
+      self.stop_n > 0.0 && self.max_units >= 1 && self.take_profit > 1.0,
+      "Turtle needs a positive stop_n, at least 1 unit and a take_profit above 1"
+    );
+    Ok(())
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TurtleSetting {
+  pub interval: String,
+  pub warmup: usize, // number of candles used to initialize N and the rolling high/low
+  pub params: TurtleParams,
+}
+
+impl Default for TurtleSetting {
+  fn default() -> Self {
+    Self {
+      interval: "1d".into(),
+      warmup: 21,
+      params: TurtleParams::default(),
+    }
+  }
+}
+
+/// Bollinger band and RSI parameters of the mean reversion strategy
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MeanReversionSetting {
+  pub interval: String,
+  pub period: usize, // candles in the moving average and standard deviation
+  pub num_std: f64,  // entry distance from the average in standard deviations
+  pub rsi_period: usize,
+  pub rsi_oversold: f64,   // long entries need the RSI below it
+  pub rsi_overbought: f64, // short entries need the RSI above it
+  pub stop_std: f64,       // stop distance from the entry in standard deviations at entry
+  pub risk_fraction: f64,  // share of equity put into one entry
+  pub long_only: bool,     // no short entries, for spot accounts
+}
+
+impl MeanReversionSetting {
+  /// Closed candles needed before the first signal
+  pub fn warmup(&self) -> usize {
+    self.period.max(self.rsi_period + 1)
+  }
+}
+
+impl Default for MeanReversionSetting {
+  fn default() -> Self {
+    Self {
+      interval: "1h".into(),
+      period: 20,
+      num_std: 2.0,
+      rsi_period: 14,
+      rsi_oversold: 30.0,
+      rsi_overbought: 70.0,
+      stop_std: 3.0,
+      risk_fraction: 0.1,
+      long_only: true,
+    }
+  }
+}
+
+/// Quoting parameters of the market maker, quantities in base asset
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MarketMakerSetting {
+  pub spread_bps: f64,    // distance between bid and ask, around the mid price
+  pub quantity: f64,      // size of each quote
+  pub tick_size: f64,     // quote prices are rounded to it
+  pub skew_bps: f64,      // shift of both quotes at max_inventory, away from the inventory
+  pub max_inventory: f64, // no more quotes that would grow the inventory past it
+  pub min_update_ms: i64, // minimum time between two requotes
+  pub requote_bps: f64,   // how far a quote may drift before it's replaced
+}
+
+impl Default for MarketMakerSetting {
+  fn default() -> Self {
+    Self {
+      spread_bps: 10.0,
+      quantity: 0.001,
+      tick_size: 0.01,
+      skew_bps: 5.0,
+      max_inventory: 0.01,
+      min_update_ms: 500,
+      requote_bps: 1.0,
+    }
+  }
+}
+
+/// How grid levels are spread between the bounds
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GridSpacing {
+  Arithmetic, // same price step between levels
+  Geometric,  // same ratio between levels
+}
+
+/// What the grid does once price leaves its bounds
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GridExit {
+  Stop,     // cancel the ladder and stop trading
+  Recenter, // cancel the ladder and lay it again around the price
+}
+
+/// Ladder of the grid strategy, quantities in base asset
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GridSetting {
+  pub interval: String,
+  pub lower: Option<f64>,
+  pub upper: Option<f64>,
+  pub levels: usize, // price levels between and including the bounds
+  pub spacing: GridSpacing,
+  pub quantity: f64, // order size at every level
+  pub tick_size: f64,
+  pub on_exit: GridExit,
+}
+
+impl Default for GridSetting {
+  fn default() -> Self {
+    Self {
+      interval: "1m".into(),
+      lower: None,
+      upper: None,
+      levels: 10,
+      spacing: GridSpacing::Arithmetic,
+      quantity: 0.001,
+      tick_size: 0.01,
+      on_exit: GridExit::Stop,
+    }
+  }
+}
+
+/// How the pairs strategy estimates the hedge ratio
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HedgeMethod {
+  Ols,    // regression over the last `window` candles
+  Kalman, // hedge ratio and intercept tracked as a random walk
+}
+
+/// Legs, hedge ratio estimation and z-score thresholds of the pairs
+/// strategy. The traded symbol is regressed on `hedge_symbol` in log prices.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PairsSetting {
+  pub interval: String,
+  pub hedge_symbol: String,
+  pub hedge_method: HedgeMethod,
+  pub window: usize,     // candles in the regression, z-score and diagnostics
+  pub kalman_delta: f64, // drift of the Kalman hedge ratio, 0 < delta < 1
+  pub entry_z: f64,      // spread z-score that opens a position
+  pub exit_z: f64,       // z-score the spread closes back within
+  pub stop_z: f64,       // z-score past which the position is stopped out
+  pub notional: f64,     // quote asset per entry in the traded symbol
+  pub require_cointegration: bool, // enter only while Engle-Granger passes at 5%
+}
+
+impl Default for PairsSetting {
+  fn default() -> Self {
+    Self {
+      interval: "1h".into(),
+      hedge_symbol: "btcusdt".into(),
+      hedge_method: HedgeMethod::Ols,
+      window: 100,
+      kalman_delta: 1e-8,
+      entry_z: 2.0,
+      exit_z: 0.5,
+      stop_z: 4.0,
+      notional: 1000.0,
+      require_cointegration: true,
+    }
+  }
+}
+
+/// Symbols and thresholds of the triangular arbitrage detector
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TriangleSetting {
+  pub symbols: Vec<String>,
+  pub start_assets: Vec<String>, // cycles start and end in one of these, any asset if empty
+  pub min_profit_bps: f64,       // net of fees and rounding
+  pub max_notional: f64,         // start asset put into one cycle
+  pub cooldown_ms: i64,          // between two opportunities of the same cycle
+}
+
+impl Default for TriangleSetting {
+  fn default() -> Self {
+    Self {
+      symbols: vec!["btcusdt".into(), "ethbtc".into(), "ethusdt".into()],
+      start_assets: vec!["USDT".into()],
+      min_profit_bps: 5.0,
+      max_notional: 100.0,
+      cooldown_ms: 1000,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct StrategySetting {
+  pub turtle: TurtleSetting,
+  pub mean_reversion: MeanReversionSetting,
+  pub market_maker: MarketMakerSetting,
+  pub grid: GridSetting,
+  pub pairs: PairsSetting,
+  pub triangle: TriangleSetting,
+}
+
+/// Pre-trade limits, every limit is in quote asset unless noted
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RiskSetting {
+  pub max_order_notional: f64,
+  pub max_position_notional: f64,
+  pub max_open_orders: usize,
+  pub max_orders_per_minute: usize,
+  pub max_daily_loss: f64,
+  pub price_collar_pct: f64, // max deviation from last trade price, in percent
+  pub max_consecutive_errors: usize, // failed orders in a row before the kill switch trips
+}
+
+impl Default for RiskSetting {
+  fn default() -> Self {
+    Self {
+      max_order_notional: 1_000.0,
+      max_position_notional: 5_000.0,
+      max_open_orders: 20,
+      max_orders_per_minute: 30,
+      max_daily_loss: 500.0,
+      price_collar_pct: 5.0,
+      max_consecutive_errors: 5,
+    }
+  }
+}
+
+/// Rate overrides of one symbol, in basis points
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SymbolFeeSetting {
+  pub maker_bps: Option<f64>,
+  pub taker_bps: Option<f64>,
+}
+
+/// Commission rates simulated fills pay and arbitrage nets out, the VIP
+/// tier's rates apply unless maker_bps or taker_bps are set
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FeeSetting {
+  pub vip_tier: usize, // 0 to 9, regular accounts are 0
+  pub maker_bps: Option<f64>,
+  pub taker_bps: Option<f64>,
+  pub bnb_discount: bool,                         // fees are paid in BNB
+  pub bnb_discount_pct: f64,                      // taken off every rate when they are
+  pub from_account: bool,                         // live commands read the account's rates instead
+  pub symbols: HashMap<String, SymbolFeeSetting>, // by symbol, override everything else
+}
+
+impl Default for FeeSetting {
+  fn default() -> Self {
+    Self {
+      vip_tier: 0,
+      maker_bps: None,
+      taker_bps: None,
+      bnb_discount: false,
+      bnb_discount_pct: 25.0,
+      from_account: false,
+      symbols: HashMap::new(),
+    }
+  }
+}
+
+/// Fill simulation of backtests on recorded depth and trades
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DepthSetting {
+  pub latency_ms: i64, // between a decision and its order or cancel reaching the book
+  pub queue_position: f64, // share of the quantity shown at a price that is ahead of a new order
+}
+
+impl Default for DepthSetting {
+  fn default() -> Self {
+    Self {
+      latency_ms: 50,
+      queue_position: 1.0,
+    }
+  }
+}
+
+/// Exchanges other than Binance market data can be read from
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Venue {
+  Coinbase,
+}
+
+impl fmt::Display for Venue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Venue::Coinbase => "coinbase",
+    })
+  }
+}
+
+/// Symbol and sampling of the cross venue spread monitor
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SpreadSetting {
+  pub venue: Venue,
+  pub venue_symbol: Option<String>, // e.g. BTC-USDT, derived from the symbol if unset
+  pub sample_ms: i64,               // at most one record per period
+  pub max_age_ms: i64,              // books older than this aren't compared
+}
+
+impl Default for SpreadSetting {
+  fn default() -> Self {
+    Self {
+      venue: Venue::Coinbase,
+      venue_symbol: None,
+      sample_ms: 1000,
+      max_age_ms: 60_000,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BitcoindSetting {
+  pub url: String,
+  pub user: String,
+  pub password: SecretSource,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Setting {
-  pub binance: BinanceSetting,
+  pub profile: Profile,
+  pub symbol: String,
+  pub recorder: Option<RecorderSetting>,
+  #[serde(default)]
+  pub strategy: StrategySetting,
+  #[serde(default)]
+  pub risk: RiskSetting,
+  #[serde(default)]
+  pub fees: FeeSetting,
+  #[serde(default)]
+  pub depth: DepthSetting,
+  #[serde(default)]
+  pub spread: SpreadSetting,
+  pub bitcoind: Option<BitcoindSetting>,
+  pub exchanges: Vec<ExchangeSetting>,
+}
+
+impl Setting {
+  /// Exchange entry orders are sent to under `profile`
+  pub fn exchange(&self, profile: Profile) -> Result<&ExchangeSetting> {
+    ensure!(
+      profile != Profile::Paper,
+      "Paper profile has no exchange account"
+    );
+    self
+      .exchanges
+      .iter()
+      .find(|exchange| exchange.name == "binance" && exchange.profile == profile)
+      .ok_or_else(|| anyhow!("No [[exchanges]] entry for binance {}", profile))
+  }
+
+  /// Exchange entry market data is read from, mainnet unless we trade
+  /// on the testnet
+  pub fn market_data(&self, profile: Profile) -> Result<&ExchangeSetting> {
+    match profile {
+      Profile::Testnet => self.exchange(Profile::Testnet),
+      Profile::Mainnet | Profile::Paper => self.exchange(Profile::Mainnet),
+    }
+  }
+
+  /// Mainnet entry of another venue, only read for market data
+  pub fn venue(&self, venue: Venue) -> Result<&ExchangeSetting> {
+    let name = venue.to_string();
+    self
+      .exchanges
+      .iter()
+      .find(|exchange| exchange.name == name && exchange.profile == Profile::Mainnet)
+      .ok_or_else(|| anyhow!("No [[exchanges]] entry for {} mainnet", venue))
+  }
+
+  pub fn recorder(&self) -> Result<&RecorderSetting> {
+    self
+      .recorder
+      .as_ref()
+      .ok_or_else(|| anyhow!("Missing [recorder] section"))
+  }
+
+  pub fn bitcoind(&self) -> Result<&BitcoindSetting> {
+    self
+      .bitcoind
+      .as_ref()
+      .ok_or_else(|| anyhow!("Missing [bitcoind] section"))
+  }
+
+  pub fn validate(&self) -> Result<()> {
+    ensure!(!self.symbol.is_empty(), "symbol can't be empty");
+    ensure!(
+      !self.exchanges.is_empty(),
+      "At least one [[exchanges]] entry is required"
+    );
+    for (i, exchange) in self.exchanges.iter().enumerate() {
+      let section = format!("exchanges[{}] ({} {})", i, exchange.name, exchange.profile);
+      ensure!(
+        exchange.name == "binance" || exchange.name == "coinbase",
+        "{}: unsupported exchange {}",
+        section,
+        exchange.name
+      );
+      ensure!(
+        exchange.profile != Profile::Paper,
+        "{}: paper trading doesn't need an exchange entry",
+        section
+      );
+      ensure!(
+        exchange.host.starts_with("http://") || exchange.host.starts_with("https://"),
+        "{}: host must be an http(s) url, got {:?}",
+        section,
+        exchange.host
+      );
+      ensure!(
+        exchange.ws_base.starts_with("ws://") || exchange.ws_base.starts_with("wss://"),
+        "{}: ws_base must be a ws(s) url, got {:?}",
+        section,
+        exchange.ws_base
+      );
+      if let Some(ws_api) = &exchange.ws_api {
+        ensure!(
+          ws_api.starts_with("ws://") || ws_api.starts_with("wss://"),
+          "{}: ws_api must be a ws(s) url, got {:?}",
+          section,
+          ws_api
+        );
+      }
+      if let Some(futures_host) = &exchange.futures_host {
+        ensure!(
+          futures_host.starts_with("http://") || futures_host.starts_with("https://"),
+          "{}: futures_host must be an http(s) url, got {:?}",
+          section,
+          futures_host
+        );
+      }
+      if let Some(futures_ws_base) = &exchange.futures_ws_base {
+        ensure!(
+          futures_ws_base.starts_with("ws://") || futures_ws_base.starts_with("wss://"),
+          "{}: futures_ws_base must be a ws(s) url, got {:?}",
+          section,
+          futures_ws_base
+        );
+      }
+      let duplicates = self
+        .exchanges
+        .iter()
+        .filter(|other| other.name == exchange.name && other.profile == exchange.profile)
+        .count();
+      ensure!(duplicates == 1, "{}: defined more than once", section);
+    }
+    self
+      .market_data(self.profile)
+      .with_context(|| format!("profile {} needs it", self.profile))?;
+
+    if let Some(recorder) = &self.recorder {
+      ensure!(
+        !recorder.csv_dir.is_empty(),
+        "recorder.csv_dir can't be empty"
+      );
+    }
+
+    let turtle = &self.strategy.turtle;
+    parse_interval(&turtle.interval).context("strategy.turtle.interval")?;
+    turtle.params.validate().context("strategy.turtle.params")?;
+    let lookback = turtle.params.breakout_window.max(turtle.params.atr_period);
+    ensure!(
+      turtle.warmup > lookback,
+      "strategy.turtle.warmup must be greater than {}, got {}",
+      lookback,
+      turtle.warmup
+    );
+
+    let mean_reversion = &self.strategy.mean_reversion;
+    parse_interval(&mean_reversion.interval).context("strategy.mean_reversion.interval")?;
+    ensure!(
+      mean_reversion.period >= 2 && mean_reversion.rsi_period >= 1,
+      "strategy.mean_reversion needs period >= 2 and rsi_period >= 1"
+    );
+    ensure!(
+      0.0 < mean_reversion.rsi_oversold
+        && mean_reversion.rsi_oversold < mean_reversion.rsi_overbought
+        && mean_reversion.rsi_overbought < 100.0,
+      "strategy.mean_reversion needs 0 < rsi_oversold < rsi_overbought < 100"
+    );
+    ensure!(
+      mean_reversion.num_std > 0.0 && mean_reversion.stop_std > 0.0,
+      "strategy.mean_reversion.num_std and stop_std must be positive"
+    );
+    ensure!(
+      mean_reversion.risk_fraction > 0.0 && mean_reversion.risk_fraction <= 1.0,
+      "strategy.mean_reversion.risk_fraction must be in (0, 1], got {}",
+      mean_reversion.risk_fraction
+    );
+
+    let market_maker = &self.strategy.market_maker;
+    for (name, value) in [
+      ("spread_bps", market_maker.spread_bps),
+      ("quantity", market_maker.quantity),
+      ("tick_size", market_maker.tick_size),
+      ("max_inventory", market_maker.max_inventory),
+    ]
+    .iter()
+    {
+      ensure!(
+        *value > 0.0,
+        "strategy.market_maker.{} must be positive, got {}",
+        name,
+        value
+      );
+    }
+    ensure!(
+      market_maker.skew_bps >= 0.0 && market_maker.requote_bps >= 0.0,
+      "strategy.market_maker.skew_bps and requote_bps can't be negative"
+    );
+    ensure!(
+      market_maker.min_update_ms >= 0,
+      "strategy.market_maker.min_update_ms can't be negative"
+    );
+
+    let grid = &self.strategy.grid;
+    parse_interval(&grid.interval).context("strategy.grid.interval")?;
+    ensure!(
+      grid.levels >= 2,
+      "strategy.grid.levels must be at least 2, got {}",
+      grid.levels
+    );
+    ensure!(
+      grid.quantity > 0.0 && grid.tick_size > 0.0,
+      "strategy.grid.quantity and tick_size must be positive"
+    );
+    if let (Some(lower), Some(upper)) = (grid.lower, grid.upper) {
+      ensure!(
+        lower > 0.0 && lower < upper,
+        "strategy.grid needs 0 < lower < upper, got {} and {}",
+        lower,
+        upper
+      );
+    }
+
+    let pairs = &self.strategy.pairs;
+    parse_interval(&pairs.interval).context("strategy.pairs.interval")?;
+    ensure!(
+      pairs.window >= 10,
+      "strategy.pairs.window must be at least 10, got {}",
+      pairs.window
+    );
+    ensure!(
+      pairs.kalman_delta > 0.0 && pairs.kalman_delta < 1.0,
+      "strategy.pairs needs 0 < kalman_delta < 1, got {}",
+      pairs.kalman_delta
+    );
+    ensure!(
+      0.0 <= pairs.exit_z && pairs.exit_z < pairs.entry_z && pairs.entry_z < pairs.stop_z,
+      "strategy.pairs needs 0 <= exit_z < entry_z < stop_z"
+    );
+    ensure!(
+      pairs.notional > 0.0,
+      "strategy.pairs.notional must be positive, got {}",
+      pairs.notional
+    );
+
+    let triangle = &self.strategy.triangle;
+    ensure!(
+      triangle.symbols.len() >= 3,
+      "strategy.triangle.symbols needs at least 3 symbols, got {}",
+      triangle.symbols.len()
+    );
+    ensure!(
+      triangle.max_notional > 0.0 && triangle.cooldown_ms >= 0,
+      "strategy.triangle needs a positive max_notional and a cooldown_ms of at least 0"
+    );
+
+    let risk = &self.risk;
+    for (name, value) in [
+      ("max_order_notional", risk.max_order_notional),
+      ("max_position_notional", risk.max_position_notional),
+      ("max_daily_loss", risk.max_daily_loss),
+      ("price_collar_pct", risk.price_collar_pct),
+    ]
+    .iter()
+    {
+      ensure!(
+        *value > 0.0,
+        "risk.{} must be positive, got {}",
+        name,
+        value
+      );
+    }
+    ensure!(
+      risk.max_order_notional <= risk.max_position_notional,
+      "risk.max_order_notional can't exceed risk.max_position_notional"
+    );
+    ensure!(
+      risk.max_open_orders > 0,
+      "risk.max_open_orders must be positive"
+    );
+    ensure!(
+      risk.max_orders_per_minute > 0,
+      "risk.max_orders_per_minute must be positive"
+    );
+    ensure!(
+      risk.max_consecutive_errors > 0,
+      "risk.max_consecutive_errors must be positive"
+    );
+
+    let fees = &self.fees;
+    ensure!(
+      fees.vip_tier < VIP_TIERS.len(),
+      "fees.vip_tier must be at most {}, got {}",
+      VIP_TIERS.len() - 1,
+      fees.vip_tier
+    );
+    let overrides = fees
+      .symbols
+      .values()
+      .flat_map(|rates| vec![rates.maker_bps, rates.taker_bps]);
+    for bps in vec![fees.maker_bps, fees.taker_bps]
+      .into_iter()
+      .chain(overrides)
+      .flatten()
+    {
+      ensure!(
+        (0.0..10_000.0).contains(&bps),
+        "fees rates must be in [0, 10000) bps, got {}",
+        bps
+      );
+    }
+    ensure!(
+      (0.0..100.0).contains(&fees.bnb_discount_pct),
+      "fees.bnb_discount_pct must be in [0, 100), got {}",
+      fees.bnb_discount_pct
+    );
+
+    let depth = &self.depth;
+    ensure!(
+      depth.latency_ms >= 0,
+      "depth.latency_ms can't be negative, got {}",
+      depth.latency_ms
+    );
+    ensure!(
+      (0.0..=1.0).contains(&depth.queue_position),
+      "depth.queue_position must be in [0, 1], got {}",
+      depth.queue_position
+    );
+
+    let spread = &self.spread;
+    ensure!(
+      spread.sample_ms >= 0 && spread.max_age_ms > 0,
+      "spread needs a sample_ms of at least 0 and a positive max_age_ms"
+    );
+
+    if let Some(bitcoind) = &self.bitcoind {
+      ensure!(
+        bitcoind.url.starts_with("http://") || bitcoind.url.starts_with("https://"),
+        "bitcoind.url must be an http(s) url, got {:?}",
+        bitcoind.url
+      );
+    }
+    Ok(())
+  }
 }
 
+/// Load `file`, apply `CRYPTO_TRADING__...` environment overrides and
+/// validate the result
 pub fn get_config(file: &str) -> Result<Setting> {
   let mut setting = config::Config::new();
-  setting.merge(config::File::with_name(file)).unwrap();
-  Ok(setting.try_into()?)
+  setting
+    .merge(config::File::with_name(file))
+    .with_context(|| format!("Failed to read config file {}", file))?;
+  setting
+    .merge(
+      config::Environment::with_prefix(ENV_PREFIX)
+        .separator("__")
+        .ignore_empty(true),
+    )
+    .context("Failed to apply environment overrides")?;
+  let setting: Setting = setting
+    .try_into()
+    .with_context(|| format!("Invalid config {}", file))?;
+  setting
+    .validate()
+    .with_context(|| format!("Invalid config {}", file))?;
+  Ok(setting)
 }
@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 pub enum CsvDataType {
   Trade,
   OrderBook,
+  Kline(String),  // interval
+  Spread(String), // venue compared with
 }
 
 impl From<CsvDataType> for String {
@@ -11,10 +13,28 @@ impl From<CsvDataType> for String {
     match data_type {
       CsvDataType::Trade => "trade".to_string(),
       CsvDataType::OrderBook => "orderbook".to_string(),
+      CsvDataType::Kline(interval) => format!("kline_{}", interval),
+      CsvDataType::Spread(venue) => format!("spread_{}", venue),
     }
   }
 }
 
+/// Header row of the trade files, as `Trade` serializes
+pub const TRADE_HEADER: [&str; 3] = ["md_time", "price", "amount"];
+
+/// Header row of the orderbook files, bid, ask, their quantities and the
+/// mid of each of the 10 levels after `md_time`
+pub fn orderbook_header() -> Vec<String> {
+  (1..11).fold(vec!["md_time".to_string()], |mut header, i| {
+    header.push(format!("buy{}", i));
+    header.push(format!("sale{}", i));
+    header.push(format!("bc{}", i));
+    header.push(format!("sc{}", i));
+    header.push(format!("mid{}", i));
+    header
+  })
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Trade {
   #[serde(rename(serialize = "md_time", deserialize = "T"))]
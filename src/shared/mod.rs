@@ -1,3 +1,5 @@
 pub mod config;
 pub mod csv_schema;
+pub mod reader;
+pub mod secret;
 pub mod utils;
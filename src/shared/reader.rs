@@ -0,0 +1,362 @@
+use super::csv_schema::CsvDataType;
+use crate::strategy::orderbook::OrderBook;
+use anyhow::{ensure, Context, Result};
+use chrono::NaiveDate;
+use csv::StringRecord;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+
+/// Path `get_csv_writer` writes `data_type` of `symbol` to on `date`
+pub fn csv_path(csv_dir: &str, symbol: &str, data_type: &CsvDataType, date: NaiveDate) -> PathBuf {
+  Path::new(csv_dir).join(format!(
+    "{}_{}_{}",
+    symbol.to_lowercase(),
+    String::from(data_type.clone()),
+    date.format("%Y%m%d")
+  ))
+}
+
+/// Files of `data_type` for every day between `start` and `end` inclusive,
+/// days without one are logged and left out
+pub fn find_files(
+  csv_dir: &str,
+  symbol: &str,
+  data_type: &CsvDataType,
+  start: NaiveDate,
+  end: NaiveDate,
+) -> Vec<PathBuf> {
+  let mut files = vec![];
+  for date in start.iter_days().take_while(|date| *date <= end) {
+    let path = csv_path(csv_dir, symbol, data_type, date);
+    if path.exists() {
+      files.push(path);
+    } else {
+      log::warn!(
+        "Missing {} file {}",
+        String::from(data_type.clone()),
+        path.display()
+      );
+    }
+  }
+  files
+}
+
+// First column of the header row each data type is written with
+fn header_column(data_type: &CsvDataType) -> &'static str {
+  match data_type {
+    CsvDataType::Kline(_) => "open_time",
+    CsvDataType::Trade | CsvDataType::OrderBook | CsvDataType::Spread(_) => "md_time",
+  }
+}
+
+/// Rows of a run of recorded files, read one file after the other.
+/// Every append to a file re-emits its header row, those are skipped
+/// wherever they show up.
+pub struct RecordReader {
+  files: VecDeque<PathBuf>,
+  header: &'static str,
+  reader: Option<(PathBuf, csv::Reader<File>)>,
+}
+
+impl RecordReader {
+  pub fn new(files: Vec<PathBuf>, data_type: &CsvDataType) -> Self {
+    Self {
+      files: files.into(),
+      header: header_column(data_type),
+      reader: None,
+    }
+  }
+
+  /// Reader of the files of `data_type` between `start` and `end` inclusive
+  pub fn open(
+    csv_dir: &str,
+    symbol: &str,
+    data_type: &CsvDataType,
+    start: NaiveDate,
+    end: NaiveDate,
+  ) -> Self {
+    Self::new(
+      find_files(csv_dir, symbol, data_type, start, end),
+      data_type,
+    )
+  }
+}
+
+impl Iterator for RecordReader {
+  type Item = Result<StringRecord>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      if self.reader.is_none() {
+        let path = self.files.pop_front()?;
+        let reader = csv::ReaderBuilder::new()
+          .has_headers(false)
+          .flexible(true)
+          .from_path(&path)
+          .with_context(|| format!("Failed to open {}", path.display()));
+        match reader {
+          Ok(reader) => self.reader = Some((path, reader)),
+          Err(e) => return Some(Err(e)),
+        }
+      }
+      let (path, reader) = self.reader.as_mut().unwrap();
+      let mut record = StringRecord::new();
+      match reader.read_record(&mut record) {
+        Ok(true) if record.get(0) == Some(self.header) => continue,
+        Ok(true) => return Some(Ok(record)),
+        Ok(false) => self.reader = None,
+        Err(e) => {
+          let e = anyhow::Error::new(e).context(format!("Failed to read {}", path.display()));
+          return Some(Err(e));
+        }
+      }
+    }
+  }
+}
+
+/// Trade of the trade files `record` writes, which carry no aggressor side
+#[derive(Clone, Debug)]
+pub struct MarketTrade {
+  pub time: i64,
+  pub price: f64,
+  pub quantity: f64,
+}
+
+impl MarketTrade {
+  pub fn from_record(record: &StringRecord) -> Result<Self> {
+    ensure!(record.len() >= 3, "Malformed trade row {:?}", record);
+    Ok(Self {
+      time: record[0].parse()?,
+      price: record[1].parse()?,
+      quantity: record[2].parse()?,
+    })
+  }
+}
+
+/// Orderbook snapshots `record` wrote for `symbol`, in recording order
+pub fn read_orderbooks(
+  csv_dir: &str,
+  symbol: &str,
+  start: NaiveDate,
+  end: NaiveDate,
+) -> impl Iterator<Item = Result<OrderBook>> {
+  let symbol = symbol.to_string();
+  RecordReader::open(csv_dir, &symbol, &CsvDataType::OrderBook, start, end)
+    .map(move |record| OrderBook::from_record(&symbol, &record?))
+}
+
+/// Trades `record` wrote for `symbol`, in recording order
+pub fn read_trades(
+  csv_dir: &str,
+  symbol: &str,
+  start: NaiveDate,
+  end: NaiveDate,
+) -> impl Iterator<Item = Result<MarketTrade>> {
+  RecordReader::open(csv_dir, symbol, &CsvDataType::Trade, start, end)
+    .map(|record| MarketTrade::from_record(&record?))
+}
+
+/// Recorded books and trades of one symbol
+#[derive(Clone, Debug)]
+pub enum RecordedEvent {
+  Book(OrderBook),
+  Trade(MarketTrade),
+}
+
+impl RecordedEvent {
+  pub fn time(&self) -> i64 {
+    match self {
+      RecordedEvent::Book(book) => book.time,
+      RecordedEvent::Trade(trade) => trade.time,
+    }
+  }
+}
+
+/// Books and trades merged in time order as they are read, trades first
+/// within a millisecond as a snapshot shows the book after them. Both
+/// sides have to be in time order already, as `record` writes them.
+pub struct MergedEvents<B, T>
+where
+  B: Iterator<Item = Result<OrderBook>>,
+  T: Iterator<Item = Result<MarketTrade>>,
+{
+  books: Peekable<B>,
+  trades: Peekable<T>,
+}
+
+impl<B, T> Iterator for MergedEvents<B, T>
+where
+  B: Iterator<Item = Result<OrderBook>>,
+  T: Iterator<Item = Result<MarketTrade>>,
+{
+  type Item = Result<RecordedEvent>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    // Errors go out as soon as they are peeked
+    let trade_first = match (self.books.peek(), self.trades.peek()) {
+      (None, None) => return None,
+      (Some(Err(_)), _) => false,
+      (_, Some(Err(_))) => true,
+      (Some(Ok(book)), Some(Ok(trade))) => trade.time <= book.time,
+      (None, Some(_)) => true,
+      (Some(_), None) => false,
+    };
+    if trade_first {
+      self
+        .trades
+        .next()
+        .map(|trade| trade.map(RecordedEvent::Trade))
+    } else {
+      self.books.next().map(|book| book.map(RecordedEvent::Book))
+    }
+  }
+}
+
+pub fn merge_events<B, T>(books: B, trades: T) -> MergedEvents<B::IntoIter, T::IntoIter>
+where
+  B: IntoIterator<Item = Result<OrderBook>>,
+  T: IntoIterator<Item = Result<MarketTrade>>,
+{
+  MergedEvents {
+    books: books.into_iter().peekable(),
+    trades: trades.into_iter().peekable(),
+  }
+}
+
+/// Books and trades `record` wrote for `symbol` between `start` and `end`
+/// inclusive, as one time ordered stream
+pub fn read_events(
+  csv_dir: &str,
+  symbol: &str,
+  start: NaiveDate,
+  end: NaiveDate,
+) -> impl Iterator<Item = Result<RecordedEvent>> {
+  merge_events(
+    read_orderbooks(csv_dir, symbol, start, end),
+    read_trades(csv_dir, symbol, start, end),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  const SYMBOL: &str = "btcusdt";
+  const BOOK_HEADER: &str = "md_time,buy1,sale1,bc1,sc1,mid1\n";
+  const TRADE_HEADER: &str = "md_time,price,amount\n";
+
+  fn day(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2023, 6, day).unwrap()
+  }
+
+  fn csv_dir(dir: &TempDir) -> &str {
+    dir.path().to_str().unwrap()
+  }
+
+  fn write(dir: &TempDir, data_type: &CsvDataType, date: NaiveDate, content: &str) {
+    std::fs::write(csv_path(csv_dir(dir), SYMBOL, data_type, date), content).unwrap();
+  }
+
+  #[test]
+  fn trade_headers_are_skipped_anywhere() {
+    let dir = TempDir::new().unwrap();
+    let content = format!("{0}1,100,1\n{0}2,101,2\n{0}", TRADE_HEADER);
+    write(&dir, &CsvDataType::Trade, day(1), &content);
+    let trades = read_trades(csv_dir(&dir), SYMBOL, day(1), day(1))
+      .collect::<Result<Vec<_>>>()
+      .unwrap();
+    let trades = trades
+      .iter()
+      .map(|trade| (trade.time, trade.price, trade.quantity))
+      .collect::<Vec<_>>();
+    assert_eq!(trades, vec![(1, 100.0, 1.0), (2, 101.0, 2.0)]);
+  }
+
+  #[test]
+  fn orderbook_headers_are_skipped_anywhere() {
+    let dir = TempDir::new().unwrap();
+    let content = format!("{0}1,99,101,1,2,100\n{0}2,98,102,3,4,100\n", BOOK_HEADER);
+    write(&dir, &CsvDataType::OrderBook, day(1), &content);
+    let books = read_orderbooks(csv_dir(&dir), SYMBOL, day(1), day(1))
+      .collect::<Result<Vec<_>>>()
+      .unwrap();
+    assert_eq!(books.len(), 2);
+    assert_eq!(books[0].symbol, "BTCUSDT");
+    assert_eq!(
+      (books[0].time, books[0].bids.clone(), books[0].asks.clone()),
+      (1, vec![(99.0, 1.0)], vec![(101.0, 2.0)])
+    );
+    assert_eq!(
+      (books[1].time, books[1].best_bid(), books[1].best_ask()),
+      (2, Some(98.0), Some(102.0))
+    );
+  }
+
+  #[test]
+  fn missing_days_are_skipped() {
+    let dir = TempDir::new().unwrap();
+    write(
+      &dir,
+      &CsvDataType::Trade,
+      day(1),
+      &format!("{}1,100,1\n", TRADE_HEADER),
+    );
+    write(
+      &dir,
+      &CsvDataType::Trade,
+      day(3),
+      &format!("{}3,102,1\n", TRADE_HEADER),
+    );
+    let files = find_files(csv_dir(&dir), SYMBOL, &CsvDataType::Trade, day(1), day(3));
+    assert_eq!(files.len(), 2);
+    let times = read_trades(csv_dir(&dir), SYMBOL, day(1), day(3))
+      .map(|trade| trade.unwrap().time)
+      .collect::<Vec<_>>();
+    assert_eq!(times, vec![1, 3]);
+  }
+
+  #[test]
+  fn trades_go_before_books_of_the_same_millisecond() {
+    let dir = TempDir::new().unwrap();
+    let books = format!("{}1,99,101,1,2,100\n2,99,101,1,2,100\n", BOOK_HEADER);
+    write(&dir, &CsvDataType::OrderBook, day(1), &books);
+    write(
+      &dir,
+      &CsvDataType::Trade,
+      day(1),
+      &format!("{}2,101,1\n3,101,1\n", TRADE_HEADER),
+    );
+    let events = read_events(csv_dir(&dir), SYMBOL, day(1), day(1))
+      .map(|event| match event.unwrap() {
+        RecordedEvent::Book(book) => ('b', book.time),
+        RecordedEvent::Trade(trade) => ('t', trade.time),
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(events, vec![('b', 1), ('t', 2), ('b', 2), ('t', 3)]);
+  }
+
+  #[test]
+  fn errors_come_through_the_merged_stream() {
+    let dir = TempDir::new().unwrap();
+    let books = format!("{}1,99,101,1,2,100\n3,99,101,1,2,100\n", BOOK_HEADER);
+    write(&dir, &CsvDataType::OrderBook, day(1), &books);
+    write(
+      &dir,
+      &CsvDataType::Trade,
+      day(1),
+      &format!("{}2,101,1\nnot a time,101,1\n", TRADE_HEADER),
+    );
+    let events = read_events(csv_dir(&dir), SYMBOL, day(1), day(1)).collect::<Vec<_>>();
+    assert_eq!(events.len(), 4);
+    assert!(matches!(events[0], Ok(RecordedEvent::Book(_))));
+    assert!(matches!(events[1], Ok(RecordedEvent::Trade(_))));
+    assert!(events[2].is_err());
+    assert!(matches!(events[3], Ok(RecordedEvent::Book(_))));
+    assert!(read_events(csv_dir(&dir), SYMBOL, day(1), day(1))
+      .collect::<Result<Vec<_>>>()
+      .is_err());
+  }
+}
@@ -0,0 +1,244 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use chacha20poly1305::aead::{rand_core::RngCore, Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use zeroize::Zeroize;
+
+/// Environment variable read before prompting for the keystore passphrase
+pub const KEYSTORE_PASSPHRASE_ENV: &str = "CRYPTO_TRADING_KEYSTORE_PASSPHRASE";
+
+/// String wiped from memory on drop and redacted from `Debug` output
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+  pub fn new(value: String) -> Self {
+    Self(value)
+  }
+
+  pub fn expose(&self) -> &str {
+    &self.0
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+}
+
+impl Drop for Secret {
+  fn drop(&mut self) {
+    self.0.zeroize();
+  }
+}
+
+impl fmt::Debug for Secret {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("Secret([REDACTED])")
+  }
+}
+
+/// Where a secret is loaded from. In the config it's either a plain
+/// string or one of
+///   `{ env = "BINANCE_API_SECRET" }`
+///   `{ file = "/etc/crypto_trading/api_secret" }`
+///   `{ keystore = "keystore.json", entry = "binance_mainnet_secret" }`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SecretSource {
+  Plain(Secret),
+  Env { env: String },
+  File { file: String },
+  Keystore { keystore: String, entry: String },
+}
+
+impl SecretSource {
+  pub fn resolve(&self) -> Result<Secret> {
+    let secret = match self {
+      SecretSource::Plain(secret) => secret.clone(),
+      SecretSource::Env { env } => Secret::new(
+        std::env::var(env).with_context(|| format!("Secret env var {} is not set", env))?,
+      ),
+      SecretSource::File { file } => read_secret_file(file)?,
+      SecretSource::Keystore { keystore, entry } => keystore_entry(keystore, entry)?,
+    };
+    ensure!(
+      !secret.is_empty(),
+      "Secret from {} is empty",
+      self.describe()
+    );
+    Ok(secret)
+  }
+
+  /// Where the secret comes from, without the secret itself
+  pub fn describe(&self) -> String {
+    match self {
+      SecretSource::Plain(_) => "config file".into(),
+      SecretSource::Env { env } => format!("env var {}", env),
+      SecretSource::File { file } => format!("file {}", file),
+      SecretSource::Keystore { keystore, entry } => {
+        format!("keystore {} entry {}", keystore, entry)
+      }
+    }
+  }
+}
+
+fn read_secret_file(file: &str) -> Result<Secret> {
+  let metadata = std::fs::metadata(file).with_context(|| format!("Can't read {}", file))?;
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = metadata.permissions().mode();
+    ensure!(
+      mode & 0o077 == 0,
+      "Secret file {} has mode {:o}, it must not be accessible by group or others (chmod 600)",
+      file,
+      mode & 0o777
+    );
+  }
+  #[cfg(not(unix))]
+  let _ = metadata;
+  let mut content = std::fs::read_to_string(file)?;
+  let secret = Secret::new(content.trim().to_string());
+  content.zeroize();
+  Ok(secret)
+}
+
+// Keystores already unlocked by this process, so every entry doesn't
+// ask for the passphrase again
+static UNLOCKED: Mutex<BTreeMap<String, BTreeMap<String, Secret>>> = Mutex::new(BTreeMap::new());
+
+fn keystore_entry(path: &str, entry: &str) -> Result<Secret> {
+  let mut unlocked = UNLOCKED
+    .lock()
+    .map_err(|_| anyhow!("Keystore cache poisoned"))?;
+  if !unlocked.contains_key(path) {
+    let passphrase = read_passphrase(path)?;
+    let entries = Keystore::load(path)?.decrypt(&passphrase)?;
+    unlocked.insert(path.to_string(), entries);
+  }
+  unlocked[path]
+    .get(entry)
+    .cloned()
+    .ok_or_else(|| anyhow!("Keystore {} has no entry {}", path, entry))
+}
+
+/// Passphrase from `CRYPTO_TRADING_KEYSTORE_PASSPHRASE` or the terminal
+pub fn read_passphrase(path: &str) -> Result<Secret> {
+  if let Ok(passphrase) = std::env::var(KEYSTORE_PASSPHRASE_ENV) {
+    return Ok(Secret::new(passphrase));
+  }
+  let prompt = format!("Passphrase for keystore {}: ", path);
+  Ok(Secret::new(rpassword::read_password_from_tty(Some(
+    &prompt,
+  ))?))
+}
+
+/// Encrypted file holding named secrets. The entries are serialized to
+/// JSON and sealed with ChaCha20-Poly1305 under a key derived from the
+/// passphrase with scrypt.
+#[derive(Serialize, Deserialize)]
+pub struct Keystore {
+  pub version: u32,
+  pub log_n: u8,
+  pub r: u32,
+  pub p: u32,
+  pub salt: String,       // hex
+  pub nonce: String,      // hex
+  pub ciphertext: String, // hex
+}
+
+impl Keystore {
+  const VERSION: u32 = 1;
+
+  pub fn load(path: &str) -> Result<Self> {
+    let content =
+      std::fs::read_to_string(path).with_context(|| format!("Can't read keystore {}", path))?;
+    let keystore: Self =
+      serde_json::from_str(&content).with_context(|| format!("Malformed keystore {}", path))?;
+    ensure!(
+      keystore.version == Self::VERSION,
+      "Unsupported keystore version {}",
+      keystore.version
+    );
+    Ok(keystore)
+  }
+
+  pub fn save(&self, path: &str) -> Result<()> {
+    let content = serde_json::to_string_pretty(self)?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::OpenOptionsExt;
+      options.mode(0o600);
+    }
+    use std::io::Write;
+    options.open(path)?.write_all(content.as_bytes())?;
+    Ok(())
+  }
+
+  pub fn encrypt(entries: &BTreeMap<String, Secret>, passphrase: &Secret) -> Result<Self> {
+    let (log_n, r, p) = (15, 8, 1);
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+
+    let plain_entries: BTreeMap<&str, &str> = entries
+      .iter()
+      .map(|(name, secret)| (name.as_str(), secret.expose()))
+      .collect();
+    let mut plaintext = serde_json::to_vec(&plain_entries)?;
+    let cipher = Self::cipher(passphrase, &salt, log_n, r, p)?;
+    let ciphertext = cipher
+      .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+      .map_err(|_| anyhow!("Failed to encrypt keystore"))?;
+    plaintext.zeroize();
+
+    Ok(Self {
+      version: Self::VERSION,
+      log_n,
+      r,
+      p,
+      salt: hex::encode(salt),
+      nonce: hex::encode(nonce),
+      ciphertext: hex::encode(ciphertext),
+    })
+  }
+
+  pub fn decrypt(&self, passphrase: &Secret) -> Result<BTreeMap<String, Secret>> {
+    let salt = hex::decode(&self.salt)?;
+    let nonce = hex::decode(&self.nonce)?;
+    ensure!(nonce.len() == 12, "Malformed keystore nonce");
+    let ciphertext = hex::decode(&self.ciphertext)?;
+    let cipher = Self::cipher(passphrase, &salt, self.log_n, self.r, self.p)?;
+    let mut plaintext = match cipher.decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice()) {
+      Ok(plaintext) => plaintext,
+      Err(_) => bail!("Wrong passphrase or corrupted keystore"),
+    };
+    let entries = serde_json::from_slice::<BTreeMap<String, Secret>>(&plaintext);
+    plaintext.zeroize();
+    Ok(entries?)
+  }
+
+  fn cipher(
+    passphrase: &Secret,
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+  ) -> Result<ChaCha20Poly1305> {
+    let params =
+      scrypt::Params::new(log_n, r, p, 32).map_err(|e| anyhow!("Invalid scrypt params: {}", e))?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(passphrase.expose().as_bytes(), salt, &params, &mut key)
+      .map_err(|e| anyhow!("Failed to derive keystore key: {}", e))?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    key.zeroize();
+    Ok(cipher)
+  }
+}
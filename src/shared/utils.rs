@@ -1,11 +1,22 @@
-use anyhow::{ensure, Result};
+use anyhow::{anyhow, bail, ensure, Result};
 use csv::Writer;
 use serde_json::Value;
 use std::collections::BTreeMap;
 
-use crate::binance::api::{KlineInput, OrderInput, OrderType};
+use crate::binance::api::{
+  BorrowRepay, CancelOrderInput, CancelOrderListInput, InterestHistoryInput, KlineInput,
+  MarginOrderInput, OcoInput, OrderInput, OrderListLeg, OrderRespType, OrderSide, OrderType,
+  OtoInput, OtocoInput, QueryOrderInput,
+};
 
-use super::csv_schema::CsvDataType;
+use crate::binance::futures::api::{
+  FundingRateInput, FuturesOrderInput, FuturesOrderType, MarginType,
+};
+
+use super::csv_schema::{orderbook_header, CsvDataType};
+
+/// Quantities closer than this are equal, well under any exchange step
+pub const QTY_EPSILON: f64 = 1e-9;
 
 pub fn get_timestamp() -> i64 {
   chrono::Utc::now().timestamp_millis()
@@ -28,6 +39,57 @@ pub fn to_f64(v: &Value) -> f64 {
   v.as_str().unwrap().parse().unwrap()
 }
 
+/// Percent encode everything but RFC 3986 unreserved characters, needed
+/// for base64 signatures in query strings
+pub fn percent_encode(value: &str) -> String {
+  value
+    .bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+        (b as char).to_string()
+      }
+      _ => format!("%{:02X}", b),
+    })
+    .collect()
+}
+
+/// Quote assets we recognise when splitting a symbol like `BTCUSDT`
+const QUOTE_ASSETS: [&str; 8] = ["USDT", "BUSD", "USDC", "TUSD", "BTC", "ETH", "BNB", "EUR"];
+
+/// Split a symbol into its base and quote asset, e.g. `btcusdt` into
+/// (`BTC`, `USDT`)
+pub fn split_symbol(symbol: &str) -> Result<(String, String)> {
+  let symbol = symbol.to_uppercase();
+  for quote in QUOTE_ASSETS.iter() {
+    if symbol.len() > quote.len() && symbol.ends_with(quote) {
+      let base = symbol[..symbol.len() - quote.len()].to_string();
+      return Ok((base, quote.to_string()));
+    }
+  }
+  bail!("Can't find quote asset of symbol {}", symbol)
+}
+
+/// Convert interval strings like `1s`, `15m` or `1d` into milliseconds
+pub fn parse_interval(interval: &str) -> Result<i64> {
+  let unit_start = interval
+    .find(|c: char| !c.is_ascii_digit())
+    .ok_or_else(|| anyhow!("Missing unit in interval: {}", interval))?;
+  let (amount, unit) = interval.split_at(unit_start);
+  let amount = amount
+    .parse::<i64>()
+    .map_err(|_| anyhow!("Invalid interval: {}", interval))?;
+  let unit_ms = match unit {
+    "ms" => 1,
+    "s" => 1_000,
+    "m" => 60_000,
+    "h" => 3_600_000,
+    "d" => 86_400_000,
+    "w" => 604_800_000,
+    _ => bail!("Unknown interval unit: {}", unit),
+  };
+  Ok(amount * unit_ms)
+}
+
 pub fn get_csv_writer(
   csv_dir: &str,
   symbol: &str,
@@ -35,7 +97,6 @@ pub fn get_csv_writer(
   date: &str,
 ) -> Writer<std::fs::File> {
   let file = std::fs::OpenOptions::new()
-    .write(true)
     .create(true)
     .append(true)
     .open(format!(
@@ -48,23 +109,19 @@ pub fn get_csv_writer(
     .unwrap();
   let mut writer = csv::Writer::from_writer(file);
   if let CsvDataType::OrderBook = data_type {
-    let orderbook_csv_header = (1..11).fold(vec!["md_time".to_string()], |mut header, i| {
-      header.push(format!("buy{}", i));
-      header.push(format!("sale{}", i));
-      header.push(format!("bc{}", i));
-      header.push(format!("sc{}", i));
-      header.push(format!("mid{}", i));
-      header
-    });
-    writer.write_record(orderbook_csv_header).unwrap();
+    writer.write_record(orderbook_header()).unwrap();
     writer.flush().unwrap();
   }
   writer
 }
 
-/// Query Builders
+// Query Builders
 
 pub fn build_order_query(request: OrderInput) -> Result<String> {
+  Ok(construct_query(build_order_params(request)?))
+}
+
+pub fn build_order_params(request: OrderInput) -> Result<BTreeMap<String, String>> {
   // Sanity check for order input
   match request.order_type {
     OrderType::Limit => {
@@ -95,7 +152,7 @@ pub fn build_order_query(request: OrderInput) -> Result<String> {
   }
 
   let mut params: BTreeMap<String, String> = BTreeMap::new();
-  params.insert("symbol".into(), request.symbol.into());
+  params.insert("symbol".into(), request.symbol);
   params.insert("side".into(), request.side.into());
   params.insert("type".into(), request.order_type.into());
   params.insert("timestamp".into(), request.timestamp.to_string());
@@ -125,6 +182,500 @@ pub fn build_order_query(request: OrderInput) -> Result<String> {
     params.insert("recvWindow".into(), recv_window.to_string());
   }
 
+  Ok(params)
+}
+
+pub fn build_cancel_order_query(request: CancelOrderInput) -> Result<String> {
+  Ok(construct_query(build_cancel_order_params(request)?))
+}
+
+pub fn build_cancel_order_params(request: CancelOrderInput) -> Result<BTreeMap<String, String>> {
+  ensure!(
+    request.order_id.is_some() || request.orig_client_order_id.is_some(),
+    "Either Order Id or Orig Client Order Id must be sent"
+  );
+
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), request.symbol);
+  params.insert("timestamp".into(), request.timestamp.to_string());
+  if let Some(order_id) = request.order_id {
+    params.insert("orderId".into(), order_id.to_string());
+  }
+  if let Some(client_order_id) = request.orig_client_order_id {
+    params.insert("origClientOrderId".into(), client_order_id);
+  }
+  if let Some(recv_window) = request.recv_window {
+    params.insert("recvWindow".into(), recv_window.to_string());
+  }
+
+  Ok(params)
+}
+
+pub fn build_oco_query(request: OcoInput) -> Result<String> {
+  Ok(construct_query(build_oco_params(request)?))
+}
+
+pub fn build_oco_params(request: OcoInput) -> Result<BTreeMap<String, String>> {
+  ensure!(request.quantity > 0.0, "Quantity must be positive");
+  check_oco_legs(&request.side, &request.above, &request.below)?;
+
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), request.symbol);
+  params.insert("side".into(), request.side.into());
+  params.insert("quantity".into(), request.quantity.to_string());
+  params.insert("timestamp".into(), request.timestamp.to_string());
+  insert_leg(&mut params, "above", request.above);
+  insert_leg(&mut params, "below", request.below);
+  insert_list_options(
+    &mut params,
+    request.list_client_order_id,
+    request.new_order_resp_type,
+    request.recv_window,
+  );
+  Ok(params)
+}
+
+pub fn build_oto_query(request: OtoInput) -> Result<String> {
+  Ok(construct_query(build_oto_params(request)?))
+}
+
+pub fn build_oto_params(request: OtoInput) -> Result<BTreeMap<String, String>> {
+  ensure!(
+    request.working_quantity > 0.0 && request.pending_quantity > 0.0,
+    "Working and pending quantity must be positive"
+  );
+  check_working_leg(&request.working)?;
+  check_leg(&request.pending, "pending")?;
+
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), request.symbol);
+  params.insert("timestamp".into(), request.timestamp.to_string());
+  params.insert("workingSide".into(), request.working_side.into());
+  params.insert(
+    "workingQuantity".into(),
+    request.working_quantity.to_string(),
+  );
+  insert_leg(&mut params, "working", request.working);
+  params.insert("pendingSide".into(), request.pending_side.into());
+  params.insert(
+    "pendingQuantity".into(),
+    request.pending_quantity.to_string(),
+  );
+  insert_leg(&mut params, "pending", request.pending);
+  insert_list_options(
+    &mut params,
+    request.list_client_order_id,
+    request.new_order_resp_type,
+    request.recv_window,
+  );
+  Ok(params)
+}
+
+pub fn build_otoco_query(request: OtocoInput) -> Result<String> {
+  Ok(construct_query(build_otoco_params(request)?))
+}
+
+pub fn build_otoco_params(request: OtocoInput) -> Result<BTreeMap<String, String>> {
+  ensure!(
+    request.working_quantity > 0.0 && request.pending_quantity > 0.0,
+    "Working and pending quantity must be positive"
+  );
+  check_working_leg(&request.working)?;
+  check_oco_legs(
+    &request.pending_side,
+    &request.pending_above,
+    &request.pending_below,
+  )?;
+  ensure!(
+    is_buy(&request.working_side) != is_buy(&request.pending_side),
+    "Pending OCO must be on the opposite side of the working order"
+  );
+  // Checked by check_working_leg
+  let working_price = request.working.price.unwrap_or_default();
+  ensure!(
+    trigger_price(&request.pending_above) > working_price
+      && working_price > trigger_price(&request.pending_below),
+    "Pending above and below legs must bracket the working price {}",
+    working_price
+  );
+
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), request.symbol);
+  params.insert("timestamp".into(), request.timestamp.to_string());
+  params.insert("workingSide".into(), request.working_side.into());
+  params.insert(
+    "workingQuantity".into(),
+    request.working_quantity.to_string(),
+  );
+  insert_leg(&mut params, "working", request.working);
+  params.insert("pendingSide".into(), request.pending_side.into());
+  params.insert(
+    "pendingQuantity".into(),
+    request.pending_quantity.to_string(),
+  );
+  insert_leg(&mut params, "pendingAbove", request.pending_above);
+  insert_leg(&mut params, "pendingBelow", request.pending_below);
+  insert_list_options(
+    &mut params,
+    request.list_client_order_id,
+    request.new_order_resp_type,
+    request.recv_window,
+  );
+  Ok(params)
+}
+
+pub fn build_cancel_order_list_query(request: CancelOrderListInput) -> Result<String> {
+  ensure!(
+    request.order_list_id.is_some() || request.list_client_order_id.is_some(),
+    "Either Order List Id or List Client Order Id must be sent"
+  );
+
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), request.symbol);
+  params.insert("timestamp".into(), request.timestamp.to_string());
+  if let Some(order_list_id) = request.order_list_id {
+    params.insert("orderListId".into(), order_list_id.to_string());
+  }
+  if let Some(list_client_order_id) = request.list_client_order_id {
+    params.insert("listClientOrderId".into(), list_client_order_id);
+  }
+  if let Some(recv_window) = request.recv_window {
+    params.insert("recvWindow".into(), recv_window.to_string());
+  }
+  Ok(construct_query(params))
+}
+
+fn is_buy(side: &OrderSide) -> bool {
+  matches!(side, OrderSide::Buy)
+}
+
+fn is_stop_loss(leg: &OrderListLeg) -> bool {
+  matches!(
+    leg.order_type,
+    OrderType::StopLoss | OrderType::StopLossLimit
+  )
+}
+
+fn is_take_profit(leg: &OrderListLeg) -> bool {
+  matches!(
+    leg.order_type,
+    OrderType::LimitMaker | OrderType::TakeProfit | OrderType::TakeProfitLimit
+  )
+}
+
+// Price the leg triggers or rests at, check_leg makes sure it's set
+fn trigger_price(leg: &OrderListLeg) -> f32 {
+  leg.stop_price.or(leg.price).unwrap_or_default()
+}
+
+fn check_leg(leg: &OrderListLeg, name: &str) -> Result<()> {
+  match leg.order_type {
+    OrderType::Limit => {
+      ensure!(
+        leg.time_in_force.is_some(),
+        "Missing {} Time In Force",
+        name
+      );
+      ensure!(leg.price.is_some(), "Missing {} Price", name);
+    }
+    OrderType::Market => {}
+    OrderType::StopLoss | OrderType::TakeProfit => {
+      ensure!(leg.stop_price.is_some(), "Missing {} Stop Price", name);
+    }
+    OrderType::StopLossLimit | OrderType::TakeProfitLimit => {
+      ensure!(
+        leg.time_in_force.is_some(),
+        "Missing {} Time In Force",
+        name
+      );
+      ensure!(leg.price.is_some(), "Missing {} Price", name);
+      ensure!(leg.stop_price.is_some(), "Missing {} Stop Price", name);
+    }
+    OrderType::LimitMaker => {
+      ensure!(leg.price.is_some(), "Missing {} Price", name);
+    }
+  }
+  Ok(())
+}
+
+fn check_working_leg(working: &OrderListLeg) -> Result<()> {
+  ensure!(
+    matches!(working.order_type, OrderType::Limit | OrderType::LimitMaker),
+    "Working order must be LIMIT or LIMIT_MAKER, got {:?}",
+    working.order_type
+  );
+  check_leg(working, "working")
+}
+
+/// A sell OCO takes profit above and stops out below the market, a buy
+/// OCO stops out above and takes profit below
+fn check_oco_legs(side: &OrderSide, above: &OrderListLeg, below: &OrderListLeg) -> Result<()> {
+  check_leg(above, "above")?;
+  check_leg(below, "below")?;
+  let (above_ok, below_ok) = if is_buy(side) {
+    (is_stop_loss(above), is_take_profit(below))
+  } else {
+    (is_take_profit(above), is_stop_loss(below))
+  };
+  ensure!(
+    above_ok && below_ok,
+    "{:?} OCO can't have a {:?} above leg and a {:?} below leg",
+    side,
+    above.order_type,
+    below.order_type
+  );
+  ensure!(
+    trigger_price(above) > trigger_price(below),
+    "Above leg price {} must be higher than below leg price {}",
+    trigger_price(above),
+    trigger_price(below)
+  );
+  Ok(())
+}
+
+fn insert_leg(params: &mut BTreeMap<String, String>, prefix: &str, leg: OrderListLeg) {
+  params.insert(format!("{}Type", prefix), leg.order_type.into());
+  if let Some(price) = leg.price {
+    params.insert(format!("{}Price", prefix), price.to_string());
+  }
+  if let Some(stop_price) = leg.stop_price {
+    params.insert(format!("{}StopPrice", prefix), stop_price.to_string());
+  }
+  if let Some(time_in_force) = leg.time_in_force {
+    params.insert(format!("{}TimeInForce", prefix), time_in_force.into());
+  }
+  if let Some(iceberg_qty) = leg.iceberg_qty {
+    params.insert(format!("{}IcebergQty", prefix), iceberg_qty.to_string());
+  }
+  if let Some(client_order_id) = leg.client_order_id {
+    params.insert(format!("{}ClientOrderId", prefix), client_order_id);
+  }
+}
+
+fn insert_list_options(
+  params: &mut BTreeMap<String, String>,
+  list_client_order_id: Option<String>,
+  new_order_resp_type: Option<OrderRespType>,
+  recv_window: Option<u32>,
+) {
+  if let Some(list_client_order_id) = list_client_order_id {
+    params.insert("listClientOrderId".into(), list_client_order_id);
+  }
+  if let Some(resp_type) = new_order_resp_type {
+    params.insert("newOrderRespType".into(), resp_type.into());
+  }
+  if let Some(recv_window) = recv_window {
+    params.insert("recvWindow".into(), recv_window.to_string());
+  }
+}
+
+pub fn build_query_order_query(request: QueryOrderInput) -> Result<String> {
+  Ok(construct_query(build_query_order_params(request)?))
+}
+
+pub fn build_query_order_params(request: QueryOrderInput) -> Result<BTreeMap<String, String>> {
+  ensure!(
+    request.order_id.is_some() || request.orig_client_order_id.is_some(),
+    "Either Order Id or Orig Client Order Id must be sent"
+  );
+
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), request.symbol);
+  params.insert("timestamp".into(), request.timestamp.to_string());
+  if let Some(order_id) = request.order_id {
+    params.insert("orderId".into(), order_id.to_string());
+  }
+  if let Some(client_order_id) = request.orig_client_order_id {
+    params.insert("origClientOrderId".into(), client_order_id);
+  }
+  if let Some(recv_window) = request.recv_window {
+    params.insert("recvWindow".into(), recv_window.to_string());
+  }
+
+  Ok(params)
+}
+
+pub fn build_open_orders_query(symbol: Option<String>) -> Result<String> {
+  Ok(construct_query(build_open_orders_params(symbol)?))
+}
+
+pub fn build_open_orders_params(symbol: Option<String>) -> Result<BTreeMap<String, String>> {
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  if let Some(symbol) = symbol {
+    params.insert("symbol".into(), symbol);
+  }
+  params.insert("timestamp".into(), get_timestamp().to_string());
+  Ok(params)
+}
+
+pub fn build_futures_order_query(request: FuturesOrderInput) -> Result<String> {
+  // Sanity check for order input
+  match request.order_type {
+    FuturesOrderType::Limit => {
+      ensure!(request.time_in_force.is_some(), "Missing Time In Force");
+      ensure!(request.quantity.is_some(), "Missing Quantity");
+      ensure!(request.price.is_some(), "Missing Price");
+    }
+    FuturesOrderType::Market => {
+      ensure!(request.quantity.is_some(), "Missing Quantity");
+    }
+    FuturesOrderType::Stop | FuturesOrderType::TakeProfit => {
+      ensure!(request.quantity.is_some(), "Missing Quantity");
+      ensure!(request.price.is_some(), "Missing Price");
+      ensure!(request.stop_price.is_some(), "Missing Stop Price");
+    }
+    FuturesOrderType::StopMarket | FuturesOrderType::TakeProfitMarket => {
+      ensure!(request.stop_price.is_some(), "Missing Stop Price");
+      ensure!(
+        request.quantity.is_some() || request.close_position == Some(true),
+        "Missing Quantity or Close Position"
+      );
+    }
+    FuturesOrderType::TrailingStopMarket => {
+      ensure!(request.quantity.is_some(), "Missing Quantity");
+      match request.callback_rate {
+        Some(rate) => ensure!(
+          (0.1..=5.0).contains(&rate),
+          "Callback rate must be between 0.1 and 5, got {}",
+          rate
+        ),
+        None => bail!("Missing Callback Rate"),
+      }
+    }
+  }
+  ensure!(
+    !(request.close_position == Some(true) && request.reduce_only.is_some()),
+    "Reduce Only can't be sent with Close Position"
+  );
+
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), request.symbol);
+  params.insert("side".into(), request.side.into());
+  params.insert("type".into(), request.order_type.into());
+  params.insert("timestamp".into(), request.timestamp.to_string());
+  params.insert("newClientOrderId".into(), request.new_client_order_id);
+  if let Some(position_side) = request.position_side {
+    params.insert("positionSide".into(), position_side.into());
+  }
+  if let Some(time_in_force) = request.time_in_force {
+    params.insert("timeInForce".into(), time_in_force.into());
+  }
+  if let Some(quantity) = request.quantity {
+    params.insert("quantity".into(), quantity.to_string());
+  }
+  if let Some(reduce_only) = request.reduce_only {
+    params.insert("reduceOnly".into(), reduce_only.to_string());
+  }
+  if let Some(price) = request.price {
+    params.insert("price".into(), price.to_string());
+  }
+  if let Some(stop_price) = request.stop_price {
+    params.insert("stopPrice".into(), stop_price.to_string());
+  }
+  if let Some(close_position) = request.close_position {
+    params.insert("closePosition".into(), close_position.to_string());
+  }
+  if let Some(callback_rate) = request.callback_rate {
+    params.insert("callbackRate".into(), callback_rate.to_string());
+  }
+  if let Some(resp_type) = request.new_order_resp_type {
+    params.insert("newOrderRespType".into(), resp_type.into());
+  }
+  if let Some(recv_window) = request.recv_window {
+    params.insert("recvWindow".into(), recv_window.to_string());
+  }
+
+  Ok(construct_query(params))
+}
+
+pub fn build_leverage_query(symbol: String, leverage: u32) -> Result<String> {
+  ensure!(
+    (1..=125).contains(&leverage),
+    "Leverage must be between 1 and 125, got {}",
+    leverage
+  );
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), symbol);
+  params.insert("leverage".into(), leverage.to_string());
+  params.insert("timestamp".into(), get_timestamp().to_string());
+  Ok(construct_query(params))
+}
+
+pub fn build_margin_type_query(symbol: String, margin_type: MarginType) -> Result<String> {
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), symbol);
+  params.insert("marginType".into(), margin_type.into());
+  params.insert("timestamp".into(), get_timestamp().to_string());
+  Ok(construct_query(params))
+}
+
+pub fn build_funding_rate_query(req: FundingRateInput) -> Result<String> {
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), req.symbol);
+  if let Some(start_time) = req.start_time {
+    params.insert("startTime".into(), start_time.to_string());
+  }
+  if let Some(end_time) = req.end_time {
+    params.insert("endTime".into(), end_time.to_string());
+  }
+  if let Some(limit) = req.limit {
+    ensure!(limit <= 1000, "Limit value exceeds 1000");
+    params.insert("limit".into(), limit.to_string());
+  }
+  Ok(construct_query(params))
+}
+
+pub fn build_margin_order_query(request: MarginOrderInput) -> Result<String> {
+  let mut params = build_order_params(request.order)?;
+  params.insert("isIsolated".into(), "FALSE".into());
+  if let Some(side_effect_type) = request.side_effect_type {
+    params.insert("sideEffectType".into(), side_effect_type.into());
+  }
+  Ok(construct_query(params))
+}
+
+pub fn build_borrow_repay_query(
+  asset: String,
+  amount: f64,
+  borrow_repay: BorrowRepay,
+) -> Result<String> {
+  ensure!(amount > 0.0, "Amount must be positive, got {}", amount);
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("asset".into(), asset);
+  params.insert("amount".into(), amount.to_string());
+  params.insert("isIsolated".into(), "FALSE".into());
+  params.insert("type".into(), borrow_repay.into());
+  params.insert("timestamp".into(), get_timestamp().to_string());
+  Ok(construct_query(params))
+}
+
+pub fn build_max_borrowable_query(asset: String) -> Result<String> {
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("asset".into(), asset);
+  params.insert("timestamp".into(), get_timestamp().to_string());
+  Ok(construct_query(params))
+}
+
+pub fn build_interest_history_query(req: InterestHistoryInput) -> Result<String> {
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  if let Some(asset) = req.asset {
+    params.insert("asset".into(), asset);
+  }
+  if let Some(start_time) = req.start_time {
+    params.insert("startTime".into(), start_time.to_string());
+  }
+  if let Some(end_time) = req.end_time {
+    params.insert("endTime".into(), end_time.to_string());
+  }
+  if let Some(current) = req.current {
+    ensure!(current >= 1, "Page starts from 1");
+    params.insert("current".into(), current.to_string());
+  }
+  if let Some(size) = req.size {
+    ensure!(size <= 100, "Size value exceeds 100");
+    params.insert("size".into(), size.to_string());
+  }
+  params.insert("timestamp".into(), get_timestamp().to_string());
   Ok(construct_query(params))
 }
 
@@ -149,6 +700,13 @@ pub fn build_kline_query(req: KlineInput) -> Result<String> {
   Ok(construct_query(params))
 }
 
+pub fn build_account_commission_query(symbol: String) -> Result<String> {
+  let mut params: BTreeMap<String, String> = BTreeMap::new();
+  params.insert("symbol".into(), symbol.to_uppercase());
+  params.insert("timestamp".into(), get_timestamp().to_string());
+  Ok(construct_query(params))
+}
+
 pub fn build_spot_account_info_query(recv_window: Option<i64>) -> Result<String> {
   let mut params: BTreeMap<String, String> = BTreeMap::new();
   if let Some(window) = recv_window {
@@ -161,3 +719,157 @@ pub fn build_spot_account_info_query(recv_window: Option<i64>) -> Result<String>
   );
   Ok(construct_query(params))
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::binance::api::TimeInForce;
+
+  fn leg(order_type: OrderType, price: Option<f32>, stop_price: Option<f32>) -> OrderListLeg {
+    let time_in_force = match order_type {
+      OrderType::Limit | OrderType::StopLossLimit | OrderType::TakeProfitLimit => {
+        Some(TimeInForce::GTC)
+      }
+      _ => None,
+    };
+    OrderListLeg {
+      order_type,
+      price,
+      stop_price,
+      time_in_force,
+      iceberg_qty: None,
+      client_order_id: None,
+    }
+  }
+
+  fn take_profit(price: f32) -> OrderListLeg {
+    leg(OrderType::LimitMaker, Some(price), None)
+  }
+
+  fn stop_loss(stop_price: f32) -> OrderListLeg {
+    leg(OrderType::StopLossLimit, Some(stop_price), Some(stop_price))
+  }
+
+  fn oco(side: OrderSide, above: OrderListLeg, below: OrderListLeg) -> OcoInput {
+    OcoInput {
+      symbol: "BTCUSDT".into(),
+      list_client_order_id: None,
+      side,
+      quantity: 1.0,
+      above,
+      below,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: 1,
+    }
+  }
+
+  fn otoco(
+    working_side: OrderSide,
+    working_price: f32,
+    pending_side: OrderSide,
+    above: OrderListLeg,
+    below: OrderListLeg,
+  ) -> OtocoInput {
+    OtocoInput {
+      symbol: "BTCUSDT".into(),
+      list_client_order_id: None,
+      working_side,
+      working_quantity: 1.0,
+      working: leg(OrderType::Limit, Some(working_price), None),
+      pending_side,
+      pending_quantity: 1.0,
+      pending_above: above,
+      pending_below: below,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: 1,
+    }
+  }
+
+  #[test]
+  fn sell_oco_takes_profit_above_and_stops_below() {
+    let params =
+      build_oco_params(oco(OrderSide::Sell, take_profit(110.0), stop_loss(90.0))).unwrap();
+    assert_eq!(params["side"], "SELL");
+    assert_eq!(params["aboveType"], "LIMIT_MAKER");
+    assert_eq!(params["abovePrice"], "110");
+    assert_eq!(params["belowType"], "STOP_LOSS_LIMIT");
+    assert_eq!(params["belowStopPrice"], "90");
+    assert_eq!(params["belowTimeInForce"], "GTC");
+
+    assert!(build_oco_params(oco(OrderSide::Sell, stop_loss(110.0), take_profit(90.0))).is_err());
+    // Legs the wrong way round
+    assert!(build_oco_params(oco(OrderSide::Sell, take_profit(90.0), stop_loss(110.0))).is_err());
+  }
+
+  #[test]
+  fn buy_oco_stops_above_and_takes_profit_below() {
+    assert!(build_oco_params(oco(OrderSide::Buy, stop_loss(110.0), take_profit(90.0))).is_ok());
+    let above = leg(OrderType::StopLoss, None, Some(110.0));
+    let below = leg(OrderType::TakeProfit, None, Some(90.0));
+    assert!(build_oco_params(oco(OrderSide::Buy, above, below)).is_ok());
+
+    assert!(build_oco_params(oco(OrderSide::Buy, take_profit(110.0), stop_loss(90.0))).is_err());
+    // Legs the wrong way round
+    assert!(build_oco_params(oco(OrderSide::Buy, stop_loss(90.0), take_profit(110.0))).is_err());
+    // A stop leg without its stop price
+    let above = leg(OrderType::StopLoss, None, None);
+    assert!(build_oco_params(oco(OrderSide::Buy, above, take_profit(90.0))).is_err());
+  }
+
+  #[test]
+  fn otoco_brackets_the_working_price_on_the_other_side() {
+    let params = build_otoco_params(otoco(
+      OrderSide::Buy,
+      100.0,
+      OrderSide::Sell,
+      take_profit(110.0),
+      stop_loss(90.0),
+    ))
+    .unwrap();
+    assert_eq!(params["workingSide"], "BUY");
+    assert_eq!(params["workingPrice"], "100");
+    assert_eq!(params["pendingSide"], "SELL");
+    assert_eq!(params["pendingAboveType"], "LIMIT_MAKER");
+    assert_eq!(params["pendingBelowType"], "STOP_LOSS_LIMIT");
+    // Short entry covered by a buy OCO
+    assert!(build_otoco_params(otoco(
+      OrderSide::Sell,
+      100.0,
+      OrderSide::Buy,
+      stop_loss(110.0),
+      take_profit(90.0),
+    ))
+    .is_ok());
+
+    // Pending OCO on the same side as the entry
+    assert!(build_otoco_params(otoco(
+      OrderSide::Buy,
+      100.0,
+      OrderSide::Buy,
+      stop_loss(110.0),
+      take_profit(90.0),
+    ))
+    .is_err());
+    // Working price outside the bracket
+    assert!(build_otoco_params(otoco(
+      OrderSide::Buy,
+      120.0,
+      OrderSide::Sell,
+      take_profit(110.0),
+      stop_loss(90.0),
+    ))
+    .is_err());
+    // Market entries can't be the working order
+    let mut request = otoco(
+      OrderSide::Buy,
+      100.0,
+      OrderSide::Sell,
+      take_profit(110.0),
+      stop_loss(90.0),
+    );
+    request.working = leg(OrderType::Market, None, None);
+    assert!(build_otoco_params(request).is_err());
+  }
+}
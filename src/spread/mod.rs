@@ -0,0 +1,195 @@
+use crate::arbitrage::BookTicker;
+use crate::shared::config::SpreadSetting;
+use crate::venue::MarketEvent;
+use anyhow::Result;
+use serde::Serialize;
+
+// Top of book and last trade price of one venue
+#[derive(Default)]
+struct Quotes {
+  book: Option<BookTicker>,
+  last_price: Option<f64>,
+}
+
+impl Quotes {
+  fn on_event(&mut self, event: &MarketEvent, time: i64) -> Result<()> {
+    match event {
+      MarketEvent::Trade(trade) => self.last_price = Some(trade.price.parse()?),
+      MarketEvent::BookTicker(ticker) => self.book = Some(BookTicker::from_stream(ticker, time)?),
+    }
+    Ok(())
+  }
+}
+
+/// One sample of the basis, the other venue's prices against Binance's
+#[derive(Serialize, Debug)]
+pub struct SpreadRecord {
+  pub md_time: i64,
+  pub binance_bid: f64,
+  pub binance_ask: f64,
+  pub venue_bid: f64,
+  pub venue_ask: f64,
+  pub basis: f64,           // venue mid minus Binance mid
+  pub basis_bps: f64,       // of the Binance mid
+  pub buy_binance_bps: f64, // buying the Binance ask and selling the venue bid, before fees
+  pub buy_venue_bps: f64,   // buying the venue ask and selling the Binance bid, before fees
+  pub binance_last: Option<f64>,
+  pub venue_last: Option<f64>,
+}
+
+/// Compares the top of book of a symbol on Binance and another venue.
+/// Every book update yields a record once both books are known and
+/// fresh, at most one per `sample_ms`.
+pub struct SpreadMonitor {
+  setting: SpreadSetting,
+  binance: Quotes,
+  venue: Quotes,
+  last_sample: Option<i64>,
+}
+
+impl SpreadMonitor {
+  pub fn new(setting: SpreadSetting) -> Self {
+    Self {
+      setting,
+      binance: Quotes::default(),
+      venue: Quotes::default(),
+      last_sample: None,
+    }
+  }
+
+  /// `time` is when the event was received, books are aged by it
+  pub fn on_binance(&mut self, event: &MarketEvent, time: i64) -> Result<Option<SpreadRecord>> {
+    self.binance.on_event(event, time)?;
+    Ok(self.sample(event, time))
+  }
+
+  pub fn on_venue(&mut self, event: &MarketEvent, time: i64) -> Result<Option<SpreadRecord>> {
+    self.venue.on_event(event, time)?;
+    Ok(self.sample(event, time))
+  }
+
+  fn sample(&mut self, event: &MarketEvent, time: i64) -> Option<SpreadRecord> {
+    if let MarketEvent::Trade(_) = event {
+      return None;
+    }
+    if matches!(self.last_sample, Some(last) if time - last < self.setting.sample_ms) {
+      return None;
+    }
+    let (binance, venue) = match (&self.binance.book, &self.venue.book) {
+      (Some(binance), Some(venue)) => (binance, venue),
+      _ => return None,
+    };
+    let max_age = self.setting.max_age_ms;
+    if time - binance.time > max_age || time - venue.time > max_age {
+      log::debug!("Skipping a stale book");
+      return None;
+    }
+    let binance_mid = (binance.bid_price + binance.ask_price) / 2.0;
+    let venue_mid = (venue.bid_price + venue.ask_price) / 2.0;
+    self.last_sample = Some(time);
+    Some(SpreadRecord {
+      md_time: time,
+      binance_bid: binance.bid_price,
+      binance_ask: binance.ask_price,
+      venue_bid: venue.bid_price,
+      venue_ask: venue.ask_price,
+      basis: venue_mid - binance_mid,
+      basis_bps: (venue_mid - binance_mid) / binance_mid * 1e4,
+      buy_binance_bps: (venue.bid_price - binance.ask_price) / binance.ask_price * 1e4,
+      buy_venue_bps: (binance.bid_price - venue.ask_price) / venue.ask_price * 1e4,
+      binance_last: self.binance.last_price,
+      venue_last: self.venue.last_price,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::shared::config::Venue;
+
+  fn binance_book() -> MarketEvent {
+    MarketEvent::from_binance(include_str!(
+      "../../tests/fixtures/binance_book_ticker.json"
+    ))
+    .unwrap()
+    .unwrap()
+  }
+
+  fn venue_book() -> MarketEvent {
+    MarketEvent::from_venue(
+      Venue::Coinbase,
+      include_str!("../../tests/fixtures/coinbase_ticker.json"),
+    )
+    .unwrap()
+    .unwrap()
+  }
+
+  fn assert_close(actual: f64, expected: f64) {
+    assert!(
+      (actual - expected).abs() < 1e-9,
+      "{} != {}",
+      actual,
+      expected
+    );
+  }
+
+  #[test]
+  fn basis_of_both_books() {
+    let mut monitor = SpreadMonitor::new(SpreadSetting::default());
+    assert!(monitor
+      .on_binance(&binance_book(), 1_000)
+      .unwrap()
+      .is_none());
+    let trade = MarketEvent::from_binance(include_str!("../../tests/fixtures/binance_trade.json"))
+      .unwrap()
+      .unwrap();
+    assert!(monitor.on_binance(&trade, 1_100).unwrap().is_none());
+    let record = monitor.on_venue(&venue_book(), 1_200).unwrap().unwrap();
+    assert_eq!(record.md_time, 1_200);
+    assert_eq!((record.binance_bid, record.binance_ask), (30000.0, 30000.1));
+    assert_eq!((record.venue_bid, record.venue_ask), (30010.0, 30012.0));
+    assert_close(record.basis, 10.95);
+    assert_close(record.basis_bps, 3.649993916676805);
+    assert_close(record.buy_binance_bps, 3.2999890000371517);
+    assert_close(record.buy_venue_bps, -3.9984006397441023);
+    assert_eq!(record.binance_last, Some(30000.05));
+    assert_eq!(record.venue_last, None);
+  }
+
+  #[test]
+  fn samples_at_most_once_per_sample_ms() {
+    let mut monitor = SpreadMonitor::new(SpreadSetting {
+      sample_ms: 500,
+      ..Default::default()
+    });
+    monitor.on_binance(&binance_book(), 1_000).unwrap();
+    assert!(monitor.on_venue(&venue_book(), 1_000).unwrap().is_some());
+    assert!(monitor
+      .on_binance(&binance_book(), 1_499)
+      .unwrap()
+      .is_none());
+    assert!(monitor.on_venue(&venue_book(), 1_499).unwrap().is_none());
+    assert!(monitor
+      .on_binance(&binance_book(), 1_500)
+      .unwrap()
+      .is_some());
+  }
+
+  #[test]
+  fn stale_books_are_not_compared() {
+    let mut monitor = SpreadMonitor::new(SpreadSetting {
+      sample_ms: 0,
+      max_age_ms: 1_000,
+      ..Default::default()
+    });
+    monitor.on_binance(&binance_book(), 1_000).unwrap();
+    assert!(monitor.on_venue(&venue_book(), 2_000).unwrap().is_some());
+    // Binance's book is now 1001ms old
+    assert!(monitor.on_venue(&venue_book(), 2_001).unwrap().is_none());
+    assert!(monitor
+      .on_binance(&binance_book(), 2_001)
+      .unwrap()
+      .is_some());
+  }
+}
@@ -0,0 +1,233 @@
+use crate::binance::websocket::StreamTrade;
+use crate::shared::reader::MarketTrade;
+use crate::shared::utils::parse_interval;
+use crate::strategy::CandleStick;
+use anyhow::{bail, ensure, Result};
+use std::str::FromStr;
+
+/// Rule deciding when an aggregated bar is complete
+#[derive(Clone, Debug, PartialEq)]
+pub enum BarType {
+  // Fixed interval in milliseconds, aligned to the unix epoch
+  Time(i64),
+  // Close once this much base asset volume has traded
+  Volume(f64),
+  // Close once this much quote asset notional has traded
+  Dollar(f64),
+  // Close every N trades
+  Tick(u64),
+}
+
+/// Parses bar specs such as `1s`, `5s`, `1m`, `4h`, `1d`, `500ms`,
+/// `volume:10`, `dollar:1000000` and `tick:100`
+impl FromStr for BarType {
+  type Err = anyhow::Error;
+
+  fn from_str(spec: &str) -> Result<Self> {
+    let bar_type = match spec.split_once(':') {
+      Some(("volume", threshold)) => BarType::Volume(threshold.parse::<f64>()?),
+      Some(("dollar", threshold)) => BarType::Dollar(threshold.parse::<f64>()?),
+      Some(("tick", threshold)) => BarType::Tick(threshold.parse::<u64>()?),
+      Some((kind, _)) => bail!("Unknown bar type: {}", kind),
+      None => BarType::Time(parse_interval(spec)?),
+    };
+    match bar_type {
+      BarType::Time(interval) => ensure!(interval > 0, "Bar interval must be positive"),
+      BarType::Volume(threshold) | BarType::Dollar(threshold) => {
+        ensure!(threshold > 0.0, "Bar threshold must be positive")
+      }
+      BarType::Tick(threshold) => ensure!(threshold > 0, "Bar threshold must be positive"),
+    }
+    Ok(bar_type)
+  }
+}
+
+/// Builds candles out of individual trades, works the same on the live
+/// `@trade` stream and on recorded trade rows
+pub struct CandleAggregator {
+  symbol: String,
+  bar_type: BarType,
+  current: Option<CandleStick>,
+  notional: f64, // quote asset volume of the current bar
+}
+
+impl CandleAggregator {
+  pub fn new(symbol: String, bar_type: BarType) -> Self {
+    Self {
+      symbol,
+      bar_type,
+      current: None,
+      notional: 0.0,
+    }
+  }
+
+  /// Feed one trade, returns the bars it completed. A trade larger than
+  /// what is left of a volume or dollar bar is split, the rest of it
+  /// opens the next bar, so one trade can complete several bars and
+  /// counts as a trade in each of them.
+  pub fn update(&mut self, price: f64, quantity: f64, trade_time: i64) -> Vec<CandleStick> {
+    let mut completed = vec![];
+    // Time bars are closed by the first trade of the next interval
+    if let (BarType::Time(interval), Some(candle)) = (&self.bar_type, &self.current) {
+      if trade_time >= candle.open_time + interval {
+        completed.extend(self.take_current());
+      }
+    }
+
+    let mut remaining = quantity;
+    loop {
+      // What is left of the bar's threshold, in base asset
+      let room = match (&self.bar_type, &self.current) {
+        (BarType::Volume(threshold), Some(candle)) => threshold - candle.volume,
+        (BarType::Volume(threshold), None) => *threshold,
+        (BarType::Dollar(threshold), _) => (threshold - self.notional) / price,
+        _ => f64::INFINITY,
+      };
+      let filled = remaining >= room;
+      let quantity = if filled { room } else { remaining };
+      remaining -= quantity;
+      self.add(price, quantity, trade_time);
+
+      let threshold_reached = match (&self.bar_type, &self.current) {
+        (BarType::Tick(threshold), Some(candle)) => candle.num_trades >= *threshold,
+        (BarType::Volume(_), _) | (BarType::Dollar(_), _) => filled,
+        _ => false,
+      };
+      if threshold_reached {
+        completed.extend(self.take_current());
+      }
+      if !filled || remaining <= 0.0 {
+        return completed;
+      }
+    }
+  }
+
+  fn add(&mut self, price: f64, quantity: f64, trade_time: i64) {
+    let symbol = &self.symbol;
+    let bar_type = &self.bar_type;
+    let candle = self.current.get_or_insert_with(|| {
+      let open_time = match bar_type {
+        BarType::Time(interval) => trade_time - trade_time.rem_euclid(*interval),
+        _ => trade_time,
+      };
+      CandleStick {
+        symbol: symbol.clone(),
+        open_time,
+        open: price,
+        high: price,
+        low: price,
+        close: price,
+        volume: 0.0,
+        close_time: trade_time,
+        num_trades: 0,
+      }
+    });
+    candle.high = candle.high.max(price);
+    candle.low = candle.low.min(price);
+    candle.close = price;
+    candle.volume += quantity;
+    candle.num_trades += 1;
+    self.notional += price * quantity;
+    if let BarType::Time(interval) = self.bar_type {
+      candle.close_time = candle.open_time + interval - 1;
+    } else {
+      candle.close_time = trade_time;
+    }
+  }
+
+  pub fn update_stream_trade(&mut self, trade: &StreamTrade) -> Result<Vec<CandleStick>> {
+    let price = trade.price.parse::<f64>()?;
+    let quantity = trade.quantity.parse::<f64>()?;
+    Ok(self.update(price, quantity, trade.trade_time))
+  }
+
+  pub fn update_trade(&mut self, trade: &MarketTrade) -> Vec<CandleStick> {
+    self.update(trade.price, trade.quantity, trade.time)
+  }
+
+  /// Close a time bar once the clock passes its end, so quiet markets
+  /// still produce candles without waiting for the next trade
+  pub fn update_time(&mut self, now: i64) -> Option<CandleStick> {
+    match (&self.bar_type, &self.current) {
+      (BarType::Time(_), Some(candle)) if now > candle.close_time => self.take_current(),
+      _ => None,
+    }
+  }
+
+  /// Emit the partial bar, e.g. at the end of a recorded file
+  pub fn flush(&mut self) -> Option<CandleStick> {
+    self.take_current()
+  }
+
+  fn take_current(&mut self) -> Option<CandleStick> {
+    self.notional = 0.0;
+    self.current.take()
+  }
+}
+
+/// Aggregate recorded trades, as `shared::reader::read_trades` yields
+/// them, into completed candles, the trailing partial bar is dropped
+pub fn aggregate_trades<I>(symbol: String, bar_type: BarType, trades: I) -> Result<Vec<CandleStick>>
+where
+  I: IntoIterator<Item = Result<MarketTrade>>,
+{
+  let mut aggregator = CandleAggregator::new(symbol, bar_type);
+  let mut candles = vec![];
+  for trade in trades {
+    candles.extend(aggregator.update_trade(&trade?));
+  }
+  Ok(candles)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn volume_bars_split_large_trades() {
+    let mut aggregator = CandleAggregator::new("BTCUSDT".into(), BarType::Volume(1.0));
+    assert!(aggregator.update(100.0, 0.4, 1).is_empty());
+    let bars = aggregator.update(101.0, 2.1, 2);
+    assert_eq!(bars.len(), 2);
+    assert!((bars[0].volume - 1.0).abs() < 1e-12);
+    assert_eq!((bars[0].open, bars[0].close), (100.0, 101.0));
+    assert!((bars[1].volume - 1.0).abs() < 1e-12);
+    assert_eq!(bars[1].num_trades, 1);
+    let rest = aggregator.flush().unwrap();
+    assert!((rest.volume - 0.5).abs() < 1e-12);
+  }
+
+  #[test]
+  fn dollar_bars_split_on_notional() {
+    let mut aggregator = CandleAggregator::new("BTCUSDT".into(), BarType::Dollar(1000.0));
+    let bars = aggregator.update(100.0, 25.0, 1);
+    assert_eq!(bars.len(), 2);
+    assert!(bars.iter().all(|bar| (bar.volume - 10.0).abs() < 1e-9));
+    assert!((aggregator.flush().unwrap().volume - 5.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn time_bars_close_on_the_next_interval() {
+    let mut aggregator = CandleAggregator::new("BTCUSDT".into(), "1s".parse().unwrap());
+    assert!(aggregator.update(100.0, 1.0, 1_500).is_empty());
+    assert!(aggregator.update(102.0, 1.0, 1_900).is_empty());
+    let bars = aggregator.update(99.0, 1.0, 2_100);
+    assert_eq!(bars.len(), 1);
+    let bar = &bars[0];
+    assert_eq!((bar.open_time, bar.close_time), (1_000, 1_999));
+    assert_eq!(
+      (bar.open, bar.high, bar.low, bar.close),
+      (100.0, 102.0, 100.0, 102.0)
+    );
+    assert_eq!(bar.num_trades, 2);
+  }
+
+  #[test]
+  fn tick_bars_never_split() {
+    let mut aggregator = CandleAggregator::new("BTCUSDT".into(), "tick:2".parse().unwrap());
+    assert!(aggregator.update(100.0, 50.0, 1).is_empty());
+    let bars = aggregator.update(100.0, 50.0, 2);
+    assert_eq!(bars.len(), 1);
+    assert_eq!(bars[0].volume, 100.0);
+  }
+}
@@ -0,0 +1,454 @@
+use super::{CandleStick, Strategy};
+use crate::binance::api::{OrderInput, OrderSide, OrderType, TimeInForce};
+use crate::oms::{Fill, OrderStatus};
+use crate::shared::config::{GridExit, GridSetting, GridSpacing};
+use crate::shared::utils::QTY_EPSILON;
+use anyhow::{anyhow, ensure, Result};
+
+#[derive(Debug)]
+struct GridOrder {
+  client_order_id: String,
+  side: OrderSide,
+  filled: f64,
+  notional: f64,
+  entry_price: Option<f64>, // price of the fill this order takes profit on
+}
+
+#[derive(Debug, PartialEq)]
+enum GridState {
+  Idle,        // no ladder yet
+  Running,     // ladder working
+  Recentering, // waiting for the old ladder's cancels before laying a new one
+  Stopped,
+}
+
+/// Ladder of limit orders between two bounds, buys below the price and
+/// sells above it with the level nearest the price left empty. A filled
+/// buy is replaced by a sell one level up and a filled sell by a buy one
+/// level down, every such round trip earns the step between the levels.
+/// Once price leaves the bounds the ladder is canceled, then either
+/// trading stops or a ladder of the same width is laid around the price.
+/// With an inventory set, sells are only laid for the base asset held,
+/// from the level nearest the price up, and the levels above what it
+/// covers are left empty.
+pub struct Grid {
+  symbol: String,
+  setting: GridSetting,
+  lower: f64,
+  upper: f64,
+  prices: Vec<f64>,
+  orders: Vec<Option<GridOrder>>, // working order of each level
+  state: GridState,
+  inventory: Option<f64>, // base asset held, None when sells can go short
+  pending: Vec<OrderInput>,
+  cancels: Vec<String>,
+  grid_profit: f64,
+  round_trips: usize,
+  last_time: i64,
+  next_seq: u64,
+}
+
+impl Grid {
+  pub fn new(symbol: &str, setting: GridSetting) -> Result<Self> {
+    let (lower, upper) = match (setting.lower, setting.upper) {
+      (Some(lower), Some(upper)) => (lower, upper),
+      _ => return Err(anyhow!("Set strategy.grid.lower and strategy.grid.upper")),
+    };
+    ensure!(
+      lower > 0.0 && lower < upper,
+      "Grid needs 0 < lower < upper, got {} and {}",
+      lower,
+      upper
+    );
+    ensure!(setting.levels >= 2, "Grid needs at least 2 levels");
+    let mut grid = Self {
+      symbol: symbol.to_uppercase(),
+      setting,
+      lower,
+      upper,
+      prices: vec![],
+      orders: vec![],
+      state: GridState::Idle,
+      inventory: None,
+      pending: vec![],
+      cancels: vec![],
+      grid_profit: 0.0,
+      round_trips: 0,
+      last_time: 0,
+      next_seq: 0,
+    };
+    grid.set_bounds(lower, upper)?;
+    Ok(grid)
+  }
+
+  /// Lay only the sells `base_balance` covers, for spot accounts
+  pub fn with_inventory(mut self, base_balance: f64) -> Self {
+    self.inventory = Some(base_balance);
+    self
+  }
+
+  /// Profit of completed round trips in quote asset, before fees
+  pub fn grid_profit(&self) -> f64 {
+    self.grid_profit
+  }
+
+  pub fn round_trips(&self) -> usize {
+    self.round_trips
+  }
+
+  pub fn prices(&self) -> &[f64] {
+    &self.prices
+  }
+
+  pub fn is_stopped(&self) -> bool {
+    self.state == GridState::Stopped
+  }
+
+  fn set_bounds(&mut self, lower: f64, upper: f64) -> Result<()> {
+    let levels = self.setting.levels;
+    let tick = self.setting.tick_size;
+    let prices = (0..levels)
+      .map(|i| {
+        let fraction = i as f64 / (levels - 1) as f64;
+        let price = match self.setting.spacing {
+          GridSpacing::Arithmetic => lower + (upper - lower) * fraction,
+          GridSpacing::Geometric => lower * (upper / lower).powf(fraction),
+        };
+        (price / tick).round() * tick
+      })
+      .collect::<Vec<_>>();
+    ensure!(
+      prices.windows(2).all(|pair| pair[0] < pair[1]),
+      "Grid levels between {} and {} are closer than the tick size {}",
+      lower,
+      upper,
+      tick
+    );
+    self.lower = lower;
+    self.upper = upper;
+    self.prices = prices;
+    self.orders = (0..levels).map(|_| None).collect();
+    Ok(())
+  }
+
+  // Same width around `price`, in steps for arithmetic grids and in
+  // ratio for geometric ones
+  fn recenter(&mut self, price: f64) -> Result<()> {
+    let (lower, upper) = match self.setting.spacing {
+      GridSpacing::Arithmetic => {
+        let half_width = (self.upper - self.lower) / 2.0;
+        (price - half_width, price + half_width)
+      }
+      GridSpacing::Geometric => {
+        let half_ratio = (self.upper / self.lower).sqrt();
+        (price / half_ratio, price * half_ratio)
+      }
+    };
+    ensure!(
+      lower > 0.0,
+      "Can't recenter the grid around {}, its lower bound would be {}",
+      price,
+      lower
+    );
+    log::warn!("Recentering grid on {} to {} - {}", price, lower, upper);
+    self.set_bounds(lower, upper)
+  }
+
+  fn lay_ladder(&mut self, price: f64, time: i64) -> Vec<OrderInput> {
+    let nearest = (0..self.prices.len())
+      .min_by(|a, b| {
+        let distance = |i: &usize| (self.prices[*i] - price).abs();
+        distance(a).partial_cmp(&distance(b)).unwrap()
+      })
+      .unwrap();
+    log::info!(
+      "Laying grid of {} levels between {} and {} at {}",
+      self.prices.len(),
+      self.lower,
+      self.upper,
+      price
+    );
+    let quantity = self.setting.quantity;
+    let held = self.inventory.unwrap_or(f64::INFINITY);
+    let mut uncovered = held;
+    let mut skipped = 0;
+    let mut orders = vec![];
+    for level in 0..self.prices.len() {
+      let side = match level {
+        level if level < nearest => OrderSide::Buy,
+        level if level > nearest => OrderSide::Sell,
+        _ => continue,
+      };
+      if side == OrderSide::Sell {
+        if uncovered < quantity - QTY_EPSILON {
+          skipped += 1;
+          continue;
+        }
+        uncovered -= quantity;
+      }
+      orders.push(self.level_order(level, side, quantity, None, time));
+    }
+    if skipped > 0 {
+      let sells = self.prices.len() - nearest - 1;
+      log::warn!(
+        "Base balance {} covers {} of {} sell levels, leaving the top {} empty",
+        held,
+        sells - skipped,
+        sells,
+        skipped
+      );
+    }
+    self.state = GridState::Running;
+    orders
+  }
+
+  fn level_order(
+    &mut self,
+    level: usize,
+    side: OrderSide,
+    quantity: f64,
+    entry_price: Option<f64>,
+    time: i64,
+  ) -> OrderInput {
+    self.next_seq += 1;
+    let client_order_id = format!(
+      "grid_{}_{}{}_{}",
+      time,
+      match side {
+        OrderSide::Buy => "buy",
+        OrderSide::Sell => "sell",
+      },
+      level,
+      self.next_seq
+    );
+    self.orders[level] = Some(GridOrder {
+      client_order_id: client_order_id.clone(),
+      side: side.clone(),
+      filled: 0.0,
+      notional: 0.0,
+      entry_price,
+    });
+    OrderInput {
+      symbol: self.symbol.clone(),
+      side,
+      order_type: OrderType::Limit,
+      time_in_force: Some(TimeInForce::GTC),
+      quantity: Some(quantity as f32),
+      quote_order_qty: None,
+      price: Some(self.prices[level] as f32),
+      new_client_order_id: client_order_id,
+      stop_price: None,
+      iceberg_qty: None,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: time,
+    }
+  }
+
+  fn cancel_ladder(&mut self) {
+    // Replacements not sent yet only need forgetting
+    for order in std::mem::take(&mut self.pending) {
+      if let Some(level) = self.level_of(&order.new_client_order_id) {
+        self.orders[level] = None;
+      }
+    }
+    for order in self.orders.iter().flatten() {
+      self.cancels.push(order.client_order_id.clone());
+    }
+  }
+
+  fn level_of(&self, client_order_id: &str) -> Option<usize> {
+    self
+      .orders
+      .iter()
+      .position(|order| matches!(order, Some(order) if order.client_order_id == client_order_id))
+  }
+}
+
+impl Strategy for Grid {
+  fn on_candle(&mut self, candle: CandleStick) -> Result<Vec<OrderInput>> {
+    let (price, time) = (candle.close, candle.close_time);
+    self.last_time = time;
+    let out_of_range = price < self.lower || price > self.upper;
+    match self.state {
+      GridState::Stopped => Ok(vec![]),
+      GridState::Idle | GridState::Recentering if self.orders.iter().any(Option::is_some) => {
+        Ok(vec![])
+      }
+      GridState::Idle | GridState::Recentering => {
+        if out_of_range {
+          match self.setting.on_exit {
+            GridExit::Recenter => self.recenter(price)?,
+            GridExit::Stop => {
+              log::warn!(
+                "Price {} outside the grid {} - {}, waiting",
+                price,
+                self.lower,
+                self.upper
+              );
+              return Ok(vec![]);
+            }
+          }
+        }
+        Ok(self.lay_ladder(price, time))
+      }
+      GridState::Running if out_of_range => {
+        log::warn!(
+          "Price {} left the grid {} - {}, canceling the ladder",
+          price,
+          self.lower,
+          self.upper
+        );
+        self.cancel_ladder();
+        match self.setting.on_exit {
+          GridExit::Stop => self.state = GridState::Stopped,
+          // Bounds move once the ladder is gone, its fills still count
+          GridExit::Recenter => self.state = GridState::Recentering,
+        }
+        Ok(vec![])
+      }
+      GridState::Running => {
+        let mut orders = std::mem::take(&mut self.pending);
+        for order in orders.iter_mut() {
+          order.timestamp = time;
+        }
+        Ok(orders)
+      }
+    }
+  }
+
+  fn take_cancels(&mut self) -> Vec<String> {
+    std::mem::take(&mut self.cancels)
+  }
+
+  fn on_fill(&mut self, fill: &Fill) -> Result<()> {
+    if let Some(inventory) = self.inventory.as_mut() {
+      *inventory += match fill.side {
+        OrderSide::Buy => fill.quantity,
+        OrderSide::Sell => -fill.quantity,
+      };
+    }
+    if let Some(level) = self.level_of(&fill.client_order_id) {
+      let order = self.orders[level].as_mut().unwrap();
+      order.filled += fill.quantity;
+      order.notional += fill.quantity * fill.price;
+    }
+    Ok(())
+  }
+
+  fn on_order_finished(&mut self, client_order_id: &str, status: OrderStatus) {
+    let level = match self.level_of(client_order_id) {
+      Some(level) => level,
+      None => return,
+    };
+    let order = self.orders[level].take().unwrap();
+    if order.filled < QTY_EPSILON {
+      if status != OrderStatus::Canceled {
+        log::warn!("Grid order {} {:?}", client_order_id, status);
+      }
+      return;
+    }
+    let avg_price = order.notional / order.filled;
+    if let Some(entry_price) = order.entry_price {
+      let profit = match order.side {
+        OrderSide::Sell => order.filled * (avg_price - entry_price),
+        OrderSide::Buy => order.filled * (entry_price - avg_price),
+      };
+      self.grid_profit += profit;
+      self.round_trips += 1;
+      log::info!(
+        "Grid round trip {} earned {}, grid profit {}",
+        self.round_trips,
+        profit,
+        self.grid_profit
+      );
+    }
+    // Canceled ladders are not replaced, what filled stays in the inventory
+    if self.state != GridState::Running {
+      return;
+    }
+    let (counter_level, counter_side) = match order.side {
+      OrderSide::Buy if level + 1 < self.prices.len() => (level + 1, OrderSide::Sell),
+      OrderSide::Sell if level > 0 => (level - 1, OrderSide::Buy),
+      _ => return,
+    };
+    if self.orders[counter_level].is_some() {
+      log::warn!(
+        "Grid level {} still working, not replacing {}",
+        counter_level,
+        client_order_id
+      );
+      return;
+    }
+    let order = self.level_order(
+      counter_level,
+      counter_side,
+      order.filled,
+      Some(avg_price),
+      self.last_time,
+    );
+    self.pending.push(order);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn candle(close: f64) -> CandleStick {
+    CandleStick {
+      symbol: "BTCUSDT".to_string(),
+      open_time: 0,
+      open: close,
+      high: close,
+      low: close,
+      close,
+      volume: 1.0,
+      close_time: 59_999,
+      num_trades: 1,
+    }
+  }
+
+  fn ladder(grid: &mut Grid) -> Vec<(OrderSide, f32)> {
+    grid
+      .on_candle(candle(102.0))
+      .unwrap()
+      .into_iter()
+      .map(|order| (order.side, order.price.unwrap()))
+      .collect()
+  }
+
+  fn setting() -> GridSetting {
+    GridSetting {
+      lower: Some(100.0),
+      upper: Some(106.0),
+      levels: 7,
+      quantity: 1.0,
+      tick_size: 1.0,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn sells_are_limited_to_the_inventory() {
+    let mut grid = Grid::new("BTCUSDT", setting()).unwrap().with_inventory(2.5);
+    let orders = ladder(&mut grid);
+    let sells = orders
+      .iter()
+      .filter(|(side, _)| *side == OrderSide::Sell)
+      .map(|(_, price)| *price)
+      .collect::<Vec<_>>();
+    assert_eq!(sells, vec![103.0, 104.0]);
+    assert_eq!(orders.len(), 4);
+  }
+
+  #[test]
+  fn sells_without_inventory_can_go_short() {
+    let mut grid = Grid::new("BTCUSDT", setting()).unwrap();
+    let sells = ladder(&mut grid)
+      .iter()
+      .filter(|(side, _)| *side == OrderSide::Sell)
+      .count();
+    assert_eq!(sells, 4);
+  }
+}
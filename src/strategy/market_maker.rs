@@ -0,0 +1,445 @@
+use super::orderbook::OrderBook;
+use super::{CandleStick, Strategy};
+use crate::binance::api::{OrderInput, OrderSide, OrderType};
+use crate::oms::{Fill, OrderStatus};
+use crate::shared::config::MarketMakerSetting;
+use crate::shared::utils::QTY_EPSILON;
+use anyhow::Result;
+
+// Prices within this many ticks below a tick boundary round up to it
+const TICK_TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug)]
+struct Quote {
+  client_order_id: String,
+  price: f64,
+  quantity: f64,
+  filled: f64,
+  canceling: bool,
+}
+
+impl Quote {
+  fn remaining(&self) -> f64 {
+    self.quantity - self.filled
+  }
+}
+
+/// Quotes one bid and one ask with `LIMIT_MAKER` orders around the mid
+/// price. Both quotes lean away from the inventory built up by fills so
+/// it drifts back to flat, and the side that would grow the inventory
+/// past `max_inventory` stops quoting. Quotes that drift too far from
+/// where the book says they should be are canceled, at most once per
+/// `min_update_ms`, and placed again once the cancel is confirmed.
+/// With a base balance set, asks are only quoted while it covers them.
+pub struct MarketMaker {
+  symbol: String,
+  setting: MarketMakerSetting,
+  inventory: f64,    // base asset bought minus sold
+  held: Option<f64>, // base asset held, None when asks can go short
+  bid: Option<Quote>,
+  ask: Option<Quote>,
+  cancels: Vec<String>,
+  last_requote: Option<i64>,
+  next_seq: u64,
+}
+
+impl MarketMaker {
+  pub fn new(symbol: &str, setting: MarketMakerSetting) -> Self {
+    Self {
+      symbol: symbol.to_uppercase(),
+      setting,
+      inventory: 0.0,
+      held: None,
+      bid: None,
+      ask: None,
+      cancels: vec![],
+      last_requote: None,
+      next_seq: 0,
+    }
+  }
+
+  /// Quote only the asks `base_balance` covers, for spot accounts
+  pub fn with_inventory(mut self, base_balance: f64) -> Self {
+    self.held = Some(base_balance);
+    self
+  }
+
+  pub fn inventory(&self) -> f64 {
+    self.inventory
+  }
+
+  /// Where the bid and ask should be for `book`, None for a side that
+  /// can't quote
+  fn target_prices(&self, book: &OrderBook, mid: f64) -> (Option<f64>, Option<f64>) {
+    let setting = &self.setting;
+    let tick = setting.tick_size;
+    let skew = setting.skew_bps / 10_000.0 * self.inventory / setting.max_inventory;
+    let reservation = mid * (1.0 - skew);
+    let half_spread = mid * setting.spread_bps / 20_000.0;
+
+    // LIMIT_MAKER orders that would take are rejected, stay off the touch
+    let mut bid = (((reservation - half_spread) / tick) + TICK_TOLERANCE).floor() * tick;
+    let mut ask = (((reservation + half_spread) / tick) - TICK_TOLERANCE).ceil() * tick;
+    if let Some(best_ask) = book.best_ask() {
+      bid = bid.min(best_ask - tick);
+    }
+    if let Some(best_bid) = book.best_bid() {
+      ask = ask.max(best_bid + tick);
+    }
+    (
+      Some(bid).filter(|price| *price > 0.0),
+      Some(ask).filter(|price| *price > 0.0),
+    )
+  }
+
+  // Whether a quote of `quantity` on `side` keeps the inventory within
+  // max_inventory if it fills completely, and an ask the base held
+  fn within_limit(&self, side: &OrderSide, quantity: f64) -> bool {
+    let limit = self.setting.max_inventory + QTY_EPSILON;
+    match side {
+      OrderSide::Buy => self.inventory + quantity <= limit,
+      OrderSide::Sell => {
+        self.inventory - quantity >= -limit
+          && self.held.is_none_or(|held| quantity <= held + QTY_EPSILON)
+      }
+    }
+  }
+
+  // Cancel or place the quote of one side, returns whether it requoted
+  fn update_side(
+    &mut self,
+    side: OrderSide,
+    target: Option<f64>,
+    mid: f64,
+    can_requote: bool,
+    orders: &mut Vec<OrderInput>,
+    time: i64,
+  ) -> bool {
+    let quote = match side {
+      OrderSide::Buy => &self.bid,
+      OrderSide::Sell => &self.ask,
+    };
+    match quote {
+      Some(quote) if quote.canceling => false,
+      Some(quote) => {
+        let keep = match target {
+          Some(price) => {
+            (quote.price - price).abs() / mid * 10_000.0 <= self.setting.requote_bps
+              && self.within_limit(&side, quote.remaining())
+          }
+          None => false,
+        };
+        if keep || !can_requote {
+          return false;
+        }
+        let client_order_id = quote.client_order_id.clone();
+        log::debug!("Canceling {:?} quote {}", side, client_order_id);
+        self.cancels.push(client_order_id);
+        let quote = match side {
+          OrderSide::Buy => self.bid.as_mut(),
+          OrderSide::Sell => self.ask.as_mut(),
+        };
+        if let Some(quote) = quote {
+          quote.canceling = true;
+        }
+        true
+      }
+      None => {
+        let quantity = self.setting.quantity;
+        let price = match target {
+          Some(price) if self.within_limit(&side, quantity) => price,
+          _ => return false,
+        };
+        self.next_seq += 1;
+        let client_order_id = format!(
+          "mm_{}_{}_{}",
+          time,
+          match side {
+            OrderSide::Buy => "bid",
+            OrderSide::Sell => "ask",
+          },
+          self.next_seq
+        );
+        log::debug!(
+          "Quoting {:?} {} @ {} as {}",
+          side,
+          quantity,
+          price,
+          client_order_id
+        );
+        orders.push(OrderInput {
+          symbol: self.symbol.clone(),
+          side: side.clone(),
+          order_type: OrderType::LimitMaker,
+          time_in_force: None,
+          quantity: Some(quantity as f32),
+          quote_order_qty: None,
+          price: Some(price as f32),
+          new_client_order_id: client_order_id.clone(),
+          stop_price: None,
+          iceberg_qty: None,
+          new_order_resp_type: None,
+          recv_window: None,
+          timestamp: time,
+        });
+        let quote = Some(Quote {
+          client_order_id,
+          price,
+          quantity,
+          filled: 0.0,
+          canceling: false,
+        });
+        match side {
+          OrderSide::Buy => self.bid = quote,
+          OrderSide::Sell => self.ask = quote,
+        }
+        false
+      }
+    }
+  }
+}
+
+impl Strategy for MarketMaker {
+  // Quotes follow the book, candles carry nothing to act on
+  fn on_candle(&mut self, _candle: CandleStick) -> Result<Vec<OrderInput>> {
+    Ok(vec![])
+  }
+
+  fn on_book(&mut self, book: &OrderBook) -> Result<Vec<OrderInput>> {
+    let mid = match book.mid() {
+      Some(mid) => mid,
+      None => {
+        log::warn!("No mid price in the {} book, not quoting", book.symbol);
+        return Ok(vec![]);
+      }
+    };
+    let can_requote = match self.last_requote {
+      Some(last) => book.time - last >= self.setting.min_update_ms,
+      None => true,
+    };
+    let (bid, ask) = self.target_prices(book, mid);
+    let mut orders = vec![];
+    let requoted_bid = self.update_side(
+      OrderSide::Buy,
+      bid,
+      mid,
+      can_requote,
+      &mut orders,
+      book.time,
+    );
+    let requoted_ask = self.update_side(
+      OrderSide::Sell,
+      ask,
+      mid,
+      can_requote,
+      &mut orders,
+      book.time,
+    );
+    if requoted_bid || requoted_ask {
+      self.last_requote = Some(book.time);
+    }
+    Ok(orders)
+  }
+
+  fn take_cancels(&mut self) -> Vec<String> {
+    std::mem::take(&mut self.cancels)
+  }
+
+  fn on_fill(&mut self, fill: &Fill) -> Result<()> {
+    if let Some(held) = self.held.as_mut() {
+      *held += match fill.side {
+        OrderSide::Buy => fill.quantity,
+        OrderSide::Sell => -fill.quantity,
+      };
+    }
+    let quote = self
+      .bid
+      .iter_mut()
+      .chain(self.ask.iter_mut())
+      .find(|quote| quote.client_order_id == fill.client_order_id);
+    if let Some(quote) = quote {
+      quote.filled += fill.quantity;
+      self.inventory += match fill.side {
+        OrderSide::Buy => fill.quantity,
+        OrderSide::Sell => -fill.quantity,
+      };
+      log::info!(
+        "Quote {} {:?} {} @ {}, inventory {}",
+        fill.client_order_id,
+        fill.side,
+        fill.quantity,
+        fill.price,
+        self.inventory
+      );
+    }
+    Ok(())
+  }
+
+  fn on_order_finished(&mut self, client_order_id: &str, status: OrderStatus) {
+    for quote in [&mut self.bid, &mut self.ask].iter_mut() {
+      if matches!(quote, Some(quote) if quote.client_order_id == client_order_id) {
+        log::debug!("Quote {} {:?}", client_order_id, status);
+        **quote = None;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn setting() -> MarketMakerSetting {
+    MarketMakerSetting {
+      spread_bps: 10.0,
+      quantity: 1.0,
+      tick_size: 0.01,
+      skew_bps: 5.0,
+      max_inventory: 2.0,
+      min_update_ms: 500,
+      requote_bps: 1.0,
+    }
+  }
+
+  fn book(time: i64, bid: f64, ask: f64) -> OrderBook {
+    OrderBook {
+      symbol: "BTCUSDT".to_string(),
+      time,
+      bids: vec![(bid, 1.0)],
+      asks: vec![(ask, 1.0)],
+    }
+  }
+
+  fn fill(client_order_id: &str, side: OrderSide, quantity: f64) -> Fill {
+    Fill {
+      client_order_id: client_order_id.to_string(),
+      order_id: None,
+      trade_id: None,
+      symbol: "BTCUSDT".to_string(),
+      side,
+      price: 100.0,
+      quantity,
+      commission: 0.0,
+      commission_asset: None,
+      time: 0,
+      replaces_qty: 0.0,
+    }
+  }
+
+  // Side and price of each order
+  fn quotes(orders: &[OrderInput]) -> Vec<(OrderSide, f32)> {
+    orders
+      .iter()
+      .map(|order| (order.side.clone(), order.price.unwrap()))
+      .collect()
+  }
+
+  fn assert_prices(prices: (Option<f64>, Option<f64>), bid: f64, ask: f64) {
+    let (quoted_bid, quoted_ask) = (prices.0.unwrap(), prices.1.unwrap());
+    assert!((quoted_bid - bid).abs() < 1e-9, "bid {}", quoted_bid);
+    assert!((quoted_ask - ask).abs() < 1e-9, "ask {}", quoted_ask);
+  }
+
+  #[test]
+  fn quotes_lean_away_from_the_inventory() {
+    let mut mm = MarketMaker::new("BTCUSDT", setting());
+    let book = book(0, 99.9, 100.1);
+    assert_prices(mm.target_prices(&book, 100.0), 99.95, 100.05);
+    // Half of max_inventory long shifts both by half of skew_bps
+    mm.inventory = 1.0;
+    assert_prices(mm.target_prices(&book, 100.0), 99.92, 100.03);
+    mm.inventory = -1.0;
+    assert_prices(mm.target_prices(&book, 100.0), 99.97, 100.08);
+  }
+
+  #[test]
+  fn quotes_stay_off_the_touch() {
+    let mut mm = MarketMaker::new(
+      "BTCUSDT",
+      MarketMakerSetting {
+        skew_bps: 100.0,
+        ..setting()
+      },
+    );
+    let book = book(0, 99.99, 100.0);
+    // Short enough that the bid would cross the ask
+    mm.inventory = -2.0;
+    let (bid, _) = mm.target_prices(&book, 99.995);
+    assert!((bid.unwrap() - 99.99).abs() < 1e-9);
+    mm.inventory = 2.0;
+    let (_, ask) = mm.target_prices(&book, 99.995);
+    assert!((ask.unwrap() - 100.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn side_past_max_inventory_stops_quoting() {
+    let mut mm = MarketMaker::new("BTCUSDT", setting());
+    mm.inventory = 1.5;
+    let orders = mm.on_book(&book(0, 99.9, 100.1)).unwrap();
+    assert_eq!(quotes(&orders), vec![(OrderSide::Sell, 100.02)]);
+    assert!(matches!(orders[0].order_type, OrderType::LimitMaker));
+
+    let mut mm = MarketMaker::new("BTCUSDT", setting());
+    mm.inventory = -1.5;
+    let orders = mm.on_book(&book(0, 99.9, 100.1)).unwrap();
+    assert_eq!(quotes(&orders), vec![(OrderSide::Buy, 99.98)]);
+  }
+
+  #[test]
+  fn drifted_quotes_are_canceled_then_replaced() {
+    let mut mm = MarketMaker::new("BTCUSDT", setting());
+    let orders = mm.on_book(&book(0, 99.9, 100.1)).unwrap();
+    assert_eq!(orders.len(), 2);
+    let ids = orders
+      .iter()
+      .map(|order| order.new_client_order_id.clone())
+      .collect::<Vec<_>>();
+
+    // The book moved, both quotes are canceled and nothing new goes out
+    // until the cancels are confirmed
+    assert!(mm.on_book(&book(1000, 100.9, 101.1)).unwrap().is_empty());
+    assert_eq!(mm.take_cancels(), ids);
+    assert!(mm.on_book(&book(1100, 100.9, 101.1)).unwrap().is_empty());
+    assert!(mm.take_cancels().is_empty());
+
+    mm.on_order_finished(&ids[0], OrderStatus::Canceled);
+    let orders = mm.on_book(&book(1200, 100.9, 101.1)).unwrap();
+    assert_eq!(quotes(&orders), vec![(OrderSide::Buy, 100.94)]);
+    mm.on_order_finished(&ids[1], OrderStatus::Canceled);
+    let orders = mm.on_book(&book(1300, 100.9, 101.1)).unwrap();
+    assert_eq!(quotes(&orders), vec![(OrderSide::Sell, 101.06)]);
+  }
+
+  #[test]
+  fn requotes_wait_for_min_update_ms() {
+    let mut mm = MarketMaker::new("BTCUSDT", setting());
+    let orders = mm.on_book(&book(0, 99.9, 100.1)).unwrap();
+    let bid = orders[0].new_client_order_id.clone();
+    mm.on_book(&book(1000, 100.9, 101.1)).unwrap();
+    assert_eq!(mm.take_cancels().len(), 2);
+    mm.on_order_finished(&bid, OrderStatus::Canceled);
+    mm.on_book(&book(1000, 100.9, 101.1)).unwrap();
+
+    // Too soon after the last requote, the new bid stays where it is
+    assert!(mm.on_book(&book(1400, 101.9, 102.1)).unwrap().is_empty());
+    assert!(mm.take_cancels().is_empty());
+    mm.on_book(&book(1500, 101.9, 102.1)).unwrap();
+    assert_eq!(mm.take_cancels().len(), 1);
+  }
+
+  #[test]
+  fn asks_are_limited_to_the_base_held() {
+    let mut mm = MarketMaker::new("BTCUSDT", setting()).with_inventory(0.5);
+    let orders = mm.on_book(&book(0, 99.9, 100.1)).unwrap();
+    assert_eq!(quotes(&orders), vec![(OrderSide::Buy, 99.95)]);
+
+    mm.on_fill(&fill(&orders[0].new_client_order_id, OrderSide::Buy, 1.0))
+      .unwrap();
+    mm.on_order_finished(&orders[0].new_client_order_id, OrderStatus::Filled);
+    let sides = quotes(&mm.on_book(&book(100, 99.9, 100.1)).unwrap())
+      .into_iter()
+      .map(|(side, _)| side)
+      .collect::<Vec<_>>();
+    assert_eq!(sides, vec![OrderSide::Buy, OrderSide::Sell]);
+  }
+}
@@ -0,0 +1,344 @@
+use crate::binance::api::{KlineResp, OrderInput, OrderSide, OrderType};
+use crate::oms::{Fill, OrderStatus};
+use crate::portfolio::Portfolio;
+use crate::shared::config::MeanReversionSetting;
+use crate::shared::utils::QTY_EPSILON;
+use crate::strategy::{base_change, floor_qty, CandleStick, Strategy};
+use anyhow::{ensure, Result};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Intent {
+  EnterLong,
+  EnterShort,
+  ExitLong,
+  ExitShort,
+}
+
+// The open position, built up from the fills of its entry order
+#[derive(Debug)]
+struct Entry {
+  long: bool,
+  amount: f64,        // base asset
+  price: f64,         // average fill price
+  stop_distance: f64, // from the entry price, set when the signal fired
+}
+
+/// Fades moves outside the Bollinger bands: goes long when the close
+/// is `num_std` standard deviations below the moving average with the
+/// RSI oversold, short on the mirror image unless `long_only`. The
+/// position is closed once price is back at the average, or on a stop
+/// `stop_std` standard deviations past the entry. Indicators roll on
+/// closed candles, the forming candle's close is what gets traded on.
+pub struct MeanReversion {
+  setting: MeanReversionSetting,
+  closes: VecDeque<f64>,       // closes of the last `period` closed candles
+  last_open_time: i64,         // open time of the newest closed candle
+  forming: Option<(i64, f64)>, // open time and latest close of the forming candle
+  last_close: f64,
+  avg_gain: f64,
+  avg_loss: f64,
+  rsi_samples: usize,
+  portfolio: Portfolio,
+  position: Option<Entry>,
+  // orders sent but not finished yet
+  in_flight: HashMap<String, Intent>,
+  order_prefix: String,
+  order_seq: u64,
+}
+
+impl MeanReversion {
+  /// `candles` are closed candles to warm the indicators up with,
+  /// `portfolio` holds the starting balances
+  pub fn new(
+    symbol: &str,
+    candles: Vec<KlineResp>,
+    setting: MeanReversionSetting,
+    mut portfolio: Portfolio,
+  ) -> Result<Self> {
+    ensure!(
+      candles.len() >= setting.warmup(),
+      "Mean reversion needs {} candles to warm up, got {}",
+      setting.warmup(),
+      candles.len()
+    );
+    let last = candles.last().unwrap();
+    portfolio.on_price(symbol, last.close);
+    let mut strategy = Self {
+      closes: VecDeque::new(),
+      last_open_time: last.open_time,
+      forming: None,
+      last_close: candles[0].close,
+      avg_gain: 0.0,
+      avg_loss: 0.0,
+      rsi_samples: 0,
+      portfolio,
+      position: None,
+      in_flight: HashMap::new(),
+      order_prefix: format!("mr_{}", chrono::Utc::now().timestamp_millis()),
+      order_seq: 0,
+      setting,
+    };
+    for candle in &candles {
+      strategy.push_close(candle.close);
+    }
+    Ok(strategy)
+  }
+
+  pub fn portfolio(&self) -> &Portfolio {
+    &self.portfolio
+  }
+
+  pub fn execute(&mut self, candle: CandleStick) -> Result<Vec<OrderInput>> {
+    self.roll(&candle);
+    let price = candle.close;
+    self.portfolio.on_price(&candle.symbol, price);
+
+    let (mean, std) = self.bands();
+    let rsi = self.rsi(price);
+    log::info!(
+      "Symbol: {} Price: {} Mean: {} Std: {} RSI: {}",
+      candle.symbol,
+      price,
+      mean,
+      std,
+      rsi
+    );
+    // One order at a time, the next signal waits for its fills
+    if !self.in_flight.is_empty() {
+      return Ok(vec![]);
+    }
+
+    let setting = &self.setting;
+    let entry_amount = self.portfolio.equity() * setting.risk_fraction;
+    let stop_distance = setting.stop_std * std;
+    let intent = match &self.position {
+      None if price < mean - setting.num_std * std && rsi < setting.rsi_oversold => {
+        log::info!("Price below the lower band with RSI {}, entering long", rsi);
+        Intent::EnterLong
+      }
+      None
+        if !setting.long_only
+          && price > mean + setting.num_std * std
+          && rsi > setting.rsi_overbought =>
+      {
+        log::info!(
+          "Price above the upper band with RSI {}, entering short",
+          rsi
+        );
+        Intent::EnterShort
+      }
+      Some(entry) if entry.long && price >= mean => {
+        log::info!("Price back at the mean, closing long");
+        Intent::ExitLong
+      }
+      Some(entry) if entry.long && price <= entry.price - entry.stop_distance => {
+        log::info!("Long stopped out at {}", price);
+        Intent::ExitLong
+      }
+      Some(entry) if !entry.long && price <= mean => {
+        log::info!("Price back at the mean, closing short");
+        Intent::ExitShort
+      }
+      Some(entry) if !entry.long && price >= entry.price + entry.stop_distance => {
+        log::info!("Short stopped out at {}", price);
+        Intent::ExitShort
+      }
+      _ => return Ok(vec![]),
+    };
+    let amount = match (intent, &self.position) {
+      (Intent::EnterLong, _) | (Intent::EnterShort, _) => {
+        self.position = Some(Entry {
+          long: intent == Intent::EnterLong,
+          amount: 0.0,
+          price: 0.0,
+          stop_distance,
+        });
+        entry_amount
+      }
+      (_, Some(entry)) => entry.amount,
+      (_, None) => return Ok(vec![]),
+    };
+    Ok(vec![self.order(candle.symbol, intent, amount)])
+  }
+
+  // Candles of a new period close the forming one, updates of candles
+  // already rolled in are only traded on
+  fn roll(&mut self, candle: &CandleStick) {
+    if candle.open_time <= self.last_open_time {
+      return;
+    }
+    if let Some((open_time, close)) = self.forming {
+      if candle.open_time > open_time {
+        self.push_close(close);
+        self.last_open_time = open_time;
+      }
+    }
+    self.forming = Some((candle.open_time, candle.close));
+  }
+
+  fn push_close(&mut self, close: f64) {
+    if !self.closes.is_empty() {
+      let (gain, loss) = gain_loss(close - self.last_close);
+      let period = self.setting.rsi_period as f64;
+      if self.rsi_samples < self.setting.rsi_period {
+        // Simple average over the first period, Wilder's smoothing after
+        self.rsi_samples += 1;
+        let samples = self.rsi_samples as f64;
+        self.avg_gain += (gain - self.avg_gain) / samples;
+        self.avg_loss += (loss - self.avg_loss) / samples;
+      } else {
+        self.avg_gain = (self.avg_gain * (period - 1.0) + gain) / period;
+        self.avg_loss = (self.avg_loss * (period - 1.0) + loss) / period;
+      }
+    }
+    self.last_close = close;
+    self.closes.push_back(close);
+    if self.closes.len() > self.setting.period {
+      self.closes.pop_front();
+    }
+  }
+
+  // Moving average and population standard deviation of the closes
+  fn bands(&self) -> (f64, f64) {
+    let count = self.closes.len() as f64;
+    let mean = self.closes.iter().sum::<f64>() / count;
+    let variance = self
+      .closes
+      .iter()
+      .map(|close| (close - mean).powi(2))
+      .sum::<f64>()
+      / count;
+    (mean, variance.sqrt())
+  }
+
+  // RSI with `price` as the close of the forming candle
+  fn rsi(&self, price: f64) -> f64 {
+    let period = self.setting.rsi_period as f64;
+    let (gain, loss) = gain_loss(price - self.last_close);
+    let avg_gain = (self.avg_gain * (period - 1.0) + gain) / period;
+    let avg_loss = (self.avg_loss * (period - 1.0) + loss) / period;
+    if avg_loss == 0.0 {
+      return 100.0;
+    }
+    100.0 - 100.0 / (1.0 + avg_gain / avg_loss)
+  }
+
+  // Entries are sized in quote asset, exits in the base amount closed
+  fn order(&mut self, symbol: String, intent: Intent, amount: f64) -> OrderInput {
+    let now = chrono::Utc::now().timestamp_millis();
+    self.order_seq += 1;
+    let (side, label) = match intent {
+      Intent::EnterLong => (OrderSide::Buy, "long"),
+      Intent::EnterShort => (OrderSide::Sell, "short"),
+      Intent::ExitLong => (OrderSide::Sell, "xlong"),
+      Intent::ExitShort => (OrderSide::Buy, "xshort"),
+    };
+    let order_id = format!("{}_{}_{}", self.order_prefix, label, self.order_seq);
+    self.in_flight.insert(order_id.clone(), intent);
+    let (quantity, quote_order_qty) = match intent {
+      Intent::EnterLong | Intent::EnterShort => (None, Some(amount as f32)),
+      Intent::ExitLong | Intent::ExitShort => (Some(floor_qty(amount)), None),
+    };
+    OrderInput {
+      symbol,
+      side,
+      order_type: OrderType::Market,
+      time_in_force: None,
+      quantity,
+      quote_order_qty,
+      price: None,
+      new_client_order_id: order_id,
+      stop_price: None,
+      iceberg_qty: None,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: now,
+    }
+  }
+
+  // The entry holds what its fills left after commission in the base
+  // asset, the exit can't sell more than that
+  fn apply_fill(&mut self, intent: Intent, fill: &Fill) {
+    let entry = match self.position.as_mut() {
+      Some(entry) => entry,
+      None => return,
+    };
+    let change = base_change(fill);
+    match intent {
+      Intent::EnterLong | Intent::EnterShort => {
+        let filled = entry.amount + fill.quantity;
+        if filled > 0.0 {
+          entry.price = (entry.amount * entry.price + fill.quantity * fill.price) / filled;
+        }
+        entry.amount += if entry.long { change } else { -change };
+      }
+      Intent::ExitLong | Intent::ExitShort => {
+        entry.amount -= if entry.long { -change } else { change };
+        if entry.amount < QTY_EPSILON {
+          self.position = None;
+        }
+      }
+    }
+  }
+}
+
+fn gain_loss(change: f64) -> (f64, f64) {
+  if change > 0.0 {
+    (change, 0.0)
+  } else {
+    (0.0, -change)
+  }
+}
+
+impl Strategy for MeanReversion {
+  fn on_candle(&mut self, candle: CandleStick) -> Result<Vec<OrderInput>> {
+    self.execute(candle)
+  }
+
+  fn on_fill(&mut self, fill: &Fill) -> Result<()> {
+    self.portfolio.on_fill(fill)?;
+    // Reports of synthesized fills only carry their commission, it
+    // matters when paid in the base asset
+    if fill.quantity <= 0.0 && base_change(fill) == 0.0 {
+      return Ok(());
+    }
+    match self.in_flight.get(&fill.client_order_id) {
+      Some(intent) => {
+        let intent = *intent;
+        self.apply_fill(intent, fill);
+      }
+      None => log::warn!(
+        "Fill of unknown order {}: {} @ {}",
+        fill.client_order_id,
+        fill.quantity,
+        fill.price
+      ),
+    }
+    Ok(())
+  }
+
+  fn on_order_finished(&mut self, client_order_id: &str, status: OrderStatus) {
+    let intent = match self.in_flight.remove(client_order_id) {
+      Some(intent) => intent,
+      None => return,
+    };
+    if status != OrderStatus::Filled {
+      log::warn!("Order {} finished as {:?}", client_order_id, status);
+    }
+    match intent {
+      // An entry that never filled leaves nothing to manage
+      Intent::EnterLong | Intent::EnterShort => {
+        if matches!(&self.position, Some(entry) if entry.amount < QTY_EPSILON) {
+          self.position = None;
+        }
+      }
+      // Order quantities are f32, a filled exit may leave dust behind
+      Intent::ExitLong | Intent::ExitShort => {
+        if status == OrderStatus::Filled {
+          self.position = None;
+        }
+      }
+    }
+  }
+}
@@ -1,8 +1,20 @@
+use crate::binance::api::{KlineResp, OrderInput, OrderSide};
 use crate::binance::websocket::StreamCandle;
+use crate::oms::{Fill, OrderStatus};
+use crate::shared::utils::split_symbol;
+use anyhow::Result;
+use orderbook::OrderBook;
 
+pub mod aggregator;
+pub mod grid;
+pub mod market_maker;
+pub mod mean_reversion;
+pub mod orderbook;
+pub mod pairs;
+pub mod sync;
 pub mod turtle_trade;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CandleStick {
   pub symbol: String,
   pub open_time: i64,
@@ -12,6 +24,7 @@ pub struct CandleStick {
   pub close: f64,
   pub volume: f64,
   pub close_time: i64,
+  pub num_trades: u64,
 }
 
 impl From<StreamCandle> for CandleStick {
@@ -25,6 +38,133 @@ impl From<StreamCandle> for CandleStick {
       close: stream_candle.close.parse::<f64>().unwrap(),
       volume: stream_candle.base_asset_vol.parse::<f64>().unwrap(),
       close_time: stream_candle.close_time,
+      num_trades: stream_candle.num_of_trades as u64,
     }
   }
 }
+
+impl CandleStick {
+  pub fn from_kline(symbol: String, kline: &KlineResp) -> Self {
+    Self {
+      symbol,
+      open_time: kline.open_time,
+      open: kline.open,
+      high: kline.high,
+      low: kline.low,
+      close: kline.close,
+      volume: kline.volume,
+      close_time: kline.close_time,
+      num_trades: kline.num_trades as u64,
+    }
+  }
+
+  /// Kline of the candle, to warm strategies up on aggregated bars. The
+  /// quote and taker volumes aren't tracked and come out as 0.
+  pub fn to_kline(&self) -> KlineResp {
+    KlineResp {
+      open_time: self.open_time,
+      open: self.open,
+      high: self.high,
+      low: self.low,
+      close: self.close,
+      volume: self.volume,
+      close_time: self.close_time,
+      quote_asset_vol: 0.0,
+      num_trades: self.num_trades as i64,
+      taker_buy_base_asset_vol: 0.0,
+      taker_buy_quote_asset_vol: 0.0,
+    }
+  }
+}
+
+/// Largest order quantity that doesn't exceed `amount`, closing a
+/// position can't sell more than it holds
+pub(crate) fn floor_qty(amount: f64) -> f32 {
+  let quantity = amount as f32;
+  if quantity as f64 > amount {
+    quantity.next_down()
+  } else {
+    quantity
+  }
+}
+
+/// Change a fill makes to the base asset held, commission paid in the
+/// base asset comes out of it. Fills that only report their commission
+/// change it by that much.
+pub(crate) fn base_change(fill: &Fill) -> f64 {
+  let quantity = match fill.side {
+    OrderSide::Buy => fill.quantity,
+    OrderSide::Sell => -fill.quantity,
+  };
+  let base_commission = match (&fill.commission_asset, split_symbol(&fill.symbol)) {
+    (Some(asset), Ok((base_asset, _))) if asset.eq_ignore_ascii_case(&base_asset) => {
+      fill.commission
+    }
+    _ => 0.0,
+  };
+  quantity - base_commission
+}
+
+/// Common interface the live trading loop and the backtester drive
+/// strategies through
+pub trait Strategy {
+  fn on_candle(&mut self, candle: CandleStick) -> Result<Vec<OrderInput>>;
+
+  /// Called with one closed candle of every symbol for the same period,
+  /// lined up by `sync::CandleSync`, for strategies trading several
+  /// symbols together. Single symbol strategies see each candle in turn.
+  fn on_candles(&mut self, candles: &[CandleStick]) -> Result<Vec<OrderInput>> {
+    let mut orders = vec![];
+    for candle in candles {
+      orders.extend(self.on_candle(candle.clone())?);
+    }
+    Ok(orders)
+  }
+
+  /// Called on every order book update, for strategies that quote
+  /// against the book rather than trade on candles
+  fn on_book(&mut self, _book: &OrderBook) -> Result<Vec<OrderInput>> {
+    Ok(vec![])
+  }
+
+  /// Client order ids the strategy wants canceled, drained after every
+  /// `on_candle` and `on_book`. Cancels show up in `on_order_finished`.
+  fn take_cancels(&mut self) -> Vec<String> {
+    vec![]
+  }
+
+  /// Called for every execution of an order the strategy placed,
+  /// partial fills arrive one by one
+  fn on_fill(&mut self, _fill: &Fill) -> Result<()> {
+    Ok(())
+  }
+
+  /// Called once an order is filled, canceled, rejected or expired
+  fn on_order_finished(&mut self, _client_order_id: &str, _status: OrderStatus) {}
+}
+
+impl<S: Strategy + ?Sized> Strategy for Box<S> {
+  fn on_candle(&mut self, candle: CandleStick) -> Result<Vec<OrderInput>> {
+    (**self).on_candle(candle)
+  }
+
+  fn on_candles(&mut self, candles: &[CandleStick]) -> Result<Vec<OrderInput>> {
+    (**self).on_candles(candles)
+  }
+
+  fn on_book(&mut self, book: &OrderBook) -> Result<Vec<OrderInput>> {
+    (**self).on_book(book)
+  }
+
+  fn take_cancels(&mut self) -> Vec<String> {
+    (**self).take_cancels()
+  }
+
+  fn on_fill(&mut self, fill: &Fill) -> Result<()> {
+    (**self).on_fill(fill)
+  }
+
+  fn on_order_finished(&mut self, client_order_id: &str, status: OrderStatus) {
+    (**self).on_order_finished(client_order_id, status)
+  }
+}
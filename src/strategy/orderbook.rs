@@ -0,0 +1,84 @@
+use crate::binance::websocket::StreamOrderbook;
+use anyhow::{ensure, Result};
+use csv::StringRecord;
+
+// Columns per level in recorded orderbook files: buy, sale, bc, sc, mid
+const RECORD_LEVEL_COLUMNS: usize = 5;
+
+/// Snapshot of the top levels of a symbol's order book, bids from the
+/// best down and asks from the best up, as (price, quantity)
+#[derive(Clone, Debug)]
+pub struct OrderBook {
+  pub symbol: String,
+  pub time: i64,
+  pub bids: Vec<(f64, f64)>,
+  pub asks: Vec<(f64, f64)>,
+}
+
+impl OrderBook {
+  /// From a partial depth stream message, which carries no event time
+  pub fn from_stream(symbol: &str, time: i64, book: &StreamOrderbook) -> Result<Self> {
+    let parse = |levels: &[Vec<String>]| -> Result<Vec<(f64, f64)>> {
+      levels
+        .iter()
+        .map(|level| {
+          ensure!(level.len() >= 2, "Malformed orderbook level {:?}", level);
+          Ok((level[0].parse::<f64>()?, level[1].parse::<f64>()?))
+        })
+        .collect()
+    };
+    Ok(Self {
+      symbol: symbol.to_uppercase(),
+      time,
+      bids: parse(&book.bids)?,
+      asks: parse(&book.asks)?,
+    })
+  }
+
+  /// From a row of the orderbook files written by `record`
+  pub fn from_record(symbol: &str, record: &StringRecord) -> Result<Self> {
+    ensure!(
+      record.len() > RECORD_LEVEL_COLUMNS
+        && (record.len() - 1).is_multiple_of(RECORD_LEVEL_COLUMNS),
+      "Malformed orderbook row of {} columns",
+      record.len()
+    );
+    let field = |i: usize| -> Result<f64> { Ok(record[i].parse::<f64>()?) };
+    let mut bids = vec![];
+    let mut asks = vec![];
+    for level in 0..(record.len() - 1) / RECORD_LEVEL_COLUMNS {
+      let column = 1 + level * RECORD_LEVEL_COLUMNS;
+      bids.push((field(column)?, field(column + 2)?));
+      asks.push((field(column + 1)?, field(column + 3)?));
+    }
+    Ok(Self {
+      symbol: symbol.to_uppercase(),
+      time: record[0].parse::<i64>()?,
+      bids,
+      asks,
+    })
+  }
+
+  pub fn best_bid(&self) -> Option<f64> {
+    self.bids.first().map(|level| level.0)
+  }
+
+  pub fn best_ask(&self) -> Option<f64> {
+    self.asks.first().map(|level| level.0)
+  }
+
+  /// Mid price, None when a side is empty or the book is crossed
+  pub fn mid(&self) -> Option<f64> {
+    match (self.best_bid(), self.best_ask()) {
+      (Some(bid), Some(ask)) if bid < ask => Some((bid + ask) / 2.0),
+      _ => None,
+    }
+  }
+
+  pub fn spread(&self) -> Option<f64> {
+    match (self.best_bid(), self.best_ask()) {
+      (Some(bid), Some(ask)) => Some(ask - bid),
+      _ => None,
+    }
+  }
+}
@@ -0,0 +1,643 @@
+use super::{base_change, floor_qty, CandleStick, Strategy};
+use crate::binance::api::{OrderInput, OrderSide, OrderType};
+use crate::oms::{Fill, OrderStatus};
+use crate::shared::config::{HedgeMethod, PairsSetting};
+use crate::shared::utils::QTY_EPSILON;
+use anyhow::{anyhow, ensure, Result};
+use std::collections::{HashMap, VecDeque};
+// Engle-Granger 5% critical value of the residuals' Dickey-Fuller
+// t-statistic, two variables with a constant
+const EG_CRITICAL_5PCT: f64 = -3.34;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Leg {
+  Traded, // y of the regression
+  Hedge,  // x of the regression
+}
+
+// Filled quantities of an open pair, signed, negative when short
+#[derive(Debug)]
+struct PairPosition {
+  long: bool, // long the spread: long the traded symbol, short the hedge
+  traded: f64,
+  hedge: f64,
+  cash: f64, // quote asset received minus paid, the PnL once flat
+}
+
+// Regression of the traded symbol's log price on the hedge symbol's,
+// with the Engle-Granger diagnostics of its residuals
+#[derive(Debug)]
+struct Fit {
+  beta: f64,
+  alpha: f64,
+  resid_std: f64,
+  adf_t: f64,             // Dickey-Fuller t-statistic of the residuals
+  half_life: Option<f64>, // of the residuals in candles, None if they don't revert
+  correlation: f64,
+}
+
+impl Fit {
+  fn cointegrated(&self) -> bool {
+    self.adf_t < EG_CRITICAL_5PCT
+  }
+}
+
+// Least squares fit of (x, y) log prices, None if x never moved
+fn fit(window: &VecDeque<(f64, f64)>) -> Option<Fit> {
+  let n = window.len() as f64;
+  let mean_x = window.iter().map(|(x, _)| x).sum::<f64>() / n;
+  let mean_y = window.iter().map(|(_, y)| y).sum::<f64>() / n;
+  let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+  for (x, y) in window {
+    sxx += (x - mean_x).powi(2);
+    syy += (y - mean_y).powi(2);
+    sxy += (x - mean_x) * (y - mean_y);
+  }
+  if sxx <= 0.0 {
+    return None;
+  }
+  let beta = sxy / sxx;
+  let alpha = mean_y - beta * mean_x;
+  let residuals = window
+    .iter()
+    .map(|(x, y)| y - alpha - beta * x)
+    .collect::<Vec<_>>();
+  let resid_std = (residuals.iter().map(|r| r * r).sum::<f64>() / (n - 2.0)).sqrt();
+
+  // Dickey-Fuller regression of the residuals' changes on their level
+  let (mut sll, mut sld) = (0.0, 0.0);
+  for pair in residuals.windows(2) {
+    sll += pair[0] * pair[0];
+    sld += pair[0] * (pair[1] - pair[0]);
+  }
+  if sll <= 0.0 {
+    return None;
+  }
+  let gamma = sld / sll;
+  let sse = residuals
+    .windows(2)
+    .map(|pair| (pair[1] - pair[0] - gamma * pair[0]).powi(2))
+    .sum::<f64>();
+  let se = (sse / (n - 2.0) / sll).sqrt();
+  let adf_t = if se > 0.0 {
+    gamma / se
+  } else {
+    f64::NEG_INFINITY
+  };
+  let half_life = Some(gamma)
+    .filter(|gamma| *gamma < 0.0 && *gamma > -1.0)
+    .map(|gamma| -(2f64.ln()) / (1.0 + gamma).ln());
+  let correlation = if syy > 0.0 {
+    sxy / (sxx * syy).sqrt()
+  } else {
+    0.0
+  };
+  Some(Fit {
+    beta,
+    alpha,
+    resid_std,
+    adf_t,
+    half_life,
+    correlation,
+  })
+}
+
+// Hedge ratio and intercept as a random walk observed through
+// y = beta * x + alpha + noise
+struct Kalman {
+  state: [f64; 2], // beta, alpha
+  cov: [[f64; 2]; 2],
+  drift: f64, // state noise variance
+  noise: f64, // observation noise variance
+}
+
+impl Kalman {
+  fn new(beta: f64, alpha: f64, delta: f64, noise: f64) -> Self {
+    Self {
+      state: [beta, alpha],
+      cov: [[0.0; 2]; 2],
+      drift: delta / (1.0 - delta),
+      noise,
+    }
+  }
+
+  // Predict and correct with one observation, returns the forecast
+  // error and its variance
+  fn update(&mut self, x: f64, y: f64) -> (f64, f64) {
+    let h = [x, 1.0];
+    let mut prior = self.cov;
+    prior[0][0] += self.drift;
+    prior[1][1] += self.drift;
+    let error = y - (h[0] * self.state[0] + h[1] * self.state[1]);
+    let ph = [
+      prior[0][0] * h[0] + prior[0][1] * h[1],
+      prior[1][0] * h[0] + prior[1][1] * h[1],
+    ];
+    let variance = h[0] * ph[0] + h[1] * ph[1] + self.noise;
+    let gain = [ph[0] / variance, ph[1] / variance];
+    for i in 0..2 {
+      self.state[i] += gain[i] * error;
+      for j in 0..2 {
+        self.cov[i][j] = prior[i][j] - gain[i] * ph[j];
+      }
+    }
+    (error, variance)
+  }
+}
+
+/// Trades the spread between the log prices of two symbols, the traded
+/// symbol regressed on the hedge symbol with a rolling OLS or a Kalman
+/// filter. The spread going `entry_z` standard deviations below zero
+/// buys the traded symbol and sells the hedge, scaled by the hedge
+/// ratio, and the mirror image sells the spread. Both legs close once
+/// the z-score is back within `exit_z`, or past `stop_z`. Signals use
+/// the model fitted on the candles before the one traded on.
+pub struct Pairs {
+  symbol: String,
+  hedge_symbol: String,
+  setting: PairsSetting,
+  window: VecDeque<(f64, f64)>, // log closes of the hedge and traded symbols
+  kalman: Option<Kalman>,
+  position: Option<PairPosition>,
+  // orders sent but not finished yet, with whether they close the pair
+  in_flight: HashMap<String, (Leg, bool)>,
+  step_sizes: (f64, f64), // of the traded and hedge symbols, 0 to send quantities as computed
+  order_prefix: String,
+  order_seq: u64,
+}
+
+impl Pairs {
+  /// `history` holds lined up candles of `symbol` and the hedge symbol
+  /// to fit the first hedge ratio on
+  pub fn new(symbol: &str, setting: PairsSetting, history: &[Vec<CandleStick>]) -> Result<Self> {
+    let hedge_symbol = setting.hedge_symbol.to_uppercase();
+    ensure!(
+      !symbol.eq_ignore_ascii_case(&hedge_symbol),
+      "Pairs needs two different symbols, both are {}",
+      hedge_symbol
+    );
+    ensure!(
+      history.len() >= setting.window,
+      "Pairs needs {} candles of both symbols to warm up, got {}",
+      setting.window,
+      history.len()
+    );
+    let mut pairs = Self {
+      symbol: symbol.to_uppercase(),
+      hedge_symbol,
+      window: VecDeque::new(),
+      kalman: None,
+      position: None,
+      in_flight: HashMap::new(),
+      step_sizes: (0.0, 0.0),
+      order_prefix: format!("pair_{}", chrono::Utc::now().timestamp_millis()),
+      order_seq: 0,
+      setting,
+    };
+    for candles in history {
+      if let Some((hedge_close, close)) = pairs.closes(candles) {
+        pairs.push(hedge_close.ln(), close.ln());
+      }
+    }
+    if pairs.setting.hedge_method == HedgeMethod::Kalman {
+      let fit = fit(&pairs.window)
+        .ok_or_else(|| anyhow!("{} never moved while warming up", pairs.hedge_symbol))?;
+      // Observation noise as large as the warm up regression's residuals
+      let noise = fit.resid_std.powi(2);
+      pairs.kalman = Some(Kalman::new(
+        fit.beta,
+        fit.alpha,
+        pairs.setting.kalman_delta,
+        noise,
+      ));
+    }
+    Ok(pairs)
+  }
+
+  /// Round order quantities down to the LOT_SIZE step sizes of the
+  /// traded and hedge symbols
+  pub fn with_step_sizes(mut self, step_size: f64, hedge_step_size: f64) -> Self {
+    self.step_sizes = (step_size, hedge_step_size);
+    self
+  }
+
+  /// Filled quantities of the traded and hedge symbols, negative when short
+  pub fn position(&self) -> Option<(f64, f64)> {
+    self
+      .position
+      .as_ref()
+      .map(|position| (position.traded, position.hedge))
+  }
+
+  fn closes(&self, candles: &[CandleStick]) -> Option<(f64, f64)> {
+    let close = |symbol: &str| {
+      candles
+        .iter()
+        .find(|candle| candle.symbol == symbol)
+        .map(|candle| candle.close)
+        .filter(|close| *close > 0.0)
+    };
+    Some((close(&self.hedge_symbol)?, close(&self.symbol)?))
+  }
+
+  fn push(&mut self, x: f64, y: f64) {
+    self.window.push_back((x, y));
+    if self.window.len() > self.setting.window {
+      self.window.pop_front();
+    }
+  }
+
+  pub fn execute(&mut self, candles: &[CandleStick]) -> Result<Vec<OrderInput>> {
+    let (hedge_price, price) = match self.closes(candles) {
+      Some(closes) => closes,
+      None => {
+        log::warn!("Missing a {}/{} close", self.symbol, self.hedge_symbol);
+        return Ok(vec![]);
+      }
+    };
+    let (x, y) = (hedge_price.ln(), price.ln());
+    let fit = fit(&self.window);
+    let (beta, z) = match (self.kalman.as_mut(), &fit) {
+      (Some(kalman), _) => {
+        let beta = kalman.state[0];
+        let (error, variance) = kalman.update(x, y);
+        (beta, error / variance.sqrt())
+      }
+      (None, Some(fit)) if fit.resid_std > 0.0 => {
+        let spread = y - fit.alpha - fit.beta * x;
+        (fit.beta, spread / fit.resid_std)
+      }
+      (None, _) => (0.0, 0.0),
+    };
+    self.push(x, y);
+    let fit = match fit {
+      Some(fit) => fit,
+      None => {
+        log::warn!("{} never moved over the window, no fit", self.hedge_symbol);
+        return Ok(vec![]);
+      }
+    };
+    log::info!(
+      "Pair: {}/{} Beta: {} Z: {} ADF t: {} ({}) Half-life: {} Correlation: {}",
+      self.symbol,
+      self.hedge_symbol,
+      beta,
+      z,
+      fit.adf_t,
+      if fit.cointegrated() {
+        "cointegrated at 5%"
+      } else {
+        "not cointegrated"
+      },
+      fit
+        .half_life
+        .map_or_else(|| "none".to_string(), |half_life| half_life.to_string()),
+      fit.correlation
+    );
+    // One pair of orders at a time, the next signal waits for its fills
+    if !self.in_flight.is_empty() {
+      return Ok(vec![]);
+    }
+
+    let setting = &self.setting;
+    match &self.position {
+      None if z.abs() < setting.entry_z => Ok(vec![]),
+      None if setting.require_cointegration && !fit.cointegrated() => {
+        log::info!("Spread at z {} but the pair isn't cointegrated", z);
+        Ok(vec![])
+      }
+      None => {
+        let long = z < 0.0;
+        log::info!(
+          "Spread at z {}, {} the spread",
+          z,
+          if long { "buying" } else { "selling" }
+        );
+        Ok(self.enter(long, beta, price, hedge_price))
+      }
+      Some(position) => {
+        let (stop, revert) = match position.long {
+          true => (z <= -setting.stop_z, z >= -setting.exit_z),
+          false => (z >= setting.stop_z, z <= setting.exit_z),
+        };
+        if stop {
+          log::warn!("Spread stopped out at z {}", z);
+        } else if revert {
+          log::info!("Spread back at z {}, closing the pair", z);
+        } else {
+          return Ok(vec![]);
+        }
+        Ok(self.exit())
+      }
+    }
+  }
+
+  // Traded leg worth `notional`, the hedge leg `beta` times that
+  fn enter(&mut self, long: bool, beta: f64, price: f64, hedge_price: f64) -> Vec<OrderInput> {
+    let notional = self.setting.notional;
+    let quantity = notional / price;
+    let hedge_quantity = beta.abs() * notional / hedge_price;
+    let side = if long {
+      OrderSide::Buy
+    } else {
+      OrderSide::Sell
+    };
+    // The hedge leg takes the other side unless the ratio is negative
+    let hedge_side = if long == (beta > 0.0) {
+      OrderSide::Sell
+    } else {
+      OrderSide::Buy
+    };
+    self.position = Some(PairPosition {
+      long,
+      traded: 0.0,
+      hedge: 0.0,
+      cash: 0.0,
+    });
+    let (quantity, hedge_quantity) = (
+      self.round_qty(Leg::Traded, quantity),
+      self.round_qty(Leg::Hedge, hedge_quantity),
+    );
+    if quantity <= 0.0 {
+      log::warn!(
+        "{} notional is less than a step of {}",
+        notional,
+        self.symbol
+      );
+      self.position = None;
+      return vec![];
+    }
+    let mut orders = vec![self.order(Leg::Traded, side, quantity, false)];
+    if hedge_quantity > 0.0 {
+      orders.push(self.order(Leg::Hedge, hedge_side, hedge_quantity, false));
+    }
+    orders
+  }
+
+  // Largest whole number of the leg's steps in `quantity`, the epsilon
+  // keeps exact multiples whole. The nearest f32 prints as the multiple.
+  fn round_qty(&self, leg: Leg, quantity: f64) -> f32 {
+    let step = match leg {
+      Leg::Traded => self.step_sizes.0,
+      Leg::Hedge => self.step_sizes.1,
+    };
+    if step > 0.0 {
+      ((quantity / step + 1e-9).floor() * step) as f32
+    } else {
+      floor_qty(quantity)
+    }
+  }
+
+  fn exit(&mut self) -> Vec<OrderInput> {
+    let (traded, hedge) = match &self.position {
+      Some(position) => (position.traded, position.hedge),
+      None => return vec![],
+    };
+    let mut orders = vec![];
+    for (leg, amount) in [(Leg::Traded, traded), (Leg::Hedge, hedge)].iter() {
+      if amount.abs() < QTY_EPSILON {
+        continue;
+      }
+      let side = if *amount > 0.0 {
+        OrderSide::Sell
+      } else {
+        OrderSide::Buy
+      };
+      let quantity = self.round_qty(*leg, amount.abs());
+      if quantity > 0.0 {
+        orders.push(self.order(*leg, side, quantity, true));
+        continue;
+      }
+      // Less than a step can't be sold, the leg counts as closed
+      log::warn!("Leaving {} of the {:?} leg behind", amount, leg);
+      if let Some(position) = self.position.as_mut() {
+        match leg {
+          Leg::Traded => position.traded = 0.0,
+          Leg::Hedge => position.hedge = 0.0,
+        }
+      }
+    }
+    if orders.is_empty() {
+      self.position = None;
+    }
+    orders
+  }
+
+  fn order(&mut self, leg: Leg, side: OrderSide, quantity: f32, closing: bool) -> OrderInput {
+    self.order_seq += 1;
+    let order_id = format!(
+      "{}_{}_{}_{}",
+      self.order_prefix,
+      if closing { "exit" } else { "enter" },
+      match leg {
+        Leg::Traded => "y",
+        Leg::Hedge => "x",
+      },
+      self.order_seq
+    );
+    self.in_flight.insert(order_id.clone(), (leg, closing));
+    OrderInput {
+      symbol: match leg {
+        Leg::Traded => self.symbol.clone(),
+        Leg::Hedge => self.hedge_symbol.clone(),
+      },
+      side,
+      order_type: OrderType::Market,
+      time_in_force: None,
+      quantity: Some(quantity),
+      quote_order_qty: None,
+      price: None,
+      new_client_order_id: order_id,
+      stop_price: None,
+      iceberg_qty: None,
+      new_order_resp_type: None,
+      recv_window: None,
+      timestamp: chrono::Utc::now().timestamp_millis(),
+    }
+  }
+}
+
+impl Strategy for Pairs {
+  // Signals need both symbols, single candles carry half of one
+  fn on_candle(&mut self, _candle: CandleStick) -> Result<Vec<OrderInput>> {
+    Ok(vec![])
+  }
+
+  fn on_candles(&mut self, candles: &[CandleStick]) -> Result<Vec<OrderInput>> {
+    self.execute(candles)
+  }
+
+  fn on_fill(&mut self, fill: &Fill) -> Result<()> {
+    let (leg, position) = match (
+      self.in_flight.get(&fill.client_order_id),
+      &mut self.position,
+    ) {
+      (Some((leg, _)), Some(position)) => (*leg, position),
+      _ => {
+        log::warn!(
+          "Fill of unknown order {}: {} @ {}",
+          fill.client_order_id,
+          fill.quantity,
+          fill.price
+        );
+        return Ok(());
+      }
+    };
+    let quantity = match fill.side {
+      OrderSide::Buy => fill.quantity,
+      OrderSide::Sell => -fill.quantity,
+    };
+    // Legs hold what the fills left after commission in the base asset,
+    // the exit can't sell more than that
+    match leg {
+      Leg::Traded => position.traded += base_change(fill),
+      Leg::Hedge => position.hedge += base_change(fill),
+    }
+    position.cash -= quantity * fill.price;
+    Ok(())
+  }
+
+  fn on_order_finished(&mut self, client_order_id: &str, status: OrderStatus) {
+    let (leg, closing) = match self.in_flight.remove(client_order_id) {
+      Some(order) => order,
+      None => return,
+    };
+    if status != OrderStatus::Filled {
+      log::warn!("Order {} finished as {:?}", client_order_id, status);
+    }
+    let position = match self.position.as_mut() {
+      Some(position) => position,
+      None => return,
+    };
+    // Order quantities are f32, a filled exit may leave dust behind
+    if closing && status == OrderStatus::Filled {
+      match leg {
+        Leg::Traded => position.traded = 0.0,
+        Leg::Hedge => position.hedge = 0.0,
+      }
+    }
+    if !self.in_flight.is_empty() {
+      return;
+    }
+    let traded_flat = position.traded.abs() < QTY_EPSILON;
+    let hedge_flat = position.hedge.abs() < QTY_EPSILON;
+    match (traded_flat && hedge_flat, closing) {
+      (true, true) => {
+        log::info!("Pair closed, PnL {}", position.cash);
+        self.position = None;
+      }
+      (true, false) => {
+        log::warn!("Pair entry didn't fill");
+        self.position = None;
+      }
+      (false, false) if traded_flat || hedge_flat => {
+        log::warn!("Pair entry filled one leg only, the exit closes it alone")
+      }
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn close(actual: f64, expected: f64) -> bool {
+    (actual - expected).abs() < 1e-6
+  }
+
+  fn candle(symbol: &str, minute: i64, close: f64) -> CandleStick {
+    CandleStick {
+      symbol: symbol.to_string(),
+      open_time: minute * 60_000,
+      open: close,
+      high: close,
+      low: close,
+      close,
+      volume: 1.0,
+      close_time: (minute + 1) * 60_000 - 1,
+      num_trades: 1,
+    }
+  }
+
+  #[test]
+  fn fit_matches_a_hand_computed_regression() {
+    let window = [(1.0, 2.0), (2.0, 4.0), (3.0, 5.0), (4.0, 4.0), (5.0, 5.0)]
+      .iter()
+      .cloned()
+      .collect::<VecDeque<_>>();
+    let fit = fit(&window).unwrap();
+    assert!(close(fit.beta, 0.6));
+    assert!(close(fit.alpha, 2.2));
+    // Residuals -0.8, 0.6, 1.0, -0.6, -0.2
+    assert!(close(fit.resid_std, 0.8f64.sqrt()));
+    assert!(close(fit.correlation, 6.0 / 60f64.sqrt()));
+    // Their changes on their levels: gamma = -2.72 / 2.36, past -1 so
+    // they overshoot instead of decaying and have no half-life
+    let gamma: f64 = -2.72 / 2.36;
+    let sse = [(1.4, -0.8), (0.4, 0.6), (-1.6, 1.0), (0.4, -0.6)]
+      .iter()
+      .map(|(change, level): &(f64, f64)| (change - gamma * level).powi(2))
+      .sum::<f64>();
+    assert!(close(fit.adf_t, gamma / (sse / 3.0 / 2.36).sqrt()));
+    assert!(fit.half_life.is_none());
+    assert!(!fit.cointegrated());
+
+    let flat = [(1.0, 2.0), (1.0, 3.0)].iter().cloned().collect();
+    assert!(super::fit(&flat).is_none());
+  }
+
+  #[test]
+  fn kalman_update_matches_a_hand_computed_step() {
+    // delta 0.5 drifts both states by a variance of 1 per step
+    let mut kalman = Kalman::new(1.0, 0.0, 0.5, 1.0);
+    let (error, variance) = kalman.update(2.0, 3.0);
+    assert!(close(error, 1.0));
+    assert!(close(variance, 6.0));
+    assert!(close(kalman.state[0], 4.0 / 3.0));
+    assert!(close(kalman.state[1], 1.0 / 6.0));
+    let expected = [[1.0 / 3.0, -1.0 / 3.0], [-1.0 / 3.0, 5.0 / 6.0]];
+    for (row, expected) in kalman.cov.iter().zip(expected.iter()) {
+      for (cov, expected) in row.iter().zip(expected.iter()) {
+        assert!(close(*cov, *expected));
+      }
+    }
+  }
+
+  #[test]
+  fn quantities_are_whole_steps() {
+    let setting = PairsSetting {
+      hedge_symbol: "btcusdt".to_string(),
+      window: 3,
+      notional: 1000.0,
+      ..Default::default()
+    };
+    let history = (0..3)
+      .map(|minute| {
+        let price = 100.0 + minute as f64;
+        vec![
+          candle("ETHUSDT", minute, price),
+          candle("BTCUSDT", minute, price * 10.0),
+        ]
+      })
+      .collect::<Vec<_>>();
+    let mut pairs = Pairs::new("ethusdt", setting, &history)
+      .unwrap()
+      .with_step_sizes(0.001, 0.1);
+    let orders = pairs.enter(true, 0.5, 300.0, 2000.0);
+    let quantities = orders
+      .iter()
+      .map(|order| (order.symbol.as_str(), order.quantity.unwrap()))
+      .collect::<Vec<_>>();
+    assert_eq!(quantities, vec![("ETHUSDT", 3.333), ("BTCUSDT", 0.2)]);
+
+    // Less than a step of the hedge leg is left behind on exit
+    let position = pairs.position.as_mut().unwrap();
+    position.traded = 3.333;
+    position.hedge = -0.05;
+    let orders = pairs.exit();
+    assert_eq!(orders.len(), 1);
+    assert_eq!(orders[0].quantity, Some(3.333));
+    assert_eq!(pairs.position(), Some((3.333, 0.0)));
+  }
+}
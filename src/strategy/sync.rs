@@ -0,0 +1,157 @@
+use super::CandleStick;
+use crate::binance::api::KlineResp;
+use std::collections::BTreeMap;
+
+/// Lines up closed candles of several symbols by open time. A period is
+/// handed out once every symbol has its candle, periods older than that
+/// one which are still missing a symbol never complete and are dropped.
+pub struct CandleSync {
+  symbols: Vec<String>,
+  pending: BTreeMap<i64, Vec<Option<CandleStick>>>, // by open time, in `symbols` order
+  last_open_time: Option<i64>,                      // of the last complete period
+}
+
+impl CandleSync {
+  pub fn new(symbols: &[String]) -> Self {
+    Self {
+      symbols: symbols.iter().map(|symbol| symbol.to_uppercase()).collect(),
+      pending: BTreeMap::new(),
+      last_open_time: None,
+    }
+  }
+
+  /// Line up backfilled klines, one list per symbol in `symbols` order,
+  /// into the periods every symbol has
+  pub fn align(symbols: &[String], klines: &[Vec<KlineResp>]) -> Vec<Vec<CandleStick>> {
+    let mut candles = symbols
+      .iter()
+      .zip(klines)
+      .flat_map(|(symbol, klines)| {
+        klines
+          .iter()
+          .map(move |kline| CandleStick::from_kline(symbol.to_uppercase(), kline))
+      })
+      .collect::<Vec<_>>();
+    candles.sort_by_key(|candle| candle.open_time);
+    let mut sync = Self::new(symbols);
+    candles
+      .into_iter()
+      .filter_map(|candle| sync.push(candle))
+      .collect()
+  }
+
+  /// Add a closed candle, returns the candles of its period in `symbols`
+  /// order once it completes the period
+  pub fn push(&mut self, candle: CandleStick) -> Option<Vec<CandleStick>> {
+    let index = match self.symbols.iter().position(|s| *s == candle.symbol) {
+      Some(index) => index,
+      None => {
+        log::warn!("Candle of unexpected symbol {}", candle.symbol);
+        return None;
+      }
+    };
+    if matches!(self.last_open_time, Some(last) if candle.open_time <= last) {
+      log::debug!(
+        "Late {} candle of {}, its period is gone",
+        candle.symbol,
+        candle.open_time
+      );
+      return None;
+    }
+    let open_time = candle.open_time;
+    let symbols = self.symbols.len();
+    let period = self
+      .pending
+      .entry(open_time)
+      .or_insert_with(|| vec![None; symbols]);
+    period[index] = Some(candle);
+    if period.iter().any(Option::is_none) {
+      return None;
+    }
+
+    let newer = self.pending.split_off(&(open_time + 1));
+    let older = std::mem::replace(&mut self.pending, newer);
+    if older.len() > 1 {
+      log::warn!(
+        "Dropping {} periods before {} missing a symbol",
+        older.len() - 1,
+        open_time
+      );
+    }
+    self.last_open_time = Some(open_time);
+    older
+      .into_iter()
+      .next_back()
+      .map(|(_, period)| period.into_iter().flatten().collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MINUTE: i64 = 60_000;
+
+  fn candle(symbol: &str, minute: i64) -> CandleStick {
+    CandleStick {
+      symbol: symbol.to_string(),
+      open_time: minute * MINUTE,
+      open: 1.0,
+      high: 1.0,
+      low: 1.0,
+      close: 1.0,
+      volume: 1.0,
+      close_time: (minute + 1) * MINUTE - 1,
+      num_trades: 1,
+    }
+  }
+
+  fn symbols(candles: &[CandleStick]) -> Vec<&str> {
+    candles
+      .iter()
+      .map(|candle| candle.symbol.as_str())
+      .collect()
+  }
+
+  #[test]
+  fn periods_complete_in_symbol_order() {
+    let mut sync = CandleSync::new(&["ethusdt".to_string(), "btcusdt".to_string()]);
+    assert!(sync.push(candle("BTCUSDT", 0)).is_none());
+    let period = sync.push(candle("ETHUSDT", 0)).unwrap();
+    assert_eq!(symbols(&period), vec!["ETHUSDT", "BTCUSDT"]);
+    assert_eq!(period[0].open_time, period[1].open_time);
+    assert!(sync.push(candle("SOLUSDT", 1)).is_none());
+  }
+
+  #[test]
+  fn incomplete_periods_behind_a_complete_one_are_dropped() {
+    let mut sync = CandleSync::new(&["ethusdt".to_string(), "btcusdt".to_string()]);
+    assert!(sync.push(candle("ETHUSDT", 0)).is_none());
+    assert!(sync.push(candle("ETHUSDT", 1)).is_none());
+    assert!(sync.push(candle("BTCUSDT", 2)).is_none());
+    let period = sync.push(candle("BTCUSDT", 1)).unwrap();
+    assert_eq!(period[0].open_time, MINUTE);
+    // Minute 0 never completes, minute 2 still can
+    assert!(sync.push(candle("BTCUSDT", 0)).is_none());
+    let period = sync.push(candle("ETHUSDT", 2)).unwrap();
+    assert_eq!(period[1].open_time, 2 * MINUTE);
+  }
+
+  #[test]
+  fn align_keeps_periods_every_symbol_has() {
+    let symbols = ["ethusdt".to_string(), "btcusdt".to_string()];
+    let klines = [
+      vec![candle("", 0).to_kline(), candle("", 1).to_kline()],
+      vec![candle("", 1).to_kline(), candle("", 2).to_kline()],
+    ];
+    let periods = CandleSync::align(&symbols, &klines);
+    assert_eq!(periods.len(), 1);
+    assert_eq!(
+      periods[0]
+        .iter()
+        .map(|candle| (candle.symbol.as_str(), candle.open_time))
+        .collect::<Vec<_>>(),
+      vec![("ETHUSDT", MINUTE), ("BTCUSDT", MINUTE)]
+    );
+  }
+}
@@ -1,197 +1,263 @@
 use crate::binance::api::{KlineResp, OrderInput, OrderSide, OrderType};
-use crate::strategy::CandleStick;
+use crate::oms::{Fill, OrderStatus};
+use crate::portfolio::Portfolio;
+use crate::shared::config::TurtleParams;
+use crate::strategy::{base_change, floor_qty, CandleStick, Strategy};
 use anyhow::{ensure, Result};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Intent {
+  EnterLong,
+  EnterShort,
+  ExitLong,
+  ExitShort,
+}
+
+// One entry, built up from the fills of its order
+#[derive(Debug)]
+struct Entry {
+  client_order_id: String,
+  amount: f64, // BTC
+  price: f64,  // Average fill price
+}
 
 pub struct Turtle {
+  params: TurtleParams,
   n: f64,
   // monotonic queues to capture high and lows with a rolling window
-  high_20: VecDeque<(f64, i64)>,
-  low_20: VecDeque<(f64, i64)>,
-  time_anchor: i64, // timestamp that marks the end of a day
+  highs: VecDeque<(f64, i64)>,
+  lows: VecDeque<(f64, i64)>,
+  window_ms: i64,               // length of the breakout window
+  time_anchor: i64,             // close time of the last candle rolled in
+  forming: Option<CandleStick>, // latest update of the candle not closed yet
   prev_close: f64,
-  initial_asset: f64, // in terms of USDT
-  usdt_balance: f64,
-  btc_balance: f64,
-  // record each entry for both position
-  long_position: Vec<(f64, f64)>,  // BTC Amount, Price
-  short_position: Vec<(f64, f64)>, // BTC Amount, Price
+  initial_asset: f64, // in terms of the portfolio's quote currency
+  portfolio: Portfolio,
+  // record each filled entry for both position
+  long_position: Vec<Entry>,
+  short_position: Vec<Entry>,
+  // orders sent but not finished yet
+  in_flight: HashMap<String, Intent>,
+  order_prefix: String,
+  order_seq: u64,
 }
 
 impl Turtle {
-  pub fn new(candles: Vec<KlineResp>, usdt_balance: f64, btc_balance: f64) -> Result<Self> {
-    ensure!(candles.len() > 20, "Not enough data supplied");
-
-    // init with starting day high and low
-    let mut initial_high_20 = candles[0].high;
-    let mut high_timestamp = candles[0].close_time;
-    let mut initial_low_20 = candles[0].low;
-    let mut low_timestamp = candles[0].close_time;
-
-    let mut atr_sum = initial_high_20 - initial_low_20; // Init the atr sum
-    let mut prev_close = candles[0].close;
-    for i in 1..candles.len() {
-      prev_close = candles[i - 1].close;
-      let curr_high = candles[i].high;
-      let curr_low = candles[i].low;
-      let curr_close = candles[i].close_time;
-      if curr_high > initial_high_20 {
-        initial_high_20 = curr_high;
-        high_timestamp = curr_close;
-      }
-
-      if curr_low < initial_low_20 {
-        initial_low_20 = curr_low;
-        low_timestamp = curr_close;
-      }
+  /// `candles` are closed candles to warm up N and the rolling high and
+  /// low with, `portfolio` holds the starting balances, it's marked at
+  /// the close of the last candle
+  pub fn new(
+    symbol: &str,
+    candles: Vec<KlineResp>,
+    params: TurtleParams,
+    mut portfolio: Portfolio,
+  ) -> Result<Self> {
+    params.validate()?;
+    let lookback = params.breakout_window.max(params.atr_period);
+    ensure!(
+      candles.len() > lookback,
+      "Turtle needs more than {} candles to warm up, got {}",
+      lookback,
+      candles.len()
+    );
+    let last = candles.last().unwrap();
+    // The window is kept in time, candles missing from it don't stretch it
+    let window_ms = (last.close_time - last.open_time + 1) * params.breakout_window as i64;
 
-      let tr = vec![
-        curr_high - curr_low,
-        (curr_high - prev_close).abs(),
-        (prev_close - curr_low).abs(),
-      ]
+    // For the initial N, it's just the simple avg of the last true ranges
+    let true_ranges = candles
+      .windows(2)
+      .map(|pair| true_range(pair[1].high, pair[1].low, pair[0].close))
+      .collect::<Vec<_>>();
+    let n = true_ranges[true_ranges.len() - params.atr_period..]
       .iter()
-      .cloned()
-      .fold(0. / 0., f64::max);
-      atr_sum += tr;
-    }
-    let mut high_20 = VecDeque::new();
-    let mut low_20 = VecDeque::new();
-    high_20.push_back((initial_high_20, high_timestamp));
-    low_20.push_back((initial_low_20, low_timestamp));
-
-    let initial_asset = candles.last().unwrap().close * btc_balance + usdt_balance;
-
-    Ok(Self {
-      n: atr_sum / 20.0, // For the initial N, it's just the simple avg of 20 day's true range
-      high_20,
-      low_20,
-      time_anchor: candles.last().unwrap().close_time,
-      prev_close,
+      .sum::<f64>()
+      / params.atr_period as f64;
+
+    portfolio.on_price(symbol, last.close);
+    let initial_asset = portfolio.equity();
+    ensure!(initial_asset > 0.0, "Portfolio has no value to trade with");
+
+    let mut turtle = Self {
+      n,
+      highs: VecDeque::new(),
+      lows: VecDeque::new(),
+      window_ms,
+      time_anchor: last.close_time,
+      forming: None,
+      prev_close: last.close,
       initial_asset,
-      usdt_balance,
-      btc_balance,
+      portfolio,
       // long, short position in terms of btc
       long_position: vec![],
       short_position: vec![],
-    })
+      in_flight: HashMap::new(),
+      order_prefix: format!("turtle_{}", chrono::Utc::now().timestamp_millis()),
+      order_seq: 0,
+      params,
+    };
+    for candle in &candles[candles.len() - turtle.params.breakout_window..] {
+      turtle.update_high(candle.high, candle.close_time);
+      turtle.update_low(candle.low, candle.close_time);
+    }
+    Ok(turtle)
+  }
+
+  pub fn params(&self) -> &TurtleParams {
+    &self.params
   }
 
   pub fn execute(&mut self, curr_candle: CandleStick) -> Result<Vec<OrderInput>> {
+    self.roll(&curr_candle);
     let curr_price = curr_candle.close;
-    let curr_high_20 = self.high_20.front().unwrap().0;
-    let curr_low_20 = self.low_20.front().unwrap().0;
-    let total_asset = self.calc_total_asset(curr_price);
+    let breakout_high = self.highs.front().unwrap().0;
+    let breakout_low = self.lows.front().unwrap().0;
+    self.portfolio.on_price(&curr_candle.symbol, curr_price);
+    let total_asset = self.portfolio.equity();
 
     log::info!(
-      "Symbol: {} Curr Price: {} High 20: {} Low 20: {} Total Asset: {}",
+      "Symbol: {} Curr Price: {} High: {} Low: {} Total Asset: {}",
       curr_candle.symbol,
       curr_price,
-      curr_high_20,
-      curr_low_20,
+      breakout_high,
+      breakout_low,
       total_asset
     );
-    // Turtle trades in terms of unit, if the price exceeds 20 day high,
-    // we long 1 unit. If the price is below 20 day low, we short 1 unit
-    // unit is in USDT
+    // Turtle trades in terms of unit, if the price exceeds the window's
+    // high, we long 1 unit. If the price is below its low, we short 1
+    // unit, unit is in USDT
     let unit = total_asset / self.n;
 
-    // Update 20 day rolling high&low per 24hr period
-    if chrono::Utc::now().timestamp_millis() > self.time_anchor {
-      self.pop_old_high_low();
-      self.update_high(curr_candle.high, curr_candle.close_time);
-      self.update_low(curr_candle.low, curr_candle.close_time);
-      self.update_n(curr_candle.high, curr_candle.low, curr_candle.close);
-
-      self.time_anchor = (chrono::Utc::now() + chrono::Duration::days(1)).timestamp_millis();
-    };
-
-    // We make 4 unit position max to limit our exposure
-    if curr_price > curr_high_20 && self.long_position.len() < 4 {
-      let order = self.buy_order(curr_candle.symbol, unit);
+    // We cap the position at `max_units` to limit our exposure,
+    // counting entries that are still being filled
+    let max_units = self.params.max_units;
+    if curr_price > breakout_high
+      && self.long_position.len() + self.pending(Intent::EnterLong) < max_units
+    {
+      let order = self.order(curr_candle.symbol, Intent::EnterLong, unit);
       log::info!("Sending Long Order: {:#?}", order);
-      // TODO: We should really be adding the orders to the order
-      //  list when we heard back from user stream that this order
-      //  is filled, but for simplicity let's just add it here for now
-      self.long_position.push((unit, curr_price));
       return Ok(vec![order]);
     }
 
-    if curr_price < curr_low_20 && self.short_position.len() < 4 {
-      let order = self.sell_order(curr_candle.symbol, unit);
+    if curr_price < breakout_low
+      && self.short_position.len() + self.pending(Intent::EnterShort) < max_units
+    {
+      let order = self.order(curr_candle.symbol, Intent::EnterShort, unit);
       log::info!("Sending Short Order: {:#?}", order);
-      self.short_position.push((unit, curr_price));
       return Ok(vec![order]);
     }
 
     // take profit
-    if total_asset / self.initial_asset > 1.5 {
+    if total_asset / self.initial_asset > self.params.take_profit {
       log::info!("Profit Taking");
-      return Ok(vec![
-        self.exit_long(curr_candle.symbol.clone(), curr_price),
-        self.exit_short(curr_candle.symbol, curr_price),
-      ]);
+      let orders = vec![
+        self.exit_long(curr_candle.symbol.clone()),
+        self.exit_short(curr_candle.symbol),
+      ];
+      return Ok(orders.into_iter().flatten().collect());
     }
 
-    // Curr price is more than 2N lower than our last long position, we
-    // should exit
-    if self.long_position.len() > 0
-      && self.long_position.last().unwrap().1 - curr_price > 2.0 * self.n
+    // Curr price is more than `stop_n` N lower than our last long
+    // position, we should exit
+    let stop = self.params.stop_n * self.n;
+    if !self.long_position.is_empty()
+      && self.long_position.last().unwrap().price - curr_price > stop
     {
       log::info!("Closing Long");
-      return Ok(vec![self.exit_long(curr_candle.symbol, curr_price)]);
+      return Ok(self.exit_long(curr_candle.symbol).into_iter().collect());
     }
 
-    // Curr price is more than 2N higher than our last short position, we
-    // should exit
-    if self.short_position.len() > 0
-      && curr_price - self.short_position.last().unwrap().1 > 2.0 * self.n
+    // Curr price is more than `stop_n` N higher than our last short
+    // position, we should exit
+    if !self.short_position.is_empty()
+      && curr_price - self.short_position.last().unwrap().price > stop
     {
       log::info!("Closing Short");
-      return Ok(vec![self.exit_short(curr_candle.symbol, curr_price)]);
+      return Ok(self.exit_short(curr_candle.symbol).into_iter().collect());
     }
 
     Ok(vec![])
   }
 
-  fn calc_total_asset(&self, curr_price: f64) -> f64 {
-    let short_profit = self.short_position.iter().fold(0.0, |profit, elem| {
-      let (amount, price) = elem;
-      profit + (curr_price - price) * amount
-    });
-    curr_price * self.btc_balance + self.usdt_balance + short_profit
+  // Candles of a new period close the forming one, which rolls into the
+  // high, low and N once. Driven by candle time so replayed candles
+  // behave the same as live ones, updates of candles already rolled in
+  // are only traded on.
+  fn roll(&mut self, candle: &CandleStick) {
+    if candle.open_time <= self.time_anchor {
+      return;
+    }
+    match self.forming.clone() {
+      Some(forming) if candle.open_time < forming.open_time => return,
+      Some(closed) if candle.open_time > closed.open_time => {
+        self.pop_old_high_low(closed.close_time);
+        self.update_high(closed.high, closed.close_time);
+        self.update_low(closed.low, closed.close_time);
+        self.update_n(closed.high, closed.low, closed.close);
+        self.time_anchor = closed.close_time;
+      }
+      _ => {}
+    }
+    self.forming = Some(candle.clone());
+  }
+
+  fn pending(&self, intent: Intent) -> usize {
+    self.in_flight.values().filter(|i| **i == intent).count()
   }
 
-  fn exit_long(&mut self, symbol: String, curr_price: f64) -> OrderInput {
+  // None if there is nothing to close or a close is already in flight
+  fn exit_long(&mut self, symbol: String) -> Option<OrderInput> {
+    if self.pending(Intent::ExitLong) > 0 {
+      return None;
+    }
     let total_long_amount = self
       .long_position
       .iter()
-      .fold(0.0, |position, elem| position + elem.0)
-      * curr_price;
-    self.long_position = vec![];
-    self.sell_order(symbol, total_long_amount)
+      .fold(0.0, |position, elem| position + elem.amount);
+    if total_long_amount <= 0.0 {
+      return None;
+    }
+    Some(self.order(symbol, Intent::ExitLong, total_long_amount))
   }
 
-  fn exit_short(&mut self, symbol: String, curr_price: f64) -> OrderInput {
+  fn exit_short(&mut self, symbol: String) -> Option<OrderInput> {
+    if self.pending(Intent::ExitShort) > 0 {
+      return None;
+    }
     let total_short_amount = self
       .short_position
       .iter()
-      .fold(0.0, |position, elem| position + elem.0)
-      * curr_price;
-    self.short_position = vec![];
-    self.buy_order(symbol, total_short_amount)
+      .fold(0.0, |position, elem| position + elem.amount);
+    if total_short_amount <= 0.0 {
+      return None;
+    }
+    Some(self.order(symbol, Intent::ExitShort, total_short_amount))
   }
 
-  fn buy_order(&self, symbol: String, unit: f64) -> OrderInput {
+  // Entries are sized in USDT, exits in the BTC amount being closed
+  fn order(&mut self, symbol: String, intent: Intent, amount: f64) -> OrderInput {
     let now = chrono::Utc::now().timestamp_millis();
-    let order_id = format!("long_{}", now);
+    self.order_seq += 1;
+    let (side, label) = match intent {
+      Intent::EnterLong => (OrderSide::Buy, "long"),
+      Intent::EnterShort => (OrderSide::Sell, "short"),
+      Intent::ExitLong => (OrderSide::Sell, "xlong"),
+      Intent::ExitShort => (OrderSide::Buy, "xshort"),
+    };
+    let order_id = format!("{}_{}_{}", self.order_prefix, label, self.order_seq);
+    self.in_flight.insert(order_id.clone(), intent);
+    let (quantity, quote_order_qty) = match intent {
+      Intent::EnterLong | Intent::EnterShort => (None, Some(amount as f32)),
+      Intent::ExitLong | Intent::ExitShort => (Some(floor_qty(amount)), None),
+    };
     OrderInput {
       symbol,
-      side: OrderSide::Buy,
+      side,
       order_type: OrderType::Market,
       time_in_force: None,
-      quantity: None,
-      quote_order_qty: Some(unit as f32),
+      quantity,
+      quote_order_qty,
       price: None,
       new_client_order_id: order_id,
       stop_price: None,
@@ -202,65 +268,236 @@ impl Turtle {
     }
   }
 
-  fn sell_order(&self, symbol: String, unit: f64) -> OrderInput {
-    let now = chrono::Utc::now().timestamp_millis();
-    let order_id = format!("short_{}", now);
-    OrderInput {
-      symbol,
-      side: OrderSide::Sell,
-      order_type: OrderType::Market,
-      time_in_force: None,
-      quantity: None,
-      quote_order_qty: Some(unit as f32),
-      price: None,
-      new_client_order_id: order_id,
-      stop_price: None,
-      iceberg_qty: None,
-      new_order_resp_type: None,
-      recv_window: None,
-      timestamp: now,
+  pub fn portfolio(&self) -> &Portfolio {
+    &self.portfolio
+  }
+
+  // Entries hold what their fills left after commission in the base
+  // asset, exits can't sell more than that
+  fn apply_fill(&mut self, intent: Intent, fill: &Fill) {
+    let change = base_change(fill);
+    match intent {
+      Intent::EnterLong => add_entry(&mut self.long_position, fill, change),
+      Intent::EnterShort => add_entry(&mut self.short_position, fill, -change),
+      Intent::ExitLong => reduce_entries(&mut self.long_position, -change),
+      Intent::ExitShort => reduce_entries(&mut self.short_position, change),
     }
   }
 
+  // Lower highs behind a new one can never be the max again
   fn update_high(&mut self, val: f64, timestamp: i64) {
-    while self.high_20.len() > 0 && self.high_20.back().unwrap().0 < val {
-      self.high_20.pop_front();
+    while matches!(self.highs.back(), Some((high, _)) if *high < val) {
+      self.highs.pop_back();
     }
-    self.high_20.push_back((val, timestamp))
+    self.highs.push_back((val, timestamp))
   }
 
   fn update_low(&mut self, val: f64, timestamp: i64) {
-    while self.low_20.len() > 0 && self.low_20.back().unwrap().0 > val {
-      self.low_20.pop_front();
+    while matches!(self.lows.back(), Some((low, _)) if *low > val) {
+      self.lows.pop_back();
     }
-    self.low_20.push_back((val, timestamp))
+    self.lows.push_back((val, timestamp))
   }
 
-  fn pop_old_high_low(&mut self) {
-    let high_front_timestamp = self.high_20.front().unwrap().1;
-    let low_front_timestamp = self.low_20.front().unwrap().1;
-    let time_limit = chrono::Utc::now()
-      .checked_sub_signed(chrono::Duration::days(20))
-      .unwrap()
-      .timestamp();
-    if high_front_timestamp < time_limit {
-      self.high_20.pop_front();
+  // The newest candle is always kept, the queues never run empty
+  fn pop_old_high_low(&mut self, now: i64) {
+    let time_limit = now - self.window_ms;
+    while self.highs.len() > 1 && self.highs.front().unwrap().1 < time_limit {
+      self.highs.pop_front();
     }
-    if low_front_timestamp < time_limit {
-      self.low_20.pop_front();
+    while self.lows.len() > 1 && self.lows.front().unwrap().1 < time_limit {
+      self.lows.pop_front();
     }
   }
 
   fn update_n(&mut self, curr_high: f64, curr_low: f64, curr_close: f64) {
-    let tr = vec![
-      curr_high - curr_low,
-      (curr_high - self.prev_close).abs(),
-      (self.prev_close - curr_low).abs(),
-    ]
-    .iter()
-    .cloned()
-    .fold(0. / 0., f64::max);
-    self.n = (19.0 * self.n + tr) / 20.0;
+    let tr = true_range(curr_high, curr_low, self.prev_close);
+    let period = self.params.atr_period as f64;
+    self.n = ((period - 1.0) * self.n + tr) / period;
     self.prev_close = curr_close;
   }
 }
+
+fn true_range(high: f64, low: f64, prev_close: f64) -> f64 {
+  [
+    high - low,
+    (high - prev_close).abs(),
+    (prev_close - low).abs(),
+  ]
+  .iter()
+  .cloned()
+  .fold(f64::NAN, f64::max)
+}
+
+// Partial fills of one order merge into a single entry of the `amount`
+// they hold after fees, commission only fills adjust the entry they're for
+fn add_entry(position: &mut Vec<Entry>, fill: &Fill, amount: f64) {
+  if let Some(entry) = position.last_mut() {
+    if entry.client_order_id == fill.client_order_id {
+      let filled = entry.amount + fill.quantity;
+      if filled > 0.0 {
+        entry.price = (entry.amount * entry.price + fill.quantity * fill.price) / filled;
+      }
+      entry.amount += amount;
+      return;
+    }
+  }
+  if amount <= 0.0 {
+    return;
+  }
+  position.push(Entry {
+    client_order_id: fill.client_order_id.clone(),
+    amount,
+    price: fill.price,
+  });
+}
+
+// Close the oldest entries first
+fn reduce_entries(position: &mut Vec<Entry>, mut amount: f64) {
+  while amount > 0.0 && !position.is_empty() {
+    if position[0].amount <= amount {
+      amount -= position.remove(0).amount;
+    } else {
+      position[0].amount -= amount;
+      amount = 0.0;
+    }
+  }
+}
+
+impl Strategy for Turtle {
+  fn on_candle(&mut self, candle: CandleStick) -> Result<Vec<OrderInput>> {
+    self.execute(candle)
+  }
+
+  fn on_fill(&mut self, fill: &Fill) -> Result<()> {
+    // Shorts on spot show up as a negative base balance
+    self.portfolio.on_fill(fill)?;
+    // Reports of synthesized fills only carry their commission, it
+    // matters when paid in the base asset
+    if fill.quantity <= 0.0 && base_change(fill) == 0.0 {
+      return Ok(());
+    }
+    match self.in_flight.get(&fill.client_order_id) {
+      Some(intent) => {
+        let intent = *intent;
+        self.apply_fill(intent, fill);
+      }
+      None => log::warn!(
+        "Fill of unknown order {}: {} @ {}",
+        fill.client_order_id,
+        fill.quantity,
+        fill.price
+      ),
+    }
+    Ok(())
+  }
+
+  fn on_order_finished(&mut self, client_order_id: &str, status: OrderStatus) {
+    if self.in_flight.remove(client_order_id).is_some() && status != OrderStatus::Filled {
+      log::warn!("Order {} finished as {:?}", client_order_id, status);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MINUTE: i64 = 60_000;
+
+  fn kline(minute: i64, close: f64) -> KlineResp {
+    KlineResp {
+      open_time: minute * MINUTE,
+      open: close,
+      high: close + 1.0,
+      low: close - 1.0,
+      close,
+      volume: 1.0,
+      close_time: (minute + 1) * MINUTE - 1,
+      quote_asset_vol: 0.0,
+      num_trades: 1,
+      taker_buy_base_asset_vol: 0.0,
+      taker_buy_quote_asset_vol: 0.0,
+    }
+  }
+
+  fn turtle() -> Turtle {
+    let mut portfolio = Portfolio::new("USDT");
+    portfolio.set_balance("USDT", 10_000.0, 0.0);
+    let candles = (0..30).map(|minute| kline(minute, 100.0)).collect();
+    Turtle::new("BTCUSDT", candles, TurtleParams::default(), portfolio).unwrap()
+  }
+
+  fn fill(client_order_id: &str, side: OrderSide, quantity: f64, commission: f64) -> Fill {
+    Fill {
+      client_order_id: client_order_id.to_string(),
+      order_id: None,
+      trade_id: None,
+      symbol: "BTCUSDT".to_string(),
+      side,
+      price: 100.0,
+      quantity,
+      commission,
+      commission_asset: Some("BTC".to_string()),
+      time: 0,
+      replaces_qty: 0.0,
+    }
+  }
+
+  fn candle(minute: i64, high: f64, close: f64) -> CandleStick {
+    CandleStick {
+      symbol: "BTCUSDT".to_string(),
+      open_time: minute * MINUTE,
+      open: close,
+      high,
+      low: close - 1.0,
+      close,
+      volume: 1.0,
+      close_time: (minute + 1) * MINUTE - 1,
+      num_trades: 1,
+    }
+  }
+
+  #[test]
+  fn candles_roll_in_once_closed() {
+    let mut turtle = turtle();
+    let n = turtle.n;
+    // Updates of the forming candle trade against the closed ones
+    assert!(turtle.execute(candle(30, 150.0, 100.5)).unwrap().is_empty());
+    assert!(turtle.execute(candle(30, 160.0, 100.5)).unwrap().is_empty());
+    assert_eq!(turtle.highs.front().unwrap().0, 101.0);
+    assert_eq!(turtle.n, n);
+
+    // The next period closes it with its last update
+    let orders = turtle.execute(candle(31, 101.0, 100.0)).unwrap();
+    assert!(orders.is_empty());
+    assert_eq!(turtle.highs.front().unwrap().0, 160.0);
+    assert_eq!(turtle.time_anchor, 31 * MINUTE - 1);
+    assert!(turtle.n > n);
+    // A late update of the closed candle changes nothing
+    turtle.execute(candle(30, 170.0, 100.5)).unwrap();
+    assert_eq!(turtle.highs.front().unwrap().0, 160.0);
+  }
+
+  #[test]
+  fn exits_sell_what_entries_hold_after_base_commission() {
+    let mut turtle = turtle();
+    let entry = turtle.order("BTCUSDT".to_string(), Intent::EnterLong, 1000.0);
+    let id = entry.new_client_order_id;
+    turtle
+      .on_fill(&fill(&id, OrderSide::Buy, 3.3, 0.0033))
+      .unwrap();
+    // The trades reported after a totals only response carry the rest
+    // of the commission
+    let mut late = fill(&id, OrderSide::Buy, 0.0, 0.0001);
+    late.replaces_qty = 0.1;
+    turtle.on_fill(&late).unwrap();
+    turtle.on_order_finished(&id, OrderStatus::Filled);
+
+    let exit = turtle.exit_long("BTCUSDT".to_string()).unwrap();
+    let quantity = exit.quantity.unwrap() as f64;
+    assert!(quantity <= 3.3 - 0.0034);
+    assert!(quantity > 3.3 - 0.0034 - 1e-6);
+    assert_eq!(exit.side, OrderSide::Sell);
+  }
+}
@@ -0,0 +1,220 @@
+use super::MarketEvent;
+use crate::binance::websocket::StreamBookTicker;
+use crate::shared::csv_schema::Trade;
+use crate::shared::utils::split_symbol;
+use anyhow::{anyhow, bail, Result};
+use crossbeam_channel::Sender;
+use futures::{stream::StreamExt, SinkExt};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// Coinbase product of a Binance symbol, e.g. `BTC-USDT` for `btcusdt`
+pub fn product_id(symbol: &str) -> Result<String> {
+  let (base, quote) = split_symbol(symbol)?;
+  Ok(format!("{}-{}", base, quote))
+}
+
+/// Binance style symbol of a Coinbase product, e.g. `BTCUSDT` for `BTC-USDT`
+pub fn product_symbol(product_id: &str) -> String {
+  product_id.replace('-', "").to_uppercase()
+}
+
+fn parse_time(time: &str) -> Result<i64> {
+  Ok(chrono::DateTime::parse_from_rfc3339(time)?.timestamp_millis())
+}
+
+/// `ticker` channel update, pushed after every trade with the book's
+/// best bid and ask at that moment
+#[derive(Deserialize, Debug)]
+pub struct FeedTicker {
+  pub sequence: u64,
+  pub product_id: String,
+  pub price: String,
+  pub best_bid: String,
+  pub best_bid_size: String,
+  pub best_ask: String,
+  pub best_ask_size: String,
+  pub time: String,
+}
+
+/// `matches` channel trade
+#[derive(Deserialize, Debug)]
+pub struct FeedMatch {
+  pub trade_id: u64,
+  pub sequence: u64,
+  pub product_id: String,
+  pub price: String,
+  pub size: String,
+  pub side: String, // of the maker order
+  pub time: String,
+}
+
+/// Messages of the websocket feed, subscription acks and channels we
+/// don't read are `Other`
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedMessage {
+  Ticker(FeedTicker),
+  Match(FeedMatch),
+  LastMatch(FeedMatch), // most recent trade, sent once on subscribing
+  Error {
+    message: String,
+    reason: Option<String>,
+  },
+  #[serde(other)]
+  Other,
+}
+
+/// Read a websocket feed message, messages without trades or a book
+/// ticker give `None`
+pub fn parse_message(msg: &str) -> Result<Option<MarketEvent>> {
+  match serde_json::from_str::<FeedMessage>(msg)? {
+    FeedMessage::Ticker(ticker) => Ok(Some(MarketEvent::BookTicker(StreamBookTicker {
+      update_id: ticker.sequence,
+      symbol: product_symbol(&ticker.product_id),
+      bid_price: ticker.best_bid,
+      bid_qty: ticker.best_bid_size,
+      ask_price: ticker.best_ask,
+      ask_qty: ticker.best_ask_size,
+    }))),
+    FeedMessage::Match(trade) | FeedMessage::LastMatch(trade) => {
+      Ok(Some(MarketEvent::Trade(Trade {
+        trade_time: parse_time(&trade.time)?,
+        price: trade.price,
+        amount: trade.size,
+      })))
+    }
+    FeedMessage::Error { message, reason } => bail!(
+      "Coinbase feed error: {} {}",
+      message,
+      reason.unwrap_or_default()
+    ),
+    FeedMessage::Other => Ok(None),
+  }
+}
+
+#[derive(Deserialize, Debug)]
+struct BookResp {
+  sequence: u64,
+  bids: Vec<(String, String, u64)>, // price, size and number of orders
+  asks: Vec<(String, String, u64)>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TradeResp {
+  time: String,
+  price: String,
+  size: String,
+}
+
+/// Public REST endpoints of Coinbase Exchange, no keys needed
+pub struct CoinbaseClient {
+  host: String,
+  client: reqwest::Client,
+}
+
+impl CoinbaseClient {
+  pub fn new(host: String, proxy: Option<String>) -> Result<Self> {
+    // Requests without a user agent are turned away
+    let mut client_builder = reqwest::Client::builder()
+      .connect_timeout(Duration::new(10, 0))
+      .user_agent("crypto_trading");
+    if let Some(proxy) = proxy {
+      client_builder = client_builder.proxy(reqwest::Proxy::https(proxy)?);
+    }
+    Ok(Self {
+      host,
+      client: client_builder.build()?,
+    })
+  }
+
+  /// Best bid and ask of `product_id`
+  pub async fn book_ticker(&self, product_id: &str) -> Result<StreamBookTicker> {
+    let url = format!("{}/products/{}/book?level=1", self.host, product_id);
+    let book = self.get::<BookResp>(&url).await?;
+    let (bid, ask) = match (book.bids.first(), book.asks.first()) {
+      (Some(bid), Some(ask)) => (bid, ask),
+      _ => return Err(anyhow!("Book of {} has an empty side", product_id)),
+    };
+    Ok(StreamBookTicker {
+      update_id: book.sequence,
+      symbol: product_symbol(product_id),
+      bid_price: bid.0.clone(),
+      bid_qty: bid.1.clone(),
+      ask_price: ask.0.clone(),
+      ask_qty: ask.1.clone(),
+    })
+  }
+
+  /// Latest trades of `product_id`, newest first
+  pub async fn trades(&self, product_id: &str) -> Result<Vec<Trade>> {
+    let url = format!("{}/products/{}/trades", self.host, product_id);
+    self
+      .get::<Vec<TradeResp>>(&url)
+      .await?
+      .into_iter()
+      .map(|trade| {
+        Ok(Trade {
+          trade_time: parse_time(&trade.time)?,
+          price: trade.price,
+          amount: trade.size,
+        })
+      })
+      .collect()
+  }
+
+  async fn get<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+    let res = self.client.get(url).send().await?;
+    let status = res.status();
+    let body = res.text().await?;
+    if !status.is_success() {
+      bail!("Coinbase request failed ({}): {}", status.as_u16(), body);
+    }
+    Ok(serde_json::from_str::<T>(&body)?)
+  }
+}
+
+/// Websocket feed of Coinbase Exchange
+pub struct CoinbaseStream {
+  endpoint: String,
+}
+
+impl CoinbaseStream {
+  pub fn new(endpoint: String) -> Self {
+    Self { endpoint }
+  }
+
+  /// Subscribe to the `ticker` and `matches` channels of `product_ids`,
+  /// raw messages go to `sender` to be read with `parse_message`
+  pub async fn subscribe(&self, product_ids: Vec<String>, sender: Sender<String>) -> Result<()> {
+    log::info!("Connecting to {}", self.endpoint);
+    let (mut stream, resp) = connect_async(self.endpoint.as_str()).await?;
+    log::debug!("Websocket server response: {:#?}", resp);
+    let subscribe = serde_json::json!({
+      "type": "subscribe",
+      "product_ids": product_ids,
+      "channels": ["ticker", "matches"],
+    });
+    stream.send(Message::Text(subscribe.to_string())).await?;
+    while let Some(item) = stream.next().await {
+      match item {
+        Ok(Message::Text(data)) => {
+          sender.send(data).map_err(|e| {
+            log::error!("Failed to send data to receiver: {:#?}", e);
+            e
+          })?;
+        }
+        Ok(Message::Ping(ping)) => {
+          stream.send(Message::Pong(ping)).await.map_err(|e| {
+            log::error!("Failed to send pong to server: {:#?}", e);
+            e
+          })?;
+        }
+        Ok(_) => log::error!("Received unsupported data type"),
+        Err(e) => log::error!("Failed to get message from stream: {:#?}", e),
+      }
+    }
+    Ok(())
+  }
+}
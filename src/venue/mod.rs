@@ -0,0 +1,145 @@
+use crate::binance::websocket::StreamBookTicker;
+use crate::shared::config::Venue;
+use crate::shared::csv_schema::Trade;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+pub mod coinbase;
+
+/// Trades and top of book of any venue, read into the types the Binance
+/// streams are read into. Symbols are Binance style, e.g. `BTCUSDT`.
+#[derive(Debug)]
+pub enum MarketEvent {
+  Trade(Trade),
+  BookTicker(StreamBookTicker),
+}
+
+impl MarketEvent {
+  /// Read a message of the combined Binance `@trade` and `@bookTicker`
+  /// streams, messages of other streams are skipped
+  pub fn from_binance(msg: &str) -> Result<Option<Self>> {
+    let msg = serde_json::from_str::<Value>(msg)?;
+    let stream = msg["stream"]
+      .as_str()
+      .ok_or_else(|| anyhow!("Combined stream message without a stream name"))?;
+    let data = msg["data"].clone();
+    if stream.ends_with("@trade") {
+      Ok(Some(MarketEvent::Trade(serde_json::from_value(data)?)))
+    } else if stream.ends_with("@bookTicker") {
+      Ok(Some(MarketEvent::BookTicker(serde_json::from_value(data)?)))
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// Read a websocket message of `venue`
+  pub fn from_venue(venue: Venue, msg: &str) -> Result<Option<Self>> {
+    match venue {
+      Venue::Coinbase => coinbase::parse_message(msg),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn coinbase(msg: &str) -> Result<Option<MarketEvent>> {
+    MarketEvent::from_venue(Venue::Coinbase, msg)
+  }
+
+  #[test]
+  fn coinbase_ticker_is_a_book_ticker() {
+    let event = coinbase(include_str!("../../tests/fixtures/coinbase_ticker.json")).unwrap();
+    match event {
+      Some(MarketEvent::BookTicker(ticker)) => {
+        assert_eq!(ticker.update_id, 37475248783);
+        assert_eq!(ticker.symbol, "BTCUSDT");
+        assert_eq!(
+          (ticker.bid_price.as_str(), ticker.bid_qty.as_str()),
+          ("30010.00", "0.25000000")
+        );
+        assert_eq!(
+          (ticker.ask_price.as_str(), ticker.ask_qty.as_str()),
+          ("30012.00", "0.10400000")
+        );
+      }
+      _ => panic!("Expected a book ticker, got {:?}", event),
+    }
+  }
+
+  #[test]
+  fn coinbase_matches_are_trades() {
+    let fixtures = [
+      (
+        include_str!("../../tests/fixtures/coinbase_match.json"),
+        1685620800250,
+        "30011.50",
+        "0.00120000",
+      ),
+      (
+        include_str!("../../tests/fixtures/coinbase_last_match.json"),
+        1685620798500,
+        "30009.00",
+        "0.05000000",
+      ),
+    ];
+    for (msg, time, price, size) in fixtures.iter() {
+      match coinbase(msg).unwrap() {
+        Some(MarketEvent::Trade(trade)) => {
+          assert_eq!(trade.trade_time, *time);
+          assert_eq!(
+            (trade.price.as_str(), trade.amount.as_str()),
+            (*price, *size)
+          );
+        }
+        event => panic!("Expected a trade, got {:?}", event),
+      }
+    }
+  }
+
+  #[test]
+  fn coinbase_error_fails_and_acks_are_skipped() {
+    let e = coinbase(include_str!("../../tests/fixtures/coinbase_error.json")).unwrap_err();
+    assert!(e.to_string().contains("BTC-XYZ is not a valid product"));
+    let ack = coinbase(include_str!(
+      "../../tests/fixtures/coinbase_subscriptions.json"
+    ))
+    .unwrap();
+    assert!(ack.is_none());
+  }
+
+  #[test]
+  fn binance_combined_streams() {
+    match MarketEvent::from_binance(include_str!("../../tests/fixtures/binance_trade.json"))
+      .unwrap()
+    {
+      Some(MarketEvent::Trade(trade)) => {
+        assert_eq!(trade.trade_time, 1685620800123);
+        assert_eq!(
+          (trade.price.as_str(), trade.amount.as_str()),
+          ("30000.05000000", "0.01200000")
+        );
+      }
+      event => panic!("Expected a trade, got {:?}", event),
+    }
+    match MarketEvent::from_binance(include_str!(
+      "../../tests/fixtures/binance_book_ticker.json"
+    ))
+    .unwrap()
+    {
+      Some(MarketEvent::BookTicker(ticker)) => {
+        assert_eq!(ticker.symbol, "BTCUSDT");
+        assert_eq!(
+          (ticker.bid_price.as_str(), ticker.ask_price.as_str()),
+          ("30000.00000000", "30000.10000000")
+        );
+      }
+      event => panic!("Expected a book ticker, got {:?}", event),
+    }
+    let depth =
+      MarketEvent::from_binance(include_str!("../../tests/fixtures/binance_depth.json")).unwrap();
+    assert!(depth.is_none());
+    assert!(MarketEvent::from_binance(r#"{"result":null,"id":1}"#).is_err());
+  }
+}